@@ -0,0 +1,661 @@
+//! Adaptive Huffman coding, which needs no header and a single pass over the data.
+//!
+//! The encoder and decoder start from the same empty tree and update it identically after every
+//! byte, so the codes follow the data without ever being stored. A byte seen for the first time is
+//! written as the code of the "not yet transmitted" (NYT) leaf followed by 9 raw bits holding the
+//! byte. The 9 bit value `ADAPTIVE_END` marks the end of the data, so the decoder knows where to
+//! stop without being told the size.
+//!
+//! The tree is updated with one of two algorithms, chosen by `AdaptiveAlgorithm`. The encoder and
+//! decoder must use the same one.
+
+use std::io;
+use std::mem;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Value written after the NYT code to mark the end of the data.
+pub const ADAPTIVE_END: u16 = NUM_BYTES as u16;
+
+/// How an `AdaptiveTree` is updated after each byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdaptiveAlgorithm {
+    /// The algorithm of Faller, Gallager and Knuth, which keeps the sibling property by swapping
+    /// each node on the path to the root with the highest numbered node of the same weight before
+    /// incrementing it.
+    #[default]
+    Fgk,
+    /// Vitter's algorithm Λ, which also keeps leaves ahead of internal nodes of the same weight.
+    /// This minimizes the height of the tree and the total length of the codes, and it never
+    /// uses more than one bit per byte more than a two pass static code.
+    Vitter,
+}
+
+/// Most nodes a tree can have, with a leaf for every byte and the NYT leaf.
+const MAX_NODES: usize = 2 * (NUM_BYTES + 1) - 1;
+
+#[derive(Debug, Clone)]
+struct AdaptiveNode {
+    weight: u64,
+    parent: Option<usize>,
+    /// Left and right child of an internal node
+    children: Option<(usize, usize)>,
+    /// Byte of a leaf. `None` for internal nodes and the NYT leaf.
+    symbol: Option<u8>,
+    /// Position in the numbering of the sibling property. Weights never decrease with the number.
+    number: usize,
+}
+
+/// Huffman tree that is updated after every byte, shared by `AdaptiveEncoder` and
+/// `AdaptiveDecoder`.
+#[derive(Debug, Clone)]
+pub struct AdaptiveTree {
+    nodes: Vec<AdaptiveNode>,
+    /// Node with each number. The root has the highest number and new nodes take the lowest.
+    numbered: Vec<Option<usize>>,
+    leaves: Vec<Option<usize>>,
+    nyt: usize,
+    algorithm: AdaptiveAlgorithm,
+    /// Bits of the code being written, reused between bytes
+    path: Vec<bool>,
+}
+
+impl AdaptiveTree {
+    /// The tree before any byte is seen, which is just the NYT leaf, updated with
+    /// `AdaptiveAlgorithm::Fgk`.
+    pub fn new() -> AdaptiveTree {
+        AdaptiveTree::with_algorithm(AdaptiveAlgorithm::default())
+    }
+
+    /// The tree before any byte is seen, updated with `algorithm`.
+    pub fn with_algorithm(algorithm: AdaptiveAlgorithm) -> AdaptiveTree {
+        let mut numbered = vec![None; MAX_NODES];
+        numbered[MAX_NODES - 1] = Some(0);
+
+        AdaptiveTree {
+            nodes: vec![AdaptiveNode {
+                weight: 0,
+                parent: None,
+                children: None,
+                symbol: None,
+                number: MAX_NODES - 1,
+            }],
+            numbered,
+            leaves: vec![None; NUM_BYTES],
+            nyt: 0,
+            algorithm,
+            path: Vec::new(),
+        }
+    }
+
+    /// The algorithm used to update the tree.
+    pub fn algorithm(&self) -> AdaptiveAlgorithm {
+        self.algorithm
+    }
+
+    /// Bytes of memory held by the tree once it has seen every byte.
+    pub fn memory(&self) -> usize {
+        mem::size_of::<AdaptiveNode>() * MAX_NODES + mem::size_of_val(&self.numbered[..]) + mem::size_of_val(&self.leaves[..])
+    }
+
+    /// Number of times `byte` has been seen.
+    pub fn weight(&self, byte: u8) -> u64 {
+        self.leaves[byte as usize].map_or(0, |leaf| self.nodes[leaf].weight)
+    }
+
+    /// Write the code for `byte` and update the tree.
+    pub fn encode_byte<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        match self.leaves[byte as usize] {
+            Some(leaf) => self.write_path(leaf, bit_writer)?,
+            None => {
+                self.write_path(self.nyt, bit_writer)?;
+                bit_writer.write_bits_u64(byte as u64, 9)?;
+            },
+        }
+
+        self.update(byte);
+
+        Ok(())
+    }
+
+    /// Write the end of the data. The tree should not be used to encode afterwards.
+    pub fn encode_end<W: Write>(&mut self, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        self.write_path(self.nyt, bit_writer)?;
+        bit_writer.write_bits_u64(ADAPTIVE_END as u64, 9)
+    }
+
+    /// Read the code of a byte and update the tree.
+    ///
+    /// Returns `None` at the end of the data, and fails with a `DecodeError` if the `BitReader`
+    /// ends first. `bytes_decoded` is only used to describe errors.
+    pub fn decode_byte<R: Read>(&mut self, bit_reader: &mut BitReader<R>, bytes_decoded: u64) -> Result<Option<u8>, Box<dyn Error>> {
+        let bit_offset = bit_reader.position();
+        let mut partial = Code::new(0, 0);
+        let mut node = self.root();
+
+        let unexpected_end = |partial| DecodeError {
+            kind: DecodeErrorKind::UnexpectedEnd,
+            bit_offset,
+            symbols_decoded: bytes_decoded,
+            partial_code: partial,
+        };
+
+        while let Some((left, right)) = self.nodes[node].children {
+            let bit = bit_reader.read_bit()?.ok_or_else(|| unexpected_end(partial))?;
+
+            if partial.length() < MAX_CODE_LENGTH {
+                partial = Code::new((partial.bits() << 1) | bit as u64, partial.length() + 1);
+            }
+
+            node = if bit { right } else { left };
+        }
+
+        let byte = match self.nodes[node].symbol {
+            Some(byte) => byte,
+            None => {
+                if bit_reader.fill(9)? < 9 {
+                    return Err(Box::new(unexpected_end(partial)));
+                }
+
+                let value = (bit_reader.peek() >> 55) as u16;
+                bit_reader.consume(9);
+
+                if value == ADAPTIVE_END {
+                    return Ok(None);
+                }
+
+                if value > ADAPTIVE_END || self.leaves[value as usize].is_some() {
+                    return Err(Box::new(DecodeError {
+                        kind: DecodeErrorKind::InvalidCode,
+                        bit_offset,
+                        symbols_decoded: bytes_decoded,
+                        partial_code: partial,
+                    }));
+                }
+
+                value as u8
+            },
+        };
+
+        self.update(byte);
+
+        Ok(Some(byte))
+    }
+
+    fn root(&self) -> usize {
+        self.numbered[MAX_NODES - 1].expect("The root always exists")
+    }
+
+    fn write_path<W: Write>(&mut self, node: usize, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        self.path.clear();
+
+        let mut node = node;
+        while let Some(parent) = self.nodes[node].parent {
+            let (_left, right) = self.nodes[parent].children.expect("Parents have children");
+            self.path.push(node == right);
+            node = parent;
+        }
+
+        for &bit in self.path.iter().rev() {
+            bit_writer.write_bit(bit)?;
+        }
+
+        Ok(())
+    }
+
+    /// Count one more `byte`, giving it a leaf if it is new.
+    fn update(&mut self, byte: u8) {
+        match self.algorithm {
+            AdaptiveAlgorithm::Fgk => self.update_fgk(byte),
+            AdaptiveAlgorithm::Vitter => self.update_vitter(byte),
+        }
+    }
+
+    fn update_fgk(&mut self, byte: u8) {
+        let mut node = match self.leaves[byte as usize] {
+            Some(leaf) => leaf,
+            None => self.add_leaf(byte),
+        };
+
+        loop {
+            let leader = self.block_leader(node);
+
+            if leader != node && Some(leader) != self.nodes[node].parent {
+                self.swap(node, leader);
+            }
+
+            self.nodes[node].weight += 1;
+
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+    }
+
+    fn update_vitter(&mut self, byte: u8) {
+        // A leaf whose increment waits until its ancestors are done
+        let mut leaf_to_increment = None;
+
+        let mut node = match self.leaves[byte as usize] {
+            Some(leaf) => {
+                let leader = self.leaf_block_leader(leaf);
+                if leader != leaf {
+                    self.swap(leaf, leader);
+                }
+
+                let parent = self.nodes[leaf].parent;
+                match parent.and_then(|parent| self.nodes[parent].children) {
+                    Some((left, right)) if left == self.nyt || right == self.nyt => {
+                        leaf_to_increment = Some(leaf);
+                        parent
+                    },
+                    _ => Some(leaf),
+                }
+            },
+            None => {
+                let leaf = self.add_leaf(byte);
+                leaf_to_increment = Some(leaf);
+                self.nodes[leaf].parent
+            },
+        };
+
+        while let Some(next) = node {
+            node = self.slide_and_increment(next);
+        }
+
+        if let Some(leaf) = leaf_to_increment {
+            self.slide_and_increment(leaf);
+        }
+    }
+
+    /// Move `node` past the block that follows it in the numbering, increment it, and return the
+    /// next node to increment.
+    ///
+    /// A leaf moves past the internal nodes of its weight, and an internal node past the leaves
+    /// of one more than its weight, so the leaves of each weight stay ahead of its internal nodes.
+    fn slide_and_increment(&mut self, node: usize) -> Option<usize> {
+        let weight = self.nodes[node].weight;
+        let is_leaf = self.nodes[node].children.is_none();
+        let former_parent = self.nodes[node].parent;
+
+        let start = self.nodes[node].number;
+        let mut end = start;
+
+        while let Some(next) = self.numbered.get(end + 1).and_then(|&next| next) {
+            let next = &self.nodes[next];
+            let in_block = match is_leaf {
+                true => next.children.is_some() && next.weight == weight,
+                false => next.children.is_none() && next.weight == weight + 1,
+            };
+
+            if !in_block {
+                break;
+            }
+
+            end += 1;
+        }
+
+        if end > start {
+            self.slide(start, end);
+        }
+
+        self.nodes[node].weight += 1;
+
+        match is_leaf {
+            true => self.nodes[node].parent,
+            false => former_parent,
+        }
+    }
+
+    /// Move the node numbered `start` to the place of the node numbered `end`, and every node
+    /// numbered above `start` down one place, along with their subtrees.
+    fn slide(&mut self, start: usize, end: usize) {
+        // The parent of each place and whether it is the right child
+        let places: Vec<(usize, bool)> = (start..=end)
+            .map(|number| {
+                let node = self.numbered[number].expect("Numbers below the root are used");
+                let parent = self.nodes[node].parent.expect("Only the root has no parent");
+                let (_left, right) = self.nodes[parent].children.expect("Parents have children");
+
+                (parent, node == right)
+            })
+            .collect();
+
+        let moved: Vec<usize> = (start + 1..=end).chain(Some(start))
+            .map(|number| self.numbered[number].expect("Numbers below the root are used"))
+            .collect();
+
+        for ((number, &(parent, is_right)), node) in (start..=end).zip(places.iter()).zip(moved) {
+            let (left, right) = self.nodes[parent].children.expect("Parents have children");
+            self.nodes[parent].children = Some(if is_right { (left, node) } else { (node, right) });
+            self.nodes[node].parent = Some(parent);
+            self.nodes[node].number = number;
+            self.numbered[number] = Some(node);
+        }
+    }
+
+    /// The highest numbered leaf with the same weight as `leaf`.
+    fn leaf_block_leader(&self, leaf: usize) -> usize {
+        let weight = self.nodes[leaf].weight;
+        let mut leader = leaf;
+
+        while let Some(next) = self.numbered.get(self.nodes[leader].number + 1).and_then(|&next| next) {
+            if self.nodes[next].weight != weight || self.nodes[next].children.is_some() {
+                break;
+            }
+
+            leader = next;
+        }
+
+        leader
+    }
+
+    /// Split the NYT leaf into a new NYT leaf and a leaf for `byte`, both of weight 0.
+    fn add_leaf(&mut self, byte: u8) -> usize {
+        let parent = self.nyt;
+        let number = self.nodes[parent].number;
+
+        let nyt = self.push(AdaptiveNode { weight: 0, parent: Some(parent), children: None, symbol: None, number: number - 2 });
+        let leaf = self.push(AdaptiveNode { weight: 0, parent: Some(parent), children: None, symbol: Some(byte), number: number - 1 });
+
+        self.nodes[parent].children = Some((nyt, leaf));
+        self.nyt = nyt;
+        self.leaves[byte as usize] = Some(leaf);
+
+        leaf
+    }
+
+    fn push(&mut self, node: AdaptiveNode) -> usize {
+        let index = self.nodes.len();
+        self.numbered[node.number] = Some(index);
+        self.nodes.push(node);
+
+        index
+    }
+
+    /// The highest numbered node with the same weight as `node`.
+    fn block_leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let mut leader = node;
+
+        while let Some(next) = self.numbered.get(self.nodes[leader].number + 1).and_then(|&next| next) {
+            if self.nodes[next].weight != weight {
+                break;
+            }
+
+            leader = next;
+        }
+
+        leader
+    }
+
+    /// Exchange the places of two nodes, neither of which is an ancestor of the other, along with
+    /// their subtrees.
+    fn swap(&mut self, a: usize, b: usize) {
+        let (parent_a, parent_b) = match (self.nodes[a].parent, self.nodes[b].parent) {
+            (Some(parent_a), Some(parent_b)) => (parent_a, parent_b),
+            _ => return,
+        };
+
+        if parent_a == parent_b {
+            let (left, right) = self.nodes[parent_a].children.expect("Parents have children");
+            self.nodes[parent_a].children = Some((right, left));
+        } else {
+            self.replace_child(parent_a, a, b);
+            self.replace_child(parent_b, b, a);
+            self.nodes[a].parent = Some(parent_b);
+            self.nodes[b].parent = Some(parent_a);
+        }
+
+        let (number_a, number_b) = (self.nodes[a].number, self.nodes[b].number);
+        self.nodes[a].number = number_b;
+        self.nodes[b].number = number_a;
+        self.numbered[number_a] = Some(b);
+        self.numbered[number_b] = Some(a);
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        let (left, right) = self.nodes[parent].children.expect("Parents have children");
+
+        self.nodes[parent].children = Some(if left == old { (new, right) } else { (left, new) });
+    }
+}
+
+impl Default for AdaptiveTree {
+    fn default() -> AdaptiveTree {
+        AdaptiveTree::new()
+    }
+}
+
+/// Encodes bytes written to it with adaptive Huffman codes.
+///
+/// Call `finish` after the last byte to mark the end of the data. The final partial byte is
+/// padded with zeros when the encoder is dropped.
+pub struct AdaptiveEncoder<W: Write> {
+    bit_writer: BitWriter<W>,
+    tree: AdaptiveTree,
+}
+
+impl<W: Write> AdaptiveEncoder<W> {
+    pub fn new(write: W) -> AdaptiveEncoder<W> {
+        AdaptiveEncoder {
+            bit_writer: BitWriter::new(write),
+            tree: AdaptiveTree::new(),
+        }
+    }
+
+    /// Update the tree with `algorithm`. Must be called before any byte is written, and the
+    /// `AdaptiveDecoder` must use the same algorithm.
+    pub fn algorithm(mut self, algorithm: AdaptiveAlgorithm) -> AdaptiveEncoder<W> {
+        self.tree = AdaptiveTree::with_algorithm(algorithm);
+        self
+    }
+
+    /// The tree as updated by the bytes written so far.
+    pub fn tree(&self) -> &AdaptiveTree {
+        &self.tree
+    }
+
+    /// Mark the end of the data and write the final partial byte.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.tree.encode_end(&mut self.bit_writer)
+    }
+}
+
+impl<W: Write> Write for AdaptiveEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.tree.encode_byte(byte, &mut self.bit_writer)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes data written by an `AdaptiveEncoder`, reading as far as the end of the data.
+///
+/// Errors are returned as `io::Error`s wrapping a `DecodeError` where there is one.
+pub struct AdaptiveDecoder<R> {
+    bit_reader: BitReader<R>,
+    tree: AdaptiveTree,
+    bytes_decoded: u64,
+    finished: bool,
+    /// Error to return from the next `read`, after the bytes decoded before it
+    error: Option<io::Error>,
+}
+
+impl<R: Read> AdaptiveDecoder<R> {
+    pub fn new(read: R) -> AdaptiveDecoder<R> {
+        AdaptiveDecoder {
+            bit_reader: BitReader::new(read),
+            tree: AdaptiveTree::new(),
+            bytes_decoded: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Update the tree with `algorithm`, which must match the `AdaptiveEncoder`. Must be called
+    /// before any byte is read.
+    pub fn algorithm(mut self, algorithm: AdaptiveAlgorithm) -> AdaptiveDecoder<R> {
+        self.tree = AdaptiveTree::with_algorithm(algorithm);
+        self
+    }
+
+    /// The tree as updated by the bytes read so far.
+    pub fn tree(&self) -> &AdaptiveTree {
+        &self.tree
+    }
+}
+
+impl<R: Read> Read for AdaptiveDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let mut read = 0;
+
+        while read < buf.len() && !self.finished {
+            match self.tree.decode_byte(&mut self.bit_reader, self.bytes_decoded) {
+                Ok(Some(byte)) => {
+                    buf[read] = byte;
+                    read += 1;
+                    self.bytes_decoded += 1;
+                },
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    let err = into_io_error(err);
+
+                    if read == 0 {
+                        return Err(err);
+                    }
+
+                    self.error = Some(err);
+                    break;
+                },
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// Convert an error for returning from `Read::read`, keeping a `DecodeError` where there is one.
+pub(crate) fn into_io_error<E: Into<Box<dyn Error>>>(err: E) -> io::Error {
+    match HuffmanError::from(err.into()) {
+        HuffmanError::Io(err) => err,
+        HuffmanError::Decode(err) => io::Error::new(io::ErrorKind::InvalidData, err),
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const ALGORITHMS: [AdaptiveAlgorithm; 2] = [AdaptiveAlgorithm::Fgk, AdaptiveAlgorithm::Vitter];
+
+    fn round_trip(data: &[u8], algorithm: AdaptiveAlgorithm) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = AdaptiveEncoder::new(&mut encoded).algorithm(algorithm);
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let mut decoded = Vec::new();
+        AdaptiveDecoder::new(Cursor::new(&encoded)).algorithm(algorithm).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor \
+            incididunt ut labore et dolore magna aliqua.".repeat(20);
+        let every_byte: Vec<u8> = (0..4).flat_map(|_| 0..=255).collect();
+
+        for algorithm in ALGORITHMS {
+            // Just the end marker
+            assert_eq!(round_trip(b"", algorithm), vec![0b10000000, 0]);
+
+            round_trip(b"a", algorithm);
+            round_trip(b"abracadabra", algorithm);
+            round_trip(&every_byte, algorithm);
+
+            let encoded = round_trip(text.as_bytes(), algorithm);
+            assert!(encoded.len() < text.len() * 3 / 4);
+        }
+    }
+
+    #[test]
+    fn test_sibling_property() {
+        // Skewed pseudo random bytes
+        let mut state: u32 = 1;
+        let data: Vec<u8> = (0..3000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) % 64) as u8 & ((state >> 8) as u8 | 0x0f)
+        }).collect();
+
+        for algorithm in ALGORITHMS {
+            let mut tree = AdaptiveTree::with_algorithm(algorithm);
+
+            for &byte in data.iter().chain(b"mississippi") {
+                tree.update(byte);
+
+                // Weights never decrease with the number, and every internal node holds the total
+                // weight of its children
+                let numbered: Vec<&AdaptiveNode> = tree.numbered.iter().flatten().map(|&node| &tree.nodes[node]).collect();
+                assert!(numbered.windows(2).all(|pair| pair[0].weight <= pair[1].weight));
+
+                for (index, node) in tree.nodes.iter().enumerate() {
+                    assert_eq!(tree.numbered[node.number], Some(index));
+
+                    if let Some((left, right)) = node.children {
+                        assert_eq!(node.weight, tree.nodes[left].weight + tree.nodes[right].weight);
+                        assert_eq!(tree.nodes[left].parent, Some(index));
+                        assert_eq!(tree.nodes[right].parent, Some(index));
+                    }
+                }
+
+                // Vitter's algorithm also keeps leaves ahead of internal nodes of the same weight
+                if algorithm == AdaptiveAlgorithm::Vitter {
+                    assert!(numbered.windows(2).all(|pair| pair[0].weight < pair[1].weight
+                        || pair[0].children.is_none() || pair[1].children.is_some()));
+                }
+            }
+
+            assert_eq!(tree.weight(b's'), data.iter().filter(|&&byte| byte == b's').count() as u64 + 4);
+            assert_eq!(tree.weight(0xff), 0);
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = AdaptiveEncoder::new(&mut encoded);
+            encoder.write_all(b"abcabc").unwrap();
+            encoder.finish().unwrap();
+        }
+
+        // Cut into the end marker
+        let mut decoded = Vec::new();
+        let err = AdaptiveDecoder::new(Cursor::new(&encoded[..encoded.len() - 1])).read_to_end(&mut decoded).unwrap_err();
+        let err = err.get_ref().unwrap().downcast_ref::<DecodeError>().unwrap();
+        assert_eq!(err.kind, DecodeErrorKind::UnexpectedEnd);
+        assert_eq!(err.symbols_decoded, 6);
+        assert_eq!(decoded, b"abcabc");
+    }
+}
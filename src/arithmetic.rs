@@ -0,0 +1,314 @@
+//! Arithmetic coding of bytes with a static `FrequencyModel`.
+//!
+//! Each byte narrows an interval in proportion to its frequency, so a byte costs close to its
+//! information content even when that is far below a bit. Huffman codes spend at least a bit on
+//! every byte, which loses heavily on skewed data. The coder keeps 32 bits of the interval and
+//! writes bits as soon as they are settled.
+
+use std::io;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+const CODE_BITS: u32 = 32;
+const TOP: u64 = (1 << CODE_BITS) - 1;
+const HALF: u64 = 1 << (CODE_BITS - 1);
+const QUARTER: u64 = 1 << (CODE_BITS - 2);
+
+/// State for writing bytes as arithmetic codes.
+///
+/// Call `finish` after the last byte.
+#[derive(Debug, Clone)]
+pub struct ArithmeticEncoder {
+    low: u64,
+    high: u64,
+    /// Bits that follow the next settled bit, each its opposite
+    pending: u64,
+}
+
+impl ArithmeticEncoder {
+    pub fn new() -> ArithmeticEncoder {
+        ArithmeticEncoder {
+            low: 0,
+            high: TOP,
+            pending: 0,
+        }
+    }
+
+    /// Write `byte`, which must have a nonzero frequency in `model`.
+    pub fn encode_byte<W: Write>(&mut self, model: &FrequencyModel, byte: u8, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        let frequency = model.frequency(byte) as u64;
+
+        if frequency == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Byte {} has no frequency in the model", byte)));
+        }
+
+        let start = model.cumulative(byte) as u64;
+        let range = self.high - self.low + 1;
+
+        self.high = self.low + ((range * (start + frequency)) >> MODEL_TOTAL_BITS) - 1;
+        self.low += (range * start) >> MODEL_TOTAL_BITS;
+
+        loop {
+            if self.high < HALF {
+                self.write_settled(false, bit_writer)?;
+            } else if self.low >= HALF {
+                self.write_settled(true, bit_writer)?;
+                self.low -= HALF;
+                self.high -= HALF;
+            } else if self.low >= QUARTER && self.high < HALF + QUARTER {
+                self.pending += 1;
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+            } else {
+                break;
+            }
+
+            self.low <<= 1;
+            self.high = (self.high << 1) | 1;
+        }
+
+        Ok(())
+    }
+
+    /// Write enough bits to pick out the final interval, then pad so the decoder never reads
+    /// past them. The encoder is then ready for new data.
+    pub fn finish<W: Write>(&mut self, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        self.pending += 1;
+        let bit = self.low >= QUARTER;
+        self.write_settled(bit, bit_writer)?;
+
+        // The decoder reads `CODE_BITS` bits past the last shift, two of which were just written
+        bit_writer.write_bits_u64(0, CODE_BITS as u8 - 2)?;
+
+        *self = ArithmeticEncoder::new();
+
+        Ok(())
+    }
+
+    fn write_settled<W: Write>(&mut self, bit: bool, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        bit_writer.write_bit(bit)?;
+
+        while self.pending > 0 {
+            bit_writer.write_bit(!bit)?;
+            self.pending -= 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ArithmeticEncoder {
+    fn default() -> ArithmeticEncoder {
+        ArithmeticEncoder::new()
+    }
+}
+
+/// State for reading bytes written by an `ArithmeticEncoder`.
+#[derive(Debug, Clone)]
+pub struct ArithmeticDecoder {
+    low: u64,
+    high: u64,
+    value: u64,
+    /// Whether the first `CODE_BITS` bits have been read into `value`
+    started: bool,
+}
+
+impl ArithmeticDecoder {
+    pub fn new() -> ArithmeticDecoder {
+        ArithmeticDecoder {
+            low: 0,
+            high: TOP,
+            value: 0,
+            started: false,
+        }
+    }
+
+    /// Read the next byte with the same `model` used to write it.
+    ///
+    /// Fails with a `DecodeError` if the data ends early. `bytes_decoded` is only used to
+    /// describe errors.
+    pub fn decode_byte<R: Read>(&mut self, model: &FrequencyModel, bit_reader: &mut BitReader<R>, bytes_decoded: u64) -> Result<u8, Box<dyn Error>> {
+        let bit_offset = bit_reader.position();
+
+        if !self.started {
+            for _ in 0..CODE_BITS {
+                self.value = (self.value << 1) | next_bit(bit_reader, bit_offset, bytes_decoded)? as u64;
+            }
+
+            self.started = true;
+        }
+
+        let range = self.high - self.low + 1;
+        let target = (((self.value - self.low + 1) << MODEL_TOTAL_BITS) - 1) / range;
+        let byte = model.symbol_at(target as u32);
+
+        let start = model.cumulative(byte) as u64;
+        let frequency = model.frequency(byte) as u64;
+
+        self.high = self.low + ((range * (start + frequency)) >> MODEL_TOTAL_BITS) - 1;
+        self.low += (range * start) >> MODEL_TOTAL_BITS;
+
+        loop {
+            if self.high < HALF {
+                // Nothing to remove
+            } else if self.low >= HALF {
+                self.value -= HALF;
+                self.low -= HALF;
+                self.high -= HALF;
+            } else if self.low >= QUARTER && self.high < HALF + QUARTER {
+                self.value -= QUARTER;
+                self.low -= QUARTER;
+                self.high -= QUARTER;
+            } else {
+                break;
+            }
+
+            self.low <<= 1;
+            self.high = (self.high << 1) | 1;
+            self.value = (self.value << 1) | next_bit(bit_reader, bit_offset, bytes_decoded)? as u64;
+        }
+
+        Ok(byte)
+    }
+}
+
+impl Default for ArithmeticDecoder {
+    fn default() -> ArithmeticDecoder {
+        ArithmeticDecoder::new()
+    }
+}
+
+/// A `FrequencyModel` with the state to arithmetic code with it, for use as an `EntropyCoder`.
+#[derive(Debug, Clone)]
+pub struct ArithmeticCoder {
+    model: FrequencyModel,
+    encoder: ArithmeticEncoder,
+    decoder: ArithmeticDecoder,
+}
+
+impl ArithmeticCoder {
+    pub fn new(model: FrequencyModel) -> ArithmeticCoder {
+        ArithmeticCoder {
+            model,
+            encoder: ArithmeticEncoder::new(),
+            decoder: ArithmeticDecoder::new(),
+        }
+    }
+
+    pub fn model(&self) -> &FrequencyModel {
+        &self.model
+    }
+}
+
+impl EntropyCoder for ArithmeticCoder {
+    fn table_memory(&self) -> usize {
+        self.model.memory()
+    }
+
+    fn from_frequencies(frequencies: &FrequencyTable) -> Result<ArithmeticCoder, Box<dyn Error>> {
+        Ok(ArithmeticCoder::new(FrequencyModel::from_frequencies(frequencies)?))
+    }
+
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>> {
+        Ok(self.model.write_header(write)?)
+    }
+
+    fn read_header<R: Read>(read: &mut R) -> Result<ArithmeticCoder, Box<dyn Error>> {
+        Ok(ArithmeticCoder::new(FrequencyModel::read_header(read)?))
+    }
+
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(self.encoder.encode_byte(&self.model, byte, bit_writer)?)
+    }
+
+    fn finish<W: Write>(&mut self, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(self.encoder.finish(bit_writer)?)
+    }
+
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, _symbols_left: u64) -> Result<u8, Box<dyn Error>> {
+        self.decoder.decode_byte(&self.model, bit_reader, symbols_decoded)
+    }
+}
+
+fn next_bit<R: Read>(bit_reader: &mut BitReader<R>, bit_offset: u64, bytes_decoded: u64) -> Result<bool, Box<dyn Error>> {
+    match bit_reader.read_bit()? {
+        Some(bit) => Ok(bit),
+        None => Err(Box::new(DecodeError {
+            kind: DecodeErrorKind::UnexpectedEnd,
+            bit_offset,
+            symbols_decoded: bytes_decoded,
+            partial_code: Code::new(0, 0),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(data);
+        let model = FrequencyModel::from_frequencies(&frequencies).unwrap();
+
+        let mut encoded = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(&mut encoded);
+            let mut encoder = ArithmeticEncoder::new();
+            for &byte in data {
+                encoder.encode_byte(&model, byte, &mut bit_writer).unwrap();
+            }
+            encoder.finish(&mut bit_writer).unwrap();
+        }
+
+        let mut bit_reader = BitReader::new(&encoded[..]);
+        let mut decoder = ArithmeticDecoder::new();
+        let decoded: Vec<u8> = (0..data.len() as u64).map(|i| decoder.decode_byte(&model, &mut bit_reader, i).unwrap()).collect();
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"a");
+        round_trip(b"abracadabra");
+        round_trip(&(0..=255).collect::<Vec<u8>>());
+
+        // A skewed distribution costs far less than a bit per byte
+        let mut skewed = vec![b'a'; 10000];
+        for i in (0..10000).step_by(100) {
+            skewed[i] = b'b';
+        }
+        let encoded = round_trip(&skewed);
+        assert!(encoded.len() < 10000 / 8 / 5);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(data);
+        let model = FrequencyModel::from_frequencies(&frequencies).unwrap();
+
+        let mut encoded = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(&mut encoded);
+            let mut encoder = ArithmeticEncoder::new();
+            encoder.encode_byte(&model, b'a', &mut bit_writer).unwrap();
+            assert!(encoder.encode_byte(&model, b'Z', &mut bit_writer).is_err());
+            for &byte in data.iter() {
+                encoder.encode_byte(&model, byte, &mut bit_writer).unwrap();
+            }
+            encoder.finish(&mut bit_writer).unwrap();
+        }
+
+        let mut bit_reader = BitReader::new(&encoded[..encoded.len() / 2]);
+        let mut decoder = ArithmeticDecoder::new();
+        let err = (0..data.len() as u64 + 1).map(|i| decoder.decode_byte(&model, &mut bit_reader, i)).find_map(Result::err).unwrap();
+        assert_eq!(err.downcast_ref::<DecodeError>().unwrap().kind, DecodeErrorKind::UnexpectedEnd);
+    }
+}
@@ -1,53 +1,121 @@
+#[cfg(feature = "std")]
 use std::io;
+#[cfg(feature = "std")]
 use std::io::{Read, Write};
+use core::fmt;
+use core::error::Error;
+
+use super::Code;
 
 const MAX_MASK: u8 = 1 << 7;
+const MAX_U64_MASK: u64 = 1 << 63;
 
+/// Reads the bits of a `Read`, most significant bit first.
+///
+/// Bits are buffered a byte at a time so that upcoming bits can be inspected with `peek` before
+/// they are consumed.
+#[cfg(feature = "std")]
 pub struct BitReader<T> {
     read: T,
     buf: [u8; 1],
-    current: u8,
-    mask: u8
+    bits: u64,
+    count: u8,
+    position: u64,
 }
 
+#[cfg(feature = "std")]
 impl <T: Read> BitReader<T> {
     pub fn new(read: T) -> BitReader<T> {
         BitReader {
             read,
             buf: [0; 1],
-            current: 0,
-            mask: 0,
+            bits: 0,
+            count: 0,
+            position: 0,
         }
     }
 
     pub fn read_bit(&mut self) -> io::Result<Option<bool>> {
-        if self.mask == 0 {
-            match self.read_next_byte()? {
-                None => return Ok(None),
-                _ => (),
-            }
+        if self.fill(1)? == 0 {
+            return Ok(None);
         }
 
-        let bit = (self.current & self.mask) != 0;
-        self.mask >>= 1;
+        let bit = (self.bits & MAX_U64_MASK) != 0;
+        self.consume(1);
 
         Ok(Some(bit))
     }
 
-    fn read_next_byte(&mut self) -> io::Result<Option<()>> {
-        let bytes_read = self.read.read(&mut self.buf)?;
+    /// Buffer at least `bits` bits, or as many as are left in the `Read`.
+    ///
+    /// Returns the number of buffered bits. At most 57 bits can be requested.
+    pub fn fill(&mut self, bits: u8) -> io::Result<u8> {
+        debug_assert!(bits <= 57);
 
-        if bytes_read == 0 {
-            return Ok(None);
+        while self.count < bits {
+            if read_some(&mut self.read, &mut self.buf)? == 0 {
+                break;
+            }
+
+            self.bits |= (self.buf[0] as u64) << (56 - self.count);
+            self.count += 8;
         }
 
-        self.current = self.buf[0];
-        self.mask = MAX_MASK;
+        Ok(self.count)
+    }
+
+    /// The buffered bits, left aligned. Bits past the buffered count are zero.
+    #[inline]
+    pub fn peek(&self) -> u64 {
+        self.bits
+    }
+
+    /// Discard `bits` buffered bits.
+    #[inline]
+    pub fn consume(&mut self, bits: u8) {
+        debug_assert!(bits <= self.count);
 
-        Ok(Some(()))
+        self.bits = self.bits.checked_shl(bits as u32).unwrap_or(0);
+        self.count -= bits;
+        self.position += bits as u64;
+    }
+
+    /// Number of bits consumed so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Number of bits left in the byte holding the last bit consumed.
+    pub(crate) fn bits_left_in_byte(&self) -> u8 {
+        ((8 - self.position % 8) % 8) as u8
+    }
+
+    /// Number of whole bytes buffered after the byte holding the last bit consumed.
+    pub(crate) fn bytes_read_ahead(&self) -> u64 {
+        ((self.count - self.bits_left_in_byte()) / 8) as u64
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        &mut self.read
+    }
+}
+
+/// Read into `buf` like `Read::read`, but try again when the read is `Interrupted`, as
+/// `read_exact` and `io::copy` do. Other errors, including `WouldBlock`, are returned as they are.
+#[cfg(feature = "std")]
+pub(crate) fn read_some<R: Read + ?Sized>(read: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match read.read(buf) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+            result => return result,
+        }
     }
 }
 
+/// Writes bits to a `Write`, most significant bit first.
+///
+/// The final partial byte is padded with zeros when the writer is dropped.
+#[cfg(feature = "std")]
 pub struct BitWriter<T: Write> {
     write: T,
     buf: [u8; 1],
@@ -55,6 +123,7 @@ pub struct BitWriter<T: Write> {
     mask: u8,
 }
 
+#[cfg(feature = "std")]
 impl <T: Write> BitWriter<T> {
 
     pub fn new(write: T) -> BitWriter<T> {
@@ -90,10 +159,51 @@ impl <T: Write> BitWriter<T> {
         Ok(())
     }
 
+    /// Write the low `length` bits of `bits`, most significant first. Any bits past the 64 held
+    /// are written as zeros.
+    pub fn write_bits_u64(&mut self, bits: u64, length: u8) -> io::Result<()> {
+        let mut remaining = length as u32;
+
+        while remaining > 64 {
+            self.write_bit(false)?;
+            remaining -= 1;
+        }
+
+        // Bytes completed along the way, written out together at the end
+        let mut completed = [0; 9];
+        let mut count = 0;
+
+        while remaining > 0 {
+            if self.mask == 0 {
+                completed[count] = self.current;
+                count += 1;
+
+                self.current = 0;
+                self.mask = MAX_MASK;
+            }
+
+            // Fill as much of the current byte as possible at once
+            let free = self.mask.trailing_zeros() + 1;
+            let take = free.min(remaining);
+            let chunk = (bits >> (remaining - take)) & ((1 << take) - 1);
+
+            self.current |= (chunk as u8) << (free - take);
+            self.mask = ((self.mask as u16) >> take) as u8;
+
+            remaining -= take;
+        }
+
+        self.write.write_all(&completed[..count])
+    }
+
+    pub fn write_code(&mut self, code: Code) -> io::Result<()> {
+        self.write_bits_u64(code.bits(), code.length())
+    }
+
     fn write_current_byte(&mut self) -> io::Result<()> {
         self.buf[0] = self.current;
 
-        self.write.write(&self.buf)?;
+        self.write.write_all(&self.buf)?;
 
         self.current = 0;
         self.mask = MAX_MASK;
@@ -102,6 +212,7 @@ impl <T: Write> BitWriter<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: Write> Drop for BitWriter<T> {
     fn drop(&mut self) {
         if self.mask != MAX_MASK {
@@ -110,13 +221,180 @@ impl<T: Write> Drop for BitWriter<T> {
     }
 }
 
+/// Error returned when a `SliceBitWriter` runs out of space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferOverflow;
+
+impl fmt::Display for BufferOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bit buffer is full")
+    }
+}
+
+impl Error for BufferOverflow {}
+
+#[cfg(feature = "std")]
+impl From<BufferOverflow> for io::Error {
+    fn from(err: BufferOverflow) -> io::Error {
+        io::Error::new(io::ErrorKind::WriteZero, err)
+    }
+}
+
+/// Writes bits into a caller provided buffer without allocating.
+///
+/// The bit layout is the same as `BitWriter`: most significant bit first, with the unused bits
+/// of the last byte set to zero. Writes that do not fit return `BufferOverflow` and leave the
+/// buffer untouched.
+pub struct SliceBitWriter<'a> {
+    buf: &'a mut [u8],
+    bits: usize,
+}
+
+impl<'a> SliceBitWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> SliceBitWriter<'a> {
+        SliceBitWriter { buf, bits: 0 }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> Result<(), BufferOverflow> {
+        if self.bits == self.capacity() {
+            return Err(BufferOverflow);
+        }
+
+        let index = self.bits / 8;
+        let mask = MAX_MASK >> (self.bits % 8);
+
+        // Clear stale data when starting a new byte
+        if mask == MAX_MASK {
+            self.buf[index] = 0;
+        }
+
+        if bit {
+            self.buf[index] |= mask;
+        }
+
+        self.bits += 1;
+
+        Ok(())
+    }
+
+    pub fn write_bits(&mut self, bits: &[bool]) -> Result<(), BufferOverflow> {
+        if bits.len() > self.capacity() - self.bits {
+            return Err(BufferOverflow);
+        }
+
+        for &bit in bits.iter() {
+            self.write_bit(bit)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the low `length` bits of `bits`, most significant first. Any bits past the 64 held
+    /// are written as zeros.
+    pub fn write_bits_u64(&mut self, bits: u64, length: u8) -> Result<(), BufferOverflow> {
+        if length as usize > self.capacity() - self.bits {
+            return Err(BufferOverflow);
+        }
+
+        for i in (0..length as u32).rev() {
+            self.write_bit(bits.checked_shr(i).unwrap_or(0) & 1 != 0)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_code(&mut self, code: Code) -> Result<(), BufferOverflow> {
+        self.write_bits_u64(code.bits(), code.length())
+    }
+
+    /// Number of bits written so far.
+    pub fn bits_written(&self) -> usize {
+        self.bits
+    }
+
+    /// Number of bytes touched so far, including a trailing partial byte.
+    pub fn bytes_written(&self) -> usize {
+        self.bits.div_ceil(8)
+    }
+
+    /// Returns the written portion of the buffer.
+    pub fn into_written(self) -> &'a mut [u8] {
+        let len = self.bytes_written();
+        &mut self.buf[..len]
+    }
+
+    fn capacity(&self) -> usize {
+        self.buf.len() * 8
+    }
+}
+
+/// Reads the bits of a slice without allocating, most significant bit first.
+///
+/// The bit layout is the same as `BitReader`, and the data ends at the end of the slice, so the
+/// padding bits of the last byte are read like any others.
+pub struct SliceBitReader<'a> {
+    buf: &'a [u8],
+    position: u64,
+}
+
+impl<'a> SliceBitReader<'a> {
+    pub fn new(buf: &'a [u8]) -> SliceBitReader<'a> {
+        SliceBitReader { buf, position: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Option<bool> {
+        if self.bits_left() == 0 {
+            return None;
+        }
+
+        let bit = (self.peek() & MAX_U64_MASK) != 0;
+        self.consume(1);
+
+        Some(bit)
+    }
+
+    /// The next 64 bits, left aligned. Bits past the end of the slice are zero.
+    pub fn peek(&self) -> u64 {
+        let start = (self.position / 8) as usize;
+        let byte = |index: usize| self.buf.get(start + index).cloned().unwrap_or(0) as u64;
+
+        let bits = (0..8).fold(0, |bits, index| (bits << 8) | byte(index));
+        let offset = (self.position % 8) as u32;
+
+        if offset == 0 {
+            bits
+        } else {
+            (bits << offset) | (byte(8) >> (8 - offset))
+        }
+    }
+
+    /// Skip `bits` bits, which must not be more than are left.
+    pub fn consume(&mut self, bits: u8) {
+        debug_assert!(bits as u64 <= self.bits_left());
+
+        self.position += bits as u64;
+    }
+
+    /// Number of bits consumed so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Number of bits left in the slice.
+    pub fn bits_left(&self) -> u64 {
+        (self.buf.len() as u64 * 8).saturating_sub(self.position)
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "std")]
     use std::io::Cursor;
+    #[cfg(feature = "std")]
     use std::vec::Vec;
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_empty_reader() {
         let mut bit_reader = BitReader::new(Cursor::new(Vec::new()));
@@ -125,7 +403,9 @@ mod tests {
         assert!(bit_reader.read_bit().unwrap().is_none());
     }
 
+    #[cfg(feature = "std")]
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_reader() {
         let mut bit_reader = BitReader::new( Cursor::new(vec![243, 98]));
 
@@ -151,6 +431,25 @@ mod tests {
         assert!(bit_reader.read_bit().unwrap().is_none());
     }
 
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reader_peek() {
+        let mut bit_reader = BitReader::new(Cursor::new(vec![243, 98]));
+
+        assert_eq!(bit_reader.fill(3).unwrap(), 8);
+        assert_eq!(bit_reader.peek() >> 61, 0b111);
+        bit_reader.consume(3);
+
+        assert_eq!(bit_reader.fill(12).unwrap(), 13);
+        assert_eq!(bit_reader.peek() >> 51, 0b1001101100010);
+        bit_reader.consume(13);
+
+        assert_eq!(bit_reader.fill(1).unwrap(), 0);
+        assert_eq!(bit_reader.peek(), 0);
+        assert_eq!(bit_reader.position(), 16);
+    }
+
+    #[cfg(feature = "std")]
     #[test]
     fn test_writer() {
         let mut vec: Vec<u8> = Vec::new();
@@ -182,6 +481,7 @@ mod tests {
         assert_eq!(vec[1], 98);
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_writer_partial() {
         let mut vec: Vec<u8> = Vec::new();
@@ -210,6 +510,24 @@ mod tests {
         assert_eq!(vec[1], 96);
     }
 
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_writer_bits_u64() {
+        let mut vec: Vec<u8> = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(vec.by_ref());
+
+            // 111 1001101 100010 101
+            assert!(bit_writer.write_bits_u64(0b111, 3).is_ok());
+            assert!(bit_writer.write_bits_u64(0b1001101, 7).is_ok());
+            assert!(bit_writer.write_bits_u64(0b100010, 6).is_ok());
+            assert!(bit_writer.write_code(Code::new(0b101, 3)).is_ok());
+        }
+
+        assert_eq!(vec, vec![243, 98, 160]);
+    }
+
+    #[cfg(feature = "std")]
     #[test]
     fn test_drop_no_panic() {
         struct FailOnFlush {}
@@ -230,5 +548,103 @@ mod tests {
             assert!(bit_writer.write_bit(false).is_ok());
         }
     }
-}
 
+    #[test]
+    fn test_slice_writer() {
+        let mut buf = [0xff; 2];
+        let mut bit_writer = SliceBitWriter::new(&mut buf);
+
+        // 11110011 01100
+        let bits = [true, true, true, true, false, false, true, true,
+            false, true, true, false, false];
+        assert!(bit_writer.write_bits(&bits).is_ok());
+        assert_eq!(bit_writer.bits_written(), 13);
+        assert_eq!(bit_writer.into_written(), &[243, 96]);
+    }
+
+    #[test]
+    fn test_slice_writer_overflow() {
+        let mut buf = [0; 1];
+        let mut bit_writer = SliceBitWriter::new(&mut buf);
+
+        assert!(bit_writer.write_bits(&[true; 6]).is_ok());
+        assert_eq!(bit_writer.write_bits(&[true; 3]), Err(BufferOverflow));
+        assert_eq!(bit_writer.bits_written(), 6);
+        assert!(bit_writer.write_bits(&[false; 2]).is_ok());
+        assert_eq!(bit_writer.write_bit(true), Err(BufferOverflow));
+        assert_eq!(bit_writer.into_written(), &[252]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_slice_reader() {
+        let bytes = [243, 98, 1, 2, 3, 4, 5, 6, 7];
+        let mut bit_reader = SliceBitReader::new(&bytes);
+
+        assert_eq!(bit_reader.peek() >> 61, 0b111);
+        bit_reader.consume(3);
+        assert_eq!(bit_reader.peek() >> 51, 0b1001101100010);
+        assert_eq!(bit_reader.read_bit(), Some(true));
+
+        // Matches a `BitReader` at every offset
+        let mut expected = BitReader::new(Cursor::new(bytes.to_vec()));
+        let mut bit_reader = SliceBitReader::new(&bytes);
+
+        while bit_reader.bits_left() > 0 {
+            let available = expected.fill(57).unwrap();
+            assert_eq!(bit_reader.peek() >> (64 - available), expected.peek() >> (64 - available));
+
+            let bits = available.min(5);
+            bit_reader.consume(bits);
+            expected.consume(bits);
+        }
+
+        assert_eq!(bit_reader.read_bit(), None);
+        assert_eq!(bit_reader.peek(), 0);
+        assert_eq!(bit_reader.position(), 72);
+    }
+
+    /// Fails every other read with `error`.
+    #[cfg(feature = "std")]
+    struct FlakyRead<R> {
+        read: R,
+        error: io::ErrorKind,
+        fail: bool,
+    }
+
+    #[cfg(feature = "std")]
+    impl<R: Read> Read for FlakyRead<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.fail = !self.fail;
+
+            if self.fail {
+                return Err(io::Error::new(self.error, "Flaky"));
+            }
+
+            self.read.read(buf)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_interrupted() {
+        use super::super::{Encoder, Decoder, HuffmanError};
+
+        let text = "the quick brown fox jumps over the lazy dog".repeat(100);
+        let mut encoded = Vec::new();
+        Encoder::from_slice(text.as_bytes()).unwrap().encode(&mut encoded).unwrap();
+
+        // Interrupted reads are tried again
+        let read = FlakyRead { read: Cursor::new(&encoded), error: io::ErrorKind::Interrupted, fail: false };
+        let mut decoded = Vec::new();
+        Decoder::new(read).strict(true).decode(&mut decoded).unwrap();
+        assert_eq!(decoded, text.as_bytes());
+
+        // Others are passed on with their kind
+        let read = FlakyRead { read: Cursor::new(&encoded), error: io::ErrorKind::WouldBlock, fail: false };
+        match Decoder::new(read).decode(Vec::new()) {
+            Err(HuffmanError::Io(ref err)) => assert_eq!(err.kind(), io::ErrorKind::WouldBlock),
+            result => panic!("Expected WouldBlock, got {:?}", result),
+        }
+    }
+}
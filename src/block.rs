@@ -0,0 +1,423 @@
+//! Data split into independent blocks, encoded on several threads at once.
+//!
+//! Each block of `block_size` bytes is encoded by a `Pipeline` with tables of its own, so the
+//! counting and coding of one block does not depend on any other. The `BlockEncoder` reads as
+//! many blocks as it has threads, encodes them in parallel, and writes them out in order. The
+//! `BlockDecoder` finds each block from its length without decoding it, so it decodes blocks in
+//! parallel the same way. Since no block depends on how the others were split between threads,
+//! the output is byte for byte the same for any number of threads.
+//!
+//! The stream is a sequence of blocks, each written as the length of its encoded bytes as a
+//! little endian `u32` followed by the output of `Pipeline::encode`. A length of 0 marks the end.
+//! When the top bit of the length is set, the encoded bytes are followed by the CRC-32 of the
+//! data in the block as a little endian `u32`. The encoder computes it on a thread of its own
+//! while the block is encoded, so checksums add little to the time taken.
+
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::result::Result;
+use std::thread;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use super::*;
+
+/// Default number of bytes in each block.
+pub const DEFAULT_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Largest block size, leaving room for the encoded block to grow and still fit its length.
+pub const MAX_BLOCK_SIZE: usize = 1 << 30;
+
+/// Set in the length of a block that is followed by a checksum.
+pub(crate) const CHECKSUM_FLAG: u32 = 1 << 31;
+
+/// An encoded block, along with the checksum of its data if it has one.
+type EncodedBlock = (Vec<u8>, Option<u32>);
+
+/// Encodes data as independent blocks on several threads.
+#[derive(Debug, Clone)]
+pub struct BlockEncoder {
+    pipeline: Pipeline,
+    block_size: usize,
+    threads: usize,
+    checksum: bool,
+}
+
+impl BlockEncoder {
+    /// Encode each block with `pipeline`, using a thread for each available core.
+    pub fn new(pipeline: Pipeline) -> BlockEncoder {
+        BlockEncoder {
+            pipeline,
+            block_size: DEFAULT_BLOCK_SIZE,
+            threads: available_threads(),
+            checksum: false,
+        }
+    }
+
+    /// Split the data into blocks of `bytes` bytes. Defaults to `DEFAULT_BLOCK_SIZE`.
+    ///
+    /// Smaller blocks spread better over threads, but each one stores its own tables. Fails if
+    /// `bytes` is 0 or larger than `MAX_BLOCK_SIZE`.
+    pub fn block_size(mut self, bytes: usize) -> Result<BlockEncoder, HuffmanError> {
+        if bytes == 0 || bytes > MAX_BLOCK_SIZE {
+            return Err(HuffmanError::InvalidInput(format!("Invalid block size {}", bytes)));
+        }
+
+        self.block_size = bytes;
+        Ok(self)
+    }
+
+    /// Encode up to `threads` blocks at once, holding that many in memory. Defaults to the
+    /// number of available cores, and is at least 1.
+    ///
+    /// The output is the same for any number of threads, so it can be checksummed or compared
+    /// across machines.
+    pub fn threads(mut self, threads: usize) -> BlockEncoder {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Follow each block with the CRC-32 of its data, which the decoder checks. Off by default.
+    pub fn checksum(mut self, checksum: bool) -> BlockEncoder {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Encode all of `read` to `write`.
+    pub fn encode<R: Read, W: Write>(&self, mut read: R, mut write: W) -> Result<(), HuffmanError> {
+        loop {
+            let mut blocks = Vec::with_capacity(self.threads);
+
+            while blocks.len() < self.threads {
+                let mut block = Vec::new();
+                read.by_ref().take(self.block_size as u64).read_to_end(&mut block)?;
+
+                if block.is_empty() {
+                    break;
+                }
+
+                let full = block.len() == self.block_size;
+                blocks.push(block);
+
+                if !full {
+                    break;
+                }
+            }
+
+            if blocks.is_empty() {
+                break;
+            }
+
+            let end = blocks.len() < self.threads || blocks.last().is_some_and(|block| block.len() < self.block_size);
+
+            self.write_blocks(&blocks, &mut write)?;
+
+            if end {
+                break;
+            }
+        }
+
+        Ok(write.write_u32::<LittleEndian>(0)?)
+    }
+
+    /// Encode all of `data`, which is split into blocks without copying them.
+    pub fn encode_slice<W: Write>(&self, data: &[u8], mut write: W) -> Result<(), HuffmanError> {
+        let blocks: Vec<&[u8]> = data.chunks(self.block_size).collect();
+
+        for batch in blocks.chunks(self.threads) {
+            self.write_blocks(batch, &mut write)?;
+        }
+
+        Ok(write.write_u32::<LittleEndian>(0)?)
+    }
+
+    /// Encode `blocks` in parallel and write them out in order.
+    fn write_blocks<B: AsRef<[u8]> + Sync, W: Write>(&self, blocks: &[B], write: &mut W) -> Result<(), HuffmanError> {
+        for result in self.encode_blocks(blocks) {
+            let (encoded, checksum) = result?;
+
+            match checksum {
+                Some(checksum) => {
+                    write.write_u32::<LittleEndian>(encoded.len() as u32 | CHECKSUM_FLAG)?;
+                    write.write_all(&encoded)?;
+                    write.write_u32::<LittleEndian>(checksum)?;
+                },
+                None => {
+                    write.write_u32::<LittleEndian>(encoded.len() as u32)?;
+                    write.write_all(&encoded)?;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encode each of `blocks` on its own thread, returning the results in order along with the
+    /// checksum of each block if there is one.
+    fn encode_blocks<B: AsRef<[u8]> + Sync>(&self, blocks: &[B]) -> Vec<Result<EncodedBlock, HuffmanError>> {
+        thread::scope(|scope| {
+            let handles: Vec<_> = blocks.iter()
+                .map(|block| scope.spawn(move || thread::scope(|block_scope| {
+                    let block = block.as_ref();
+                    let checksum = if self.checksum { Some(block_scope.spawn(move || crc32(block))) } else { None };
+
+                    let mut encoded = Vec::new();
+                    self.pipeline.encode(SliceSource::new(block), &mut encoded)?;
+
+                    if encoded.len() >= CHECKSUM_FLAG as usize {
+                        return Err(HuffmanError::InvalidInput("Encoded block is too large".to_string()));
+                    }
+
+                    let checksum = match checksum {
+                        Some(handle) => Some(handle.join().map_err(|_| thread_panicked("Checksum"))?),
+                        None => None,
+                    };
+
+                    Ok((encoded, checksum))
+                })))
+                .collect();
+
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| Err(thread_panicked("Encoding"))))
+                .collect()
+        })
+    }
+}
+
+/// Decodes data written by a `BlockEncoder` on several threads.
+#[derive(Debug, Clone)]
+pub struct BlockDecoder {
+    threads: usize,
+}
+
+impl BlockDecoder {
+    /// Decode with a thread for each available core.
+    pub fn new() -> BlockDecoder {
+        BlockDecoder {
+            threads: available_threads(),
+        }
+    }
+
+    /// Decode up to `threads` blocks at once, holding that many in memory. Defaults to the
+    /// number of available cores, and is at least 1.
+    pub fn threads(mut self, threads: usize) -> BlockDecoder {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Decode all of the blocks in `read` to `write`, stopping after the end marker.
+    ///
+    /// Each block must decode exactly, with nothing but padding after its coded bytes, and match
+    /// its checksum if it has one. On an error, everything decoded before it is written, apart
+    /// from a block that does not match its checksum.
+    pub fn decode<R: Read, W: Write>(&self, read: R, write: W) -> Result<(), HuffmanError> {
+        self.decode_counting(read, write, &mut 0, true)
+    }
+
+    /// Decode as much as possible to `write`, never failing.
+    ///
+    /// The `Recovery` counts the blocks and bytes written, which are every block decoded before
+    /// the first error, and holds the error. A block that fails is not written at all.
+    pub fn decode_lossy<R: Read, W: Write>(&self, read: R, write: W) -> Recovery {
+        let mut write = CountingWrite::new(write);
+        let mut blocks_decoded = 0;
+        let error = self.decode_counting(read, &mut write, &mut blocks_decoded, false).err();
+
+        Recovery {
+            bytes_decoded: write.bytes_written(),
+            blocks_decoded,
+            error,
+        }
+    }
+
+    /// Like `decode`, counting the blocks written in `blocks_decoded`. What a failing block
+    /// decoded before its error is only written if `write_failed` is set.
+    fn decode_counting<R: Read, W: Write>(&self, mut read: R, mut write: W, blocks_decoded: &mut u64, write_failed: bool) -> Result<(), HuffmanError> {
+        loop {
+            let mut blocks = Vec::with_capacity(self.threads);
+            let mut end = Ok(false);
+
+            while blocks.len() < self.threads {
+                match read_block(&mut read) {
+                    Ok(Some(block)) => blocks.push(block),
+                    Ok(None) => {
+                        end = Ok(true);
+                        break;
+                    },
+                    Err(err) => {
+                        end = Err(err);
+                        break;
+                    },
+                }
+            }
+
+            for (decoded, result) in self.decode_blocks(&blocks) {
+                if result.is_ok() || write_failed {
+                    write.write_all(&decoded)?;
+                }
+
+                result?;
+                *blocks_decoded += 1;
+            }
+
+            if end? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Decode each of `blocks` on its own thread, returning what each decoded before any error,
+    /// in order.
+    fn decode_blocks(&self, blocks: &[EncodedBlock]) -> Vec<(Vec<u8>, Result<(), HuffmanError>)> {
+        thread::scope(|scope| {
+            let handles: Vec<_> = blocks.iter()
+                .map(|&(ref block, checksum)| scope.spawn(move || {
+                    let mut decoded = Vec::new();
+                    let mut result = Pipeline::decoder(Cursor::new(block))
+                        .and_then(|decoder| decoder.strict(true).decode(&mut decoded));
+
+                    if result.is_ok() && checksum.is_some_and(|checksum| checksum != crc32(&decoded)) {
+                        decoded.clear();
+                        result = Err(HuffmanError::ChecksumMismatch);
+                    }
+
+                    (decoded, result)
+                }))
+                .collect();
+
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| (Vec::new(), Err(thread_panicked("Decoding")))))
+                .collect()
+        })
+    }
+}
+
+impl Default for BlockDecoder {
+    fn default() -> BlockDecoder {
+        BlockDecoder::new()
+    }
+}
+
+/// Read the next encoded block and its checksum, or `None` at the end marker.
+fn read_block<R: Read>(read: &mut R) -> io::Result<Option<EncodedBlock>> {
+    let length = read.read_u32::<LittleEndian>()?;
+
+    if length == 0 {
+        return Ok(None);
+    }
+
+    let has_checksum = length & CHECKSUM_FLAG != 0;
+    let length = length & !CHECKSUM_FLAG;
+
+    let mut block = Vec::new();
+    read.take(length as u64).read_to_end(&mut block)?;
+
+    if block.len() < length as usize {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Data ends inside a block"));
+    }
+
+    let checksum = if has_checksum { Some(read.read_u32::<LittleEndian>()?) } else { None };
+
+    Ok(Some((block, checksum)))
+}
+
+fn available_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8], encoder: &BlockEncoder) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        encoder.encode(data, &mut encoded).unwrap();
+
+        let mut sliced = Vec::new();
+        encoder.encode_slice(data, &mut sliced).unwrap();
+        assert_eq!(sliced, encoded);
+
+        for &threads in &[1, 3] {
+            let mut decoded = Vec::new();
+            BlockDecoder::new().threads(threads).decode(&encoded[..], &mut decoded).unwrap();
+            assert_eq!(decoded, data);
+        }
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "the quick brown fox jumps over the lazy dog, and the dog sleeps. ".repeat(200);
+
+        let encoder = BlockEncoder::new(Pipeline::default()).block_size(1).unwrap();
+        round_trip(b"abracadabra", &encoder);
+
+        for &block_size in &[1300, text.len(), text.len() + 1] {
+            for &threads in &[1, 3, 8] {
+                let encoder = BlockEncoder::new(Pipeline::default()).block_size(block_size).unwrap().threads(threads);
+                round_trip(b"", &encoder);
+                round_trip(b"a", &encoder);
+                round_trip(&text.as_bytes()[..block_size.min(text.len())], &encoder);
+                round_trip(text.as_bytes(), &encoder);
+            }
+        }
+
+        assert!(BlockEncoder::new(Pipeline::default()).block_size(0).is_err());
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let mut data = "the quick brown fox jumps over the lazy dog, and the dog sleeps. ".repeat(100).into_bytes();
+        data.extend((0..5000u32).map(|i| (i * i % 251) as u8));
+
+        for preset in [Preset::Fast, Preset::Default, Preset::Best] {
+            let encoder = BlockEncoder::new(preset.pipeline()).block_size(1500).unwrap();
+            let single = round_trip(&data, &encoder.clone().threads(1));
+
+            for threads in 2..=8 {
+                assert_eq!(round_trip(&data, &encoder.clone().threads(threads)), single);
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let text = "the quick brown fox jumps over the lazy dog".repeat(20);
+        let encoder = BlockEncoder::new(Pipeline::default()).block_size(200).unwrap();
+
+        let plain = round_trip(text.as_bytes(), &encoder);
+        let mut encoded = round_trip(text.as_bytes(), &encoder.checksum(true));
+        assert_eq!(encoded.len(), plain.len() + text.len().div_ceil(200) * 4);
+
+        let mut decoded = Vec::new();
+        StreamingDecoder::new().decode(&encoded[..], &mut decoded).unwrap();
+        assert_eq!(decoded, text.as_bytes());
+
+        // A wrong checksum fails without writing its block
+        let length = encoded.len();
+        encoded[length - 5] ^= 1;
+
+        let mut decoded = Vec::new();
+        assert!(matches!(BlockDecoder::new().threads(2).decode(&encoded[..], &mut decoded), Err(HuffmanError::ChecksumMismatch)));
+        assert_eq!(decoded, &text.as_bytes()[..text.len() / 200 * 200]);
+        assert!(matches!(StreamingDecoder::new().decode(&encoded[..], &mut Vec::new()), Err(HuffmanError::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let text = "the quick brown fox jumps over the lazy dog".repeat(20);
+        let mut encoded = Vec::new();
+        BlockEncoder::new(Pipeline::default()).block_size(200).unwrap().encode(text.as_bytes(), &mut encoded).unwrap();
+
+        for length in [0, 3, 10, encoded.len() - 4] {
+            assert!(BlockDecoder::new().decode(&encoded[..length], &mut Vec::new()).is_err());
+        }
+
+        // The blocks before the one cut short are still decoded
+        let mut decoded = Vec::new();
+        assert!(BlockDecoder::new().threads(2).decode(&encoded[..encoded.len() - 20], &mut decoded).is_err());
+        assert_eq!(decoded.len(), text.len() / 200 * 200);
+        assert!(text.as_bytes().starts_with(&decoded));
+    }
+}
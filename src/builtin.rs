@@ -0,0 +1,224 @@
+//! Canonical codes trained ahead of time on common kinds of data.
+//!
+//! A short payload cannot make up for counting its frequencies and storing its code lengths, so
+//! an `Encoder` built with a codebook skips both and stores a single byte naming the codebook.
+//! Every byte has a code, so any data can be encoded, although data unlike the samples the
+//! codebook was trained on will grow.
+
+use super::*;
+
+/// A codebook built into the crate, trained on samples of one kind of data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinCodebook {
+    /// English prose.
+    EnglishText,
+    /// JSON documents, compact or indented.
+    Json,
+    /// Base64 with padding and line breaks.
+    Base64,
+    /// Hexadecimal digits, mostly lowercase.
+    Hex,
+    /// URLs, one per line.
+    Url,
+}
+
+impl BuiltinCodebook {
+    /// Every built-in codebook, in the order of their ids.
+    pub const ALL: [BuiltinCodebook; 5] = [
+        BuiltinCodebook::EnglishText,
+        BuiltinCodebook::Json,
+        BuiltinCodebook::Base64,
+        BuiltinCodebook::Hex,
+        BuiltinCodebook::Url,
+    ];
+
+    /// The byte stored in the header to name the codebook.
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The codebook named by `id`, if there is one.
+    pub fn from_id(id: u8) -> Option<BuiltinCodebook> {
+        BuiltinCodebook::ALL.get(id as usize).cloned()
+    }
+
+    /// The code length of every byte.
+    pub fn code_lengths(&self) -> &'static [u8; NUM_BYTES] {
+        match *self {
+            BuiltinCodebook::EnglishText => &ENGLISH_TEXT_CODE_LENGTHS,
+            BuiltinCodebook::Json => &JSON_CODE_LENGTHS,
+            BuiltinCodebook::Base64 => &BASE64_CODE_LENGTHS,
+            BuiltinCodebook::Hex => &HEX_CODE_LENGTHS,
+            BuiltinCodebook::Url => &URL_CODE_LENGTHS,
+        }
+    }
+
+    /// Build the tree for the codebook.
+    pub fn tree(&self) -> CanonicalTree {
+        let code_lengths = self.code_lengths().iter().enumerate()
+            .map(|(byte, &length)| (byte as u8, length))
+            .collect();
+
+        CanonicalTree::new(code_lengths).expect("Built-in code lengths are valid")
+    }
+
+    /// Frequencies for which the codebook is optimal, for backends that need frequencies.
+    pub fn frequencies(&self) -> FrequencyTable {
+        let mut frequencies = FrequencyTable::new();
+
+        for (byte, &length) in self.code_lengths().iter().enumerate() {
+            frequencies.set(byte as u8, 1 << (MAX_BUILTIN_LENGTH - length));
+        }
+
+        frequencies
+    }
+
+    /// The built-in codebook with the same codes as `tree`, if there is one.
+    pub fn find(tree: &CanonicalTree) -> Option<BuiltinCodebook> {
+        let code_lengths = tree.code_lengths();
+
+        BuiltinCodebook::ALL.iter().cloned()
+            .find(|codebook| *codebook.code_lengths() == code_lengths)
+    }
+}
+
+/// Longest code in any built-in codebook.
+const MAX_BUILTIN_LENGTH: u8 = 17;
+
+const ENGLISH_TEXT_CODE_LENGTHS: [u8; NUM_BYTES] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16,  6, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+     3, 10,  9, 16, 16, 16, 16,  9, 13, 13, 15, 16,  6,  8,  8, 16,
+    13, 13, 14, 15, 15, 15, 15, 14, 14, 15, 13,  8, 16, 16, 16, 11,
+    16,  9, 10, 10, 11, 10, 11, 11, 10,  9, 13, 13, 11, 11, 10, 11,
+    10, 12, 11,  9,  9, 12, 13, 10, 15, 12, 15, 16, 16, 16, 16, 16,
+    16,  4,  7,  6,  5,  3,  6,  6,  4,  4, 11,  8,  5,  6,  4,  4,
+     6, 10,  5,  4,  4,  6,  7,  6, 10,  6, 11, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+];
+
+const JSON_CODE_LENGTHS: [u8; NUM_BYTES] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16,  4, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+     2, 16,  3, 16, 16, 16, 16, 16, 16, 16, 16, 16,  5, 16,  7, 16,
+     8,  7,  7,  7,  7,  7,  7,  7,  7,  7,  5, 16, 16, 16, 16, 16,
+     8, 10, 10, 11, 11, 12, 11, 12, 10, 10, 16, 16, 11, 10, 10, 10,
+    11, 16, 16,  8,  9, 11, 16,  9, 16, 11, 16,  8, 16,  8, 16, 16,
+    16,  5, 10,  6,  6,  4,  9,  8,  8,  5, 16, 10,  6,  6,  6,  6,
+     6, 16,  5,  5,  5,  7,  8,  9,  8,  7,  8,  7, 16,  7, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+];
+
+const BASE64_CODE_LENGTHS: [u8; NUM_BYTES] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16,  7, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,  6, 16, 16, 16,  6,
+     6,  6,  7,  6,  6,  6,  6,  6,  6,  6, 16, 16, 16,  8, 16, 16,
+    16,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,
+     6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6, 16, 16, 16, 16, 16,
+    16,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,  6,
+     6,  6,  6,  6,  6,  6,  7,  6,  6,  6,  6, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+];
+
+const HEX_CODE_LENGTHS: [u8; NUM_BYTES] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+     4,  4,  4,  4,  4,  4,  4,  4,  4,  4, 16, 16, 16, 16, 16, 16,
+    16,  8,  8,  8,  8,  7,  8, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16,  4,  4,  5,  4,  4,  4, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 15, 15, 15, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+];
+
+const URL_CODE_LENGTHS: [u8; NUM_BYTES] = [
+    17, 17, 17, 17, 17, 17, 17, 17, 17, 17,  6, 17, 17, 17, 17, 17,
+    17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17,
+    17, 17, 17, 17, 17,  7,  7, 17, 17, 17, 17, 17, 17,  6,  4,  4,
+     7,  7,  6,  8,  8,  8,  8,  8,  7,  8,  6, 16, 16,  6, 16,  7,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,  6,
+    16,  5,  7,  6,  6,  4,  7,  6,  4,  5,  8,  7,  6,  5,  5,  4,
+     4, 16,  5,  5,  3,  6, 10,  5, 16,  7, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 16,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codebooks() {
+        for (id, codebook) in BuiltinCodebook::ALL.iter().enumerate() {
+            assert_eq!(codebook.id() as usize, id);
+            assert_eq!(BuiltinCodebook::from_id(id as u8), Some(*codebook));
+
+            let tree = codebook.tree();
+            assert_eq!(tree.code_book().len(), NUM_BYTES);
+            assert!(tree.max_code_length().unwrap() <= MAX_BUILTIN_LENGTH);
+            assert_eq!(BuiltinCodebook::find(&tree), Some(*codebook));
+            assert_eq!(CanonicalTree::from_frequencies(&codebook.frequencies()).unwrap().code_lengths()[..], codebook.code_lengths()[..]);
+        }
+
+        assert_eq!(BuiltinCodebook::from_id(BuiltinCodebook::ALL.len() as u8), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_encode_small_payload() {
+        use std::io::Cursor;
+
+        let json = br#"{"id":42,"name":"Ada Lovelace","active":true,"tags":["math","poetry"]}"#;
+
+        let mut encoded = Vec::new();
+        Encoder::with_codebook(Cursor::new(&json[..]), BuiltinCodebook::Json).unwrap()
+            .encode(&mut encoded).unwrap();
+        assert!(encoded.len() < json.len());
+
+        let mut decoded = Vec::new();
+        Decoder::new(Cursor::new(&encoded)).header_format(HeaderFormat::Builtin).strict(true)
+            .decode(&mut decoded).unwrap();
+        assert_eq!(decoded, &json[..]);
+
+        // A tree counted from the data is not a built-in codebook
+        let mut encoder = Encoder::new(Cursor::new(&json[..])).unwrap().header_format(HeaderFormat::Builtin);
+        assert!(encoder.encode(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Burrows-Wheeler transform of blocks of bytes.
+//!
+//! The rotations of a block are sorted, and the last byte of each is kept, along with where the
+//! block itself ended up. Bytes that precede the same contexts end up next to each other, so
+//! text turns into long stretches of a few bytes, which `Stage::Mtf` and `Stage::Rle` then turn
+//! into mostly zeros. As in bzip2, the data is split into blocks to bound the memory used.
+//!
+//! Each block is written as the position of the block among its sorted rotations, as a little
+//! endian `u32`, followed by the transformed bytes.
+
+use std::result::Result;
+use std::error::Error;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Default number of bytes transformed together, as used by bzip2 at its best setting.
+pub const DEFAULT_BWT_BLOCK_SIZE: u32 = 900 * 1000;
+
+/// Transform `bytes` in blocks of `block_size` bytes.
+///
+/// # Panics
+///
+/// Panics if `block_size` is 0.
+pub fn bwt_encode(bytes: &[u8], block_size: u32) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() + bytes.len() / block_size as usize * 4 + 4);
+
+    for block in bytes.chunks(block_size as usize) {
+        let rotations = sort_rotations(block);
+        let primary = rotations.iter().position(|&start| start == 0).expect("Every rotation is sorted");
+
+        let mut index = [0; 4];
+        LittleEndian::write_u32(&mut index, primary as u32);
+        encoded.extend_from_slice(&index);
+
+        encoded.extend(rotations.iter().map(|&start| block[(start + block.len() - 1) % block.len()]));
+    }
+
+    encoded
+}
+
+/// Undo `bwt_encode` with the same `block_size`.
+///
+/// Fails if a block is cut short or its position is out of range.
+pub fn bwt_decode(bytes: &[u8], block_size: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err(From::from("Transformed block ends before its position"));
+        }
+
+        let primary = LittleEndian::read_u32(rest) as usize;
+        let length = (rest.len() - 4).min(block_size as usize);
+        let block = &rest[4..4 + length];
+
+        if primary >= length.max(1) {
+            return Err(From::from(format!("Invalid position {} of a transformed block of {} bytes", primary, length)));
+        }
+
+        invert_block(block, primary, &mut decoded);
+        rest = &rest[4 + length..];
+    }
+
+    Ok(decoded)
+}
+
+/// The start of each rotation of `block`, in sorted order.
+///
+/// Rotations are sorted by doubling the length of the prefixes compared at each pass, with the
+/// order of the previous pass as the ranks, which takes O(n log n) time.
+fn sort_rotations(block: &[u8]) -> Vec<usize> {
+    let n = block.len();
+
+    // Sort by the first byte
+    let mut counts = vec![0; n.max(256)];
+    for &byte in block {
+        counts[byte as usize] += 1;
+    }
+    for i in 1..256 {
+        counts[i] += counts[i - 1];
+    }
+
+    let mut order = vec![0; n];
+    for start in (0..n).rev() {
+        counts[block[start] as usize] -= 1;
+        order[counts[block[start] as usize]] = start;
+    }
+
+    let mut ranks = vec![0; n];
+    let mut classes = 1;
+    for i in 1..n {
+        if block[order[i]] != block[order[i - 1]] {
+            classes += 1;
+        }
+        ranks[order[i]] = classes - 1;
+    }
+
+    let mut shifted = vec![0; n];
+    let mut new_ranks = vec![0; n];
+    let mut length = 1;
+
+    while length < n && classes < n {
+        // Sorted by the second half already, so a stable sort by the first half sorts both
+        for (i, &start) in order.iter().enumerate() {
+            shifted[i] = (start + n - length) % n;
+        }
+
+        counts[..classes].iter_mut().for_each(|count| *count = 0);
+        for &start in shifted.iter() {
+            counts[ranks[start]] += 1;
+        }
+        for i in 1..classes {
+            counts[i] += counts[i - 1];
+        }
+        for &start in shifted.iter().rev() {
+            counts[ranks[start]] -= 1;
+            order[counts[ranks[start]]] = start;
+        }
+
+        new_ranks[order[0]] = 0;
+        classes = 1;
+        for i in 1..n {
+            let current = (ranks[order[i]], ranks[(order[i] + length) % n]);
+            let previous = (ranks[order[i - 1]], ranks[(order[i - 1] + length) % n]);
+
+            if current != previous {
+                classes += 1;
+            }
+            new_ranks[order[i]] = classes - 1;
+        }
+
+        std::mem::swap(&mut ranks, &mut new_ranks);
+        length <<= 1;
+    }
+
+    order
+}
+
+/// Rebuild a block from its last bytes and position, appending it to `decoded`.
+fn invert_block(last: &[u8], primary: usize, decoded: &mut Vec<u8>) {
+    // Where each byte of `last` starts a rotation among the sorted rotations
+    let mut starts = [0; 256];
+    for &byte in last {
+        starts[byte as usize] += 1;
+    }
+
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        let count = *start;
+        *start = total;
+        total += count;
+    }
+
+    let mut previous = vec![0; last.len()];
+    for (row, &byte) in last.iter().enumerate() {
+        previous[row] = starts[byte as usize];
+        starts[byte as usize] += 1;
+    }
+
+    // Walk backwards from the end of the block
+    let offset = decoded.len();
+    decoded.resize(offset + last.len(), 0);
+
+    let mut row = primary;
+    for position in (0..last.len()).rev() {
+        decoded[offset + position] = last[row];
+        row = previous[row];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(bwt_encode(b"banana", 100), b"\x03\x00\x00\x00nnbaaa");
+        assert_eq!(bwt_encode(b"", 100), b"");
+
+        let text = b"the quick brown fox jumps over the lazy dog, and the dog sleeps".repeat(20);
+        let cases: Vec<(&[u8], u32)> = vec![
+            (b"a", 1),
+            (b"aaaaaaaa", 3),
+            (b"abababab", 100),
+            (&text, 1),
+            (&text, 7),
+            (&text, 100000),
+        ];
+
+        for (bytes, block_size) in cases {
+            assert_eq!(bwt_decode(&bwt_encode(bytes, block_size), block_size).unwrap(), bytes);
+        }
+
+        assert!(bwt_decode(b"\x03\x00\x00", 100).is_err());
+        assert!(bwt_decode(b"\x06\x00\x00\x00nnbaaa", 100).is_err());
+    }
+}
@@ -1,157 +1,371 @@
-use std::io::{Read, Write};
-use std::collections::{Bound, HashMap, BTreeMap};
-use std::result::Result;
-use std::error::Error;
+#[cfg(feature = "std")]
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::path::Path;
+use core::result::Result;
+#[cfg(feature = "std")]
+use std::thread;
+
+#[cfg(feature = "std")]
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 
 use super::*;
 
-const MAX_U64_MASK: u64 = 1 << 63;
+/// Size of the chunks read from the input.
+#[cfg(feature = "std")]
+pub(crate) const READ_BUFFER_SIZE: usize = 8 * 1024;
 
-pub type CodeBook = HashMap<u8, Vec<bool>>;
+/// Number of decoded bytes collected before they are written out.
+#[cfg(feature = "std")]
+const DECODE_BUFFER_SIZE: usize = 8 * 1024;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct LookupEntry {
-    length: u8,
-    codes: Vec<u8>,
-}
+/// Smallest number of bytes counted by each thread when counting frequencies in parallel.
+#[cfg(feature = "std")]
+const PARALLEL_CHUNK_SIZE: usize = 1024 * 1024;
 
-impl LookupEntry {
-    pub fn new(length: u8, codes: Vec<u8>) -> LookupEntry {
-        LookupEntry {length, codes}
-    }
-}
+/// Version of the format written by `CanonicalTree::to_bytes`.
+pub const TREE_FORMAT_VERSION: u8 = 1;
 
+/// Canonical huffman codes for an alphabet of symbols, bytes by default.
+///
+/// A tree is never changed by coding, and is `Send` and `Sync`, so a single tree can be shared
+/// between threads with an `Arc` to code many streams at once.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct CanonicalTree {
-    code_book: CodeBook,
-    lookup: BTreeMap<u64, LookupEntry>,
+pub struct CanonicalTree<S = u8> {
+    code_book: CodeBook<S>,
+    table: DecodeTable<S>,
 }
 
-impl CanonicalTree {
-    pub fn new(code_lengths: Vec<(u8, u8)>) -> CanonicalTree {
-        // Build the canonical code book
+impl<S: Symbol> CanonicalTree<S> {
+    /// Build the tree from `(symbol, length)` pairs. A length of 0 means the symbol has no code.
+    ///
+    /// Fails if a symbol is given more than once, a code is longer than `MAX_CODE_LENGTH` bits,
+    /// or the lengths are over-subscribed, i.e. they do not satisfy the Kraft inequality.
+    pub fn new(code_lengths: Vec<(S, u8)>) -> Result<CanonicalTree<S>, HuffmanError> {
+        validate_code_lengths(&code_lengths)?;
+
+        // Build the canonical codes
         let code_book = canonical_code_book(&code_lengths);
 
-        // Build the lookup tree
-        let lookup = lookup_tree(&code_book);
+        // Build the decode table
+        let table = DecodeTable::new(&code_book);
 
-        CanonicalTree {
+        Ok(CanonicalTree {
             code_book,
-            lookup,
-        }
+            table,
+        })
     }
 
-    pub fn from_read<R: Read>(read: R) -> Result<(u64, CanonicalTree), Box<Error>> {
-        // Keep track of state
-        let mut bytes_read: u64 = 0;
-        let mut freq_table: [u64; NUM_BYTES] = [0; NUM_BYTES];
+    /// Build the tree from existing codes.
+    ///
+    /// Fails unless the codes are exactly the canonical codes for their lengths, which also
+    /// guarantees they are prefix free.
+    pub fn from_code_book(code_book: &CodeBook<S>) -> Result<CanonicalTree<S>, HuffmanError> {
+        code_book.validate()?;
+
+        let code_lengths: Vec<(S, u8)> = code_book.iter()
+            .map(|(symbol, code)| (symbol, code.length()))
+            .collect();
 
-        for byte in read.bytes() {
-            if bytes_read == u64::max_value() {
-                return Err(From::from(format!("Cannot read file larger than {} bytes", u64::max_value())));
+        let tree = CanonicalTree::new(code_lengths)?;
+
+        for (symbol, code) in code_book.iter() {
+            if tree.code_book.get(symbol) != Some(code) {
+                return Err(HuffmanError::InvalidInput(format!("Code for symbol {} is not canonical", symbol)));
             }
-            bytes_read += 1;
-            freq_table[byte? as usize] += 1;
         }
 
-        // Read was empty
-        if bytes_read == 0 {
-            return Err(From::from("Read was empty"));
+        Ok(tree)
+    }
+
+    /// Build the tree from the frequency of each symbol, given as a `FrequencyTable` or as a
+    /// `[u64; 256]` indexed by byte.
+    ///
+    /// Only symbols with a nonzero frequency are given a code. Very skewed frequencies can produce
+    /// huffman codes longer than `MAX_CODE_LENGTH` bits, in which case the codes are limited to
+    /// `MAX_CODE_LENGTH` bits instead.
+    pub fn from_frequencies<F: Frequencies<Symbol = S> + ?Sized>(frequencies: &F) -> Result<CanonicalTree<S>, HuffmanError> {
+        if frequencies.frequencies().iter().all(|&frequency| frequency == 0) {
+            return Err(HuffmanError::EmptyInput);
+        }
+
+        let mut code_lengths = optimal_code_lengths(frequencies)?;
+
+        if code_lengths.iter().any(|&(_symbol, length)| length > MAX_CODE_LENGTH) {
+            code_lengths = limited_code_lengths(frequencies, MAX_CODE_LENGTH)?;
         }
 
-        // Create a huffman from the frequencies
-        let huff_tree = HuffmanTree::new(&freq_table)
-            .ok_or("Could not create buffman tree")?;
+        CanonicalTree::new(code_lengths)
+    }
+
+    /// Like `from_frequencies`, but no code will be longer than `max_length` bits.
+    pub fn from_frequencies_limited<F: Frequencies<Symbol = S> + ?Sized>(frequencies: &F, max_length: u8) -> Result<CanonicalTree<S>, HuffmanError> {
+        let code_lengths = limited_code_lengths(frequencies, max_length)?;
 
-        // Get code lengths from huffman tree
-        let code_lengths = huff_tree.get_code_lengths();
+        CanonicalTree::new(code_lengths)
+    }
+
+    /// Write the code for a single symbol.
+    #[cfg(feature = "std")]
+    pub fn encode_symbol<W: Write>(&self, symbol: S, bit_writer: &mut BitWriter<W>) -> Result<(), HuffmanError> {
+        bit_writer.write_code(self.code_at(symbol, 0)?)?;
 
-        Ok((bytes_read, CanonicalTree::new(code_lengths)))
+        Ok(())
     }
 
-    pub fn encode<R: Read, W: Write>(&self, read: & mut R, write: & mut W) -> Result<(), Box<Error>> {
+    /// Write the codes for every symbol in `symbols`, padding the last byte with zeros.
+    #[cfg(feature = "std")]
+    pub fn encode_symbols<W: Write>(&self, symbols: &[S], write: &mut W) -> Result<(), HuffmanError> {
         let mut bit_writer = BitWriter::new(write);
 
-        for byte_res in read.bytes() {
-            let byte = byte_res?;
-            let code = self.code_book.get(&byte)
-                .ok_or(format!("Symbol {} not found in code book", byte))?;
+        for (offset, &symbol) in symbols.iter().enumerate() {
+            bit_writer.write_code(self.code_at(symbol, offset as u64)?)?;
+        }
+
+        Ok(())
+    }
 
-            bit_writer.write_bits(&code)?;
+    /// Write the codes for every symbol in `symbols` to a buffer, without allocating unless there
+    /// is an error.
+    ///
+    /// Fails with a `BufferOverflow` as `HuffmanError::Other` if the codes do not fit, keeping
+    /// the codes written before.
+    pub fn encode_to_slice(&self, symbols: &[S], bit_writer: &mut SliceBitWriter) -> Result<(), HuffmanError> {
+        for (offset, &symbol) in symbols.iter().enumerate() {
+            bit_writer.write_code(self.code_at(symbol, offset as u64)?)?;
         }
 
         Ok(())
     }
 
-    pub fn decode<R: Read, W: Write>(&self, read: &mut R, write: &mut W) -> Result<u64, Box<Error>> {
-        self.decode_impl(read, write, u64::max_value())
+    /// Decode exactly `out.len()` symbols from `data`, without allocating unless there is an
+    /// error.
+    ///
+    /// Returns the number of bits decoded. The bits after them are left unchecked.
+    pub fn decode_from_slice(&self, data: &[u8], out: &mut [S]) -> Result<u64, HuffmanError> {
+        let mut bit_reader = SliceBitReader::new(data);
+
+        for (decoded, symbol) in out.iter_mut().enumerate() {
+            *symbol = match self.table.decode_checked(&mut bit_reader, decoded as u64)? {
+                Some(symbol) => symbol,
+                None => return Err(self.table.unexpected_end(&mut bit_reader, decoded as u64)),
+            };
+        }
+
+        Ok(bit_reader.position())
+    }
+
+    /// The code of `symbol`, found `offset` symbols into the input.
+    fn code_at(&self, symbol: S, offset: u64) -> Result<Code, HuffmanError> {
+        self.code_book.get(symbol).ok_or(HuffmanError::SymbolNotInCodebook { symbol: symbol.to_index(), offset })
     }
 
-    pub fn decode_exact<R: Read, W: Write>(&self, read: &mut R, write: &mut W, bytes: u64) -> Result<(), Box<Error>> {
-        let bytes_read = self.decode_impl(read, write, bytes)?;
+    /// Read a single symbol.
+    ///
+    /// Returns `None` if the `BitReader` ends before a complete code.
+    #[cfg(feature = "std")]
+    pub fn decode_symbol<R: Read>(&self, bit_reader: &mut BitReader<R>) -> Result<Option<S>, HuffmanError> {
+        self.decode_next(bit_reader, 0)
+    }
 
-        if bytes_read != bytes {
-            return Err(From::from("File corrupt"));
+    /// Decode exactly `count` symbols, failing with a `DecodeError` if the data ends early.
+    #[cfg(feature = "std")]
+    pub fn decode_symbols<R: Read>(&self, read: R, count: u64) -> Result<Vec<S>, HuffmanError> {
+        let mut bit_reader = BitReader::new(read);
+        let mut symbols = Vec::new();
+
+        while (symbols.len() as u64) < count {
+            match self.decode_next(&mut bit_reader, symbols.len() as u64)? {
+                Some(symbol) => symbols.push(symbol),
+                None => return Err(self.unexpected_end(&mut bit_reader, symbols.len() as u64)),
+            }
         }
 
-        Ok(())
+        Ok(symbols)
     }
 
-    fn decode_impl<R: Read, W: Write>(&self, read: &mut R, write: &mut W, bytes: u64) -> Result<u64, Box<Error>> {
-        let mut bit_reader = BitReader::new(read);
+    /// Decode the next symbol, failing with a `DecodeError` on an invalid code.
+    #[cfg(feature = "std")]
+    fn decode_next<R: Read>(&self, bit_reader: &mut BitReader<R>, symbols_decoded: u64) -> Result<Option<S>, HuffmanError> {
+        self.table.decode_checked(bit_reader, symbols_decoded)
+    }
 
-        let mut bytes_read: u64 = 0;
-        let mut buf: [u8; 1] = [0; 1];
-        let mut code: u64 = 0;
-        let mut mask: u64 = MAX_U64_MASK;
-        let mut offset: u64 = 0;
+    /// The `DecodeError` for data that ended in the middle of a code.
+    #[cfg(feature = "std")]
+    fn unexpected_end<R: Read>(&self, bit_reader: &mut BitReader<R>, symbols_decoded: u64) -> HuffmanError {
+        self.table.unexpected_end(bit_reader, symbols_decoded)
+    }
 
-        loop {
-            if let Some(bit) = bit_reader.read_bit()? {
-                if bit {
-                    code |= mask;
-                }
+    /// Lazily decode a `Read`, yielding one decoded symbol at a time.
+    ///
+    /// Like `decode`, the iterator runs until the `Read` is exhausted, so the padding at the end
+    /// of the data may decode to extra symbols. Use `take` when the number of symbols is known. The
+    /// iterator ends after yielding an error.
+    #[cfg(feature = "std")]
+    pub fn decode_iter<R: Read>(&self, read: R) -> DecodeIter<'_, R, S> {
+        DecodeIter {
+            tree: self,
+            bit_reader: BitReader::new(read),
+            symbols_decoded: 0,
+            done: false,
+        }
+    }
 
-                mask >>= 1;
-                offset += 1;
+    /// Serialize the tree into a compact binary form that can be read back with `from_bytes`.
+    ///
+    /// The format is:
+    ///
+    /// | Bytes         | Contents                                                        |
+    /// |---------------|-----------------------------------------------------------------|
+    /// | 1             | Format version, currently `TREE_FORMAT_VERSION`                 |
+    /// | 2 or 4        | Number of symbols with a code, `n`, as a little endian `u16`    |
+    /// |               | for byte alphabets and a `u32` for wider alphabets              |
+    /// | `n * (b + 1)` | Each symbol as `b` little endian bytes, where `b` is            |
+    /// |               | `Symbol::BYTES`, followed by its code length, in increasing     |
+    /// |               | order of symbol                                                 |
+    ///
+    /// Only the code lengths are stored, as the canonical codes are derived from them.
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let code_lengths: Vec<(S, u8)> = self.code_book.iter()
+            .map(|(symbol, code)| (symbol, code.length()))
+            .collect();
 
-                if mask > 0 {
-                    continue;
-                }
-            } else if offset == 0 {
-                return Ok(bytes_read);
-            } else if bytes_read == bytes {
-                return Ok(bytes_read);
-            }
+        let mut bytes = Vec::with_capacity(5 + (S::BYTES + 1) * code_lengths.len());
+        bytes.push(TREE_FORMAT_VERSION);
 
-            // Find the lookup entry
-            let (&min_code, entry) = self.lookup.range((Bound::Unbounded, Bound::Included(code)))
-                .next_back()
-                .ok_or("File corrupt")?;
+        if S::BYTES == 1 {
+            bytes.write_u16::<LittleEndian>(code_lengths.len() as u16)
+        } else {
+            bytes.write_u32::<LittleEndian>(code_lengths.len() as u32)
+        }.expect("Writing to a Vec cannot fail");
 
-            // Index into the entry
-            let index = (code - min_code) >> (64 - entry.length);
+        for (symbol, length) in code_lengths {
+            bytes.write_uint::<LittleEndian>(symbol.to_index() as u64, S::BYTES)
+                .expect("Writing to a Vec cannot fail");
+            bytes.push(length);
+        }
 
-            // Lookup the index in the entry
-            buf[0] = entry.codes[index as usize];
+        bytes
+    }
 
-            // Increment counter
-            bytes_read += 1;
+    /// Deserialize a tree written by `to_bytes`.
+    #[cfg(feature = "std")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<CanonicalTree<S>, HuffmanError> {
+        let mut read = Cursor::new(bytes);
 
-            // Write out the byte
-            write.write(&buf)?;
+        let version = read.read_u8()?;
+        if version != TREE_FORMAT_VERSION {
+            return Err(HuffmanError::CorruptHeader(format!("Unsupported tree format version {}", version)));
+        }
 
-            // Clear the first entry.length bits and left shift the code
-            mask = MAX_U64_MASK;
-            for _ in 0..entry.length {
-                code &= !mask;
-                mask >>= 1;
-            }
+        let count = if S::BYTES == 1 {
+            read.read_u16::<LittleEndian>()? as usize
+        } else {
+            read.read_u32::<LittleEndian>()? as usize
+        };
+
+        if count > S::ALPHABET_SIZE {
+            return Err(HuffmanError::CorruptHeader(format!("Tree has {} symbols, at most {} are allowed", count, S::ALPHABET_SIZE)));
+        }
+
+        let mut code_lengths = Vec::with_capacity(count);
+        for _ in 0..count {
+            let symbol = read.read_uint::<LittleEndian>(S::BYTES)? as usize;
+            code_lengths.push((S::from_index(symbol), read.read_u8()?));
+        }
+
+        if read.position() != bytes.len() as u64 {
+            return Err(HuffmanError::CorruptHeader("Unexpected bytes after tree".to_string()));
+        }
+
+        CanonicalTree::new(code_lengths)
+    }
+
+    /// Length of the longest code, or `None` if no symbol has a code.
+    pub fn max_code_length(&self) -> Option<u8> {
+        self.code_book.iter().map(|(_symbol, code)| code.length()).max()
+    }
+
+    /// Length of the shortest code, or `None` if no symbol has a code.
+    pub fn min_code_length(&self) -> Option<u8> {
+        self.code_book.iter().map(|(_symbol, code)| code.length()).min()
+    }
+
+    /// Length of the code for `symbol`, or `None` if it has no code.
+    pub fn code_length_of(&self, symbol: S) -> Option<u8> {
+        self.code_book.get(symbol).map(|code| code.length())
+    }
+
+    /// Whether every symbol that occurs in `frequencies` has a code, so data with these
+    /// frequencies can be encoded.
+    pub fn covers<F: Frequencies<Symbol = S> + ?Sized>(&self, frequencies: &F) -> bool {
+        frequencies.frequencies().iter().enumerate()
+            .all(|(index, &frequency)| frequency == 0 || self.code_book.get(S::from_index(index)).is_some())
+    }
+
+    /// Expected size of the encoded data as a fraction of its original size, for data with these
+    /// frequencies. Headers are not counted, as a reused tree need not be stored.
+    ///
+    /// Returns `None` if the tree does not cover the frequencies or they are all 0.
+    #[cfg(feature = "std")]
+    pub fn estimated_ratio_for<F: Frequencies<Symbol = S> + ?Sized>(&self, frequencies: &F) -> Option<f64> {
+        expected_bits_per_symbol(self, frequencies).map(|bits| bits / (8 * S::BYTES) as f64)
+    }
+
+    /// Get the code assigned to each symbol.
+    pub fn code_book(&self) -> &CodeBook<S> {
+        &self.code_book
+    }
+
+    /// The table used to decode symbols.
+    pub fn decode_table(&self) -> &DecodeTable<S> {
+        &self.table
+    }
+
+    /// Symbols with a code in the order their codes are assigned: by increasing code length,
+    /// then by increasing symbol.
+    pub fn sorted_symbols(&self) -> Vec<S> {
+        let mut symbols: Vec<(u8, S)> = self.code_book.iter()
+            .map(|(symbol, code)| (code.length(), symbol))
+            .collect();
+        symbols.sort();
+
+        symbols.into_iter().map(|(_length, symbol)| symbol).collect()
+    }
+
+    /// The first code of each length, indexed by length, or `None` if no code has that length.
+    ///
+    /// The codes of a length are consecutive, so together with `sorted_symbols` this is enough
+    /// to decode with the classic canonical decoding algorithm.
+    pub fn first_codes(&self) -> Vec<Option<Code>> {
+        let mut first_codes = vec![None; self.max_code_length().unwrap_or(0) as usize + 1];
+
+        for (_symbol, code) in self.code_book.iter() {
+            let first = &mut first_codes[code.length() as usize];
 
-            code <<= entry.length;
-            offset -= entry.length as u64;
-            mask = 1 << entry.length as u64 - 1;
+            if first.is_none_or(|first: Code| code.bits() < first.bits()) {
+                *first = Some(code);
+            }
         }
+
+        first_codes
+    }
+}
+
+impl CanonicalTree {
+    /// Build the tree from the code length of every byte, as returned by `code_lengths` and
+    /// stored in headers.
+    ///
+    /// Fails unless at least one byte has a code and the codes are complete, i.e. every string of
+    /// bits starts with a code, as they are for trees built from frequencies. A lone byte has a 1
+    /// bit code.
+    pub fn from_code_lengths(code_lengths: &[u8; NUM_BYTES]) -> Result<CanonicalTree, HuffmanError> {
+        validate_complete_code_lengths(code_lengths)?;
+
+        CanonicalTree::new(code_lengths.iter().enumerate().map(|(i, &l)| (i as u8, l)).collect())
     }
 
     /// Get the raw code lengths used to build the tree.
@@ -161,110 +375,670 @@ impl CanonicalTree {
     pub fn code_lengths(&self) -> [u8; NUM_BYTES] {
         let mut result = [0; NUM_BYTES];
 
-        for (&byte, code) in self.code_book.iter() {
-            result[byte as usize] = code.len() as u8;
+        for (symbol, code) in self.code_book.iter() {
+            result[symbol as usize] = code.length();
         }
 
         result
     }
 }
 
-fn canonical_code_book(code_lengths: &[(u8, u8)]) -> CodeBook {
-    // Sort by code_length and then by symbol
-    let mut sorted = Vec::from(code_lengths);
-    sorted.sort_by_key(|&(symbol, length)| (length,  symbol));
+/// Methods for coding bytes.
+#[cfg(feature = "std")]
+impl CanonicalTree {
+    pub fn from_read<R: Read>(read: R) -> Result<(u64, CanonicalTree), HuffmanError> {
+        let (bytes_read, freq_table) = read_frequencies(read)?;
 
-    let mut result = HashMap::new();
+        // Read was empty
+        if bytes_read == 0 {
+            return Err(HuffmanError::EmptyInput);
+        }
 
-    // Current code
-    let mut code: u64 = 0;
+        Ok((bytes_read, CanonicalTree::from_frequencies(&freq_table)?))
+    }
 
-    let mut iter = sorted.iter().peekable();
-    while let Some(&(symbol, length)) = iter.next() {
-        if length == 0 {
-            continue;
+    /// Build the tree from a slice, counting the frequencies on multiple threads.
+    pub fn from_slice(bytes: &[u8]) -> Result<CanonicalTree, HuffmanError> {
+        if bytes.is_empty() {
+            return Err(HuffmanError::EmptyInput);
         }
 
-        result.insert(symbol, code_to_vec(length, code));
+        CanonicalTree::from_frequencies(&slice_frequencies(bytes)?)
+    }
 
-        if let Some(&&(_symbol_next, length_next)) = iter.peek() {
-            code = (code + 1) << (length_next - length);
+    /// Build the tree from a file, counting the frequencies on multiple threads.
+    ///
+    /// Each thread reads its own range of the file. Returns the size of the file along with the
+    /// tree.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<(u64, CanonicalTree), HuffmanError> {
+        let (bytes_read, freq_table) = file_frequencies(path)?;
+
+        if bytes_read == 0 {
+            return Err(HuffmanError::EmptyInput);
+        }
+
+        Ok((bytes_read, CanonicalTree::from_frequencies(&freq_table)?))
+    }
+
+    /// Build the tree from only the first `sample_bytes` bytes of a `Read`.
+    ///
+    /// This avoids a full pass over very large inputs, at the cost of a slightly worse
+    /// compression ratio when the sample is not representative. Since bytes outside the sample
+    /// can differ from the ones in it, every byte is given a code.
+    pub fn from_read_sampled<R: Read>(read: R, sample_bytes: u64) -> Result<CanonicalTree, HuffmanError> {
+        let (_bytes_read, mut freq_table) = read_frequencies(read.take(sample_bytes))?;
+
+        for symbol in 0..=u8::MAX {
+            freq_table.add_symbol(symbol);
         }
+
+        CanonicalTree::from_frequencies(&freq_table)
     }
 
-    result
-}
+    /// Like `from_read`, but no code will be longer than `max_length` bits.
+    ///
+    /// The code lengths are computed with the package-merge algorithm, so the codes are optimal
+    /// among codes satisfying the limit.
+    pub fn from_read_limited<R: Read>(read: R, max_length: u8) -> Result<(u64, CanonicalTree), HuffmanError> {
+        let (bytes_read, freq_table) = read_frequencies(read)?;
+
+        // Read was empty
+        if bytes_read == 0 {
+            return Err(HuffmanError::EmptyInput);
+        }
+
+        Ok((bytes_read, CanonicalTree::from_frequencies_limited(&freq_table, max_length)?))
+    }
+
+    /// Write the codes for every byte of `data`.
+    ///
+    /// The codes are looked up in a table, and when no code is longer than 16 bits, the codes of
+    /// several bytes are joined and written with a single call to the `BitWriter`.
+    pub fn encode_slice<W: Write>(&self, data: &[u8], bit_writer: &mut BitWriter<W>) -> Result<(), HuffmanError> {
+        let codes = self.code_book.as_table();
+        let code_of = |byte: u8, offset: usize| codes[byte as usize]
+            .ok_or(HuffmanError::SymbolNotInCodebook { symbol: byte as usize, offset: offset as u64 });
+        let max_length = codes.iter().flatten().map(|code| code.length()).max().unwrap_or(0);
+
+        let mut rest = data;
+
+        if max_length as usize * PACKED_SYMBOLS <= 64 {
+            let mut chunks = data.chunks_exact(PACKED_SYMBOLS);
+
+            for (index, chunk) in (&mut chunks).enumerate() {
+                let mut bits = 0;
+                let mut length = 0;
+
+                for (i, &byte) in chunk.iter().enumerate() {
+                    let code = code_of(byte, index * PACKED_SYMBOLS + i)?;
+                    bits = (bits << code.length()) | code.bits();
+                    length += code.length();
+                }
+
+                bit_writer.write_bits_u64(bits, length)?;
+            }
+
+            rest = chunks.remainder();
+        }
+
+        let start = data.len() - rest.len();
+
+        for (i, &byte) in rest.iter().enumerate() {
+            bit_writer.write_code(code_of(byte, start + i)?)?;
+        }
 
-#[inline]
-fn code_to_vec(length: u8, code: u64) -> Vec<bool> {
-    let mut vec = Vec::with_capacity(length as usize);
-    let mut mask = 1 << ((length - 1) as u64);
+        Ok(())
+    }
+
+    pub fn encode<R: Read, W: Write>(&self, read: & mut R, write: & mut W) -> Result<(), HuffmanError> {
+        self.encode_impl(read, write, None)
+    }
 
-    for _ in 0..(length as u64) {
-        vec.push((mask & code) != 0);
-        mask >>= 1;
+    /// Like `encode`, but also returns how many bits of output each byte produced.
+    pub fn encode_with_usage<R: Read, W: Write>(&self, read: &mut R, write: &mut W) -> Result<BitUsage, HuffmanError> {
+        let mut freq_table = FrequencyTable::new();
+
+        self.encode_impl(read, write, Some(&mut freq_table))?;
+
+        Ok(BitUsage::new(self, &freq_table))
     }
 
-    vec
+    /// Encode every byte of `read`, counting the bytes in `freq_table` if one is given.
+    fn encode_impl<R: Read, W: Write>(&self, read: &mut R, write: &mut W, mut freq_table: Option<&mut FrequencyTable>) -> Result<(), HuffmanError> {
+        let mut word_writer = WordWriter::new(write);
+        let codes = self.code_book.as_table();
+
+        let mut buf = [0; READ_BUFFER_SIZE];
+        let mut bytes_encoded = 0;
+
+        loop {
+            let bytes_read = read_some(read, &mut buf)?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            word_writer.write_bytes(codes, &buf[..bytes_read]).map_err(|mut err| {
+                err.add_offset(bytes_encoded);
+                err
+            })?;
+            bytes_encoded += bytes_read as u64;
+
+            if let Some(ref mut freq_table) = freq_table {
+                freq_table.count(&buf[..bytes_read]);
+            }
+        }
+
+        Ok(word_writer.finish()?)
+    }
+
+    /// Write the codes for every byte of `data` straight from the slice, padded to a whole byte.
+    pub(crate) fn encode_packed<W: Write>(&self, data: &[u8], write: &mut W) -> Result<(), HuffmanError> {
+        let mut word_writer = WordWriter::new(write);
+        word_writer.write_bytes(self.code_book.as_table(), data)?;
+
+        Ok(word_writer.finish()?)
+    }
+
+    pub fn decode<R: Read, W: Write>(&self, read: &mut R, write: &mut W) -> Result<u64, HuffmanError> {
+        self.decode_with(read, |chunk| Ok(write.write_all(chunk)?))
+    }
+
+    /// Like `decode`, but passes the decoded bytes to `output` in chunks instead of writing them.
+    ///
+    /// Decoding stops at the first error returned by `output`.
+    pub fn decode_with<R: Read, F>(&self, read: &mut R, mut output: F) -> Result<u64, HuffmanError>
+        where F: FnMut(&[u8]) -> Result<(), HuffmanError>
+    {
+        self.decode_impl(&mut BitReader::new(read), &mut output, u64::MAX, None)
+            .map(|(bytes_read, _found)| bytes_read)
+    }
+
+    /// Decode exactly `bytes` bytes, failing with a `DecodeError` if the data ends early.
+    pub fn decode_exact<R: Read, W: Write>(&self, read: &mut R, write: &mut W, bytes: u64) -> Result<(), HuffmanError> {
+        self.decode_exact_impl(&mut BitReader::new(read), write, bytes)
+    }
+
+    /// Like `decode_exact`, but also fails unless the data after the last code is only zero
+    /// padding up to the next byte boundary.
+    ///
+    /// This catches truncated or concatenated streams that `decode_exact` would accept.
+    pub fn decode_exact_strict<R: Read, W: Write>(&self, read: &mut R, write: &mut W, bytes: u64) -> Result<(), HuffmanError> {
+        let mut bit_reader = BitReader::new(read);
+
+        self.decode_exact_impl(&mut bit_reader, write, bytes)?;
+
+        check_padding(&mut bit_reader)
+    }
+
+    /// Decode exactly `out.len()` bytes into `out`, failing with a `DecodeError` if the data
+    /// ends early.
+    ///
+    /// Nothing is allocated unless there is an error, so this suits decoding into buffers that
+    /// are reused.
+    pub fn decode_into<R: Read>(&self, read: &mut R, out: &mut [u8]) -> Result<(), HuffmanError> {
+        self.decode_into_impl(&mut BitReader::new(read), out)
+    }
+
+    pub(crate) fn decode_into_impl<R: Read>(&self, bit_reader: &mut BitReader<R>, out: &mut [u8]) -> Result<(), HuffmanError> {
+        for (decoded, byte) in out.iter_mut().enumerate() {
+            *byte = match self.decode_next(bit_reader, decoded as u64)? {
+                Some(symbol) => symbol,
+                None => return Err(self.unexpected_end(bit_reader, decoded as u64)),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn decode_exact_impl<R: Read, W: Write>(&self, bit_reader: &mut BitReader<R>, write: &mut W, bytes: u64) -> Result<(), HuffmanError> {
+        let (bytes_read, _found) = self.decode_impl(bit_reader, &mut |chunk| Ok(write.write_all(chunk)?), bytes, None)?;
+
+        if bytes_read != bytes {
+            return Err(self.unexpected_end(bit_reader, bytes_read));
+        }
+
+        Ok(())
+    }
+
+    /// Decode until the sentinel is decoded, without writing the sentinel.
+    ///
+    /// Takes a `BitReader` so that more records can be decoded from the bits following the
+    /// sentinel. Returns the number of bytes written, or fails with a `DecodeError` if the data
+    /// ends before the sentinel.
+    pub fn decode_until<R: Read, W: Write>(&self, bit_reader: &mut BitReader<R>, write: &mut W, sentinel: u8) -> Result<u64, HuffmanError> {
+        let (bytes_read, found) = self.decode_impl(bit_reader, &mut |chunk| Ok(write.write_all(chunk)?), u64::MAX, Some(sentinel))?;
+
+        if !found {
+            return Err(self.unexpected_end(bit_reader, bytes_read));
+        }
+
+        Ok(bytes_read)
+    }
+
+    /// Decode up to `bytes` bytes, stopping early at the end of the data or after decoding
+    /// `sentinel`.
+    ///
+    /// Returns the number of bytes passed to `output` and whether the sentinel was decoded.
+    fn decode_impl<R: Read, F>(&self, bit_reader: &mut BitReader<R>, output: &mut F, bytes: u64, sentinel: Option<u8>) -> Result<(u64, bool), HuffmanError>
+        where F: FnMut(&[u8]) -> Result<(), HuffmanError>
+    {
+        let mut bytes_read: u64 = 0;
+        let mut buf: Vec<u8> = Vec::with_capacity(DECODE_BUFFER_SIZE);
+        let mut found = false;
+
+        while bytes_read < bytes {
+            let symbol = match self.decode_next(bit_reader, bytes_read) {
+                Ok(Some(symbol)) if Some(symbol) == sentinel => {
+                    found = true;
+                    break;
+                },
+                Ok(Some(symbol)) => symbol,
+                Ok(None) => break,
+                Err(err) => {
+                    // Output everything decoded before the error
+                    output(&buf)?;
+                    return Err(err);
+                },
+            };
+
+            // Increment counter
+            bytes_read += 1;
+
+            buf.push(symbol);
+
+            if buf.len() == DECODE_BUFFER_SIZE {
+                output(&buf)?;
+                buf.clear();
+            }
+        }
+
+        output(&buf)?;
+
+        Ok((bytes_read, found))
+    }
+
+    /// Decode a batch of independent payloads in parallel, each given as its encoded data and its
+    /// decoded length in bytes.
+    ///
+    /// The payloads are split between threads, and the result for each payload is returned in
+    /// the order of `payloads`.
+    pub fn decode_batch<P: AsRef<[u8]> + Sync>(&self, payloads: &[(P, u64)]) -> Vec<Result<Vec<u8>, HuffmanError>> {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let chunk_size = payloads.len().div_ceil(threads).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = payloads.chunks(chunk_size)
+                .map(|chunk| (chunk.len(), scope.spawn(move || {
+                    chunk.iter()
+                        .map(|(encoded, bytes)| {
+                            let mut decoded = Vec::new();
+                            self.decode_exact(&mut encoded.as_ref(), &mut decoded, *bytes)
+                                .map(|_| decoded)
+                        })
+                        .collect::<Vec<_>>()
+                })))
+                .collect();
+
+            handles.into_iter()
+                .flat_map(|(payloads, handle)| handle.join().unwrap_or_else(|_| {
+                    (0..payloads).map(|_| Err(thread_panicked("Decoding"))).collect()
+                }))
+                .collect()
+        })
+    }
+
+    /// Get statistics about the codes, such as how many codes there are of each length.
+    pub fn stats(&self) -> TreeStats {
+        TreeStats::new(self)
+    }
+}
+
+/// Iterator over the symbols decoded from a `Read`, created by `CanonicalTree::decode_iter`.
+#[cfg(feature = "std")]
+pub struct DecodeIter<'a, R, S: 'a = u8> {
+    tree: &'a CanonicalTree<S>,
+    bit_reader: BitReader<R>,
+    symbols_decoded: u64,
+    done: bool,
 }
 
-fn lookup_tree(code_book: &CodeBook) -> BTreeMap<u64, LookupEntry> {
-    let mut tree = BTreeMap::new();
+#[cfg(feature = "std")]
+impl<'a, R: Read, S: Symbol> Iterator for DecodeIter<'a, R, S> {
+    type Item = Result<S, HuffmanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
 
-    // Group by lengths
-    let mut map: HashMap<usize, Vec<(u8, u64)>> = HashMap::new();
+        match self.tree.decode_next(&mut self.bit_reader, self.symbols_decoded) {
+            Ok(Some(symbol)) => {
+                self.symbols_decoded += 1;
+                Some(Ok(symbol))
+            },
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            },
+        }
+    }
+}
 
-    for (&symbol, code_vec) in code_book.iter() {
-        let vec = map.entry(code_vec.len())
-            .or_insert(Vec::new());
+/// What to do with anything after the coded bytes once they are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg(feature = "std")]
+pub(crate) enum Trailing {
+    /// Leave it unread.
+    Ignore,
+    /// Fail unless it is only the zero padding of the last byte.
+    Reject,
+    /// Read and skip all of it, failing if `strict` and the rest of the last byte is not zero.
+    Skip { strict: bool },
+}
 
-        let mut mask: u64 = MAX_U64_MASK;
-        let mut code: u64 = 0;
+#[cfg(feature = "std")]
+impl Trailing {
+    pub(crate) fn new(strict: bool, skip: bool) -> Trailing {
+        match (strict, skip) {
+            (strict, true) => Trailing::Skip { strict },
+            (true, false) => Trailing::Reject,
+            (false, false) => Trailing::Ignore,
+        }
+    }
+}
 
-        for &bit in code_vec.iter() {
-            if bit {
-                code |= mask;
+/// Deal with what follows the coded bytes in `bit_reader` as `trailing` says, returning the number
+/// of whole bytes skipped after the last byte of the coded bytes.
+#[cfg(feature = "std")]
+pub(crate) fn finish_trailing<R: Read>(bit_reader: &mut BitReader<R>, trailing: Trailing) -> Result<u64, HuffmanError> {
+    match trailing {
+        Trailing::Ignore => Ok(0),
+        Trailing::Reject => check_padding(bit_reader).map(|()| 0),
+        Trailing::Skip { strict } => {
+            let padding = bit_reader.bits_left_in_byte() as u32;
+
+            if strict && bit_reader.peek().checked_shr(64 - padding).unwrap_or(0) != 0 {
+                return Err(HuffmanError::TrailingData);
             }
 
-            mask >>= 1;
+            let skipped = io::copy(bit_reader.get_mut(), &mut io::sink())?;
+
+            Ok(bit_reader.bytes_read_ahead() + skipped)
+        },
+    }
+}
+
+/// Check that only the zero padding of the current byte is left in `bit_reader`.
+#[cfg(feature = "std")]
+pub(crate) fn check_padding<R: Read>(bit_reader: &mut BitReader<R>) -> Result<(), HuffmanError> {
+    // Read ahead far enough to know whether more than a byte is left
+    let remaining = bit_reader.fill(8)?;
+
+    if remaining >= 8 {
+        return Err(HuffmanError::TrailingData);
+    }
+
+    if bit_reader.peek() != 0 {
+        return Err(HuffmanError::TrailingData);
+    }
+
+    Ok(())
+}
+
+/// Count the frequency of each byte in a `Read`.
+#[cfg(feature = "std")]
+pub(crate) fn read_frequencies<R: Read>(mut read: R) -> Result<(u64, FrequencyTable), HuffmanError> {
+    // Keep track of state
+    let mut bytes_read: u64 = 0;
+    let mut freq_table = FrequencyTable::new();
+    let mut buf = [0; READ_BUFFER_SIZE];
+
+    loop {
+        let chunk_size = read_some(&mut read, &mut buf)?;
+
+        if chunk_size == 0 {
+            break;
+        }
+
+        bytes_read = bytes_read.checked_add(chunk_size as u64)
+            .ok_or_else(|| HuffmanError::InvalidInput(format!("Cannot read file larger than {} bytes", u64::MAX)))?;
+
+        freq_table.count(&buf[..chunk_size]);
+    }
+
+    Ok((bytes_read, freq_table))
+}
+
+/// Count the frequency of each byte in a slice, splitting the work across threads.
+#[cfg(feature = "std")]
+pub(crate) fn slice_frequencies(bytes: &[u8]) -> Result<FrequencyTable, HuffmanError> {
+    slice_frequencies_in_chunks(bytes, num_chunks(bytes.len() as u64))
+}
+
+/// Count the frequency of each byte in a slice split into `chunks` chunks, each counted on its
+/// own thread, or on the calling thread for a single chunk. The counts are the same for any
+/// number of chunks.
+#[cfg(feature = "std")]
+fn slice_frequencies_in_chunks(bytes: &[u8], chunks: usize) -> Result<FrequencyTable, HuffmanError> {
+    if chunks <= 1 {
+        let mut freq_table = FrequencyTable::new();
+        freq_table.count(bytes);
+        return Ok(freq_table);
+    }
+
+    let chunk_size = bytes.len().div_ceil(chunks).max(1);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = bytes.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || {
+                let mut freq_table = FrequencyTable::new();
+                freq_table.count(chunk);
+                freq_table
+            }))
+            .collect();
+
+        let mut freq_table = FrequencyTable::new();
+
+        for handle in handles {
+            let partial = handle.join().map_err(|_| thread_panicked("Frequency counting"))?;
+            freq_table.merge(&partial);
         }
 
-        vec.push((symbol, code));
+        Ok(freq_table)
+    })
+}
+
+/// Count the frequency of each byte in a file, with each thread reading its own range. A file
+/// too small to split is read on the calling thread.
+#[cfg(feature = "std")]
+pub(crate) fn file_frequencies<P: AsRef<Path>>(path: P) -> Result<(u64, FrequencyTable), HuffmanError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let chunks = num_chunks(file_size) as u64;
+
+    if chunks == 1 {
+        return read_frequencies(file);
     }
+    let chunk_size = file_size.div_ceil(chunks);
+
+    let partials = thread::scope(|scope| {
+        let handles: Vec<_> = (0..chunks)
+            .map(|chunk| scope.spawn(move || -> io::Result<(u64, FrequencyTable)> {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(chunk * chunk_size))?;
 
-    // Create the entries to put into the tree
-    for (&length, &ref vec) in map.iter() {
-        let min_code = vec.iter()
-            .map(|&(_symbol, code)| code)
-            .min()
-            .expect(&format!("No codes for length {}", length));
+                let mut read = file.take(chunk_size);
+                let mut freq_table = FrequencyTable::new();
+                let mut bytes_read = 0;
+                let mut buf = [0; READ_BUFFER_SIZE];
+
+                loop {
+                    let read_size = read_some(&mut read, &mut buf)?;
+
+                    if read_size == 0 {
+                        break;
+                    }
+
+                    bytes_read += read_size as u64;
+                    freq_table.count(&buf[..read_size]);
+                }
 
-        let mut symbols: Vec<u8> = vec.iter()
-            .map(|&(symbol, _code)| symbol)
+                Ok((bytes_read, freq_table))
+            }))
             .collect();
-        symbols.sort();
 
-        let entry = LookupEntry::new(length as u8, symbols);
+        handles.into_iter()
+            .map(|handle| handle.join().map_err(|_| thread_panicked("Frequency counting"))?.map_err(HuffmanError::from))
+            .collect::<Result<Vec<_>, HuffmanError>>()
+    })?;
+
+    let mut bytes_read = 0;
+    let mut freq_table = FrequencyTable::new();
 
-        tree.insert(min_code, entry);
+    for (partial_bytes, partial) in partials {
+        bytes_read += partial_bytes;
+        freq_table.merge(&partial);
     }
 
-    tree
+    Ok((bytes_read, freq_table))
+}
+
+/// The error for a thread that panicked, returned instead of raising the panic again.
+#[cfg(feature = "std")]
+pub(crate) fn thread_panicked(work: &str) -> HuffmanError {
+    HuffmanError::Other(From::from(format!("{} thread panicked", work)))
+}
+
+/// Number of chunks to split `len` bytes into for counting frequencies in parallel.
+#[cfg(feature = "std")]
+fn num_chunks(len: u64) -> usize {
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunks = len.div_ceil(PARALLEL_CHUNK_SIZE as u64);
+
+    (chunks.min(threads as u64) as usize).max(1)
+}
+
+/// Check that canonical codes can be assigned to the code lengths.
+fn validate_code_lengths<S: Symbol>(code_lengths: &[(S, u8)]) -> Result<(), HuffmanError> {
+    let mut seen = vec![false; S::ALPHABET_SIZE];
+
+    // Kraft sum scaled by 2^MAX_CODE_LENGTH
+    let mut kraft_sum: u128 = 0;
+
+    for &(symbol, length) in code_lengths.iter() {
+        if seen[symbol.to_index()] {
+            return Err(HuffmanError::CorruptHeader(format!("Symbol {} has more than one code length", symbol)));
+        }
+        seen[symbol.to_index()] = true;
+
+        if length == 0 {
+            continue;
+        }
+
+        if length > MAX_CODE_LENGTH {
+            return Err(HuffmanError::CorruptHeader(format!("Code length {} of symbol {} is longer than {} bits",
+                length, symbol, MAX_CODE_LENGTH)));
+        }
+
+        kraft_sum += 1 << (MAX_CODE_LENGTH - length);
+    }
+
+    if kraft_sum > 1 << MAX_CODE_LENGTH {
+        return Err(HuffmanError::CorruptHeader("Code lengths are over-subscribed".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Fail unless some byte has a code, no code is longer than `MAX_CODE_LENGTH` bits, and the codes
+/// are complete or a lone 1 bit code. Over-subscribed lengths are left to `validate_code_lengths`.
+fn validate_complete_code_lengths(code_lengths: &[u8; NUM_BYTES]) -> Result<(), HuffmanError> {
+    let lengths: Vec<u8> = code_lengths.iter().cloned().filter(|&length| length != 0).collect();
+
+    if let Some(&length) = lengths.iter().find(|&&length| length > MAX_CODE_LENGTH) {
+        return Err(HuffmanError::CorruptHeader(format!("Code length {} is longer than {} bits", length, MAX_CODE_LENGTH)));
+    }
+
+    // Kraft sum scaled by 2^MAX_CODE_LENGTH
+    let kraft_sum: u128 = lengths.iter().map(|&length| 1 << (MAX_CODE_LENGTH - length)).sum();
+
+    match lengths[..] {
+        [] => Err(HuffmanError::CorruptHeader("No byte has a code".to_string())),
+        [1] => Ok(()),
+        _ if kraft_sum < 1 << MAX_CODE_LENGTH => Err(HuffmanError::CorruptHeader("Code lengths are incomplete".to_string())),
+        _ => Ok(()),
+    }
+}
+
+fn canonical_code_book<S: Symbol>(code_lengths: &[(S, u8)]) -> CodeBook<S> {
+    // Sort by code_length and then by symbol
+    let mut sorted = Vec::from(code_lengths);
+    sorted.sort_by_key(|&(symbol, length)| (length,  symbol));
+
+    let mut result = CodeBook::new();
+
+    // Current code
+    let mut code: u64 = 0;
+
+    let mut iter = sorted.iter().peekable();
+    while let Some(&(symbol, length)) = iter.next() {
+        if length == 0 {
+            continue;
+        }
+
+        result.insert(symbol, Code::new(code, length));
+
+        if let Some(&&(_symbol_next, length_next)) = iter.peek() {
+            code = (code + 1) << (length_next - length);
+        }
+    }
+
+    result
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "std")]
     use std::io::Cursor;
+    #[cfg(feature = "std")]
     use std::vec::Vec;
+    #[cfg(feature = "std")]
+    use std::sync::Arc;
 
+    #[cfg(feature = "std")]
+    #[allow(clippy::redundant_static_lifetimes)]
     const SMALL_STR: &'static str = "a small sample string";
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_small_sample_string() {
         assert!(encode_decode_test(SMALL_STR.as_bytes()));
     }
 
+    #[test]
+    fn test_canonical_codes() {
+        // Example from RFC 1951 section 3.2.2
+        let lengths = vec![(b'A', 3), (b'B', 3), (b'C', 3), (b'D', 3),
+            (b'E', 3), (b'F', 2), (b'G', 4), (b'H', 4)];
+
+        let code_book = canonical_code_book(&lengths);
+
+        assert_eq!(code_book.get(b'A'), Some(Code::new(0b010, 3)));
+        assert_eq!(code_book.get(b'E'), Some(Code::new(0b110, 3)));
+        assert_eq!(code_book.get(b'F'), Some(Code::new(0b00, 2)));
+        assert_eq!(code_book.get(b'G'), Some(Code::new(0b1110, 4)));
+        assert_eq!(code_book.get(b'H'), Some(Code::new(0b1111, 4)));
+        assert_eq!(code_book.get(b'I'), None);
+    }
+
+    #[cfg(feature = "std")]
     #[test]
     fn test_canonical_tree_equal() {
         let (_bytes, tree1) = CanonicalTree::from_read(Cursor::new(SMALL_STR)).unwrap();
@@ -276,11 +1050,584 @@ mod tests {
             .filter(|&(_i, length)| length > 0)
             .collect();
 
-        let tree2 = CanonicalTree::new(code_lenghts);
+        let tree2 = CanonicalTree::new(code_lenghts).unwrap();
 
         assert_eq!(tree1, tree2);
     }
 
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_single_symbols() {
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 2)]).unwrap();
+
+        let mut encoded = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(&mut encoded);
+
+            // Interleave the codes with raw bits
+            tree.encode_symbol(b'c', &mut bit_writer).unwrap();
+            bit_writer.write_bits_u64(0b101, 3).unwrap();
+            tree.encode_symbol(b'a', &mut bit_writer).unwrap();
+
+            assert!(tree.encode_symbol(b'd', &mut bit_writer).is_err());
+        }
+
+        // 11 101 0 00
+        assert_eq!(encoded, vec![0b11101000]);
+
+        let mut bit_reader = BitReader::new(Cursor::new(encoded));
+
+        assert_eq!(tree.decode_symbol(&mut bit_reader).unwrap(), Some(b'c'));
+        assert_eq!(bit_reader.read_bit().unwrap(), Some(true));
+        assert_eq!(bit_reader.read_bit().unwrap(), Some(false));
+        assert_eq!(bit_reader.read_bit().unwrap(), Some(true));
+        assert_eq!(tree.decode_symbol(&mut bit_reader).unwrap(), Some(b'a'));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_to_from_bytes() {
+        let (_bytes, tree) = CanonicalTree::from_read(Cursor::new(SMALL_STR)).unwrap();
+
+        let bytes = tree.to_bytes();
+        assert_eq!(bytes.len(), 3 + 2 * 12);
+        assert_eq!(CanonicalTree::from_bytes(&bytes).unwrap(), tree);
+
+        // Truncated
+        assert!(CanonicalTree::<u8>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // Trailing bytes
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(CanonicalTree::<u8>::from_bytes(&extended).is_err());
+
+        // Unknown version
+        let mut other_version = bytes.clone();
+        other_version[0] = TREE_FORMAT_VERSION + 1;
+        assert!(CanonicalTree::<u8>::from_bytes(&other_version).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_code_book() {
+        let (_bytes, tree) = CanonicalTree::from_read(Cursor::new(SMALL_STR)).unwrap();
+
+        assert_eq!(CanonicalTree::from_code_book(tree.code_book()).unwrap(), tree);
+
+        // Prefix free, but not canonical
+        let mut code_book = CodeBook::new();
+        code_book.insert(b'a', Code::new(0b1, 1));
+        code_book.insert(b'b', Code::new(0b0, 1));
+        assert!(CanonicalTree::from_code_book(&code_book).is_err());
+
+        // Not prefix free
+        code_book.insert(b'b', Code::new(0b10, 2));
+        assert!(CanonicalTree::from_code_book(&code_book).is_err());
+
+        code_book.insert(b'a', Code::new(0b0, 1));
+        assert!(CanonicalTree::from_code_book(&code_book).is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decode_writes_in_chunks() {
+        struct CountingWrite {
+            data: Vec<u8>,
+            writes: usize,
+        }
+
+        impl Write for CountingWrite {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.writes += 1;
+                self.data.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let text: Vec<u8> = SMALL_STR.bytes().cycle().take(2 * DECODE_BUFFER_SIZE + 10).collect();
+        let (_bytes, tree) = CanonicalTree::from_read(Cursor::new(&text)).unwrap();
+
+        let mut encoded = Vec::new();
+        tree.encode(&mut Cursor::new(&text), &mut encoded).unwrap();
+
+        let mut write = CountingWrite { data: Vec::new(), writes: 0 };
+        tree.decode_exact(&mut Cursor::new(encoded), &mut write, text.len() as u64).unwrap();
+
+        assert_eq!(write.data, text);
+        assert_eq!(write.writes, 3);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decode_iter() {
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 3)]).unwrap();
+
+        // 10 0 110 0 10 0 111 (111 is not a code)
+        let encoded = vec![0b10011001, 0b00111000];
+
+        let decoded: Vec<u8> = tree.decode_iter(Cursor::new(&encoded))
+            .take(6)
+            .map(|byte| byte.unwrap())
+            .collect();
+        assert_eq!(decoded, b"bacaba");
+
+        let mut iter = tree.decode_iter(Cursor::new(&encoded)).skip(6);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decode_exact_strict() {
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 2)]).unwrap();
+
+        let decode = |encoded: Vec<u8>| {
+            let mut decoded = Vec::new();
+            tree.decode_exact_strict(&mut Cursor::new(encoded), &mut decoded, 2).map(|_| decoded)
+        };
+
+        // 0 10 00000
+        assert_eq!(decode(vec![0b01000000]).unwrap(), b"ab");
+
+        // Padding is not zero
+        assert!(decode(vec![0b01000100]).is_err());
+
+        // Extra bytes after the padding
+        assert!(decode(vec![0b01000000, 0]).is_err());
+
+        // Not strict
+        let mut decoded = Vec::new();
+        tree.decode_exact(&mut Cursor::new(vec![0b01000100, 0]), &mut decoded, 2).unwrap();
+        assert_eq!(decoded, b"ab");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_corrupt_errors() {
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 3)]).unwrap();
+
+        let decode_error = |encoded: Vec<u8>, bytes: u64| {
+            let err = tree.decode_exact(&mut Cursor::new(encoded), &mut Vec::new(), bytes).unwrap_err();
+            match err {
+                HuffmanError::Decode(err) => err,
+                err => panic!("Not a decode error: {}", err),
+            }
+        };
+
+        // 10 0 110 0 111
+        assert_eq!(decode_error(vec![0b10011001, 0b11000000], 10), DecodeError {
+            kind: DecodeErrorKind::InvalidCode,
+            bit_offset: 7,
+            symbols_decoded: 4,
+            partial_code: Code::new(0b111, 3),
+        });
+
+        // 10 0 110 11
+        assert_eq!(decode_error(vec![0b10011011], 10), DecodeError {
+            kind: DecodeErrorKind::UnexpectedEnd,
+            bit_offset: 6,
+            symbols_decoded: 3,
+            partial_code: Code::new(0b11, 2),
+        });
+    }
+
+    #[test]
+    fn test_code_length_accessors() {
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 3), (b'd', 3)]).unwrap();
+
+        assert_eq!(tree.max_code_length(), Some(3));
+        assert_eq!(tree.min_code_length(), Some(1));
+        assert_eq!(tree.code_length_of(b'b'), Some(2));
+        assert_eq!(tree.code_length_of(b'e'), None);
+
+        let empty: CanonicalTree = CanonicalTree::new(vec![]).unwrap();
+
+        assert_eq!(empty.max_code_length(), None);
+        assert_eq!(empty.min_code_length(), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_covers() {
+        let tree = CanonicalTree::from_slice(b"aaaabbc").unwrap();
+
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"abcabc");
+        assert!(tree.covers(&frequencies));
+        // Codes of 1, 2 and 2 bits against 8 bit bytes
+        assert_eq!(tree.estimated_ratio_for(&frequencies), Some(5.0 / 3.0 / 8.0));
+
+        frequencies.add_symbol(b'z');
+        assert!(!tree.covers(&frequencies));
+        assert_eq!(tree.estimated_ratio_for(&frequencies), None);
+
+        assert!(tree.covers(&FrequencyTable::new()));
+        assert_eq!(tree.estimated_ratio_for(&FrequencyTable::new()), None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_read_sampled() {
+        let mut text: Vec<u8> = SMALL_STR.bytes().cycle().take(1000).collect();
+        text.extend_from_slice(&[0, 255, b'z']);
+
+        let tree = CanonicalTree::from_read_sampled(Cursor::new(&text), 100).unwrap();
+
+        assert_eq!(tree.code_book().len(), NUM_BYTES);
+        assert!(tree.code_length_of(b'a').unwrap() < tree.code_length_of(b'z').unwrap());
+
+        let mut encoded = Vec::new();
+        tree.encode(&mut Cursor::new(&text), &mut encoded).unwrap();
+
+        let mut decoded = Vec::new();
+        tree.decode_exact(&mut Cursor::new(encoded), &mut decoded, text.len() as u64).unwrap();
+
+        assert_eq!(decoded, text);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_parallel_frequencies() {
+        let mut text = Vec::new();
+        File::open("./MobyDick.txt").unwrap().read_to_end(&mut text).unwrap();
+
+        let (bytes_read, freq_table) = read_frequencies(Cursor::new(&text)).unwrap();
+
+        assert_eq!(slice_frequencies(&text).unwrap(), freq_table);
+        for chunks in 1..=8 {
+            assert_eq!(slice_frequencies_in_chunks(&text, chunks).unwrap(), freq_table);
+        }
+        assert_eq!(file_frequencies("./MobyDick.txt").unwrap(), (bytes_read, freq_table));
+
+        let (_bytes_read, tree) = CanonicalTree::from_read(Cursor::new(&text)).unwrap();
+
+        assert_eq!(CanonicalTree::from_slice(&text).unwrap(), tree);
+        assert_eq!(CanonicalTree::from_file("./MobyDick.txt").unwrap(), (bytes_read, tree));
+
+        assert!(CanonicalTree::from_slice(&[]).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_frequencies() {
+        let mut freq_table = [0; NUM_BYTES];
+        assert!(CanonicalTree::from_frequencies(&freq_table).is_err());
+
+        freq_table[b'a' as usize] = 2;
+        freq_table[b'b' as usize] = 1;
+        freq_table[b'c' as usize] = 1;
+
+        let tree = CanonicalTree::from_frequencies(&freq_table).unwrap();
+        assert_eq!(tree.code_length_of(b'a'), Some(1));
+        assert_eq!(tree.code_length_of(b'b'), Some(2));
+        assert_eq!(tree.code_length_of(b'd'), None);
+
+        let mut table = FrequencyTable::new();
+        table.count(b"aabc");
+        assert_eq!(CanonicalTree::from_frequencies(&table).unwrap(), tree);
+
+        let (_bytes_read, read_tree) = CanonicalTree::from_read(Cursor::new("aabc")).unwrap();
+        assert_eq!(read_tree, tree);
+    }
+
+    #[test]
+    fn test_decode_lookup() {
+        let tree = CanonicalTree::new(vec![(b'd', 1), (b'c', 2), (b'a', 3), (b'b', 3)]).unwrap();
+
+        assert_eq!(tree.sorted_symbols(), b"dcab");
+        assert_eq!(tree.first_codes(), vec![
+            None,
+            Some(Code::new(0b0, 1)),
+            Some(Code::new(0b10, 2)),
+            Some(Code::new(0b110, 3)),
+        ]);
+
+        let table = tree.decode_table();
+        assert_eq!(table.bits(), 3);
+        assert_eq!(table.entries()[0b011], TableEntry::Symbol { symbol: b'd', length: 1 });
+        assert_eq!(table.entries()[0b111], TableEntry::Symbol { symbol: b'b', length: 3 });
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_wide_symbols() {
+        let tokens: Vec<u16> = (0..20000u32).map(|i| (i * i % 5003 + 60000) as u16).collect();
+
+        let mut freq_table = FrequencyTable::new();
+        freq_table.count(&tokens);
+
+        let tree = CanonicalTree::from_frequencies(&freq_table).unwrap();
+        assert_eq!(tree.code_book().len(), freq_table.symbols());
+
+        let mut encoded = Vec::new();
+        tree.encode_symbols(&tokens, &mut encoded).unwrap();
+
+        assert_eq!(tree.decode_symbols(Cursor::new(&encoded), tokens.len() as u64).unwrap(), tokens);
+        assert!(tree.decode_symbols(Cursor::new(&encoded[..100]), tokens.len() as u64).is_err());
+
+        let decoded: Vec<u16> = tree.decode_iter(Cursor::new(&encoded))
+            .take(tokens.len())
+            .map(|symbol| symbol.unwrap())
+            .collect();
+        assert_eq!(decoded, tokens);
+
+        let bytes = tree.to_bytes();
+        assert_eq!(bytes.len(), 5 + 3 * tree.code_book().len());
+        assert_eq!(CanonicalTree::from_bytes(&bytes).unwrap(), tree);
+        assert!(CanonicalTree::<u8>::from_bytes(&bytes).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decode_with() {
+        let text = SMALL_STR.repeat(1000);
+        let (_bytes_read, tree) = CanonicalTree::from_read(Cursor::new(&text)).unwrap();
+
+        let mut encoded = Vec::new();
+        tree.encode(&mut Cursor::new(&text), &mut encoded).unwrap();
+
+        let mut chunks = 0;
+        let mut decoded = Vec::new();
+        tree.decode_with(&mut Cursor::new(&encoded), |chunk| {
+            chunks += 1;
+            decoded.extend_from_slice(chunk);
+            Ok(())
+        }).unwrap();
+
+        assert!(chunks > 1);
+        assert!(decoded.starts_with(text.as_bytes()));
+
+        // Errors from the closure stop decoding
+        let result = tree.decode_with(&mut Cursor::new(&encoded), |_chunk| Err(HuffmanError::InvalidInput("Stop".to_string())));
+        assert!(matches!(result, Err(HuffmanError::InvalidInput(ref message)) if message == "Stop"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decode_until() {
+        let records: &[u8] = b"first;second;;third;";
+        let (_bytes_read, tree) = CanonicalTree::from_read(Cursor::new(records)).unwrap();
+
+        let mut encoded = Vec::new();
+        tree.encode(&mut Cursor::new(records), &mut encoded).unwrap();
+
+        let mut bit_reader = BitReader::new(Cursor::new(&encoded));
+        let mut decode_record = || {
+            let mut record = Vec::new();
+            tree.decode_until(&mut bit_reader, &mut record, b';').map(|_bytes| record)
+        };
+
+        assert_eq!(decode_record().unwrap(), b"first");
+        assert_eq!(decode_record().unwrap(), b"second");
+        assert_eq!(decode_record().unwrap(), b"");
+        assert_eq!(decode_record().unwrap(), b"third");
+
+        // The data ends in the middle of the first record
+        let mut bit_reader = BitReader::new(Cursor::new(&encoded[..1]));
+        assert!(tree.decode_until(&mut bit_reader, &mut Vec::new(), b';').is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decode_batch() {
+        let (_bytes_read, tree) = CanonicalTree::from_read(Cursor::new(SMALL_STR)).unwrap();
+
+        let messages: Vec<Vec<u8>> = (0..50)
+            .map(|i| SMALL_STR.as_bytes()[i % 10..].to_vec())
+            .collect();
+
+        let payloads: Vec<(Vec<u8>, u64)> = messages.iter()
+            .map(|message| {
+                let mut encoded = Vec::new();
+                tree.encode(&mut Cursor::new(message), &mut encoded).unwrap();
+                (encoded, message.len() as u64)
+            })
+            .collect();
+
+        let results = tree.decode_batch(&payloads);
+        assert_eq!(results.len(), messages.len());
+
+        for (result, message) in results.into_iter().zip(messages.iter()) {
+            assert_eq!(&result.unwrap(), message);
+        }
+
+        // Errors are returned for their own payload
+        let results = tree.decode_batch(&[(&payloads[0].0[..], payloads[0].1), (&[][..], 1)]);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(HuffmanError::Decode(_))));
+
+        // A tree shared between threads
+        let tree = Arc::new(tree);
+        let handles: Vec<_> = payloads.into_iter()
+            .take(4)
+            .map(|(encoded, bytes)| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    let mut decoded = Vec::new();
+                    tree.decode_exact(&mut Cursor::new(encoded), &mut decoded, bytes).unwrap();
+                    decoded
+                })
+            })
+            .collect();
+
+        for (handle, message) in handles.into_iter().zip(messages.iter()) {
+            assert_eq!(&handle.join().unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_kraft_validation() {
+        // Complete and under-subscribed code lengths are fine
+        assert!(CanonicalTree::<u8>::new(vec![(0, 1), (1, 2), (2, 2)]).is_ok());
+        assert!(CanonicalTree::<u8>::new(vec![(0, 1), (1, 2), (2, 0)]).is_ok());
+        assert!(CanonicalTree::<u8>::new(vec![(0, 64), (1, 64), (2, 1)]).is_ok());
+
+        // Over-subscribed
+        assert!(CanonicalTree::<u8>::new(vec![(0, 1), (1, 1), (2, 1)]).is_err());
+        assert!(CanonicalTree::<u8>::new(vec![(0, 1), (1, 2), (2, 2), (3, 64)]).is_err());
+
+        // Too long and duplicated
+        assert!(CanonicalTree::<u8>::new(vec![(0, 65), (1, 1)]).is_err());
+        assert!(CanonicalTree::<u8>::new(vec![(0, 1), (0, 1)]).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_long_codes() {
+        // Fibonacci frequencies give codes longer than the decode table
+        let mut text = Vec::new();
+        let (mut a, mut b) = (1, 1);
+
+        for symbol in 0..20 {
+            for _ in 0..a {
+                text.push(symbol);
+            }
+
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+
+        assert!(encode_decode_test(&text));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_encode_slice() {
+        let short = CanonicalTree::from_slice(SMALL_STR.as_bytes()).unwrap();
+        let long: CanonicalTree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 20), (b'd', 21), (b'e', 21)]).unwrap();
+
+        for (tree, data) in [(short, SMALL_STR.as_bytes()), (long, &b"abacabadeabbbacaaaba"[..])] {
+            for length in 0..data.len() {
+                let mut expected = Vec::new();
+                tree.encode_symbols(&data[..length], &mut expected).unwrap();
+
+                let mut encoded = Vec::new();
+                tree.encode_slice(&data[..length], &mut BitWriter::new(&mut encoded)).unwrap();
+                assert_eq!(encoded, expected);
+            }
+
+            assert!(tree.encode_slice(b"abcdz", &mut BitWriter::new(Vec::new())).is_err());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_slice_coding() {
+        let tree: CanonicalTree<u16> = CanonicalTree::new(vec![(1, 1), (2, 2), (300, 20), (4000, 21), (50000, 21)]).unwrap();
+        let symbols = [1, 2, 1, 300, 1, 2, 4000, 50000, 2, 2, 1];
+
+        let mut expected = Vec::new();
+        tree.encode_symbols(&symbols, &mut expected).unwrap();
+
+        let mut buf = [0; 16];
+        let mut bit_writer = SliceBitWriter::new(&mut buf);
+        tree.encode_to_slice(&symbols, &mut bit_writer).unwrap();
+        let bits = bit_writer.bits_written() as u64;
+        let encoded = bit_writer.into_written();
+        assert_eq!(encoded, &expected[..]);
+
+        let mut decoded = [0; 11];
+        assert_eq!(tree.decode_from_slice(encoded, &mut decoded).unwrap(), bits);
+        assert_eq!(decoded, symbols);
+
+        // Too little room, too little data, and symbols without a code
+        assert!(tree.encode_to_slice(&symbols, &mut SliceBitWriter::new(&mut [0; 8])).is_err());
+        match tree.decode_from_slice(&encoded[..8], &mut decoded) {
+            Err(HuffmanError::Decode(err)) => assert_eq!(err.kind, DecodeErrorKind::UnexpectedEnd),
+            result => panic!("Expected the data to end, got {:?}", result),
+        }
+        assert!(matches!(tree.encode_to_slice(&[1, 3], &mut SliceBitWriter::new(&mut buf)),
+            Err(HuffmanError::SymbolNotInCodebook { symbol: 3, offset: 1 })));
+    }
+
+    #[test]
+    fn test_slice_coding_from_frequencies() {
+        // Builds and codes with fixed buffers alone, so it runs without std
+        let data = b"abracadabra, abracadabra";
+
+        let mut freq_table = FrequencyTable::new();
+        freq_table.count(data);
+        let tree = CanonicalTree::from_frequencies(&freq_table).unwrap();
+
+        let mut buf = [0; 16];
+        let mut bit_writer = SliceBitWriter::new(&mut buf);
+        tree.encode_to_slice(data, &mut bit_writer).unwrap();
+        let bits = bit_writer.bits_written() as u64;
+        let encoded = bit_writer.into_written();
+        assert_eq!(encoded.len() as u64, bits.div_ceil(8));
+
+        let mut decoded = [0; 24];
+        assert_eq!(tree.decode_from_slice(encoded, &mut decoded).unwrap(), bits);
+        assert_eq!(&decoded, data);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_read_limited() {
+        // 12 distinct symbols
+        let (_bytes, tree) = CanonicalTree::from_read_limited(Cursor::new(SMALL_STR), 4).unwrap();
+
+        assert!(tree.code_lengths().iter().all(|&length| length <= 4));
+
+        assert!(CanonicalTree::from_read_limited(Cursor::new(SMALL_STR), 3).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_codes_longer_than_64_bits() {
+        // Fibonacci frequencies over 70 symbols give a huffman tree 69 levels deep
+        let mut freq_table = [0; NUM_BYTES];
+        let (mut a, mut b): (u64, u64) = (1, 1);
+
+        for frequency in freq_table.iter_mut().take(70) {
+            *frequency = a;
+
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+
+        let huff_tree = HuffmanTree::new(&freq_table).unwrap();
+        assert!(huff_tree.get_code_lengths().iter().any(|&(_symbol, length)| length > MAX_CODE_LENGTH));
+
+        let tree = CanonicalTree::from_frequencies(&freq_table).unwrap();
+        assert_eq!(tree.code_lengths().iter().max(), Some(&MAX_CODE_LENGTH));
+
+        let text = [0, 1, 69, 5, 68, 0, 0, 1];
+        let mut encoded = Vec::new();
+        tree.encode(&mut Cursor::new(&text[..]), &mut encoded).unwrap();
+
+        let mut decoded = Vec::new();
+        tree.decode_exact(&mut Cursor::new(encoded), &mut decoded, text.len() as u64).unwrap();
+
+        assert_eq!(decoded, text);
+    }
+
+    #[cfg(feature = "std")]
     fn encode_decode_test(text: &[u8]) -> bool {
         let mut encoded_cursor = Cursor::new(text);
         let (_bytes_read, tree) = CanonicalTree::from_read(&mut encoded_cursor).unwrap();
@@ -0,0 +1,116 @@
+//! CRC-32 checksums, with the polynomial used by zlib and gzip.
+//!
+//! The checksum is updated a chunk at a time through a 256 entry table, so it can follow data
+//! as it is written instead of taking a second pass over it.
+
+use std::io;
+use std::io::Write;
+
+const CRC_POLYNOMIAL: u32 = 0xedb8_8320;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = (crc >> 1) ^ (CRC_POLYNOMIAL & (crc & 1).wrapping_neg());
+            bit += 1;
+        }
+
+        table[byte] = crc;
+        byte += 1;
+    }
+
+    table
+}
+
+/// A CRC-32 of data seen a chunk at a time.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub(crate) fn new() -> Crc32 {
+        Crc32 {
+            crc: !0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.crc = (self.crc >> 8) ^ CRC_TABLE[((self.crc ^ byte as u32) & 0xff) as usize];
+        }
+    }
+
+    /// The checksum of everything seen so far.
+    pub(crate) fn value(&self) -> u32 {
+        !self.crc
+    }
+}
+
+/// CRC-32 of `bytes`.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+
+    crc.value()
+}
+
+/// Passes bytes on to a `Write`, keeping the CRC-32 of those written.
+pub(crate) struct ChecksumWriter<W> {
+    write: W,
+    crc: Crc32,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub(crate) fn new(write: W) -> ChecksumWriter<W> {
+        ChecksumWriter {
+            write,
+            crc: Crc32::new(),
+        }
+    }
+
+    pub(crate) fn checksum(&self) -> u32 {
+        self.crc.value()
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.write.write(buf)?;
+        self.crc.update(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        // The same checksum however the data is split
+        let text = b"the quick brown fox jumps over the lazy dog".repeat(10);
+        let mut write = ChecksumWriter::new(Vec::new());
+        for chunk in text.chunks(7) {
+            write.write_all(chunk).unwrap();
+        }
+        assert_eq!(write.checksum(), crc32(&text));
+        assert_eq!(write.write, text);
+    }
+}
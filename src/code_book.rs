@@ -0,0 +1,337 @@
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+use core::fmt;
+use core::str::FromStr;
+use core::iter::FromIterator;
+use core::marker::PhantomData;
+use core::convert::TryInto;
+use core::result::Result;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+use super::*;
+
+/// The longest code that can be represented.
+pub const MAX_CODE_LENGTH: u8 = 64;
+
+/// A huffman code packed into the low `length` bits of an integer.
+///
+/// The first bit of the code is the most significant of those bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Code {
+    bits: u64,
+    length: u8,
+}
+
+impl Code {
+    /// A code of the low `length` bits of `bits`. Any bits of a code longer than
+    /// `MAX_CODE_LENGTH` past the 64 held are zero.
+    pub fn new(bits: u64, length: u8) -> Code {
+        Code { bits, length }
+    }
+
+    /// The code right aligned in a `u64`.
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    /// The number of bits in the code.
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    /// The code left aligned in a `u64`, so the first bit of the code is the most significant bit.
+    #[inline]
+    pub fn left_aligned(&self) -> u64 {
+        self.bits.checked_shl(64u32.saturating_sub(self.length as u32)).unwrap_or(0)
+    }
+
+    /// The code with the order of its bits reversed, for writing to streams that are packed
+    /// least significant bit first such as DEFLATE.
+    pub fn reversed(&self) -> Code {
+        if self.length == 0 {
+            return *self;
+        }
+
+        Code::new(self.bits.reverse_bits() >> 64u32.saturating_sub(self.length as u32), self.length)
+    }
+
+    /// Whether this code is a prefix of `other`, including being equal to it.
+    pub fn is_prefix_of(&self, other: &Code) -> bool {
+        self.length <= other.length && other.bits.checked_shr((other.length - self.length) as u32).unwrap_or(0) == self.bits
+    }
+}
+
+/// Shows the bits of the code, first bit first, such as `0110`.
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in (0..self.length as u32).rev() {
+            write!(f, "{}", self.bits.checked_shr(i).unwrap_or(0) & 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the bits of a code written as a string of `0` and `1`, first bit first.
+impl FromStr for Code {
+    type Err = HuffmanError;
+
+    fn from_str(bits: &str) -> Result<Code, HuffmanError> {
+        if bits.is_empty() || bits.len() > MAX_CODE_LENGTH as usize {
+            return Err(HuffmanError::InvalidInput(format!("Code '{}' must be between 1 and {} bits", bits, MAX_CODE_LENGTH)));
+        }
+
+        let mut code = 0;
+
+        for bit in bits.chars() {
+            code = match bit {
+                '0' => code << 1,
+                '1' => (code << 1) | 1,
+                _ => return Err(HuffmanError::InvalidInput(format!("Code '{}' has a character other than 0 or 1", bits))),
+            };
+        }
+
+        Ok(Code::new(code, bits.len() as u8))
+    }
+}
+
+/// Code for each byte, indexed by the byte itself.
+pub type CodeTable = [Option<Code>; NUM_BYTES];
+
+/// The code assigned to each symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBook<S = u8> {
+    codes: Vec<Option<Code>>,
+    symbol: PhantomData<S>,
+}
+
+impl<S: Symbol> CodeBook<S> {
+    /// Create an empty `CodeBook`.
+    pub fn new() -> CodeBook<S> {
+        CodeBook { codes: vec![None; S::ALPHABET_SIZE], symbol: PhantomData }
+    }
+
+    /// Get the code for `symbol`.
+    pub fn get(&self, symbol: S) -> Option<Code> {
+        self.codes[symbol.to_index()]
+    }
+
+    /// Set the code for `symbol`, returning the previous code.
+    pub fn insert(&mut self, symbol: S, code: Code) -> Option<Code> {
+        self.codes[symbol.to_index()].replace(code)
+    }
+
+    /// Remove the code for `symbol`, returning it.
+    pub fn remove(&mut self, symbol: S) -> Option<Code> {
+        self.codes[symbol.to_index()].take()
+    }
+
+    /// Number of symbols with a code.
+    pub fn len(&self) -> usize {
+        self.codes.iter().filter(|code| code.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.iter().all(|code| code.is_none())
+    }
+
+    /// Iterate over each symbol with its code, in increasing order of symbol.
+    pub fn iter(&self) -> impl Iterator<Item = (S, Code)> + '_ {
+        self.codes.iter().enumerate()
+            .filter_map(|(symbol, code)| code.map(|code| (S::from_index(symbol), code)))
+    }
+
+    /// Check that every code is between 1 and `MAX_CODE_LENGTH` bits and that no code is a
+    /// prefix of another, so the codes can be decoded unambiguously.
+    pub fn validate(&self) -> Result<(), HuffmanError> {
+        for (symbol, code) in self.iter() {
+            if code.length() == 0 || code.length() > MAX_CODE_LENGTH {
+                return Err(HuffmanError::InvalidInput(format!("Code for symbol {} has invalid length {}", symbol, code.length())));
+            }
+
+            if code.length() < 64 && code.bits() >> code.length() != 0 {
+                return Err(HuffmanError::InvalidInput(format!("Code for symbol {} has bits outside its length", symbol)));
+            }
+        }
+
+        // A code that is a prefix of another sorts directly before a code it is a prefix of
+        let mut sorted: Vec<(S, Code)> = self.iter().collect();
+        sorted.sort_by_key(|&(_symbol, code)| (code.left_aligned(), code.length()));
+
+        for pair in sorted.windows(2) {
+            if pair[0].1.is_prefix_of(&pair[1].1) {
+                return Err(HuffmanError::InvalidInput(format!("Code for symbol {} is a prefix of the code for symbol {}",
+                    pair[0].0, pair[1].0)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert into a map from symbol to code.
+    #[cfg(feature = "std")]
+    pub fn to_map(&self) -> HashMap<S, Code> {
+        self.iter().collect()
+    }
+
+    /// Each symbol with its code written as a string of `0` and `1`, in increasing order of
+    /// symbol.
+    pub fn to_bit_strings(&self) -> Vec<(S, String)> {
+        self.iter().map(|(symbol, code)| (symbol, code.to_string())).collect()
+    }
+
+    /// Build a code book from symbols with their codes written as strings of `0` and `1`.
+    pub fn from_bit_strings<'a, I: IntoIterator<Item = (S, &'a str)>>(codes: I) -> Result<CodeBook<S>, HuffmanError> {
+        codes.into_iter()
+            .map(|(symbol, bits)| Ok((symbol, bits.parse()?)))
+            .collect()
+    }
+}
+
+impl CodeBook<u8> {
+    /// The codes indexed by symbol.
+    pub fn as_table(&self) -> &CodeTable {
+        self.codes[..].try_into().expect("Byte code book has a code for every byte")
+    }
+}
+
+impl<S: Symbol> Default for CodeBook<S> {
+    fn default() -> CodeBook<S> {
+        CodeBook::new()
+    }
+}
+
+impl From<CodeTable> for CodeBook<u8> {
+    fn from(codes: CodeTable) -> CodeBook<u8> {
+        CodeBook { codes: codes.to_vec(), symbol: PhantomData }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: Symbol> From<HashMap<S, Code>> for CodeBook<S> {
+    fn from(map: HashMap<S, Code>) -> CodeBook<S> {
+        map.into_iter().collect()
+    }
+}
+
+impl<S: Symbol> FromIterator<(S, Code)> for CodeBook<S> {
+    fn from_iter<I: IntoIterator<Item = (S, Code)>>(iter: I) -> CodeBook<S> {
+        let mut code_book = CodeBook::new();
+
+        for (symbol, code) in iter {
+            code_book.insert(symbol, code);
+        }
+
+        code_book
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reversed() {
+        assert_eq!(Code::new(0b1101, 4).reversed(), Code::new(0b1011, 4));
+        assert_eq!(Code::new(0b001, 3).reversed(), Code::new(0b100, 3));
+        assert_eq!(Code::new(1 << 63, 64).reversed(), Code::new(1, 64));
+        assert_eq!(Code::new(0, 0).reversed(), Code::new(0, 0));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_edge_lengths() {
+        assert_eq!(Code::new(0, 0).left_aligned(), 0);
+        assert_eq!(Code::new(1, 64).left_aligned(), 1);
+        assert!(Code::new(0, 0).is_prefix_of(&Code::new(u64::MAX, 64)));
+        assert_eq!(Code::new(0, 0).to_string(), "");
+
+        // Bits past the 64 held are zero
+        let long = Code::new(1, 66);
+        assert_eq!(long.to_string(), format!("{}1", "0".repeat(65)));
+
+        let mut written = Vec::new();
+        BitWriter::new(&mut written).write_code(long).unwrap();
+        assert_eq!(written, [0, 0, 0, 0, 0, 0, 0, 0, 0b0100_0000]);
+    }
+
+    #[test]
+    fn test_bit_strings() {
+        assert_eq!(Code::new(0b0110, 4).to_string(), "0110");
+        assert_eq!("0110".parse::<Code>().unwrap(), Code::new(0b0110, 4));
+        assert!("".parse::<Code>().is_err());
+        assert!("012".parse::<Code>().is_err());
+        assert!("0".repeat(65).parse::<Code>().is_err());
+
+        let code_book = CodeBook::from_bit_strings(vec![(b'a', "0"), (b'b', "10"), (b'c', "11")]).unwrap();
+        assert_eq!(code_book.get(b'b'), Some(Code::new(0b10, 2)));
+        assert_eq!(code_book.to_bit_strings(), vec![
+            (b'a', "0".to_string()),
+            (b'b', "10".to_string()),
+            (b'c', "11".to_string()),
+        ]);
+
+        assert!(CodeBook::from_bit_strings(vec![(b'a', "0"), (b'b', "1x")]).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_code_book() {
+        let mut code_book = CodeBook::new();
+        assert!(code_book.is_empty());
+
+        assert_eq!(code_book.insert(b'b', Code::new(0b1, 1)), None);
+        assert_eq!(code_book.insert(b'a', Code::new(0b0, 1)), None);
+        assert_eq!(code_book.insert(b'a', Code::new(0b00, 2)), Some(Code::new(0b0, 1)));
+
+        assert_eq!(code_book.len(), 2);
+        assert_eq!(code_book.get(b'a'), Some(Code::new(0b00, 2)));
+        assert_eq!(code_book.get(b'c'), None);
+        assert_eq!(code_book.iter().collect::<Vec<_>>(),
+            vec![(b'a', Code::new(0b00, 2)), (b'b', Code::new(0b1, 1))]);
+
+        assert_eq!(CodeBook::from(code_book.to_map()), code_book);
+        assert_eq!(CodeBook::from(*code_book.as_table()), code_book);
+
+        assert_eq!(code_book.remove(b'b'), Some(Code::new(0b1, 1)));
+        assert_eq!(code_book.len(), 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut code_book: CodeBook = vec![
+            (b'a', Code::new(0b0, 1)),
+            (b'b', Code::new(0b10, 2)),
+            (b'c', Code::new(0b111, 3)),
+        ].into_iter().collect();
+        assert!(code_book.validate().is_ok());
+
+        // 11 is a prefix of 111
+        code_book.insert(b'd', Code::new(0b11, 2));
+        assert!(code_book.validate().is_err());
+
+        // Bits outside of the length
+        code_book.insert(b'd', Code::new(0b1100, 3));
+        assert!(code_book.validate().is_err());
+
+        code_book.insert(b'd', Code::new(0b110, 3));
+        assert!(code_book.validate().is_ok());
+
+        code_book.insert(b'd', Code::new(0, 0));
+        assert!(code_book.validate().is_err());
+    }
+
+    #[test]
+    fn test_wide_code_book() {
+        let mut code_book = CodeBook::new();
+        code_book.insert(1000u16, Code::new(0b0, 1));
+        code_book.insert(65535u16, Code::new(0b1, 1));
+
+        assert_eq!(code_book.get(65535), Some(Code::new(0b1, 1)));
+        assert_eq!(code_book.iter().map(|(symbol, _code)| symbol).collect::<Vec<_>>(), vec![1000, 65535]);
+        assert!(code_book.validate().is_ok());
+    }
+}
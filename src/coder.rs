@@ -0,0 +1,297 @@
+//! A common interface to the ways bytes can be coded, so the framing around the coded bytes is
+//! written once.
+//!
+//! An `EntropyCoder` builds its model from the frequencies of the data, writes the model as a
+//! header, and then codes one byte at a time to a `BitWriter`. `encode_payload` and
+//! `decode_payload` run a coder over a whole stream, and are what `Encoder` and `Decoder` use for
+//! every `Backend`.
+
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+use std::sync::Arc;
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use super::*;
+
+/// A model for coding bytes, along with any state kept while coding.
+///
+/// A coder is used either to encode or to decode a single stream. The coder that encodes and the
+/// one that decodes must see the same bytes in the same order.
+pub trait EntropyCoder: Sized {
+    /// Build the model for data with `frequencies`.
+    fn from_frequencies(frequencies: &FrequencyTable) -> Result<Self, Box<dyn Error>>;
+
+    /// Write what `read_header` needs to rebuild the model.
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>>;
+
+    /// Read a model written by `write_header`.
+    fn read_header<R: Read>(read: &mut R) -> Result<Self, Box<dyn Error>>;
+
+    /// Encode `byte`, which must be in the model.
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>>;
+
+    /// Encode each of `bytes` in turn. Calls `encode_symbol` for each one by default.
+    fn encode_bytes<W: Write>(&mut self, bytes: &[u8], bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        for &byte in bytes {
+            self.encode_symbol(byte, bit_writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write anything held back after the last byte. Does nothing by default.
+    fn finish<W: Write>(&mut self, _bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// Bytes of memory held by the tables of the model, which `DecodeLimits` bounds.
+    fn table_memory(&self) -> usize;
+
+    /// Decode the next byte, failing with a `DecodeError` if the data is invalid or ends early.
+    ///
+    /// `symbols_left` counts this byte and those still to be decoded after it.
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, symbols_left: u64) -> Result<u8, Box<dyn Error>>;
+}
+
+/// Static Huffman coding, with the code lengths as the header.
+impl EntropyCoder for CanonicalTree {
+    fn table_memory(&self) -> usize {
+        self.decode_table().memory()
+    }
+
+    fn from_frequencies(frequencies: &FrequencyTable) -> Result<CanonicalTree, Box<dyn Error>> {
+        Ok(CanonicalTree::from_frequencies(frequencies)?)
+    }
+
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>> {
+        Ok(write.write_all(&self.code_lengths())?)
+    }
+
+    fn read_header<R: Read>(read: &mut R) -> Result<CanonicalTree, Box<dyn Error>> {
+        let mut code_buf = [0; NUM_BYTES];
+        read.read_exact(&mut code_buf)?;
+
+        Ok(CanonicalTree::from_code_lengths(&code_buf)?)
+    }
+
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(CanonicalTree::encode_symbol(self, byte, bit_writer)?)
+    }
+
+    fn encode_bytes<W: Write>(&mut self, bytes: &[u8], bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(self.encode_slice(bytes, bit_writer)?)
+    }
+
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, _symbols_left: u64) -> Result<u8, Box<dyn Error>> {
+        let decode_table = self.decode_table();
+
+        match decode_table.decode_checked(bit_reader, symbols_decoded)? {
+            Some(byte) => Ok(byte),
+            None => Err(decode_table.unexpected_end(bit_reader, symbols_decoded).into()),
+        }
+    }
+}
+
+/// Static Huffman coding with a tree shared with other coders, such as one from a `TreeCache`.
+impl EntropyCoder for Arc<CanonicalTree> {
+    fn table_memory(&self) -> usize {
+        self.decode_table().memory()
+    }
+
+    fn from_frequencies(frequencies: &FrequencyTable) -> Result<Arc<CanonicalTree>, Box<dyn Error>> {
+        Ok(Arc::new(CanonicalTree::from_frequencies(frequencies)?))
+    }
+
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>> {
+        Ok(write.write_all(&self.code_lengths())?)
+    }
+
+    fn read_header<R: Read>(read: &mut R) -> Result<Arc<CanonicalTree>, Box<dyn Error>> {
+        Ok(Arc::new(<CanonicalTree as EntropyCoder>::read_header(read)?))
+    }
+
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(CanonicalTree::encode_symbol(self, byte, bit_writer)?)
+    }
+
+    fn encode_bytes<W: Write>(&mut self, bytes: &[u8], bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(self.encode_slice(bytes, bit_writer)?)
+    }
+
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, _symbols_left: u64) -> Result<u8, Box<dyn Error>> {
+        let decode_table = self.decode_table();
+
+        match decode_table.decode_checked(bit_reader, symbols_decoded)? {
+            Some(byte) => Ok(byte),
+            None => Err(decode_table.unexpected_end(bit_reader, symbols_decoded).into()),
+        }
+    }
+}
+
+/// Adaptive Huffman coding, with the algorithm as the header. The frequencies are not used, as
+/// the tree is learned from the bytes as they are coded.
+impl EntropyCoder for AdaptiveTree {
+    fn table_memory(&self) -> usize {
+        self.memory()
+    }
+
+    fn from_frequencies(_frequencies: &FrequencyTable) -> Result<AdaptiveTree, Box<dyn Error>> {
+        Ok(AdaptiveTree::new())
+    }
+
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>> {
+        let algorithm = match self.algorithm() {
+            AdaptiveAlgorithm::Fgk => 0,
+            AdaptiveAlgorithm::Vitter => 1,
+        };
+
+        Ok(write.write_u8(algorithm)?)
+    }
+
+    fn read_header<R: Read>(read: &mut R) -> Result<AdaptiveTree, Box<dyn Error>> {
+        let algorithm = match read.read_u8()? {
+            0 => AdaptiveAlgorithm::Fgk,
+            1 => AdaptiveAlgorithm::Vitter,
+            algorithm => return Err(From::from(format!("Unknown adaptive algorithm {}", algorithm))),
+        };
+
+        Ok(AdaptiveTree::with_algorithm(algorithm))
+    }
+
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(self.encode_byte(byte, bit_writer)?)
+    }
+
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, _symbols_left: u64) -> Result<u8, Box<dyn Error>> {
+        let bit_offset = bit_reader.position();
+
+        match self.decode_byte(bit_reader, symbols_decoded)? {
+            Some(byte) => Ok(byte),
+            // The size is stored instead, so an end marker is never written
+            None => Err(Box::new(DecodeError {
+                kind: DecodeErrorKind::InvalidCode,
+                bit_offset,
+                symbols_decoded,
+                partial_code: Code::new(0, 0),
+            })),
+        }
+    }
+}
+
+/// Encode every byte of `read` with `coder`, which must have been built from frequencies that
+/// cover them. The header is not written.
+pub fn encode_payload<C: EntropyCoder, R: Read, W: Write>(coder: &mut C, read: &mut R, write: &mut W) -> Result<(), Box<dyn Error>> {
+    let mut bit_writer = BitWriter::new(write);
+    let mut buf = [0; READ_BUFFER_SIZE];
+    let mut bytes_encoded = 0;
+
+    loop {
+        let bytes_read = read_some(read, &mut buf)?;
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        coder.encode_bytes(&buf[..bytes_read], &mut bit_writer).map_err(|mut err| {
+            if let Some(err) = err.downcast_mut::<HuffmanError>() {
+                err.add_offset(bytes_encoded);
+            }
+
+            err
+        })?;
+        bytes_encoded += bytes_read as u64;
+    }
+
+    coder.finish(&mut bit_writer)
+}
+
+/// Decode `bytes` bytes with `coder`, writing them to `write`.
+///
+/// Everything decoded before an error is written. If `strict`, fails if anything other than zero
+/// padding follows the coded bytes.
+pub fn decode_payload<C: EntropyCoder, R: Read, W: Write>(coder: &mut C, read: &mut R, write: &mut W, bytes: u64, strict: bool) -> Result<(), Box<dyn Error>> {
+    decode_payload_trailing(coder, read, write, bytes, Trailing::new(strict, false))?;
+
+    Ok(())
+}
+
+/// Like `decode_payload`, dealing with what follows the coded bytes as `trailing` says and
+/// returning the number of bytes skipped.
+pub(crate) fn decode_payload_trailing<C: EntropyCoder, R: Read, W: Write>(coder: &mut C, read: &mut R, write: &mut W, bytes: u64, trailing: Trailing) -> Result<u64, Box<dyn Error>> {
+    let mut bit_reader = BitReader::new(read);
+    let mut buf = Vec::with_capacity(READ_BUFFER_SIZE);
+
+    for bytes_decoded in 0..bytes {
+        let byte = match coder.decode_symbol(&mut bit_reader, bytes_decoded, bytes - bytes_decoded) {
+            Ok(byte) => byte,
+            Err(err) => {
+                // Output everything decoded before the error
+                write.write_all(&buf)?;
+                return Err(err);
+            },
+        };
+
+        buf.push(byte);
+
+        if buf.len() == READ_BUFFER_SIZE {
+            write.write_all(&buf)?;
+            buf.clear();
+        }
+    }
+
+    write.write_all(&buf)?;
+
+    Ok(finish_trailing(&mut bit_reader, trailing)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip<C: EntropyCoder>(data: &[u8]) -> Vec<u8> {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(data);
+
+        let mut encoded = Vec::new();
+        let mut coder = C::from_frequencies(&frequencies).unwrap();
+        coder.write_header(&mut encoded).unwrap();
+        encode_payload(&mut coder, &mut Cursor::new(data), &mut encoded).unwrap();
+
+        let mut read = Cursor::new(&encoded);
+        let mut coder = C::read_header(&mut read).unwrap();
+        let mut decoded = Vec::new();
+        decode_payload(&mut coder, &mut read, &mut decoded, data.len() as u64, true).unwrap();
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    fn check_coder<C: EntropyCoder>() {
+        round_trip::<C>(b"a");
+        round_trip::<C>(b"abracadabra");
+
+        let text = "the quick brown fox jumps over the lazy dog".repeat(2000);
+        let encoded = round_trip::<C>(text.as_bytes());
+        assert!(encoded.len() < text.len() * 3 / 4);
+
+        // Data cut short keeps what was decoded before the end
+        let mut read = Cursor::new(&encoded[..encoded.len() / 2]);
+        let mut coder = C::read_header(&mut read).unwrap();
+        let mut decoded = Vec::new();
+        let err = decode_payload(&mut coder, &mut read, &mut decoded, text.len() as u64, false).unwrap_err();
+        assert!(matches!(HuffmanError::from(err), HuffmanError::Decode(DecodeError { kind: DecodeErrorKind::UnexpectedEnd, .. })));
+        assert!(text.as_bytes().starts_with(&decoded));
+    }
+
+    #[test]
+    fn test_coders() {
+        check_coder::<CanonicalTree>();
+        check_coder::<AdaptiveTree>();
+        check_coder::<ArithmeticCoder>();
+        check_coder::<RangeCoder>();
+        check_coder::<TansCoder>();
+    }
+}
@@ -0,0 +1,257 @@
+//! Order-1 context modeling, coding each byte with a table chosen by the byte before it.
+//!
+//! In text and structured data the previous byte says a lot about the next: `q` is followed by
+//! `u`, and a quote by a letter. A table for each previous byte captures this, at the cost of
+//! storing up to 256 tables. Contexts seen too rarely to pay for their own table share a fallback
+//! table built from all of their bytes. The first byte is coded as if it followed byte 0.
+//!
+//! The header is a bitmap of the contexts with their own table, one bit per context with the
+//! lowest context in the low bit of the first byte, then a byte that is 1 if there is a fallback
+//! table. Each table follows in order of context, with the fallback last, written by
+//! `CanonicalTree::write_shape`.
+
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use super::*;
+
+/// Fewest bytes a context must have to get its own table.
+pub const MIN_CONTEXT_BYTES: u64 = 256;
+
+/// A canonical table for each previous byte, with a shared fallback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextModel {
+    /// Table used after each byte, or `None` to use `fallback`
+    tables: Vec<Option<CanonicalTree>>,
+    fallback: Option<CanonicalTree>,
+    /// Byte before the next one to be coded
+    previous: u8,
+}
+
+impl ContextModel {
+    /// Build the tables from the frequencies of the bytes following each byte, as counted by
+    /// `context_frequencies`.
+    pub fn from_context_frequencies(frequencies: &[FrequencyTable]) -> Result<ContextModel, Box<dyn Error>> {
+        if frequencies.len() != NUM_BYTES {
+            return Err(From::from(format!("Expected {} contexts but got {}", NUM_BYTES, frequencies.len())));
+        }
+
+        let mut tables = Vec::with_capacity(NUM_BYTES);
+        let mut shared = FrequencyTable::new();
+
+        for context in frequencies {
+            if context.total() >= MIN_CONTEXT_BYTES {
+                tables.push(Some(CanonicalTree::from_frequencies(context)?));
+            } else {
+                tables.push(None);
+                shared.merge(context);
+            }
+        }
+
+        let fallback = if shared.is_empty() {
+            None
+        } else {
+            Some(CanonicalTree::from_frequencies(&shared)?)
+        };
+
+        Ok(ContextModel {tables, fallback, previous: 0})
+    }
+
+    /// Count the bytes of a `Read` and build the tables from them.
+    pub fn from_read<R: Read>(read: R) -> Result<(u64, ContextModel), Box<dyn Error>> {
+        let (bytes_read, frequencies) = context_frequencies(read)?;
+
+        Ok((bytes_read, ContextModel::from_context_frequencies(&frequencies)?))
+    }
+
+    /// The table used to code the byte after `previous`.
+    pub fn table(&self, previous: u8) -> Option<&CanonicalTree> {
+        self.tables[previous as usize].as_ref().or(self.fallback.as_ref())
+    }
+
+    /// Number of contexts with their own table.
+    pub fn context_tables(&self) -> usize {
+        self.tables.iter().filter(|table| table.is_some()).count()
+    }
+
+    /// Bytes of memory held by the decode tables of every context.
+    pub fn memory(&self) -> usize {
+        self.tables.iter().chain(Some(&self.fallback)).flatten()
+            .map(|table| table.decode_table().memory())
+            .sum()
+    }
+}
+
+/// Count the frequencies of the bytes following each byte, indexed by the previous byte.
+///
+/// Returns the number of bytes read along with the frequencies.
+pub fn context_frequencies<R: Read>(mut read: R) -> Result<(u64, Vec<FrequencyTable>), Box<dyn Error>> {
+    let mut frequencies = vec![FrequencyTable::new(); NUM_BYTES];
+    let mut buf = [0; READ_BUFFER_SIZE];
+    let mut bytes_read = 0;
+    let mut previous = 0;
+
+    loop {
+        let read_size = read_some(&mut read, &mut buf)?;
+
+        if read_size == 0 {
+            return Ok((bytes_read, frequencies));
+        }
+
+        for &byte in buf[..read_size].iter() {
+            frequencies[previous as usize].add_symbol(byte);
+            previous = byte;
+        }
+
+        bytes_read += read_size as u64;
+    }
+}
+
+/// Order-1 coding with the tables stored as described in the module documentation. Built from
+/// order-0 frequencies, every context uses the fallback table.
+impl EntropyCoder for ContextModel {
+    fn table_memory(&self) -> usize {
+        self.memory()
+    }
+
+    fn from_frequencies(frequencies: &FrequencyTable) -> Result<ContextModel, Box<dyn Error>> {
+        Ok(ContextModel {
+            tables: vec![None; NUM_BYTES],
+            fallback: Some(CanonicalTree::from_frequencies(frequencies)?),
+            previous: 0,
+        })
+    }
+
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>> {
+        let mut bitmap = [0u8; NUM_BYTES / 8];
+
+        for (context, table) in self.tables.iter().enumerate() {
+            if table.is_some() {
+                bitmap[context / 8] |= 1 << (context % 8);
+            }
+        }
+
+        write.write_all(&bitmap)?;
+        write.write_u8(self.fallback.is_some() as u8)?;
+
+        for table in self.tables.iter().chain(Some(&self.fallback)).flatten() {
+            table.write_shape(write)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_header<R: Read>(read: &mut R) -> Result<ContextModel, Box<dyn Error>> {
+        let mut bitmap = [0u8; NUM_BYTES / 8];
+        read.read_exact(&mut bitmap)?;
+
+        let has_fallback = match read.read_u8()? {
+            0 => false,
+            1 => true,
+            flag => return Err(From::from(format!("Invalid fallback flag {}", flag))),
+        };
+
+        let mut tables = Vec::with_capacity(NUM_BYTES);
+
+        for context in 0..NUM_BYTES {
+            if bitmap[context / 8] & (1 << (context % 8)) != 0 {
+                tables.push(Some(CanonicalTree::read_shape(read)?));
+            } else {
+                tables.push(None);
+            }
+        }
+
+        let fallback = if has_fallback { Some(CanonicalTree::read_shape(read)?) } else { None };
+
+        Ok(ContextModel {tables, fallback, previous: 0})
+    }
+
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        let table = self.table(self.previous)
+            .ok_or_else(|| format!("No table for bytes after {}", self.previous))?;
+
+        table.encode_symbol(byte, bit_writer)?;
+        self.previous = byte;
+
+        Ok(())
+    }
+
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, _symbols_left: u64) -> Result<u8, Box<dyn Error>> {
+        let decode_table = match self.table(self.previous) {
+            Some(table) => table.decode_table(),
+            None => return Err(Box::new(DecodeError {
+                kind: DecodeErrorKind::InvalidCode,
+                bit_offset: bit_reader.position(),
+                symbols_decoded,
+                partial_code: Code::new(0, 0),
+            })),
+        };
+
+        let byte = match decode_table.decode_checked(bit_reader, symbols_decoded)? {
+            Some(byte) => byte,
+            None => return Err(decode_table.unexpected_end(bit_reader, symbols_decoded).into()),
+        };
+
+        self.previous = byte;
+
+        Ok(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "It was the best of times, it was the worst of times, it was the age of wisdom, \
+            it was the age of foolishness, it was the epoch of belief, it was the epoch of \
+            incredulity, it was the season of Light, it was the season of Darkness. Quiet quotes \
+            \"quickly\" quell queries.\n".repeat(40);
+
+        let (bytes_read, mut model) = ContextModel::from_read(text.as_bytes()).unwrap();
+        assert_eq!(bytes_read, text.len() as u64);
+        assert!(model.context_tables() > 0);
+        assert!(model.table(b'Q').is_some());
+
+        let mut encoded = Vec::new();
+        model.write_header(&mut encoded).unwrap();
+        encode_payload(&mut model, &mut Cursor::new(&text), &mut encoded).unwrap();
+
+        let mut read = Cursor::new(&encoded);
+        let mut decoder = ContextModel::read_header(&mut read).unwrap();
+        let mut decoded = Vec::new();
+        decode_payload(&mut decoder, &mut read, &mut decoded, text.len() as u64, true).unwrap();
+        assert_eq!(decoded, text.as_bytes());
+
+        // Beats order-0 coding even after paying for the tables
+        let tree = CanonicalTree::from_slice(text.as_bytes()).unwrap();
+        let mut order0 = Vec::new();
+        tree.encode(&mut Cursor::new(&text), &mut order0).unwrap();
+        assert!(encoded.len() < (NUM_BYTES + order0.len()) * 3 / 4);
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let mut frequencies = vec![FrequencyTable::new(); NUM_BYTES];
+        frequencies[b'a' as usize].set(b'b', MIN_CONTEXT_BYTES);
+        frequencies[b'b' as usize].set(b'a', 1);
+        frequencies[b'b' as usize].set(b'c', 2);
+
+        let model = ContextModel::from_context_frequencies(&frequencies).unwrap();
+        assert_eq!(model.context_tables(), 1);
+        assert!(model.table(b'z').is_some());
+
+        let mut header = Vec::new();
+        model.write_header(&mut header).unwrap();
+        assert_eq!(ContextModel::read_header(&mut Cursor::new(&header)).unwrap(), model);
+
+        let empty = ContextModel::from_context_frequencies(&vec![FrequencyTable::new(); NUM_BYTES]).unwrap();
+        assert!(empty.table(0).is_none());
+        assert!(ContextModel::from_context_frequencies(&frequencies[1..]).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! Limits on the resources used to decode data that cannot be trusted.
+//!
+//! A `Decoder` normally trusts the header: it builds whatever tables the header describes and
+//! decodes as many bytes as the size says, however little coded data follows. `DecodeLimits`
+//! bounds the size of the output, the size of the header, the memory held by the tables built
+//! from it, and the number of bytes decoded for each byte read, which bounds the work done per
+//! byte of input. Decoding fails with `HuffmanError::LimitExceeded` as soon as a limit is hit,
+//! with a `LimitKind` saying which.
+
+use std::cell::Cell;
+use std::io;
+use std::io::{Read, Take, Write};
+use std::result::Result;
+
+use super::*;
+
+/// Bytes that may be decoded on top of those allowed by `DecodeLimits::max_expansion`, as
+/// decoded bytes are written a buffer at a time.
+const EXPANSION_ALLOWANCE: u64 = READ_BUFFER_SIZE as u64;
+
+/// Limits enforced while decoding. There are no limits by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DecodeLimits {
+    max_output_size: Option<u64>,
+    max_header_size: Option<u64>,
+    max_table_memory: Option<usize>,
+    max_expansion: Option<u64>,
+}
+
+impl DecodeLimits {
+    /// No limits.
+    pub fn new() -> DecodeLimits {
+        DecodeLimits::default()
+    }
+
+    /// Limits for data from anywhere: 64 MiB of output, a 128 KiB header, 16 MiB of tables, and
+    /// 1024 bytes decoded for each byte read.
+    pub fn hardened() -> DecodeLimits {
+        DecodeLimits::new()
+            .max_output_size(64 * 1024 * 1024)
+            .max_header_size(128 * 1024)
+            .max_table_memory(16 * 1024 * 1024)
+            .max_expansion(1024)
+    }
+
+    /// Fail before decoding anything if the data holds more than `bytes` bytes.
+    ///
+    /// Data encoded with stages is refused when there is a limit, since the size of what each
+    /// stage restores is only known once it has been undone.
+    pub fn max_output_size(mut self, bytes: u64) -> DecodeLimits {
+        self.max_output_size = Some(bytes);
+        self
+    }
+
+    /// Fail if the tree or model read before the coded data takes more than `bytes` bytes.
+    pub fn max_header_size(mut self, bytes: u64) -> DecodeLimits {
+        self.max_header_size = Some(bytes);
+        self
+    }
+
+    /// Fail before decoding anything if the tables built from the header hold more than `bytes`
+    /// bytes, as given by `EntropyCoder::table_memory`.
+    pub fn max_table_memory(mut self, bytes: usize) -> DecodeLimits {
+        self.max_table_memory = Some(bytes);
+        self
+    }
+
+    /// Fail once more than `ratio` bytes have been decoded for each byte read, give or take a
+    /// buffer of decoded bytes.
+    ///
+    /// Not checked by `Decoder::decode_into`, where the buffer bounds the work instead.
+    pub fn max_expansion(mut self, ratio: u64) -> DecodeLimits {
+        self.max_expansion = Some(ratio);
+        self
+    }
+
+    /// Fail if `bytes` bytes, restored by `stages` stages, cannot be decoded within the limits.
+    pub(crate) fn check_output_size(&self, bytes: u64, stages: usize) -> Result<(), HuffmanError> {
+        if let Some(max_size) = self.max_output_size {
+            if bytes > max_size {
+                return Err(limit_exceeded(LimitKind::OutputSize, format!("Size {} is larger than the limit of {} bytes", bytes, max_size)));
+            }
+
+            if stages > 0 {
+                return Err(limit_exceeded(LimitKind::OutputSize, "Stages cannot be decoded within a size limit".to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a tree or model with `read_header`, failing if it is longer than the header limit or
+    /// its tables are larger than the memory limit.
+    pub(crate) fn read_header<R, C, F>(&self, read: R, read_header: F) -> Result<C, HuffmanError>
+        where R: Read, C: EntropyCoder, F: FnOnce(&mut Take<R>) -> Result<C, HuffmanError>
+    {
+        let mut header = read.take(self.max_header_size.unwrap_or(u64::MAX));
+        let result = read_header(&mut header);
+
+        if let Some(max_header_size) = self.max_header_size {
+            if result.is_err() && header.limit() == 0 {
+                return Err(limit_exceeded(LimitKind::HeaderSize, format!("Header is larger than the limit of {} bytes", max_header_size)));
+            }
+        }
+
+        let coder = result?;
+
+        if let Some(max_memory) = self.max_table_memory {
+            let memory = coder.table_memory();
+
+            if memory > max_memory {
+                return Err(limit_exceeded(LimitKind::TableMemory, format!("Tables of {} bytes are larger than the limit of {} bytes", memory, max_memory)));
+            }
+        }
+
+        Ok(coder)
+    }
+
+    /// Wrap `write` to fail once it is given too many bytes for the `bytes_read` counted by a
+    /// `CountingRead`.
+    pub(crate) fn expansion_write<'a, W: Write>(&self, write: W, bytes_read: &'a Cell<u64>) -> ExpansionWrite<'a, W> {
+        ExpansionWrite {
+            write,
+            bytes_read,
+            bytes_written: 0,
+            max_expansion: self.max_expansion,
+        }
+    }
+}
+
+fn limit_exceeded(kind: LimitKind, message: String) -> HuffmanError {
+    HuffmanError::LimitExceeded { kind, message }
+}
+
+/// Passes reads on, adding the number of bytes read to `bytes_read`.
+pub(crate) struct CountingRead<'a, R> {
+    read: R,
+    bytes_read: &'a Cell<u64>,
+}
+
+impl<'a, R: Read> CountingRead<'a, R> {
+    pub(crate) fn new(read: R, bytes_read: &'a Cell<u64>) -> CountingRead<'a, R> {
+        CountingRead {
+            read,
+            bytes_read,
+        }
+    }
+}
+
+impl<'a, R: Read> Read for CountingRead<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.read.read(buf)?;
+        self.bytes_read.set(self.bytes_read.get() + bytes_read as u64);
+
+        Ok(bytes_read)
+    }
+}
+
+/// Passes writes on, failing once more bytes are written than `DecodeLimits::max_expansion`
+/// allows for those read.
+pub(crate) struct ExpansionWrite<'a, W> {
+    write: W,
+    bytes_read: &'a Cell<u64>,
+    bytes_written: u64,
+    max_expansion: Option<u64>,
+}
+
+impl<'a, W: Write> Write for ExpansionWrite<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max_expansion) = self.max_expansion {
+            let allowed = self.bytes_read.get().saturating_mul(max_expansion).saturating_add(EXPANSION_ALLOWANCE);
+
+            if self.bytes_written + buf.len() as u64 > allowed {
+                let message = format!("More than {} bytes decoded for each byte read", max_expansion);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, limit_exceeded(LimitKind::Expansion, message)));
+            }
+        }
+
+        let written = self.write.write(buf)?;
+        self.bytes_written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn decode(encoded: &[u8], backend: Backend, limits: DecodeLimits) -> Result<Vec<u8>, HuffmanError> {
+        let mut decoded = Vec::new();
+        Decoder::new(Cursor::new(encoded)).backend(backend).limits(limits).decode(&mut decoded)?;
+
+        Ok(decoded)
+    }
+
+    fn limit_kind(result: Result<Vec<u8>, HuffmanError>) -> LimitKind {
+        match result {
+            Err(HuffmanError::LimitExceeded { kind, .. }) => kind,
+            result => panic!("Expected a limit to be hit, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let text = "the quick brown fox jumps over the lazy dog".repeat(100);
+        let mut encoded = Vec::new();
+        Encoder::from_slice(text.as_bytes()).unwrap().encode(&mut encoded).unwrap();
+
+        assert_eq!(decode(&encoded, Backend::Huffman, DecodeLimits::hardened()).unwrap(), text.as_bytes());
+
+        let limits = DecodeLimits::new();
+        assert_eq!(limit_kind(decode(&encoded, Backend::Huffman, limits.max_output_size(text.len() as u64 - 1))), LimitKind::OutputSize);
+        assert_eq!(limit_kind(decode(&encoded, Backend::Huffman, limits.max_header_size(NUM_BYTES as u64 - 1))), LimitKind::HeaderSize);
+        assert_eq!(limit_kind(decode(&encoded, Backend::Huffman, limits.max_table_memory(16))), LimitKind::TableMemory);
+        assert!(decode(&encoded, Backend::Huffman, limits.max_header_size(NUM_BYTES as u64)).is_ok());
+
+        // A long run codes to a few bytes, but is decoded a buffer at a time
+        let run = vec![0; 1 << 20];
+        let mut encoded = Vec::new();
+        Encoder::from_slice(&run).unwrap().backend(Backend::Arithmetic).encode(&mut encoded).unwrap();
+
+        assert_eq!(limit_kind(decode(&encoded, Backend::Arithmetic, DecodeLimits::hardened())), LimitKind::Expansion);
+        assert_eq!(decode(&encoded, Backend::Arithmetic, limits.max_expansion(1 << 20)).unwrap(), run);
+    }
+}
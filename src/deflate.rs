@@ -0,0 +1,144 @@
+//! Conversion between `CanonicalTree` and the code tables of DEFLATE (RFC 1951).
+//!
+//! The codes of a `CanonicalTree` are assigned exactly as in section 3.2.2 of RFC 1951: shorter
+//! codes come first, and codes of the same length are assigned consecutively in increasing order
+//! of symbol. A DEFLATE table is therefore fully described by the code length of each symbol.
+
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Longest code allowed for the literal/length and distance alphabets of DEFLATE.
+pub const DEFLATE_MAX_CODE_LENGTH: u8 = 15;
+
+/// Longest code allowed for the code length alphabet of DEFLATE.
+pub const DEFLATE_MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+
+impl<S: Symbol> CanonicalTree<S> {
+    /// Build the tree from DEFLATE code lengths, indexed by symbol, where 0 means the symbol has
+    /// no code.
+    ///
+    /// The literal/length alphabet of DEFLATE has 288 symbols, so it needs a `CanonicalTree<u16>`.
+    pub fn from_deflate_lengths(lengths: &[u8]) -> Result<CanonicalTree<S>, Box<dyn Error>> {
+        if lengths.len() > S::ALPHABET_SIZE {
+            return Err(From::from(format!("Alphabet of {} symbols is larger than {} symbols",
+                lengths.len(), S::ALPHABET_SIZE)));
+        }
+
+        if let Some(&length) = lengths.iter().find(|&&length| length > DEFLATE_MAX_CODE_LENGTH) {
+            return Err(From::from(format!("Code length {} is longer than {} bits",
+                length, DEFLATE_MAX_CODE_LENGTH)));
+        }
+
+        let code_lengths = lengths.iter().enumerate()
+            .filter(|&(_symbol, &length)| length > 0)
+            .map(|(symbol, &length)| (S::from_index(symbol), length))
+            .collect();
+
+        Ok(CanonicalTree::new(code_lengths)?)
+    }
+
+    /// Build a tree usable in DEFLATE, with no code longer than `DEFLATE_MAX_CODE_LENGTH` bits.
+    pub fn from_frequencies_deflate<F: Frequencies<Symbol = S> + ?Sized>(frequencies: &F) -> Result<CanonicalTree<S>, Box<dyn Error>> {
+        Ok(CanonicalTree::from_frequencies_limited(frequencies, DEFLATE_MAX_CODE_LENGTH)?)
+    }
+
+    /// The code length of each symbol as stored in a DEFLATE header, up to the last symbol with
+    /// a code.
+    pub fn deflate_lengths(&self) -> Vec<u8> {
+        let used = self.code_book().iter().last().map_or(0, |(symbol, _code)| symbol.to_index() + 1);
+        let mut lengths = vec![0; used];
+
+        for (symbol, code) in self.code_book().iter() {
+            lengths[symbol.to_index()] = code.length();
+        }
+
+        lengths
+    }
+
+    /// The codes with their bits reversed, ready to be written to a DEFLATE stream which packs
+    /// bits starting from the least significant bit of each byte.
+    pub fn deflate_code_book(&self) -> CodeBook<S> {
+        self.code_book().iter()
+            .map(|(symbol, code)| (symbol, code.reversed()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_example() {
+        // Example from section 3.2.2 of RFC 1951
+        let tree: CanonicalTree = CanonicalTree::from_deflate_lengths(&[3, 3, 3, 3, 3, 2, 4, 4]).unwrap();
+
+        let codes: Vec<Code> = tree.code_book().iter().map(|(_symbol, code)| code).collect();
+        assert_eq!(codes, vec![
+            Code::new(0b010, 3),
+            Code::new(0b011, 3),
+            Code::new(0b100, 3),
+            Code::new(0b101, 3),
+            Code::new(0b110, 3),
+            Code::new(0b00, 2),
+            Code::new(0b1110, 4),
+            Code::new(0b1111, 4),
+        ]);
+
+        assert_eq!(tree.deflate_lengths(), vec![3, 3, 3, 3, 3, 2, 4, 4]);
+    }
+
+    #[test]
+    fn test_fixed_literal_codes() {
+        // The fixed literal/length code from section 3.2.6 of RFC 1951
+        let lengths: Vec<u8> = (0..288)
+            .map(|symbol| match symbol {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            })
+            .collect();
+        let tree = CanonicalTree::<u16>::from_deflate_lengths(&lengths).unwrap();
+
+        assert_eq!(tree.code_book().get(0), Some(Code::new(0b00110000, 8)));
+        assert_eq!(tree.code_book().get(143), Some(Code::new(0b10111111, 8)));
+        assert_eq!(tree.code_book().get(144), Some(Code::new(0b110010000, 9)));
+        assert_eq!(tree.code_book().get(255), Some(Code::new(0b111111111, 9)));
+        assert_eq!(tree.code_book().get(256), Some(Code::new(0b0000000, 7)));
+        assert_eq!(tree.code_book().get(280), Some(Code::new(0b11000000, 8)));
+        assert_eq!(tree.deflate_lengths(), lengths);
+
+        // Bit reversed codes as found in zlib's static_ltree
+        let code_book = tree.deflate_code_book();
+        assert_eq!(code_book.get(0), Some(Code::new(12, 8)));
+        assert_eq!(code_book.get(1), Some(Code::new(140, 8)));
+        assert_eq!(code_book.get(2), Some(Code::new(76, 8)));
+        assert_eq!(code_book.get(144), Some(Code::new(19, 9)));
+        assert_eq!(code_book.get(145), Some(Code::new(275, 9)));
+        assert_eq!(code_book.get(256), Some(Code::new(0, 7)));
+        assert_eq!(code_book.get(257), Some(Code::new(64, 7)));
+    }
+
+    #[test]
+    fn test_deflate_limits() {
+        assert!(CanonicalTree::<u8>::from_deflate_lengths(&[16, 1]).is_err());
+        assert!(CanonicalTree::<u8>::from_deflate_lengths(&[1; NUM_BYTES + 1]).is_err());
+
+        // A lone distance code of 1 bit is allowed
+        let tree = CanonicalTree::<u8>::from_deflate_lengths(&[0, 1]).unwrap();
+        assert_eq!(tree.deflate_lengths(), vec![0, 1]);
+
+        let mut freq_table = [0; NUM_BYTES];
+        let mut frequency = 1;
+        for entry in freq_table.iter_mut().take(30) {
+            *entry = frequency;
+            frequency *= 2;
+        }
+
+        let tree = CanonicalTree::from_frequencies_deflate(&freq_table).unwrap();
+        assert_eq!(tree.max_code_length(), Some(DEFLATE_MAX_CODE_LENGTH));
+    }
+}
@@ -0,0 +1,51 @@
+//! Delta encoding of bytes, for numeric data such as samples from sensors or audio.
+//!
+//! Each byte is replaced by its difference from the byte `stride` bytes before it, wrapping
+//! around. Smoothly changing values become small differences clustered around zero, which code
+//! far better than the values themselves. A stride of 1 differences every byte from the one
+//! before, while a stride of the sample size differences each byte of a sample from the same
+//! byte of the previous sample, such as 2 for 16-bit samples or 4 for 16-bit stereo.
+
+/// Replace each byte with its difference from the byte `stride` bytes before it.
+///
+/// The first `stride` bytes are kept as they are. A `stride` of 0 is treated as 1.
+pub fn delta_encode(bytes: &[u8], stride: u32) -> Vec<u8> {
+    let stride = (stride as usize).max(1);
+
+    bytes.iter().enumerate().map(|(i, &byte)| {
+        if i < stride { byte } else { byte.wrapping_sub(bytes[i - stride]) }
+    }).collect()
+}
+
+/// Undo `delta_encode` with the same `stride`.
+pub fn delta_decode(deltas: &[u8], stride: u32) -> Vec<u8> {
+    let stride = (stride as usize).max(1);
+    let mut bytes = Vec::with_capacity(deltas.len());
+
+    for (i, &delta) in deltas.iter().enumerate() {
+        let byte = if i < stride { delta } else { delta.wrapping_add(bytes[i - stride]) };
+        bytes.push(byte);
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(delta_encode(&[10, 12, 15, 13, 0], 1), [10, 2, 3, 254, 243]);
+        assert_eq!(delta_encode(&[1, 100, 2, 101, 3, 102], 2), [1, 100, 1, 1, 1, 1]);
+
+        // A slow 16-bit ramp, little endian
+        let samples: Vec<u8> = (0..5000u16).flat_map(|i| (i * 7).to_le_bytes()).collect();
+        for stride in 0..5 {
+            assert_eq!(delta_decode(&delta_encode(&samples, stride), stride), samples);
+        }
+
+        let deltas = delta_encode(&samples, 2);
+        assert!(deltas[2..].chunks(2).all(|delta| delta == [7, 0] || delta == [7, 1]));
+    }
+}
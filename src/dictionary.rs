@@ -0,0 +1,157 @@
+//! A versioned file format for trained codebooks, so training can be done offline and the
+//! result loaded wherever records are coded.
+//!
+//! | Bytes | Contents                                                          |
+//! |-------|-------------------------------------------------------------------|
+//! | 8     | `DICTIONARY_MAGIC`                                                |
+//! | 1     | Format version, currently `DICTIONARY_FORMAT_VERSION`             |
+//! | 4     | Length of the tree, `n`, as a little endian `u32`                 |
+//! | `n`   | The tree, written by `CanonicalTree::to_bytes`                    |
+//! | 4     | CRC-32 of everything before it, as a little endian `u32`          |
+
+use std::io::{Read, Write};
+use std::fs::File;
+use std::path::Path;
+use std::result::Result;
+use std::error::Error;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use super::*;
+
+/// Bytes that start every dictionary.
+pub const DICTIONARY_MAGIC: [u8; 8] = *b"HUFFDICT";
+
+/// Version of the format written by `write_dictionary`.
+pub const DICTIONARY_FORMAT_VERSION: u8 = 1;
+
+/// Extension of dictionary files.
+pub const DICTIONARY_EXTENSION: &str = "huffdict";
+
+/// Longest tree a dictionary can hold, with a code for every byte.
+const MAX_TREE_SIZE: u32 = 3 + 2 * NUM_BYTES as u32;
+
+/// Write `tree` as a dictionary.
+pub fn write_dictionary<W: Write>(tree: &CanonicalTree, write: &mut W) -> Result<(), Box<dyn Error>> {
+    let tree_bytes = tree.to_bytes();
+
+    let mut bytes = Vec::with_capacity(17 + tree_bytes.len());
+    bytes.extend_from_slice(&DICTIONARY_MAGIC);
+    bytes.push(DICTIONARY_FORMAT_VERSION);
+    bytes.write_u32::<LittleEndian>(tree_bytes.len() as u32)?;
+    bytes.extend_from_slice(&tree_bytes);
+
+    let checksum = crc32(&bytes);
+    bytes.write_u32::<LittleEndian>(checksum)?;
+
+    write.write_all(&bytes)?;
+
+    Ok(())
+}
+
+/// Read a dictionary written by `write_dictionary`, reading nothing past its end.
+///
+/// Fails if the data is not a dictionary, has an unsupported version, or does not match its
+/// checksum.
+pub fn read_dictionary<R: Read>(read: &mut R) -> Result<CanonicalTree, Box<dyn Error>> {
+    let mut bytes = vec![0; DICTIONARY_MAGIC.len() + 5];
+    read.read_exact(&mut bytes)?;
+
+    if bytes[..DICTIONARY_MAGIC.len()] != DICTIONARY_MAGIC {
+        return Err(From::from("Not a dictionary"));
+    }
+
+    let version = bytes[DICTIONARY_MAGIC.len()];
+    if version != DICTIONARY_FORMAT_VERSION {
+        return Err(From::from(format!("Unsupported dictionary format version {}", version)));
+    }
+
+    let tree_size = (&bytes[DICTIONARY_MAGIC.len() + 1..]).read_u32::<LittleEndian>()?;
+    if tree_size > MAX_TREE_SIZE {
+        return Err(From::from(format!("Dictionary tree of {} bytes is too long", tree_size)));
+    }
+
+    let tree_start = bytes.len();
+    bytes.resize(tree_start + tree_size as usize, 0);
+    read.read_exact(&mut bytes[tree_start..])?;
+
+    let checksum = read.read_u32::<LittleEndian>()?;
+    if checksum != crc32(&bytes) {
+        return Err(From::from("Dictionary checksum does not match"));
+    }
+
+    Ok(CanonicalTree::from_bytes(&bytes[tree_start..])?)
+}
+
+/// Save `tree` as a dictionary file, failing if the file already exists.
+pub fn save_dictionary<P: AsRef<Path>>(tree: &CanonicalTree, path: P) -> Result<(), Box<dyn Error>> {
+    if path.as_ref().exists() {
+        return Err(From::from("Dictionary file already exists"));
+    }
+
+    let mut file = File::create(path)?;
+    write_dictionary(tree, &mut file)?;
+    file.sync_all()?;
+
+    Ok(())
+}
+
+/// Load a dictionary file written by `save_dictionary`.
+pub fn load_dictionary<P: AsRef<Path>>(path: P) -> Result<CanonicalTree, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let tree = read_dictionary(&mut file)?;
+
+    if read_some(&mut file, &mut [0])? != 0 {
+        return Err(From::from("Unexpected data after the dictionary"));
+    }
+
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let tree = train(vec![&b"some sample text"[..], b"more sample text"]).unwrap();
+
+        let mut bytes = Vec::new();
+        write_dictionary(&tree, &mut bytes).unwrap();
+        bytes.extend_from_slice(b"trailing");
+
+        let mut read = Cursor::new(&bytes);
+        assert_eq!(read_dictionary(&mut read).unwrap(), tree);
+        assert_eq!(read.position(), bytes.len() as u64 - 8);
+
+        let path = std::env::temp_dir().join(format!("huffman-test-{}.{}", std::process::id(), DICTIONARY_EXTENSION));
+        save_dictionary(&tree, &path).unwrap();
+        assert!(save_dictionary(&tree, &path).is_err());
+        let loaded = load_dictionary(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), tree);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let tree = CanonicalTree::from_slice(b"abracadabra").unwrap();
+        let mut bytes = Vec::new();
+        write_dictionary(&tree, &mut bytes).unwrap();
+
+        let read = |bytes: &[u8]| read_dictionary(&mut Cursor::new(bytes)).map_err(|err| err.to_string());
+
+        for i in 0..bytes.len() {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 0x10;
+            assert!(read(&corrupt).is_err());
+        }
+
+        let mut newer = bytes.clone();
+        newer[8] = DICTIONARY_FORMAT_VERSION + 1;
+        assert_eq!(read(&newer).unwrap_err(), "Unsupported dictionary format version 2");
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -1,14 +1,39 @@
-use std::io::{Read, Seek, SeekFrom, Write, BufReader, BufWriter};
+use std::io;
+use std::cell::Cell;
+use std::io::{Cursor, Read, Seek, Write, BufWriter};
+#[cfg(feature = "builtin-codebooks")]
+use std::io::SeekFrom;
+#[cfg(not(feature = "mmap"))]
+use std::io::BufReader;
 use std::fs::File;
 use std::path::Path;
-use std::error::Error;
+use std::sync::Arc;
 
 use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 
 use super::*;
 
+/// Version of the bytes written by the encoders in the crate.
+///
+/// The same input encoded with the same options gives byte for byte the same output on every
+/// platform and architecture, with any number of threads, and with any version of Rust and of the
+/// crate that has the same `FORMAT_STABILITY`. Nothing that shapes the output depends on
+/// `HashMap` order, the number of threads, or the width of `usize`. Any change to the output, even one
+/// that older decoders still read, comes with a new `FORMAT_STABILITY`.
+pub const FORMAT_STABILITY: u32 = 3;
+
+/// Bit of the size written by the `Encoder` that is set when the backend and header format follow
+/// the size and any stages. Older data always leaves it clear, and is decoded with the backend and
+/// header format the `Decoder` is given.
+pub(crate) const CODING_FLAG: u64 = 1 << 55;
+
 /// This struct is used to encode some `Read` using Canonical Huffman codes.
 ///
+/// The size is followed by a byte naming the `Backend` and a byte naming the `HeaderFormat`, so
+/// the `Decoder` reads the data without being told how it was coded, and a flag in the size marks
+/// that they are there. An empty `Read` is encoded as just the size of 0, without any of these,
+/// code lengths or data.
+///
 /// # Examples
 ///
 /// ```compile_fail
@@ -31,99 +56,718 @@ use super::*;
 pub struct Encoder<R> {
     read: R,
     bytes_read:  u64,
-    tree: CanonicalTree,
+    freq_table: FrequencyTable,
+    tree: Option<CanonicalTree>,
+    header_format: HeaderFormat,
+    backend: Backend,
+    stages: Vec<Stage>,
+    max_code_length: Option<u8>,
 }
 
-impl<R: Read + Seek> Encoder<R> {
+/// How the `Encoder` codes each byte.
+///
+/// Like `HeaderFormat`, the backend is recorded as a single byte after the size, which the
+/// `Decoder` reads back. Data written before it was recorded is decoded with the backend the
+/// `Decoder` is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Canonical Huffman codes, stored as set by `HeaderFormat`.
+    #[default]
+    Huffman,
+    /// Arithmetic coding with a `FrequencyModel`, stored as its header. This costs a larger
+    /// header and slower coding, but gets close to the entropy of skewed data.
+    Arithmetic,
+    /// Range coding with the same model and header as `Arithmetic`, which codes faster for
+    /// nearly the same size.
+    Range,
+    /// Table based asymmetric numeral systems with the same model and header as `Arithmetic`,
+    /// which gets nearly the same size at the speed of table lookups.
+    Tans,
+    /// Adaptive Huffman codes updated with `AdaptiveAlgorithm` after every byte, stored as a
+    /// single byte naming the algorithm. The decoder reads the algorithm from the header.
+    Adaptive(AdaptiveAlgorithm),
+    /// A table for each previous byte, stored as described by `ContextModel`. The `Encoder`
+    /// reads the data an extra time to count the bytes in each context.
+    Context,
+    /// Literals and matches found with LZ77, Huffman coded as described by `lz77_encode`. The
+    /// `Encoder` holds all of the data in memory to find the matches.
+    Lz77,
+}
+
+/// How the `Encoder` stores the tree in front of the encoded data.
+///
+/// The format is recorded as a single byte after the backend, which the `Decoder` reads back. Data
+/// written before it was recorded is decoded with the format the `Decoder` is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderFormat {
+    /// The code length of every byte, 256 bytes in all.
+    #[default]
+    CodeLengths,
+    /// The shape of the tree, written by `CanonicalTree::write_shape`. This is much smaller for
+    /// data with few distinct bytes.
+    TreeShape,
+    /// A single byte naming a `BuiltinCodebook`, which the tree must be. Set by
+    /// `Encoder::with_codebook`.
+    #[cfg(feature = "builtin-codebooks")]
+    Builtin,
+}
+
+impl HeaderFormat {
+    /// Number of bytes the `Encoder` writes before the encoded data when using `tree`.
+    pub fn header_size(&self, tree: &CanonicalTree) -> u64 {
+        match *self {
+            HeaderFormat::CodeLengths => header_size(tree),
+            HeaderFormat::TreeShape => 8 + 2 + tree.shape_size(),
+            #[cfg(feature = "builtin-codebooks")]
+            HeaderFormat::Builtin => 8 + 2 + 1,
+        }
+    }
+
+    pub(crate) fn id(&self) -> u8 {
+        match *self {
+            HeaderFormat::CodeLengths => 0,
+            HeaderFormat::TreeShape => 1,
+            #[cfg(feature = "builtin-codebooks")]
+            HeaderFormat::Builtin => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<HeaderFormat, HuffmanError> {
+        Ok(match id {
+            0 => HeaderFormat::CodeLengths,
+            1 => HeaderFormat::TreeShape,
+            #[cfg(feature = "builtin-codebooks")]
+            2 => HeaderFormat::Builtin,
+            id => return Err(HuffmanError::CorruptHeader(format!("Unknown header format {}", id))),
+        })
+    }
+}
+
+impl Backend {
+    pub(crate) fn id(&self) -> u8 {
+        match *self {
+            Backend::Huffman => 0,
+            Backend::Arithmetic => 1,
+            Backend::Range => 2,
+            Backend::Tans => 3,
+            Backend::Adaptive(AdaptiveAlgorithm::Fgk) => 4,
+            Backend::Adaptive(AdaptiveAlgorithm::Vitter) => 5,
+            Backend::Context => 6,
+            Backend::Lz77 => 7,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Result<Backend, HuffmanError> {
+        Ok(match id {
+            0 => Backend::Huffman,
+            1 => Backend::Arithmetic,
+            2 => Backend::Range,
+            3 => Backend::Tans,
+            4 => Backend::Adaptive(AdaptiveAlgorithm::Fgk),
+            5 => Backend::Adaptive(AdaptiveAlgorithm::Vitter),
+            6 => Backend::Context,
+            7 => Backend::Lz77,
+            id => return Err(HuffmanError::CorruptHeader(format!("Unknown backend {}", id))),
+        })
+    }
+}
+
+impl<'a> Encoder<SliceSource<'a>> {
+    /// Build the `Encoder` for data already in memory, which is counted and coded straight from
+    /// the slice.
+    pub fn from_slice(data: &'a [u8]) -> Result<Encoder<SliceSource<'a>>, HuffmanError> {
+        Encoder::new(SliceSource::new(data))
+    }
+
+    /// Build the `Encoder` for the data of `cursor`, which is coded from the slice like
+    /// `from_slice` instead of through `Read` and `Seek`. All of the data is coded, wherever the
+    /// cursor is.
+    pub fn from_cursor<T: AsRef<[u8]>>(cursor: &'a Cursor<T>) -> Result<Encoder<SliceSource<'a>>, HuffmanError> {
+        Encoder::new(SliceSource::from(cursor))
+    }
+}
+
+impl<R: EncoderSource> Encoder<R> {
     /// Build the `Encoder` from a `Read`.
     ///
-    /// This reads the entire `Read` and then seeks back to the beginning.
-    pub fn new(mut read: R) -> Result<Encoder<R>, Box<Error>> {
-        // Create a canonical huffman tree
-        let (bytes_read, tree) = CanonicalTree::from_read(read.by_ref())?;
+    /// This reads the entire `Read` and then goes back to the beginning. A source already in
+    /// memory is counted on multiple threads without being read.
+    pub fn new(mut read: R) -> Result<Encoder<R>, HuffmanError> {
+        let (bytes_read, freq_table) = match read.as_slice() {
+            Some(data) => (data.len() as u64, slice_frequencies(data)?),
+            None => read_frequencies(read.by_ref())?,
+        };
+
+        Encoder::with_frequencies(read, bytes_read, &freq_table)
+    }
+
+    /// Build the `Encoder` from frequencies that have already been counted.
+    fn with_frequencies(mut read: R, bytes_read: u64, freq_table: &FrequencyTable) -> Result<Encoder<R>, HuffmanError> {
+        // Create a canonical huffman tree, unless there is nothing to encode
+        let tree = if bytes_read == 0 {
+            None
+        } else {
+            Some(CanonicalTree::from_frequencies(freq_table)?)
+        };
 
         // Reset the read to the beginning
-        read.seek(SeekFrom::Start(0))?;
+        read.rewind()?;
 
-        Ok(Encoder {read, bytes_read, tree})
+        Ok(Encoder {
+            read,
+            bytes_read,
+            freq_table: freq_table.clone(),
+            tree,
+            header_format: HeaderFormat::default(),
+            backend: Backend::default(),
+            stages: Vec::new(),
+            max_code_length: None,
+        })
+    }
+
+    /// Store the tree in `header_format`. Defaults to `HeaderFormat::CodeLengths`.
+    pub fn header_format(mut self, header_format: HeaderFormat) -> Encoder<R> {
+        self.header_format = header_format;
+        self
+    }
+
+    /// Code the bytes with `backend`. Defaults to `Backend::Huffman`.
+    pub fn backend(mut self, backend: Backend) -> Encoder<R> {
+        self.backend = backend;
+        self
+    }
+
+    /// Limit the Huffman codes to `max_length` bits, which keeps the tables for decoding them
+    /// small and fast at a little cost in size.
+    ///
+    /// Fails if `max_length` is too short to give a code to every byte in the data.
+    pub fn max_code_length(mut self, max_length: u8) -> Result<Encoder<R>, HuffmanError> {
+        if self.tree.is_some() {
+            self.tree = Some(CanonicalTree::from_frequencies_limited(&self.freq_table, max_length)?);
+        }
+
+        self.max_code_length = Some(max_length);
+        Ok(self)
+    }
+
+    /// Apply `stage` to the data before coding it, after any stages added before. The stages are
+    /// recorded in the header, so the `Decoder` undoes them without being told.
+    ///
+    /// With any stages, the whole of the data is held in memory while encoding and decoding.
+    pub fn stage(mut self, stage: Stage) -> Encoder<R> {
+        self.stages.push(stage);
+        self
     }
 
     /// Encode the encoder to a `Write`
-    pub fn encode<W: Write>(&mut self, mut write: W) -> Result<(), Box<Error>> {
-        // Write out the size of the original file
-        write.write_u64::<LittleEndian>(self.bytes_read)?;
+    pub fn encode<W: Write>(&mut self, mut write: W) -> Result<(), HuffmanError> {
+        if self.stages.is_empty() {
+            // Write out the size of the original file
+            write.write_u64::<LittleEndian>(size_word(self.bytes_read, 0)?)?;
 
-        // Write out the code lengths
-        write.write_all(&self.tree.code_lengths())?;
+            return self.encode_body(write);
+        }
 
-        // Use the tree to encode the read
-        self.tree.encode(self.read.by_ref(), write.by_ref())?;
+        if self.stages.len() > u8::MAX as usize {
+            return Err(HuffmanError::InvalidInput("Too many stages".to_string()));
+        }
 
-        Ok(())
+        for &stage in self.stages.iter() {
+            stage.check()?;
+        }
+
+        // Transform all of the data, then code the result in its place
+        self.read.rewind()?;
+        let mut data = match self.read.as_slice() {
+            Some(data) => data.to_vec(),
+            None => {
+                let mut data = Vec::new();
+                self.read.read_to_end(&mut data)?;
+                data
+            },
+        };
+
+        for stage in self.stages.iter() {
+            data = stage.forward(&data)?;
+        }
+
+        let bytes = data.len() as u64;
+        write.write_u64::<LittleEndian>(size_word(bytes, self.stages.len())?)?;
+        for stage in self.stages.iter() {
+            stage.write_descriptor(&mut write)?;
+        }
+
+        let freq_table = slice_frequencies(&data)?;
+        let mut encoder = Encoder::with_frequencies(SliceSource::new(&data), bytes, &freq_table)?
+            .header_format(self.header_format)
+            .backend(self.backend);
+
+        if let Some(max_length) = self.max_code_length {
+            encoder = encoder.max_code_length(max_length)?;
+        }
+
+        encoder.encode_body(write)
+    }
+
+    /// Encode everything after the size.
+    fn encode_body<W: Write>(&mut self, mut write: W) -> Result<(), HuffmanError> {
+        let tree = match self.tree {
+            Some(ref mut tree) => tree,
+            None => return Ok(()),
+        };
+
+        write.write_u8(self.backend.id())?;
+        write.write_u8(self.header_format.id())?;
+
+        match self.backend {
+            Backend::Huffman => {
+                // Write out the tree
+                match self.header_format {
+                    HeaderFormat::CodeLengths => tree.write_header(&mut write)?,
+                    HeaderFormat::TreeShape => tree.write_shape(&mut write)?,
+                    #[cfg(feature = "builtin-codebooks")]
+                    HeaderFormat::Builtin => {
+                        let codebook = BuiltinCodebook::find(tree).ok_or_else(|| HuffmanError::InvalidInput("The tree is not a built-in codebook".to_string()))?;
+                        write.write_u8(codebook.id())?;
+                    },
+                }
+
+                // Use the tree to encode the read
+                match self.read.as_slice() {
+                    Some(data) => tree.encode_packed(data, &mut write),
+                    None => Ok(tree.encode(&mut self.read, &mut write)?),
+                }
+            },
+            Backend::Adaptive(algorithm) => encode_with(AdaptiveTree::with_algorithm(algorithm), &mut self.read, write),
+            Backend::Arithmetic => encode_with(ArithmeticCoder::from_frequencies(&self.freq_table)?, &mut self.read, write),
+            Backend::Range => encode_with(RangeCoder::from_frequencies(&self.freq_table)?, &mut self.read, write),
+            Backend::Tans => encode_with(TansCoder::from_frequencies(&self.freq_table)?, &mut self.read, write),
+            Backend::Context => {
+                let (_bytes_read, model) = ContextModel::from_read(self.read.by_ref())?;
+                self.read.rewind()?;
+
+                encode_with(model, &mut self.read, write)
+            },
+            Backend::Lz77 => {
+                if let Some(data) = self.read.as_slice() {
+                    return Ok(lz77_encode(data, &mut write)?);
+                }
+
+                let mut data = Vec::new();
+                self.read.read_to_end(&mut data)?;
+
+                Ok(lz77_encode(&data, &mut write)?)
+            },
+        }
     }
 }
 
+impl<R: Read + Seek> Encoder<R> {
+    /// Build the `Encoder` to code with a built-in codebook, which skips counting the frequencies
+    /// and stores the tree as `HeaderFormat::Builtin`.
+    ///
+    /// This seeks to the end of the `Read` to find its size and then back to the beginning.
+    #[cfg(feature = "builtin-codebooks")]
+    pub fn with_codebook(mut read: R, codebook: BuiltinCodebook) -> Result<Encoder<R>, HuffmanError> {
+        let bytes_read = read.seek(SeekFrom::End(0))?;
+        read.seek(SeekFrom::Start(0))?;
+
+        Ok(Encoder {
+            read,
+            bytes_read,
+            freq_table: codebook.frequencies(),
+            tree: if bytes_read == 0 { None } else { Some(codebook.tree()) },
+            header_format: HeaderFormat::Builtin,
+            backend: Backend::default(),
+            stages: Vec::new(),
+            max_code_length: None,
+        })
+    }
+}
+
+/// The size written by the `Encoder` for `bytes` bytes of data after `stages` stages, with
+/// `CODING_FLAG` set unless there is nothing to code.
+fn size_word(bytes: u64, stages: usize) -> Result<u64, HuffmanError> {
+    if bytes >= CODING_FLAG {
+        return Err(HuffmanError::InvalidInput("The data is too large".to_string()));
+    }
+
+    let flag = if bytes == 0 { 0 } else { CODING_FLAG };
+
+    Ok(bytes | flag | (stages as u64) << STAGE_COUNT_SHIFT)
+}
+
+/// Write the header of `coder` followed by the bytes of `read`.
+fn encode_with<C: EntropyCoder, R: EncoderSource, W: Write>(mut coder: C, read: &mut R, mut write: W) -> Result<(), HuffmanError> {
+    coder.write_header(&mut write)?;
+
+    match read.as_slice() {
+        Some(data) => {
+            let mut bit_writer = BitWriter::new(&mut write);
+            coder.encode_bytes(data, &mut bit_writer)?;
+            Ok(coder.finish(&mut bit_writer)?)
+        },
+        None => Ok(encode_payload(&mut coder, read, &mut write)?),
+    }
+}
+
+/// Number of bytes the `Encoder` writes before the encoded data when using `tree`.
+pub fn header_size(_tree: &CanonicalTree) -> u64 {
+    // Size of the original file, the backend and header format, then the code lengths
+    8 + 2 + NUM_BYTES as u64
+}
+
 /// This struct is used to decode a file that has been encoded using the `Encoder`
 pub struct Decoder<R> {
     read: R,
+    strict: bool,
+    header_format: Option<HeaderFormat>,
+    backend: Option<Backend>,
+    limits: DecodeLimits,
+    tree_cache: Option<Arc<TreeCache>>,
+    skip_trailing: bool,
+    trailing_bytes: u64,
+    coding_recorded: bool,
 }
 
 impl<R: Read> Decoder<R> {
     pub fn new(read: R) -> Decoder<R> {
-        Decoder { read }
+        Decoder {
+            read,
+            strict: false,
+            header_format: None,
+            backend: None,
+            limits: DecodeLimits::new(),
+            tree_cache: None,
+            skip_trailing: false,
+            trailing_bytes: 0,
+            coding_recorded: false,
+        }
+    }
+
+    /// Fail before reading the header unless the data was coded with `backend`. Any backend is
+    /// decoded by default.
+    ///
+    /// Data written before the backend was recorded is decoded with `backend`, or with
+    /// `Backend::Huffman` if none is given.
+    pub fn backend(mut self, backend: Backend) -> Decoder<R> {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Fail before reading the header unless the tree is stored in `header_format`. Any format is
+    /// decoded by default.
+    ///
+    /// Data written before the format was recorded is read with `header_format`, or with
+    /// `HeaderFormat::CodeLengths` if none is given.
+    pub fn header_format(mut self, header_format: HeaderFormat) -> Decoder<R> {
+        self.header_format = Some(header_format);
+        self
+    }
+
+    /// Fail if anything other than zero padding follows the encoded data.
+    ///
+    /// Off by default.
+    pub fn strict(mut self, strict: bool) -> Decoder<R> {
+        self.strict = strict;
+        self
+    }
+
+    /// Once the data is decoded, read and skip everything after it, such as padding added by a
+    /// transport that sends whole blocks, and count it in `trailing_bytes`.
+    ///
+    /// This replaces the check made by `strict`, except that the rest of the last byte of the
+    /// coded data must still be zero. Off by default, when anything after the data is left unread.
+    pub fn skip_trailing(mut self, skip: bool) -> Decoder<R> {
+        self.skip_trailing = skip;
+        self
+    }
+
+    /// Number of bytes after the data skipped by the last decode with `skip_trailing`.
+    pub fn trailing_bytes(&self) -> u64 {
+        self.trailing_bytes
+    }
+
+    /// Look the code lengths of `HeaderFormat::CodeLengths` headers up in `cache`, sharing a
+    /// tree already built for the same header instead of building it again.
+    pub fn tree_cache(mut self, cache: Arc<TreeCache>) -> Decoder<R> {
+        self.tree_cache = Some(cache);
+        self
+    }
+
+    /// Fail before decoding anything if the data holds more than `bytes` bytes, as with
+    /// `DecodeLimits::max_output_size`. No limit by default.
+    pub fn max_size(mut self, bytes: u64) -> Decoder<R> {
+        self.limits = self.limits.max_output_size(bytes);
+        self
+    }
+
+    /// Enforce `limits` while decoding, such as `DecodeLimits::hardened` for data that cannot be
+    /// trusted. No limits by default.
+    pub fn limits(mut self, limits: DecodeLimits) -> Decoder<R> {
+        self.limits = limits;
+        self
     }
 
     /// Decode the decoder to a `Read`
-    pub fn decode<W: Write>(&mut self, mut write: W) -> Result<(), Box<Error>> {
-        // Read the size of the original file
-        let bytes: u64 = self.read.read_u64::<LittleEndian>()?;
+    pub fn decode<W: Write>(&mut self, mut write: W) -> Result<(), HuffmanError> {
+        let (bytes, stages) = self.read_size()?;
 
-        // Read in code lengths
-        let mut code_buf = [0; 256];
-        self.read.read_exact(&mut code_buf)?;
+        if stages.is_empty() {
+            return self.decode_body(write, bytes);
+        }
 
-        let code_lengths: Vec<(u8, u8)> = code_buf.iter().enumerate()
-            .map(|(i, &l)| (i as u8, l))
-            .collect();
+        let mut data = Vec::new();
+        self.decode_body(&mut data, bytes)?;
+
+        for stage in stages.iter().rev() {
+            data = stage.inverse(&data)?;
+        }
+
+        Ok(write.write_all(&data)?)
+    }
+
+    /// Decode as much as possible to `write`, never failing.
+    ///
+    /// The `Recovery` counts the bytes written, which are everything decoded before any error,
+    /// and holds the error. Data with stages is all or nothing, as the stages are undone only
+    /// once all of the data has been decoded.
+    pub fn decode_lossy<W: Write>(&mut self, write: W) -> Recovery {
+        let mut write = CountingWrite::new(write);
+        let error = self.decode(&mut write).err();
+
+        Recovery {
+            bytes_decoded: write.bytes_written(),
+            blocks_decoded: 0,
+            error,
+        }
+    }
+
+    /// Decode into `out`, returning the number of bytes decoded.
+    ///
+    /// Without stages, fails before decoding anything if `out` is too small. With `Backend::Huffman`
+    /// and no stages, nothing is allocated once the tree has been read unless there is an error,
+    /// so this suits decoding into buffers that are reused.
+    ///
+    /// With stages, the size stored is that of the transformed data, so the size check only comes
+    /// once the data has been decoded into memory and every stage undone. Neither that work nor
+    /// that memory is bounded by `out`; use `DecodeLimits::max_output_size` to refuse data with
+    /// stages up front.
+    pub fn decode_into(&mut self, out: &mut [u8]) -> Result<usize, HuffmanError> {
+        let (bytes, stages) = self.read_size()?;
+
+        if !stages.is_empty() {
+            let mut data = Vec::new();
+            self.decode_body(&mut data, bytes)?;
+
+            for stage in stages.iter().rev() {
+                data = stage.inverse(&data)?;
+            }
+
+            check_output_size(data.len() as u64, out)?;
+            out[..data.len()].copy_from_slice(&data);
+
+            return Ok(data.len());
+        }
+
+        check_output_size(bytes, out)?;
+        let out = &mut out[..bytes as usize];
+
+        if bytes == 0 {
+            self.decode_body(out, bytes)?;
+            return Ok(0);
+        }
+
+        match self.read_coding()? {
+            (Backend::Huffman, header_format) => {
+                let cache = self.tree_cache.as_ref();
+                let tree = self.limits.read_header(&mut self.read, |read| read_tree(read, header_format, cache))?;
+                let mut bit_reader = BitReader::new(&mut self.read);
+
+                tree.decode_into_impl(&mut bit_reader, out)?;
+                self.trailing_bytes = finish_trailing(&mut bit_reader, Trailing::new(self.strict, self.skip_trailing))?;
+            },
+            (backend, header_format) => self.decode_coded(out, bytes, backend, header_format)?,
+        }
+
+        Ok(bytes as usize)
+    }
+
+    /// Read the backend and header format, failing if they are not the ones expected.
+    fn read_coding(&mut self) -> Result<(Backend, HeaderFormat), HuffmanError> {
+        // Older data leaves them out, and is decoded as the `Decoder` is set up
+        if !self.coding_recorded {
+            return Ok((self.backend.unwrap_or_default(), self.header_format.unwrap_or_default()));
+        }
+
+        let backend = Backend::from_id(self.read.read_u8()?)?;
+        let header_format = HeaderFormat::from_id(self.read.read_u8()?)?;
+
+        if let Some(expected) = self.backend.filter(|&expected| expected != backend) {
+            return Err(HuffmanError::InvalidInput(format!("The data is coded with {:?}, not {:?}", backend, expected)));
+        }
+
+        if let Some(expected) = self.header_format.filter(|&expected| expected != header_format) {
+            return Err(HuffmanError::InvalidInput(format!("The tree is stored as {:?}, not {:?}", header_format, expected)));
+        }
+
+        Ok((backend, header_format))
+    }
+
+    /// Read the size and any stages.
+    ///
+    /// The size is that of the original file, or of the transformed data if there are stages.
+    fn read_size(&mut self) -> Result<(u64, Vec<Stage>), HuffmanError> {
+        self.trailing_bytes = 0;
 
-        let tree = CanonicalTree::new(code_lengths);
+        let size = self.read.read_u64::<LittleEndian>()?;
+        let bytes = size & (CODING_FLAG - 1);
+        self.coding_recorded = size & CODING_FLAG != 0;
 
-        tree.decode_exact(self.read.by_ref(), write.by_ref(), bytes)?;
+        let stages = (0..size >> STAGE_COUNT_SHIFT)
+            .map(|_| Stage::read_descriptor(&mut self.read))
+            .collect::<Result<Vec<Stage>, _>>()?;
+
+        self.limits.check_output_size(bytes, stages.len())?;
+
+        Ok((bytes, stages))
+    }
+
+    /// Decode `bytes` bytes from everything after the size.
+    fn decode_body<W: Write>(&mut self, write: W, bytes: u64) -> Result<(), HuffmanError> {
+        let trailing = Trailing::new(self.strict, self.skip_trailing);
+
+        // Empty files have no code lengths or data
+        if bytes == 0 {
+            match trailing {
+                Trailing::Skip { .. } => self.trailing_bytes = io::copy(&mut self.read, &mut io::sink())?,
+                Trailing::Reject if read_some(&mut self.read, &mut [0])? != 0 => return Err(HuffmanError::TrailingData),
+                _ => {},
+            }
+
+            return Ok(());
+        }
+
+        let (backend, header_format) = self.read_coding()?;
+        self.decode_coded(write, bytes, backend, header_format)
+    }
+
+    /// Decode `bytes` bytes coded with `backend` from everything after the header format.
+    fn decode_coded<W: Write>(&mut self, write: W, bytes: u64, backend: Backend, header_format: HeaderFormat) -> Result<(), HuffmanError> {
+        let trailing = Trailing::new(self.strict, self.skip_trailing);
+        let limits = &self.limits;
+
+        // Count the bytes read to bound the bytes written for them
+        let bytes_read = Cell::new(0);
+        let mut read = CountingRead::new(&mut self.read, &bytes_read);
+        let mut write = limits.expansion_write(write, &bytes_read);
+
+        self.trailing_bytes = match backend {
+            Backend::Huffman => {
+                let cache = self.tree_cache.as_ref();
+                let mut tree = limits.read_header(&mut read, |read| read_tree(read, header_format, cache))?;
+                decode_payload_trailing(&mut tree, &mut read, &mut write, bytes, trailing)?
+            },
+            Backend::Adaptive(_) => decode_with::<AdaptiveTree, _, _>(&mut read, write, bytes, trailing, limits)?,
+            Backend::Arithmetic => decode_with::<ArithmeticCoder, _, _>(&mut read, write, bytes, trailing, limits)?,
+            Backend::Range => decode_with::<RangeCoder, _, _>(&mut read, write, bytes, trailing, limits)?,
+            Backend::Tans => decode_with::<TansCoder, _, _>(&mut read, write, bytes, trailing, limits)?,
+            Backend::Context => decode_with::<ContextModel, _, _>(&mut read, write, bytes, trailing, limits)?,
+            Backend::Lz77 => lz77_decode_trailing(&mut read, &mut write, bytes, trailing)?,
+        };
 
         Ok(())
     }
 }
 
+/// Read the tree for `Backend::Huffman` in `header_format`, from `cache` if there is one.
+fn read_tree<R: Read>(read: &mut R, header_format: HeaderFormat, cache: Option<&Arc<TreeCache>>) -> Result<Arc<CanonicalTree>, HuffmanError> {
+    match header_format {
+        HeaderFormat::CodeLengths => match cache {
+            Some(cache) => {
+                let mut code_lengths = [0; NUM_BYTES];
+                read.read_exact(&mut code_lengths)?;
+
+                cache.get_or_build(&code_lengths)
+            },
+            None => Ok(<Arc<CanonicalTree> as EntropyCoder>::read_header(read)?),
+        },
+        HeaderFormat::TreeShape => Ok(Arc::new(CanonicalTree::read_shape(read)?)),
+        #[cfg(feature = "builtin-codebooks")]
+        HeaderFormat::Builtin => {
+            let id = read.read_u8()?;
+            Ok(Arc::new(BuiltinCodebook::from_id(id).ok_or_else(|| HuffmanError::CorruptHeader(format!("Unknown built-in codebook {}", id)))?.tree()))
+        },
+    }
+}
+
+/// Fail if `out` cannot hold `bytes` bytes.
+fn check_output_size(bytes: u64, out: &[u8]) -> Result<(), HuffmanError> {
+    if bytes > out.len() as u64 {
+        return Err(HuffmanError::InvalidInput(format!("Output buffer of {} bytes is too small for {} bytes", out.len(), bytes)));
+    }
+
+    Ok(())
+}
+
+/// Read the header of a `C` within `limits` and then decode `bytes` bytes with it, returning the
+/// number of bytes skipped after them.
+fn decode_with<C: EntropyCoder, R: Read, W: Write>(read: &mut R, mut write: W, bytes: u64, trailing: Trailing, limits: &DecodeLimits) -> Result<u64, HuffmanError> {
+    let mut coder = limits.read_header(&mut *read, |read| Ok(C::read_header(read)?))?;
+    Ok(decode_payload_trailing(&mut coder, read, &mut write, bytes, trailing)?)
+}
+
 /// Helper function to encode files.
-pub fn encode_file<P: AsRef<Path>>(in_file: P, out_file: P) -> Result<(), Box<Error>> {
+///
+/// With the `mmap` feature, the input file is memory mapped instead of read.
+pub fn encode_file<P: AsRef<Path>>(in_file: P, out_file: P) -> Result<(), HuffmanError> {
     if out_file.as_ref().exists() {
-        return Err(From::from("Out file already exists"));
+        return Err(HuffmanError::Io(io::Error::new(io::ErrorKind::AlreadyExists, "Out file already exists")));
     }
 
-    let read = BufReader::new(File::open(in_file)?);
-    let write = BufWriter::new(File::create(out_file)?);
+    // Count the frequencies and encode straight from the mapped file
+    #[cfg(feature = "mmap")]
+    {
+        let map = MappedFile::open(in_file)?;
+        let write = BufWriter::new(File::create(out_file)?);
+
+        Encoder::from_slice(&map)?.encode(write)
+    }
 
-    let mut encoder = Encoder::new(read)?;
+    #[cfg(not(feature = "mmap"))]
+    {
+        // Count the frequencies on multiple threads before encoding
+        let (bytes_read, freq_table) = file_frequencies(&in_file)?;
 
-    encoder.encode(write)?;
+        let read = BufReader::new(File::open(in_file)?);
+        let write = BufWriter::new(File::create(out_file)?);
 
-    Ok(())
+        let mut encoder = Encoder::with_frequencies(read, bytes_read, &freq_table)?;
+
+        encoder.encode(write)
+    }
 }
 
 /// Helper function to decode files.
-pub fn decode_file<P: AsRef<Path>>(in_file: P, out_file: P) -> Result<(), Box<Error>> {
+///
+/// With the `mmap` feature, the input file is memory mapped instead of read.
+pub fn decode_file<P: AsRef<Path>>(in_file: P, out_file: P) -> Result<(), HuffmanError> {
     if out_file.as_ref().exists() {
-        return Err(From::from("Out file already exists"));
+        return Err(HuffmanError::Io(io::Error::new(io::ErrorKind::AlreadyExists, "Out file already exists")));
     }
 
+    #[cfg(feature = "mmap")]
+    let map = MappedFile::open(in_file)?;
+    #[cfg(feature = "mmap")]
+    let read = &map[..];
+    #[cfg(not(feature = "mmap"))]
     let read = BufReader::new(File::open(in_file)?);
+
     let write = BufWriter::new(File::create(out_file)?);
 
     let mut decoder = Decoder::new(read);
 
-    decoder.decode(write)?;
-
-    Ok(())
+    decoder.decode(write)
 }
 
 #[cfg(test)]
@@ -154,6 +798,278 @@ mod tests {
         assert!(encode_decode_test(file));
     }
 
+    #[test]
+    fn test_empty() {
+        assert!(encode_decode_raw_test(&[]));
+
+        let mut encoder = Encoder::new(Cursor::new(Vec::new())).unwrap();
+
+        let mut encoded = Vec::new();
+        encoder.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, vec![0; 8]);
+
+        let mut decoded = Vec::new();
+        assert!(Decoder::new(Cursor::new(&encoded)).strict(true).decode(&mut decoded).is_ok());
+        assert!(decoded.is_empty());
+
+        encoded.push(0);
+        assert!(Decoder::new(Cursor::new(&encoded)).strict(true).decode(&mut decoded).is_err());
+    }
+
+    #[test]
+    fn test_single_symbol() {
+        assert!(encode_decode_raw_test(&[0; 10000]));
+        assert!(encode_decode_raw_test(b"a"));
+    }
+
+    #[test]
+    fn test_strict_decoder() {
+        let mut encoder = Encoder::new(Cursor::new("a small sample string")).unwrap();
+
+        let mut encoded = Vec::new();
+        encoder.encode(&mut encoded).unwrap();
+
+        let mut decoded = Vec::new();
+        assert!(Decoder::new(Cursor::new(&encoded)).strict(true).decode(&mut decoded).is_ok());
+
+        // Two streams concatenated
+        let mut concatenated = encoded.clone();
+        concatenated.extend_from_slice(&encoded);
+
+        assert!(Decoder::new(Cursor::new(&concatenated)).decode(&mut Vec::new()).is_ok());
+        assert!(Decoder::new(Cursor::new(&concatenated)).strict(true).decode(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_skip_trailing() {
+        let text = "the quick brown fox jumps over the lazy dog";
+        let backends = [Backend::Huffman, Backend::Adaptive(AdaptiveAlgorithm::Vitter), Backend::Arithmetic, Backend::Range, Backend::Tans, Backend::Context, Backend::Lz77];
+
+        for &data in [&b""[..], text.as_bytes()].iter() {
+            for &backend in backends.iter() {
+                let mut encoded = Vec::new();
+                Encoder::from_slice(data).unwrap().backend(backend).encode(&mut encoded).unwrap();
+
+                for &garbage in [0, 1, 100].iter() {
+                    let mut padded = encoded.clone();
+                    padded.resize(encoded.len() + garbage, 0xaa);
+
+                    let mut decoder = Decoder::new(Cursor::new(&padded)).backend(backend).strict(true).skip_trailing(true);
+                    let mut decoded = Vec::new();
+                    decoder.decode(&mut decoded).unwrap();
+                    assert_eq!(decoded, data);
+                    assert_eq!(decoder.trailing_bytes(), garbage as u64, "{:?} with {} bytes", backend, garbage);
+                }
+            }
+        }
+
+        // The rest of the last byte of the coded data must still be zero
+        let mut encoded = Vec::new();
+        Encoder::from_slice(b"abc").unwrap().encode(&mut encoded).unwrap();
+        *encoded.last_mut().unwrap() |= 1;
+        assert!(matches!(Decoder::new(Cursor::new(&encoded)).strict(true).skip_trailing(true).decode(Vec::new()), Err(HuffmanError::TrailingData)));
+        assert!(Decoder::new(Cursor::new(&encoded)).skip_trailing(true).decode(Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_corrupt_code_lengths() {
+        let mut encoded = Vec::new();
+        Encoder::from_slice(b"abracadabra").unwrap().encode(&mut encoded).unwrap();
+
+        let is_corrupt = |code_lengths: &[u8; NUM_BYTES]| {
+            let mut corrupt = encoded.clone();
+            corrupt[10..10 + NUM_BYTES].copy_from_slice(code_lengths);
+
+            // Nothing is decoded with a corrupt tree
+            let mut decoded = Vec::new();
+            let result = Decoder::new(Cursor::new(corrupt)).decode(&mut decoded);
+            let is_corrupt = matches!(result, Err(HuffmanError::CorruptHeader(_)));
+            assert!(!is_corrupt || decoded.is_empty());
+            is_corrupt
+        };
+
+        let mut code_lengths = [0; NUM_BYTES];
+        assert!(is_corrupt(&code_lengths));
+
+        // A lone byte with a 1 bit code is complete, but not with a longer one. The data may not
+        // decode with the wrong tree, but the tree is built
+        code_lengths[b'a' as usize] = 1;
+        assert!(!is_corrupt(&code_lengths));
+        code_lengths[b'a' as usize] = 2;
+        assert!(is_corrupt(&code_lengths));
+
+        code_lengths[b'b' as usize] = 2;
+        assert!(is_corrupt(&code_lengths));
+        code_lengths[b'c' as usize] = 1;
+        assert!(!is_corrupt(&code_lengths));
+        code_lengths[b'd' as usize] = 1;
+        assert!(is_corrupt(&code_lengths));
+
+        code_lengths = [0; NUM_BYTES];
+        code_lengths[0] = 200;
+        code_lengths[1] = 1;
+        assert!(is_corrupt(&code_lengths));
+    }
+
+    #[test]
+    fn test_tree_shape_header() {
+        let text = "a small sample string";
+
+        let mut encoder = Encoder::new(Cursor::new(text)).unwrap().header_format(HeaderFormat::TreeShape);
+        let mut encoded = Vec::new();
+        encoder.encode(&mut encoded).unwrap();
+
+        let tree = CanonicalTree::from_slice(text.as_bytes()).unwrap();
+        let header_bytes = HeaderFormat::TreeShape.header_size(&tree);
+        assert!(header_bytes < HeaderFormat::CodeLengths.header_size(&tree));
+        assert!(encoded.len() as u64 > header_bytes);
+
+        let mut decoded = Vec::new();
+        Decoder::new(Cursor::new(&encoded)).header_format(HeaderFormat::TreeShape).strict(true)
+            .decode(&mut decoded).unwrap();
+        assert_eq!(decoded, text.as_bytes());
+
+        assert!(Decoder::new(Cursor::new(&encoded)).header_format(HeaderFormat::CodeLengths).decode(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_backends() {
+        let text = "a small sample string, with a few more words to give it some skew";
+
+        let backends = [
+            Backend::Arithmetic,
+            Backend::Range,
+            Backend::Tans,
+            Backend::Adaptive(AdaptiveAlgorithm::Fgk),
+            Backend::Adaptive(AdaptiveAlgorithm::Vitter),
+            Backend::Context,
+            Backend::Lz77,
+        ];
+
+        for backend in backends {
+            let mut encoder = Encoder::new(Cursor::new(text)).unwrap().backend(backend);
+            let mut encoded = Vec::new();
+            encoder.encode(&mut encoded).unwrap();
+
+            let mut decoded = Vec::new();
+            Decoder::new(Cursor::new(&encoded)).backend(backend).strict(true)
+                .decode(&mut decoded).unwrap();
+            assert_eq!(decoded, text.as_bytes());
+
+            // The backend is read from the data, so it only needs to be given to refuse others
+            decoded.clear();
+            Decoder::new(Cursor::new(&encoded)).decode(&mut decoded).unwrap();
+            assert_eq!(decoded, text.as_bytes());
+            assert!(Decoder::new(Cursor::new(&encoded)).backend(Backend::Huffman).decode(&mut Vec::new()).is_err());
+
+            encoded.push(0);
+            assert!(Decoder::new(Cursor::new(&encoded)).backend(backend).strict(true)
+                .decode(&mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_format_stability() {
+        let text = "the quick brown fox jumps over the lazy dog, and the dog sleeps. ".repeat(50);
+
+        let pipelines = [
+            PipelineBuilder::new().build(),
+            PipelineBuilder::new().header_format(HeaderFormat::TreeShape).build(),
+            PipelineBuilder::new().max_code_length(5).build(),
+            PipelineBuilder::new().backend(Backend::Arithmetic).build(),
+            PipelineBuilder::new().backend(Backend::Range).build(),
+            PipelineBuilder::new().backend(Backend::Tans).build(),
+            PipelineBuilder::new().backend(Backend::Adaptive(AdaptiveAlgorithm::Fgk)).build(),
+            PipelineBuilder::new().backend(Backend::Adaptive(AdaptiveAlgorithm::Vitter)).build(),
+            PipelineBuilder::new().backend(Backend::Context).build(),
+            PipelineBuilder::new().backend(Backend::Lz77).build(),
+            PipelineBuilder::new().stage(Stage::Bwt { block_size: 1000 }).stage(Stage::Mtf).stage(Stage::Rle).build(),
+            PipelineBuilder::new().stage(Stage::Delta { stride: 3 }).build(),
+        ];
+
+        let checksums: Vec<u32> = pipelines.iter()
+            .map(|pipeline| {
+                let mut encoded = Vec::new();
+                pipeline.encode(SliceSource::new(text.as_bytes()), &mut encoded).unwrap();
+                crc32(&encoded)
+            })
+            .collect();
+
+        // Changing any of these changes the output, which must come with a new FORMAT_STABILITY
+        assert_eq!(FORMAT_STABILITY, 3);
+        assert_eq!(checksums, [
+            0xba6c_051c, 0x92ce_f555, 0x522f_3fa4, 0x5d22_b2a2, 0x0e35_36e7, 0xa09c_e65b,
+            0xc101_037f, 0xd14e_71e1, 0xc440_58b4, 0xeaec_c16a, 0x469e_c578, 0xf416_76b2,
+        ]);
+
+        let mut encoded = Vec::new();
+        BlockEncoder::new(Pipeline::default()).block_size(1000).unwrap().checksum(true).encode(text.as_bytes(), &mut encoded).unwrap();
+        assert_eq!(crc32(&encoded), 0xe973_0d1b);
+    }
+
+    #[test]
+    fn test_older_layout() {
+        let text = "a small sample string, with a few more words to give it some skew";
+        let freq_table = slice_frequencies(text.as_bytes()).unwrap();
+
+        // The new layout marks that the backend and header format follow the size
+        let mut encoded = Vec::new();
+        Encoder::from_slice(text.as_bytes()).unwrap().encode(&mut encoded).unwrap();
+        assert_ne!((&encoded[..]).read_u64::<LittleEndian>().unwrap() & CODING_FLAG, 0);
+
+        // Data written before they were recorded: the size, then the header and codes
+        let older = |coded: &dyn Fn(&mut Vec<u8>)| {
+            let mut older = Vec::new();
+            older.write_u64::<LittleEndian>(text.len() as u64).unwrap();
+            coded(&mut older);
+            older
+        };
+
+        let huffman = older(&|write| encode_with(CanonicalTree::from_frequencies(&freq_table).unwrap(), &mut SliceSource::new(text.as_bytes()), write).unwrap());
+        let range = older(&|write| encode_with(RangeCoder::from_frequencies(&freq_table).unwrap(), &mut SliceSource::new(text.as_bytes()), write).unwrap());
+
+        let mut decoded = Vec::new();
+        Decoder::new(Cursor::new(&huffman)).strict(true).decode(&mut decoded).unwrap();
+        assert_eq!(decoded, text.as_bytes());
+
+        let mut out = [0; 100];
+        let bytes = Decoder::new(Cursor::new(&huffman)).strict(true).decode_into(&mut out).unwrap();
+        assert_eq!(&out[..bytes], text.as_bytes());
+
+        // Decoded with the backend the `Decoder` is given
+        decoded.clear();
+        Decoder::new(Cursor::new(&range)).backend(Backend::Range).strict(true).decode(&mut decoded).unwrap();
+        assert_eq!(decoded, text.as_bytes());
+    }
+
+    #[test]
+    fn test_decode_into() {
+        let text = "a small sample string, with a few more words to give it some skew";
+
+        for backend in [Backend::Huffman, Backend::Range] {
+            let mut encoded = Vec::new();
+            Encoder::new(Cursor::new(text)).unwrap().backend(backend).encode(&mut encoded).unwrap();
+
+            let mut out = [0xff; 100];
+            let decoded = Decoder::new(Cursor::new(&encoded)).backend(backend).strict(true).decode_into(&mut out).unwrap();
+            assert_eq!(&out[..decoded], text.as_bytes());
+            assert_eq!(out[decoded], 0xff);
+
+            let mut short = [0; 10];
+            assert!(Decoder::new(Cursor::new(&encoded)).backend(backend).decode_into(&mut short).is_err());
+        }
+
+        let mut encoded = Vec::new();
+        Encoder::new(Cursor::new(text)).unwrap().stage(Stage::Mtf).encode(&mut encoded).unwrap();
+        let mut out = [0; 100];
+        let decoded = Decoder::new(Cursor::new(&encoded)).decode_into(&mut out).unwrap();
+        assert_eq!(&out[..decoded], text.as_bytes());
+
+        let mut encoded = Vec::new();
+        Encoder::new(Cursor::new("")).unwrap().encode(&mut encoded).unwrap();
+        assert_eq!(Decoder::new(Cursor::new(&encoded)).decode_into(&mut []).unwrap(), 0);
+    }
+
     fn encode_decode_test<R: Read + Seek>(mut read: R) -> bool {
         // Read the entire read into memory
         let mut original = Vec::new();
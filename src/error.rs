@@ -0,0 +1,336 @@
+use core::fmt;
+use core::convert::Infallible;
+#[cfg(feature = "std")]
+use std::io;
+use core::error::Error;
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    /// The bits do not match any code.
+    InvalidCode,
+    /// The data ended in the middle of a code, or before all expected symbols were decoded.
+    UnexpectedEnd,
+}
+
+/// Which of the `DecodeLimits` was hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    /// The size of the decoded data.
+    OutputSize,
+    /// The size of the tree or model before the coded data.
+    HeaderSize,
+    /// The memory held by the tables built from the header.
+    TableMemory,
+    /// The bytes decoded for each byte read.
+    Expansion,
+}
+
+/// Error describing where decoding failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    /// Offset of the first bit of the failing code, from the start of the encoded data.
+    pub bit_offset: u64,
+    /// Number of symbols successfully decoded before the error.
+    pub symbols_decoded: u64,
+    /// The bits of the failing code that were read.
+    pub partial_code: Code,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DecodeErrorKind::InvalidCode => write!(f, "File corrupt: Invalid code")?,
+            DecodeErrorKind::UnexpectedEnd if self.partial_code.length() == 0 => write!(f, "File corrupt: Data ended")?,
+            DecodeErrorKind::UnexpectedEnd => write!(f, "File corrupt: Data ended in the middle of code")?,
+        }
+
+        if self.partial_code.length() > 0 {
+            write!(f, " '{}'", self.partial_code)?;
+        }
+
+        write!(f, " at bit offset {} (byte {}, bit {}) after {} decoded symbols",
+            self.bit_offset, self.bit_offset / 8, self.bit_offset % 8, self.symbols_decoded)
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Error returned when building trees, encoding, or decoding.
+#[derive(Debug)]
+pub enum HuffmanError {
+    /// Reading or writing failed.
+    ///
+    /// Reads and writes that are `Interrupted` are tried again, so this is never `Interrupted`. A
+    /// `WouldBlock` from a non-blocking reader or writer is passed on with its kind, and what was
+    /// read or written before it is lost.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// There is no data to build a tree from.
+    EmptyInput,
+    /// A header, or the code lengths or tree it holds, is not valid.
+    CorruptHeader(String),
+    /// A code is not valid, or the data ends before all of the codes.
+    Decode(DecodeError),
+    /// Something other than zero padding follows the coded data.
+    TrailingData,
+    /// A symbol being encoded has no code, such as a byte not seen when a reused tree was built.
+    ///
+    /// `EscapeTree::from_tree` gives a tree with the same codes that can encode any byte.
+    SymbolNotInCodebook {
+        /// Index of the symbol.
+        symbol: usize,
+        /// Number of symbols before it in the input being encoded.
+        offset: u64,
+    },
+    /// Data does not match its checksum.
+    ChecksumMismatch,
+    /// Decoding would go past one of the `DecodeLimits`.
+    LimitExceeded { kind: LimitKind, message: String },
+    /// An argument or option is not valid.
+    InvalidInput(String),
+    /// Any other error, such as one returned by a callback.
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+/// A stable number for each kind of `HuffmanError`, for bindings to other languages.
+///
+/// The numbers never change and are never reused, and 0 is left free to mean success.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(i32)]
+pub enum ErrorCode {
+    /// `HuffmanError::Io`.
+    Io = 1,
+    /// `HuffmanError::EmptyInput`.
+    EmptyInput = 2,
+    /// `HuffmanError::CorruptHeader`.
+    CorruptHeader = 3,
+    /// `HuffmanError::Decode` with `DecodeErrorKind::InvalidCode`.
+    InvalidCode = 4,
+    /// `HuffmanError::Decode` with `DecodeErrorKind::UnexpectedEnd`.
+    UnexpectedEnd = 5,
+    /// `HuffmanError::TrailingData`.
+    TrailingData = 6,
+    /// `HuffmanError::SymbolNotInCodebook`.
+    SymbolNotInCodebook = 7,
+    /// `HuffmanError::ChecksumMismatch`.
+    ChecksumMismatch = 8,
+    /// `HuffmanError::LimitExceeded` with `LimitKind::OutputSize`.
+    OutputSizeLimit = 9,
+    /// `HuffmanError::LimitExceeded` with `LimitKind::HeaderSize`.
+    HeaderSizeLimit = 10,
+    /// `HuffmanError::LimitExceeded` with `LimitKind::TableMemory`.
+    TableMemoryLimit = 11,
+    /// `HuffmanError::LimitExceeded` with `LimitKind::Expansion`.
+    ExpansionLimit = 12,
+    /// `HuffmanError::InvalidInput`.
+    InvalidInput = 13,
+    /// `HuffmanError::Other`.
+    Other = 14,
+}
+
+impl ErrorCode {
+    /// The number for the code.
+    pub fn value(self) -> i32 {
+        self as i32
+    }
+}
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            #[cfg(feature = "std")]
+            HuffmanError::Io(ref err) => write!(f, "{}", err),
+            HuffmanError::EmptyInput => write!(f, "No data to build a tree from"),
+            HuffmanError::CorruptHeader(ref message) => write!(f, "Corrupt header: {}", message),
+            HuffmanError::Decode(ref err) => write!(f, "{}", err),
+            HuffmanError::TrailingData => write!(f, "Unexpected data after the encoded bytes"),
+            HuffmanError::SymbolNotInCodebook { symbol, offset } => write!(f, "Symbol {} not found in code book at input offset {}", symbol, offset),
+            HuffmanError::ChecksumMismatch => write!(f, "Data does not match its checksum"),
+            HuffmanError::LimitExceeded { ref message, .. } => write!(f, "{}", message),
+            HuffmanError::InvalidInput(ref message) => write!(f, "{}", message),
+            HuffmanError::Other(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl HuffmanError {
+    /// The stable code for the kind of error.
+    pub fn code(&self) -> ErrorCode {
+        match *self {
+            #[cfg(feature = "std")]
+            HuffmanError::Io(_) => ErrorCode::Io,
+            HuffmanError::EmptyInput => ErrorCode::EmptyInput,
+            HuffmanError::CorruptHeader(_) => ErrorCode::CorruptHeader,
+            HuffmanError::Decode(ref err) => match err.kind {
+                DecodeErrorKind::InvalidCode => ErrorCode::InvalidCode,
+                DecodeErrorKind::UnexpectedEnd => ErrorCode::UnexpectedEnd,
+            },
+            HuffmanError::TrailingData => ErrorCode::TrailingData,
+            HuffmanError::SymbolNotInCodebook { .. } => ErrorCode::SymbolNotInCodebook,
+            HuffmanError::ChecksumMismatch => ErrorCode::ChecksumMismatch,
+            HuffmanError::LimitExceeded { kind, .. } => match kind {
+                LimitKind::OutputSize => ErrorCode::OutputSizeLimit,
+                LimitKind::HeaderSize => ErrorCode::HeaderSizeLimit,
+                LimitKind::TableMemory => ErrorCode::TableMemoryLimit,
+                LimitKind::Expansion => ErrorCode::ExpansionLimit,
+            },
+            HuffmanError::InvalidInput(_) => ErrorCode::InvalidInput,
+            HuffmanError::Other(_) => ErrorCode::Other,
+        }
+    }
+
+    /// The message describing the error, the same as its `Display`.
+    pub fn message(&self) -> String {
+        self.to_string()
+    }
+
+    /// Move the offset of a `SymbolNotInCodebook` error `symbols` further into the input, for an
+    /// error from coding a chunk that starts there.
+    #[cfg(feature = "std")]
+    pub(crate) fn add_offset(&mut self, symbols: u64) {
+        if let HuffmanError::SymbolNotInCodebook { ref mut offset, .. } = *self {
+            *offset += symbols;
+        }
+    }
+}
+
+impl Error for HuffmanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            #[cfg(feature = "std")]
+            HuffmanError::Io(ref err) => Some(err),
+            HuffmanError::Decode(ref err) => Some(err),
+            HuffmanError::Other(ref err) => Some(&**err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for HuffmanError {
+    fn from(err: io::Error) -> HuffmanError {
+        // Errors raised inside a `Read` or `Write` keep their kind
+        if err.get_ref().is_some_and(|inner| inner.is::<HuffmanError>()) {
+            let inner = err.into_inner().expect("Has an inner error");
+            return *inner.downcast::<HuffmanError>().expect("Inner error is a HuffmanError");
+        }
+
+        HuffmanError::Io(err)
+    }
+}
+
+impl From<DecodeError> for HuffmanError {
+    fn from(err: DecodeError) -> HuffmanError {
+        HuffmanError::Decode(err)
+    }
+}
+
+impl From<Infallible> for HuffmanError {
+    fn from(err: Infallible) -> HuffmanError {
+        match err {}
+    }
+}
+
+impl From<BufferOverflow> for HuffmanError {
+    fn from(err: BufferOverflow) -> HuffmanError {
+        HuffmanError::Other(Box::new(err))
+    }
+}
+
+/// Recovers the kind of an error passed on as a `Box<dyn Error>`. Errors of other types keep only
+/// their message, as they may not be `Send`.
+impl From<Box<dyn Error>> for HuffmanError {
+    fn from(err: Box<dyn Error>) -> HuffmanError {
+        let err = match err.downcast::<HuffmanError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+
+        let err = match err.downcast::<DecodeError>() {
+            Ok(err) => return HuffmanError::Decode(*err),
+            Err(err) => err,
+        };
+
+        #[cfg(feature = "std")]
+        let err = match err.downcast::<io::Error>() {
+            Ok(err) => return HuffmanError::from(*err),
+            Err(err) => err,
+        };
+
+        HuffmanError::Other(From::from(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = DecodeError {
+            kind: DecodeErrorKind::InvalidCode,
+            bit_offset: 21,
+            symbols_decoded: 7,
+            partial_code: Code::new(0b0110, 4),
+        };
+
+        assert_eq!(err.to_string(),
+            "File corrupt: Invalid code '0110' at bit offset 21 (byte 2, bit 5) after 7 decoded symbols");
+
+        let err = DecodeError {
+            kind: DecodeErrorKind::UnexpectedEnd,
+            bit_offset: 16,
+            symbols_decoded: 5,
+            partial_code: Code::new(0, 0),
+        };
+
+        assert_eq!(err.to_string(),
+            "File corrupt: Data ended at bit offset 16 (byte 2, bit 0) after 5 decoded symbols");
+    }
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_error_kinds() {
+        use std::io::Cursor;
+
+        assert!(matches!(CanonicalTree::from_read(&b""[..]), Err(HuffmanError::EmptyInput)));
+
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 1)]).unwrap();
+        assert!(matches!(tree.encode_symbols(b"abc", &mut Vec::new()), Err(HuffmanError::SymbolNotInCodebook { symbol: 99, offset: 2 })));
+        assert!(matches!(CanonicalTree::<u8>::from_bytes(&[0xff]), Err(HuffmanError::CorruptHeader(_))));
+
+        let mut encoded = Vec::new();
+        Encoder::from_slice(b"abracadabra").unwrap().encode(&mut encoded).unwrap();
+
+        let err = Decoder::new(Cursor::new(&encoded[..encoded.len() - 1])).decode(Vec::new()).unwrap_err();
+        assert!(matches!(err, HuffmanError::Decode(DecodeError { kind: DecodeErrorKind::UnexpectedEnd, .. })));
+
+        encoded.push(1);
+        let err = Decoder::new(Cursor::new(&encoded)).strict(true).decode(Vec::new()).unwrap_err();
+        assert!(matches!(err, HuffmanError::TrailingData));
+
+        // Errors passed on as a `Box<dyn Error>` keep their kind
+        let boxed: Box<dyn Error> = Box::new(HuffmanError::ChecksumMismatch);
+        assert!(matches!(HuffmanError::from(boxed), HuffmanError::ChecksumMismatch));
+        let boxed: Box<dyn Error> = From::from(io::Error::new(io::ErrorKind::NotFound, "Missing"));
+        assert!(matches!(HuffmanError::from(boxed), HuffmanError::Io(_)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(HuffmanError::EmptyInput.code(), ErrorCode::EmptyInput);
+        assert_eq!(HuffmanError::ChecksumMismatch.code().value(), 8);
+
+        let err = HuffmanError::LimitExceeded { kind: LimitKind::Expansion, message: "Too much".to_string() };
+        assert_eq!(err.code().value(), 12);
+        assert_eq!(err.message(), "Too much");
+
+        let mut encoded = Vec::new();
+        Encoder::from_slice(b"abracadabra").unwrap().encode(&mut encoded).unwrap();
+        let err = Decoder::new(&encoded[..encoded.len() - 1]).decode(Vec::new()).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::UnexpectedEnd);
+    }
+}
@@ -0,0 +1,289 @@
+//! Coding bytes with a code book that covers only some of them.
+//!
+//! The code book has an extra `ESCAPE_SYMBOL`. A byte without a code of its own is written as the
+//! escape code followed by the 8 bits of the byte, so a code book trained on typical data can
+//! still encode data containing bytes it has never seen.
+
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// The symbol whose code precedes a byte written as 8 raw bits.
+pub const ESCAPE_SYMBOL: u16 = NUM_BYTES as u16;
+
+/// Number of decoded bytes buffered before they are written.
+const ESCAPE_BUFFER_SIZE: usize = 4096;
+
+/// Canonical codes for some bytes and for `ESCAPE_SYMBOL`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeTree {
+    tree: CanonicalTree<u16>,
+}
+
+impl EscapeTree {
+    /// Use the codes of `tree`, which must have a code for `ESCAPE_SYMBOL` and no code for a
+    /// symbol above it.
+    pub fn new(tree: CanonicalTree<u16>) -> Result<EscapeTree, Box<dyn Error>> {
+        if tree.code_book().get(ESCAPE_SYMBOL).is_none() {
+            return Err(From::from("Tree has no code for the escape symbol"));
+        }
+
+        if let Some((symbol, _code)) = tree.code_book().iter().find(|&(symbol, _code)| symbol > ESCAPE_SYMBOL) {
+            return Err(From::from(format!("Symbol {} is not a byte or the escape symbol", symbol)));
+        }
+
+        Ok(EscapeTree {tree})
+    }
+
+    /// Build the codes from the frequency of each byte.
+    ///
+    /// Bytes with a frequency of 0 are left without a code, and `ESCAPE_SYMBOL` is given the
+    /// frequency `escape_frequency`, which should estimate how often unseen bytes occur.
+    pub fn from_frequencies<F: Frequencies<Symbol = u8> + ?Sized>(frequencies: &F, escape_frequency: u64) -> Result<EscapeTree, Box<dyn Error>> {
+        let mut table = FrequencyTable::new();
+
+        for (symbol, &frequency) in frequencies.frequencies().iter().enumerate() {
+            table.set(symbol as u16, frequency);
+        }
+        table.set(ESCAPE_SYMBOL, escape_frequency.max(1));
+
+        EscapeTree::new(CanonicalTree::from_frequencies(&table)?)
+    }
+
+    /// Add a code for `ESCAPE_SYMBOL` to the codes of `tree`, so a tree built from earlier data
+    /// can still encode bytes it has no code for.
+    ///
+    /// The last of the longest codes is split in two to make room for the escape code, so every
+    /// other byte keeps its code. Fails if that code is already `MAX_CODE_LENGTH` bits long.
+    pub fn from_tree(tree: &CanonicalTree) -> Result<EscapeTree, Box<dyn Error>> {
+        let mut code_lengths: Vec<(u16, u8)> = tree.code_book().iter()
+            .map(|(byte, code)| (byte as u16, code.length()))
+            .collect();
+
+        let &mut (_byte, ref mut length) = code_lengths.iter_mut()
+            .max_by_key(|&&mut (byte, length)| (length, byte))
+            .ok_or("Tree has no codes")?;
+
+        if *length == MAX_CODE_LENGTH {
+            return Err(From::from(format!("Longest code is already {} bits", MAX_CODE_LENGTH)));
+        }
+
+        *length += 1;
+        let escape_length = *length;
+        code_lengths.push((ESCAPE_SYMBOL, escape_length));
+
+        EscapeTree::new(CanonicalTree::new(code_lengths)?)
+    }
+
+    /// The codes, including the code for `ESCAPE_SYMBOL`.
+    pub fn tree(&self) -> &CanonicalTree<u16> {
+        &self.tree
+    }
+
+    /// Whether `byte` has a code of its own, rather than being escaped.
+    pub fn has_code(&self, byte: u8) -> bool {
+        self.tree.code_book().get(byte as u16).is_some()
+    }
+
+    /// Whether data with these frequencies can be encoded, which is always the case since any
+    /// byte can be escaped.
+    pub fn covers<F: Frequencies<Symbol = u8> + ?Sized>(&self, _frequencies: &F) -> bool {
+        true
+    }
+
+    /// Expected size of the encoded data as a fraction of its original size, for data with these
+    /// frequencies, counting each escaped byte as the escape code and 8 raw bits.
+    ///
+    /// Returns `None` if the frequencies are all 0.
+    pub fn estimated_ratio_for<F: Frequencies<Symbol = u8> + ?Sized>(&self, frequencies: &F) -> Option<f64> {
+        let escape_length = self.tree.code_length_of(ESCAPE_SYMBOL).expect("Escape symbol has a code") as u64;
+        let mut bits = 0;
+        let mut total = 0;
+
+        for (byte, &frequency) in frequencies.frequencies().iter().enumerate() {
+            let length = self.tree.code_length_of(byte as u16).map_or(escape_length + 8, |length| length as u64);
+            bits += length * frequency;
+            total += frequency;
+        }
+
+        if total == 0 {
+            return None;
+        }
+
+        Some(bits as f64 / (8 * total) as f64)
+    }
+
+    /// Write the code for a single byte, escaping it if it has no code.
+    pub fn encode_byte<W: Write>(&self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        match self.tree.code_book().get(byte as u16) {
+            Some(code) => bit_writer.write_code(code)?,
+            None => {
+                self.tree.encode_symbol(ESCAPE_SYMBOL, bit_writer)?;
+                bit_writer.write_bits_u64(byte as u64, 8)?;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Encode every byte of a `Read`, padding the last byte with zeros.
+    pub fn encode<R: Read, W: Write>(&self, read: &mut R, write: &mut W) -> Result<(), Box<dyn Error>> {
+        let mut bit_writer = BitWriter::new(write);
+        let mut buf = [0; ESCAPE_BUFFER_SIZE];
+
+        loop {
+            let bytes_read = read_some(read, &mut buf)?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            for &byte in buf[..bytes_read].iter() {
+                self.encode_byte(byte, &mut bit_writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a single byte.
+    ///
+    /// Returns `None` if the `BitReader` ends before a complete code, or in the raw bits of an
+    /// escaped byte.
+    pub fn decode_byte<R: Read>(&self, bit_reader: &mut BitReader<R>) -> Result<Option<u8>, Box<dyn Error>> {
+        self.decode_next(bit_reader, 0)
+    }
+
+    /// Decode exactly `bytes` bytes, failing with a `DecodeError` if the data ends early.
+    pub fn decode_exact<R: Read, W: Write>(&self, read: &mut R, write: &mut W, bytes: u64) -> Result<(), Box<dyn Error>> {
+        let mut bit_reader = BitReader::new(read);
+        let mut buf = Vec::with_capacity(ESCAPE_BUFFER_SIZE);
+        let mut bytes_read: u64 = 0;
+
+        while bytes_read < bytes {
+            let byte = match self.decode_next(&mut bit_reader, bytes_read) {
+                Ok(Some(byte)) => byte,
+                Ok(None) => {
+                    write.write_all(&buf)?;
+                    return Err(self.tree.decode_table().unexpected_end(&mut bit_reader, bytes_read).into());
+                },
+                Err(err) => {
+                    // Output everything decoded before the error
+                    write.write_all(&buf)?;
+                    return Err(err);
+                },
+            };
+
+            bytes_read += 1;
+            buf.push(byte);
+
+            if buf.len() == ESCAPE_BUFFER_SIZE {
+                write.write_all(&buf)?;
+                buf.clear();
+            }
+        }
+
+        write.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    fn decode_next<R: Read>(&self, bit_reader: &mut BitReader<R>, bytes_decoded: u64) -> Result<Option<u8>, Box<dyn Error>> {
+        match self.tree.decode_table().decode_checked(bit_reader, bytes_decoded)? {
+            Some(ESCAPE_SYMBOL) => {
+                if bit_reader.fill(8)? < 8 {
+                    return Ok(None);
+                }
+
+                let byte = (bit_reader.peek() >> 56) as u8;
+                bit_reader.consume(8);
+
+                Ok(Some(byte))
+            },
+            Some(symbol) => Ok(Some(symbol as u8)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_escape() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"aaaabbc");
+
+        let tree = EscapeTree::from_frequencies(&frequencies, 1).unwrap();
+        assert!(tree.has_code(b'a'));
+        assert!(!tree.has_code(b'z'));
+
+        let data = b"abzcaa\xff";
+        let mut encoded = Vec::new();
+        tree.encode(&mut Cursor::new(&data[..]), &mut encoded).unwrap();
+
+        let mut decoded = Vec::new();
+        tree.decode_exact(&mut Cursor::new(&encoded), &mut decoded, data.len() as u64).unwrap();
+        assert_eq!(decoded, data.to_vec());
+
+        // The raw bits of the last escaped byte are cut short
+        let mut decoded = Vec::new();
+        let err = tree.decode_exact(&mut Cursor::new(&encoded[..encoded.len() - 1]), &mut decoded, data.len() as u64)
+            .unwrap_err();
+        assert!(matches!(HuffmanError::from(err), HuffmanError::Decode(DecodeError { kind: DecodeErrorKind::UnexpectedEnd, .. })));
+        assert_eq!(decoded, data[..6].to_vec());
+    }
+
+    #[test]
+    fn test_estimated_ratio() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"aaa");
+
+        // Codes 0 for 'a' and 1 for the escape
+        let tree = EscapeTree::from_frequencies(&frequencies, 1).unwrap();
+
+        frequencies.count(b"z");
+        assert!(tree.covers(&frequencies));
+        assert_eq!(tree.estimated_ratio_for(&frequencies), Some((3.0 + 9.0) / 32.0));
+        assert_eq!(tree.estimated_ratio_for(&FrequencyTable::new()), None);
+    }
+
+    #[test]
+    fn test_from_tree() {
+        let tree = CanonicalTree::from_slice(b"aaaabbc").unwrap();
+        let data = [&b"ab".repeat(READ_BUFFER_SIZE)[..], b"zc"].concat();
+
+        let err = tree.encode(&mut Cursor::new(&data), &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, HuffmanError::SymbolNotInCodebook { symbol: 122, offset } if offset == 2 * READ_BUFFER_SIZE as u64));
+        assert_eq!(err.to_string(), format!("Symbol 122 not found in code book at input offset {}", 2 * READ_BUFFER_SIZE));
+
+        // Only the last of the longest codes changes
+        let escape_tree = EscapeTree::from_tree(&tree).unwrap();
+        assert_eq!(escape_tree.tree().code_book().get(b'a' as u16), tree.code_book().get(b'a'));
+        assert_eq!(escape_tree.tree().code_book().get(b'b' as u16), tree.code_book().get(b'b'));
+        assert_eq!(escape_tree.tree().code_length_of(b'c' as u16), Some(3));
+
+        let mut encoded = Vec::new();
+        escape_tree.encode(&mut Cursor::new(&data), &mut encoded).unwrap();
+
+        let mut decoded = Vec::new();
+        escape_tree.decode_exact(&mut Cursor::new(&encoded), &mut decoded, data.len() as u64).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_new() {
+        let tree = CanonicalTree::new(vec![(b'a' as u16, 1), (ESCAPE_SYMBOL, 1)]).unwrap();
+        assert!(EscapeTree::new(tree).is_ok());
+
+        let tree = CanonicalTree::new(vec![(b'a' as u16, 1), (b'b' as u16, 1)]).unwrap();
+        assert!(EscapeTree::new(tree).is_err());
+
+        let tree = CanonicalTree::new(vec![(ESCAPE_SYMBOL, 1), (ESCAPE_SYMBOL + 1, 1)]).unwrap();
+        assert!(EscapeTree::new(tree).is_err());
+    }
+}
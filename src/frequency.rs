@@ -0,0 +1,175 @@
+use core::marker::PhantomData;
+use core::convert::TryInto;
+
+use super::*;
+
+/// The frequency of each symbol of an alphabet, indexed by symbol.
+pub trait Frequencies {
+    type Symbol: Symbol;
+
+    /// The frequency of each symbol, with at most `Symbol::ALPHABET_SIZE` entries.
+    fn frequencies(&self) -> &[u64];
+}
+
+impl Frequencies for [u64; NUM_BYTES] {
+    type Symbol = u8;
+
+    fn frequencies(&self) -> &[u64] {
+        self
+    }
+}
+
+/// The number of times each symbol occurs in some data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrequencyTable<S = u8> {
+    frequencies: Vec<u64>,
+    symbol: PhantomData<S>,
+}
+
+impl<S: Symbol> FrequencyTable<S> {
+    /// Create a table where every symbol has a frequency of 0.
+    pub fn new() -> FrequencyTable<S> {
+        FrequencyTable {frequencies: vec![0; S::ALPHABET_SIZE], symbol: PhantomData}
+    }
+
+    /// Count every symbol in `symbols`.
+    pub fn count(&mut self, symbols: &[S]) {
+        for &symbol in symbols {
+            self.frequencies[symbol.to_index()] += 1;
+        }
+    }
+
+    /// Count a single occurrence of `symbol`.
+    pub fn add_symbol(&mut self, symbol: S) {
+        self.frequencies[symbol.to_index()] += 1;
+    }
+
+    /// Add the frequencies counted by another table.
+    pub fn merge(&mut self, other: &FrequencyTable<S>) {
+        for (frequency, &other) in self.frequencies.iter_mut().zip(other.frequencies.iter()) {
+            *frequency = frequency.saturating_add(other);
+        }
+    }
+
+    /// The frequency of `symbol`.
+    pub fn get(&self, symbol: S) -> u64 {
+        self.frequencies[symbol.to_index()]
+    }
+
+    /// Set the frequency of `symbol`.
+    pub fn set(&mut self, symbol: S, frequency: u64) {
+        self.frequencies[symbol.to_index()] = frequency;
+    }
+
+    /// Total number of symbols counted, saturating at `u64::MAX`.
+    pub fn total(&self) -> u64 {
+        self.frequencies.iter().fold(0, |total: u64, &frequency| total.saturating_add(frequency))
+    }
+
+    /// Number of distinct symbols counted.
+    pub fn symbols(&self) -> usize {
+        self.frequencies.iter().filter(|&&frequency| frequency > 0).count()
+    }
+
+    /// Returns true if no symbols have been counted.
+    pub fn is_empty(&self) -> bool {
+        self.frequencies.iter().all(|&frequency| frequency == 0)
+    }
+
+    /// Iterate over the symbols that have been counted with their frequencies, in increasing
+    /// order of symbol.
+    pub fn iter(&self) -> impl Iterator<Item = (S, u64)> + '_ {
+        self.frequencies.iter().enumerate()
+            .filter(|&(_symbol, &frequency)| frequency > 0)
+            .map(|(symbol, &frequency)| (S::from_index(symbol), frequency))
+    }
+
+    /// The frequency of every symbol, indexed by symbol.
+    pub fn as_slice(&self) -> &[u64] {
+        &self.frequencies
+    }
+}
+
+impl FrequencyTable<u8> {
+    /// The frequency of every byte, indexed by byte.
+    pub fn as_array(&self) -> &[u64; NUM_BYTES] {
+        self.frequencies[..].try_into().expect("Byte frequency table has a frequency for every byte")
+    }
+}
+
+impl<S: Symbol> Frequencies for FrequencyTable<S> {
+    type Symbol = S;
+
+    fn frequencies(&self) -> &[u64] {
+        &self.frequencies
+    }
+}
+
+impl<S: Symbol> Default for FrequencyTable<S> {
+    fn default() -> FrequencyTable<S> {
+        FrequencyTable::new()
+    }
+}
+
+impl From<[u64; NUM_BYTES]> for FrequencyTable<u8> {
+    fn from(frequencies: [u64; NUM_BYTES]) -> FrequencyTable<u8> {
+        FrequencyTable {frequencies: frequencies.to_vec(), symbol: PhantomData}
+    }
+}
+
+impl From<FrequencyTable<u8>> for [u64; NUM_BYTES] {
+    fn from(table: FrequencyTable<u8>) -> [u64; NUM_BYTES] {
+        *table.as_array()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_and_merge() {
+        let mut table = FrequencyTable::new();
+        assert!(table.is_empty());
+
+        table.count(b"abracadabra");
+        assert_eq!(table.get(b'a'), 5);
+        assert_eq!(table.get(b'b'), 2);
+        assert_eq!(table.get(b'z'), 0);
+        assert_eq!(table.total(), 11);
+        assert_eq!(table.symbols(), 5);
+
+        let mut other = FrequencyTable::new();
+        other.add_symbol(b'z');
+        other.add_symbol(b'a');
+
+        table.merge(&other);
+        assert_eq!(table.get(b'a'), 6);
+        assert_eq!(table.get(b'z'), 1);
+        assert_eq!(table.total(), 13);
+
+        let frequencies: Vec<(u8, u64)> = table.iter().collect();
+        assert_eq!(frequencies, vec![(b'a', 6), (b'b', 2), (b'c', 1), (b'd', 1), (b'r', 2), (b'z', 1)]);
+    }
+
+    #[test]
+    fn test_from_array() {
+        let mut frequencies = [0; NUM_BYTES];
+        frequencies[3] = 7;
+
+        let table = FrequencyTable::from(frequencies);
+        assert_eq!(table.get(3), 7);
+        assert_eq!(table.as_array(), &frequencies);
+        assert_eq!(<[u64; NUM_BYTES]>::from(table), frequencies);
+    }
+
+    #[test]
+    fn test_wide_symbols() {
+        let mut table = FrequencyTable::new();
+        table.count(&[1000u16, 1000, 65535]);
+
+        assert_eq!(table.get(1000), 2);
+        assert_eq!(table.as_slice().len(), 65536);
+        assert_eq!(table.iter().collect::<Vec<_>>(), vec![(1000, 2), (65535, 1)]);
+    }
+}
@@ -1,46 +1,81 @@
-use std;
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, VecDeque};
+use core::borrow::Borrow;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+#[cfg(feature = "std")]
+use std::error::Error;
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+#[cfg(not(feature = "std"))]
+use alloc::collections::{BTreeMap, BinaryHeap, VecDeque};
+use core::fmt::Display;
+use core::hash::Hash;
 
 use super::*;
 
-#[derive(Debug, Eq)]
-pub struct HuffmanType {
-    symbol: u8,
+/// Total weight that probabilities are scaled to.
+const PROBABILITY_SCALE: f64 = (1u64 << 40) as f64;
+
+#[derive(Debug)]
+pub struct HuffmanType<S = u8> {
+    symbol: Option<S>,
     frequency: u64,
 }
 
-impl HuffmanType {
-    pub fn new(symbol: u8, frequency: u64) -> HuffmanType {
-        HuffmanType { symbol, frequency }
+impl<S> HuffmanType<S> {
+    /// The value of a leaf.
+    pub fn new(symbol: S, frequency: u64) -> HuffmanType<S> {
+        HuffmanType { symbol: Some(symbol), frequency }
+    }
+
+    /// The value of an internal node.
+    pub fn internal(frequency: u64) -> HuffmanType<S> {
+        HuffmanType { symbol: None, frequency }
+    }
+
+    /// The symbol of a leaf, or `None` for an internal node.
+    pub fn symbol(&self) -> Option<&S> {
+        self.symbol.as_ref()
+    }
+
+    /// The symbol of a node known to be a leaf.
+    fn leaf_symbol(&self) -> S where S: Clone {
+        self.symbol.clone().expect("Leaves have a symbol")
+    }
+
+    /// The frequency of a leaf, or the total frequency of the leaves below an internal node.
+    pub fn frequency(&self) -> u64 {
+        self.frequency
     }
 }
 
-impl Ord for HuffmanType {
+impl<S: Ord> Ord for HuffmanType<S> {
     fn cmp(&self, other: &Self) -> Ordering {
-        (other.frequency, other.symbol).cmp(&(self.frequency, self.symbol))
+        (other.frequency, &other.symbol).cmp(&(self.frequency, &self.symbol))
     }
 }
 
-impl PartialOrd for HuffmanType {
+impl<S: Ord> PartialOrd for HuffmanType<S> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl PartialEq for HuffmanType {
+impl<S: Ord> PartialEq for HuffmanType<S> {
     fn eq(&self, other: &Self) -> bool {
         self.cmp(other) == Ordering::Equal
     }
 }
 
+impl<S: Ord> Eq for HuffmanType<S> {}
+
 
+/// Node of a binary tree, which owns its children.
 #[derive(Debug)]
 pub struct Node<T> {
     pub value: T,
     pub left: Option<Box<Node<T>>>,
     pub right: Option<Box<Node<T>>>,
-    pub parent: * mut Node<T>,
 }
 
 impl <T> Node<T> {
@@ -49,17 +84,14 @@ impl <T> Node<T> {
             value,
             left: None,
             right: None,
-            parent: std::ptr::null_mut(),
         }
     }
 
-    pub fn set_left(&mut self, mut node: Box<Node<T>>) {
-        node.parent = self;
+    pub fn set_left(&mut self, node: Box<Node<T>>) {
         self.left = Some(node);
     }
 
-    pub fn set_right(&mut self, mut node: Box<Node<T>>) {
-        node.parent = self;
+    pub fn set_right(&mut self, node: Box<Node<T>>) {
         self.right = Some(node);
     }
 
@@ -68,94 +100,663 @@ impl <T> Node<T> {
     }
 }
 
-pub type HuffmanNode = Node<HuffmanType>;
+pub type HuffmanNode<S = u8> = Node<HuffmanType<S>>;
 
-impl Ord for HuffmanNode {
+impl<S: Ord> Ord for HuffmanNode<S> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.value.cmp(&other.value)
     }
 }
 
-impl PartialOrd for HuffmanNode {
+impl<S: Ord> PartialOrd for HuffmanNode<S> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.value.partial_cmp(&other.value)
+        Some(self.cmp(other))
     }
 }
 
-impl PartialEq for HuffmanNode {
+impl<S: Ord> PartialEq for HuffmanNode<S> {
     fn eq(&self, other: &Self) -> bool {
         self.value.eq(&other.value)
     }
 }
 
-impl Eq for HuffmanNode {}
+impl<S: Ord> Eq for HuffmanNode<S> {}
+
+/// How to choose between nodes of equal frequency when building a `HuffmanTree`.
+///
+/// Every choice gives optimal codes, but the lengths of the codes can differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// Merge internal nodes before leaves, and leaves in increasing order of symbol. This gives deep
+    /// trees with widely varying code lengths.
+    #[default]
+    Symbol,
+    /// Merge the shallowest nodes first. This gives the smallest longest code and the least
+    /// variance of code lengths, keeping decode tables small.
+    MinDepth,
+}
 
-pub struct HuffmanTree {
-    pub root_node: Box<HuffmanNode>,
+/// Huffman tree over symbols of any ordered type, such as bytes, enum variants or string tokens.
+///
+/// `CanonicalTree` computes its code lengths for bytes and other `Symbol` alphabets without
+/// building a tree. Build a `HuffmanTree` for other symbol types, or to inspect the tree itself.
+pub struct HuffmanTree<S = u8> {
+    pub root_node: Box<HuffmanNode<S>>,
 }
 
-impl HuffmanTree {
-    pub fn new(freq_table: &[u64; NUM_BYTES]) -> Option<HuffmanTree> {
-        let mut priority_queue: BinaryHeap<Box<HuffmanNode>> = BinaryHeap::new();
+impl<S: Symbol> HuffmanTree<S> {
+    pub fn new<F: Frequencies<Symbol = S> + ?Sized>(frequencies: &F) -> Option<HuffmanTree<S>> {
+        HuffmanTree::with_tie_break(frequencies, TieBreak::default())
+    }
 
-        for (symbol, &frequency) in freq_table.iter().enumerate() {
-            if frequency != 0 {
-                let node = HuffmanNode::new(HuffmanType::new(symbol as u8, frequency));
+    /// Like `new`, choosing between nodes of equal frequency with `tie_break`.
+    pub fn with_tie_break<F: Frequencies<Symbol = S> + ?Sized>(frequencies: &F, tie_break: TieBreak) -> Option<HuffmanTree<S>> {
+        let leaves = frequencies.frequencies().iter().enumerate()
+            .filter(|&(_symbol, &frequency)| frequency != 0)
+            .map(|(symbol, &frequency)| HuffmanType::new(S::from_index(symbol), frequency));
 
-                priority_queue.push(Box::new(node));
-            }
+        HuffmanTree::from_leaves(leaves, tie_break)
+    }
+}
+
+impl<S: Ord + Hash + Clone> HuffmanTree<S> {
+    /// Build the tree from `(symbol, weight)` pairs, such as a slice of pairs or a `HashMap`.
+    ///
+    /// Only the symbols given need to be stored, which suits sparse weights over a large
+    /// alphabet. The weights of a symbol given more than once are added together, and symbols with
+    /// a weight of 0 are left out. Returns `None` if no symbol has a weight.
+    pub fn from_weights<I>(weights: I) -> Option<HuffmanTree<S>>
+        where I: IntoIterator, I::Item: Borrow<(S, u64)>
+    {
+        let mut merged: BTreeMap<S, u64> = BTreeMap::new();
+
+        for pair in weights {
+            let (symbol, weight) = pair.borrow().clone();
+            let merged_weight = merged.entry(symbol).or_insert(0);
+            *merged_weight = merged_weight.saturating_add(weight);
         }
 
-        if priority_queue.len() == 0 {
+        let leaves = merged.into_iter()
+            .filter(|&(_, weight)| weight != 0)
+            .map(|(symbol, weight)| HuffmanType::new(symbol, weight));
+
+        HuffmanTree::from_leaves(leaves, TieBreak::default())
+    }
+
+    /// Build the tree from `(symbol, probability)` pairs, such as the output of a statistical
+    /// model.
+    ///
+    /// The probabilities need not sum to 1, as they are normalized and scaled to integer weights.
+    /// A positive probability always gets a weight of at least 1. Returns `None` if a probability
+    /// is negative or not finite, or none is positive.
+    pub fn from_probabilities<I>(probabilities: I) -> Option<HuffmanTree<S>>
+        where I: IntoIterator, I::Item: Borrow<(S, f64)>
+    {
+        let probabilities: Vec<(S, f64)> = probabilities.into_iter()
+            .map(|pair| pair.borrow().clone())
+            .collect();
+
+        if probabilities.iter().any(|&(_, probability)| !probability.is_finite() || probability < 0.0) {
+            return None;
+        }
+
+        let total: f64 = probabilities.iter().map(|&(_, probability)| probability).sum();
+
+        if total <= 0.0 {
+            return None;
+        }
+
+        let weights = probabilities.into_iter()
+            .filter(|&(_, probability)| probability > 0.0)
+            .map(|(symbol, probability)| {
+                // Round to nearest without f64::round, which is not in core
+                let weight = (probability / total * PROBABILITY_SCALE + 0.5) as u64;
+                (symbol, weight.max(1))
+            });
+
+        HuffmanTree::from_weights(weights.collect::<Vec<_>>())
+    }
+
+    /// Build the tree by repeatedly merging the two nodes of lowest frequency.
+    fn from_leaves<I: IntoIterator<Item = HuffmanType<S>>>(leaves: I, tie_break: TieBreak) -> Option<HuffmanTree<S>> {
+        // Nodes ordered by frequency, then by the tie break, then by when they were added
+        let mut priority_queue: BinaryHeap<QueueEntry<S>> = BinaryHeap::new();
+        let mut added = 0;
+
+        for leaf in leaves {
+            let tie = match tie_break {
+                // Leaves are given in increasing order of symbol
+                TieBreak::Symbol => 1,
+                TieBreak::MinDepth => 0,
+            };
+
+            priority_queue.push(QueueEntry::new(Box::new(HuffmanNode::new(leaf)), tie, added));
+            added += 1;
+        }
+
+        if priority_queue.is_empty() {
             return None;
         }
 
         while priority_queue.len() > 1 {
-            let node1 = priority_queue.pop().unwrap();
-            let node2 = priority_queue.pop().unwrap();
+            let entry1 = priority_queue.pop().expect("Queue has at least two entries");
+            let entry2 = priority_queue.pop().expect("Queue has at least two entries");
 
             let mut new_node = HuffmanNode::new(
-                HuffmanType::new(0, node1.value.frequency + node2.value.frequency));
+                HuffmanType::internal(entry1.node.value.frequency.saturating_add(entry2.node.value.frequency)));
+
+            let tie = match tie_break {
+                TieBreak::Symbol => 0,
+                TieBreak::MinDepth => entry1.tie.max(entry2.tie) + 1,
+            };
 
-            new_node.set_right(node1);
-            new_node.set_left(node2);
+            new_node.set_right(entry1.node);
+            new_node.set_left(entry2.node);
 
-            priority_queue.push(Box::new(new_node));
+            priority_queue.push(QueueEntry::new(Box::new(new_node), tie, added));
+            added += 1;
         }
 
-        let root_node = priority_queue.pop().unwrap();
+        let root_node = priority_queue.pop().expect("Queue ends with the root").node;
 
         Some(HuffmanTree { root_node })
     }
 
-    pub fn get_code_lengths(&self) -> Vec<(u8, u8)> {
+    /// Iterate over the leaves from left to right, with the code given by the path to each.
+    ///
+    /// A tree with a single leaf gives it a depth of 1 and the code `0`, as in
+    /// `get_code_lengths`.
+    pub fn leaves(&self) -> Leaves<'_, S> {
+        let root = if self.root_node.is_leaf() {
+            (self.root_node.as_ref(), 1, Some(Code::new(0, 1)))
+        } else {
+            (self.root_node.as_ref(), 0, Some(Code::new(0, 0)))
+        };
+
+        Leaves { stack: vec![root] }
+    }
+
+    /// Depth of the deepest leaf, which is the length of the longest code.
+    pub fn max_depth(&self) -> usize {
+        self.leaves().map(|leaf| leaf.depth).max().unwrap_or(0)
+    }
+
+    /// Number of leaves, which is the number of symbols with a code.
+    pub fn symbol_count(&self) -> usize {
+        self.leaves().count()
+    }
+
+    /// Total frequency of all symbols.
+    pub fn weight(&self) -> u64 {
+        self.root_node.value.frequency
+    }
+
+    /// The code given by the path to `symbol`, as written by `encode`.
+    ///
+    /// Walks the tree only as far as the symbol, so a single lookup needs no code book. Returns
+    /// `None` if the symbol is not in the tree or its code is longer than `MAX_CODE_LENGTH` bits.
+    pub fn code_for(&self, symbol: &S) -> Option<Code> {
+        self.leaves()
+            .find(|leaf| leaf.symbol == *symbol)
+            .and_then(|leaf| leaf.code)
+    }
+
+    /// Write the code of each symbol as given by its path in the tree, padding the last byte with
+    /// zeros.
+    ///
+    /// Unlike a `CanonicalTree`, the codes are exactly those of the tree, where a left edge is a
+    /// 0 bit and a right edge a 1 bit. Fails if a symbol is not in the tree or its code is longer
+    /// than `MAX_CODE_LENGTH` bits.
+    #[cfg(feature = "std")]
+    pub fn encode<W: Write>(&self, symbols: &[S], write: &mut W) -> Result<(), Box<dyn Error>> {
+        let codes: BTreeMap<S, Option<Code>> = self.leaves()
+            .map(|leaf| (leaf.symbol, leaf.code))
+            .collect();
+
+        let mut bit_writer = BitWriter::new(write);
+
+        for symbol in symbols {
+            match codes.get(symbol) {
+                Some(&Some(code)) => bit_writer.write_code(code)?,
+                Some(&None) => return Err(From::from(format!("Code is longer than {} bits", MAX_CODE_LENGTH))),
+                None => return Err(From::from("Symbol not found in tree")),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decode exactly `count` symbols written by `encode`, by following the path of each code
+    /// from the root.
+    ///
+    /// Fails with a `DecodeError` if the data ends early.
+    #[cfg(feature = "std")]
+    pub fn decode<R: Read>(&self, read: R, count: u64) -> Result<Vec<S>, Box<dyn Error>> {
+        let mut bit_reader = BitReader::new(read);
+        let mut symbols = Vec::new();
+
+        while (symbols.len() as u64) < count {
+            let bit_offset = bit_reader.position();
+            let mut node = self.root_node.as_ref();
+            let mut partial = Code::new(0, 0);
+
+            loop {
+                let bit = match bit_reader.read_bit()? {
+                    Some(bit) => bit,
+                    None => return Err(Box::new(DecodeError {
+                        kind: DecodeErrorKind::UnexpectedEnd,
+                        bit_offset,
+                        symbols_decoded: symbols.len() as u64,
+                        partial_code: partial,
+                    })),
+                };
+
+                if partial.length() < MAX_CODE_LENGTH {
+                    partial = Code::new((partial.bits() << 1) | bit as u64, partial.length() + 1);
+                }
+
+                // A lone leaf has the code 0
+                let next = if bit { node.right.as_ref() } else { node.left.as_ref() };
+
+                match next {
+                    Some(next) => node = next.as_ref(),
+                    None if node.is_leaf() && !bit => {},
+                    None => return Err(Box::new(DecodeError {
+                        kind: DecodeErrorKind::InvalidCode,
+                        bit_offset,
+                        symbols_decoded: symbols.len() as u64,
+                        partial_code: partial,
+                    })),
+                }
+
+                if node.is_leaf() {
+                    symbols.push(node.value.leaf_symbol());
+                    break;
+                }
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Get the depth of each leaf, which is the length of its code.
+    ///
+    /// A tree with a single leaf gets a code length of 1 so the symbol still has a code.
+    pub fn get_code_lengths(&self) -> Vec<(S, u8)> {
+        if self.root_node.is_leaf() {
+            return vec![(self.root_node.value.leaf_symbol(), 1)];
+        }
+
         // Queue for breadth-first-search with depth
-        let mut queue: VecDeque<(&HuffmanNode, u8)> = VecDeque::new();
+        let mut queue: VecDeque<(&HuffmanNode<S>, u8)> = VecDeque::new();
 
         // Push the root node onto the queue
         queue.push_back((self.root_node.as_ref(), 0));
 
         // Raw code lengths
-        let mut code_lengths: Vec<(u8, u8)> = Vec::new();
+        let mut code_lengths: Vec<(S, u8)> = Vec::new();
 
         // Do a breadth first search, keeping track of depth
         while !queue.is_empty() {
-            let (node, depth) = queue.pop_front().unwrap();
+            let (node, depth) = queue.pop_front().expect("Queue is not empty");
 
             if node.is_leaf() {
-                code_lengths.push((node.value.symbol, depth));
+                code_lengths.push((node.value.leaf_symbol(), depth));
                 continue;
             }
 
             if let Some(ref left) = node.left {
-                queue.push_back((left.as_ref(), depth + 1));
+                queue.push_back((left.as_ref(), depth.saturating_add(1)));
             }
 
             if let Some(ref right) = node.right {
-                queue.push_back((right.as_ref(), depth + 1));
+                queue.push_back((right.as_ref(), depth.saturating_add(1)));
             }
         }
 
         code_lengths
     }
-}
\ No newline at end of file
+}
+
+impl<S: Ord + Hash + Clone + Display> HuffmanTree<S> {
+    /// The tree as a Graphviz DOT graph.
+    ///
+    /// Leaves are labeled with their symbol and frequency, internal nodes with their total
+    /// frequency, and edges with the bit they add to a code.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph huffman {\n");
+        let mut stack = vec![(self.root_node.as_ref(), None::<(usize, u8)>)];
+        let mut next_id = 0;
+
+        while let Some((node, parent)) = stack.pop() {
+            let id = next_id;
+            next_id += 1;
+
+            if node.is_leaf() {
+                dot.push_str(&format!("    n{} [shape=box, label=\"{} : {}\"];\n",
+                    id, node.value.symbol.as_ref().expect("Leaves have a symbol"), node.value.frequency));
+            } else {
+                dot.push_str(&format!("    n{} [shape=circle, label=\"{}\"];\n", id, node.value.frequency));
+            }
+
+            if let Some((parent, bit)) = parent {
+                dot.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", parent, id, bit));
+            }
+
+            if let Some(ref right) = node.right {
+                stack.push((right.as_ref(), Some((id, 1))));
+            }
+
+            if let Some(ref left) = node.left {
+                stack.push((left.as_ref(), Some((id, 0))));
+            }
+        }
+
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+/// A leaf of a `HuffmanTree`, yielded by `HuffmanTree::leaves`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leaf<S = u8> {
+    pub symbol: S,
+    pub frequency: u64,
+    /// Number of edges from the root to the leaf, which is the length of its code.
+    pub depth: usize,
+    /// The code given by the path from the root, where a left edge is a 0 bit and a right edge a
+    /// 1 bit. `None` if the leaf is deeper than `MAX_CODE_LENGTH`.
+    pub code: Option<Code>,
+}
+
+/// Iterator over the leaves of a `HuffmanTree`, created by `HuffmanTree::leaves`.
+pub struct Leaves<'a, S: 'a = u8> {
+    /// Nodes still to visit, with their depth and code
+    stack: Vec<(&'a HuffmanNode<S>, usize, Option<Code>)>,
+}
+
+impl<'a, S: Clone> Iterator for Leaves<'a, S> {
+    type Item = Leaf<S>;
+
+    fn next(&mut self) -> Option<Leaf<S>> {
+        while let Some((node, depth, code)) = self.stack.pop() {
+            if node.is_leaf() {
+                return Some(Leaf {
+                    symbol: node.value.leaf_symbol(),
+                    frequency: node.value.frequency,
+                    depth,
+                    code,
+                });
+            }
+
+            let child_code = |bit| code
+                .filter(|code| code.length() < MAX_CODE_LENGTH)
+                .map(|code| Code::new((code.bits() << 1) | bit, code.length() + 1));
+
+            // Push the right child first so the left child is visited first
+            if let Some(ref right) = node.right {
+                self.stack.push((right.as_ref(), depth + 1, child_code(1)));
+            }
+
+            if let Some(ref left) = node.left {
+                self.stack.push((left.as_ref(), depth + 1, child_code(0)));
+            }
+        }
+
+        None
+    }
+}
+
+/// Node waiting to be merged, popped from the queue in increasing order of
+/// `(frequency, tie, added)`.
+struct QueueEntry<S> {
+    node: Box<HuffmanNode<S>>,
+    tie: u64,
+    added: usize,
+}
+
+impl<S> QueueEntry<S> {
+    fn new(node: Box<HuffmanNode<S>>, tie: u64, added: usize) -> QueueEntry<S> {
+        QueueEntry { node, tie, added }
+    }
+
+    fn key(&self) -> (u64, u64, usize) {
+        (self.node.value.frequency, self.tie, self.added)
+    }
+}
+
+impl<S> Ord for QueueEntry<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+impl<S> PartialOrd for QueueEntry<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S> PartialEq for QueueEntry<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<S> Eq for QueueEntry<S> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use std::io::Cursor;
+    #[cfg(feature = "std")]
+    use std::thread;
+    #[cfg(feature = "std")]
+    use std::collections::HashMap;
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_tree() {
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[b'a' as usize] = 3;
+        freq_table[b'b' as usize] = 1;
+        freq_table[b'c' as usize] = 1;
+
+        // Trees own their nodes, so they can be moved to another thread
+        let tree = HuffmanTree::new(&freq_table).unwrap();
+        let tree = thread::spawn(move || tree).join().unwrap();
+
+        assert_eq!(tree.root_node.value.frequency(), 5);
+        assert!(!tree.root_node.is_leaf());
+
+        let mut code_lengths = tree.get_code_lengths();
+        code_lengths.sort();
+        assert_eq!(code_lengths, vec![(b'a', 1), (b'b', 2), (b'c', 2)]);
+
+        assert!(HuffmanTree::new(&[0; NUM_BYTES]).is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_from_weights() {
+        let weights: &[(u8, u64)] = &[(b'a', 2), (b'b', 1), (b'c', 1), (b'a', 1), (b'z', 0)];
+        let tree = HuffmanTree::from_weights(weights).unwrap();
+
+        let mut code_lengths = tree.get_code_lengths();
+        code_lengths.sort();
+        assert_eq!(code_lengths, vec![(b'a', 1), (b'b', 2), (b'c', 2)]);
+
+        let weights: HashMap<u16, u64> = vec![(1000, 5), (60000, 1)].into_iter().collect();
+        let tree = HuffmanTree::from_weights(weights).unwrap();
+        assert_eq!(tree.root_node.value.frequency(), 6);
+        assert_eq!(tree.get_code_lengths().len(), 2);
+
+        assert!(HuffmanTree::<u8>::from_weights(vec![(b'a', 0)]).is_none());
+
+        // Weights too large to add saturate
+        let tree = HuffmanTree::from_weights(vec![(b'a', u64::MAX), (b'a', 1), (b'b', u64::MAX)]).unwrap();
+        assert_eq!(tree.root_node.value.frequency(), u64::MAX);
+    }
+
+    #[test]
+    fn test_tie_break() {
+        // Lengths of 3, 3, 2, 1 and of 2, 2, 2, 2 are both optimal for these frequencies
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[..4].copy_from_slice(&[1, 1, 1, 2]);
+
+        let lengths = |tie_break| {
+            let mut code_lengths = HuffmanTree::with_tie_break(&freq_table, tie_break).unwrap().get_code_lengths();
+            code_lengths.sort();
+            code_lengths.into_iter().map(|(_symbol, length)| length).collect::<Vec<_>>()
+        };
+
+        assert_eq!(lengths(TieBreak::Symbol), vec![3, 3, 2, 1]);
+        assert_eq!(lengths(TieBreak::MinDepth), vec![2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_leaves() {
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[..4].copy_from_slice(&[1, 1, 1, 2]);
+
+        let tree = HuffmanTree::new(&freq_table).unwrap();
+        let leaves: Vec<Leaf> = tree.leaves().collect();
+
+        assert_eq!(leaves.len(), 4);
+        assert_eq!(leaves[3], Leaf { symbol: 3, frequency: 2, depth: 1, code: Some(Code::new(0b1, 1)) });
+
+        // The leaves agree with the code lengths, and their codes are prefix free
+        let mut depths: Vec<(u8, u8)> = leaves.iter().map(|leaf| (leaf.symbol, leaf.depth as u8)).collect();
+        let mut code_lengths = tree.get_code_lengths();
+        depths.sort();
+        code_lengths.sort();
+        assert_eq!(depths, code_lengths);
+
+        let code_book: CodeBook = leaves.iter().map(|leaf| (leaf.symbol, leaf.code.unwrap())).collect();
+        assert!(code_book.validate().is_ok());
+
+        // A lone leaf
+        let tree = HuffmanTree::from_weights(vec![(b'x', 5)]).unwrap();
+        assert_eq!(tree.leaves().collect::<Vec<_>>(),
+            vec![Leaf { symbol: b'x', frequency: 5, depth: 1, code: Some(Code::new(0, 1)) }]);
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let tree = HuffmanTree::from_weights(vec![(b'a', 2), (b'b', 1)]).unwrap();
+
+        assert_eq!(tree.to_dot(), concat!(
+            "digraph huffman {\n",
+            "    n0 [shape=circle, label=\"3\"];\n",
+            "    n1 [shape=box, label=\"97 : 2\"];\n",
+            "    n0 -> n1 [label=\"0\"];\n",
+            "    n2 [shape=box, label=\"98 : 1\"];\n",
+            "    n0 -> n2 [label=\"1\"];\n",
+            "}\n",
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_generic_symbols() {
+        let tokens = vec![("the".to_string(), 5), ("a".to_string(), 3), ("whale".to_string(), 1), ("sea".to_string(), 1)];
+        let tree = HuffmanTree::from_weights(tokens).unwrap();
+
+        let code_lengths: HashMap<String, u8> = tree.get_code_lengths().into_iter().collect();
+        assert_eq!(code_lengths["the"], 1);
+        assert_eq!(code_lengths["a"], 2);
+        assert_eq!(code_lengths["whale"], 3);
+        assert!(tree.to_dot().contains("label=\"whale : 1\""));
+
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        enum Token { Literal, Match }
+
+        let tree = HuffmanTree::from_weights(vec![(Token::Literal, 9), (Token::Match, 1)]).unwrap();
+        let leaves: Vec<Token> = tree.leaves().map(|leaf| leaf.symbol).collect();
+        assert_eq!(leaves.len(), 2);
+        assert!(leaves.contains(&Token::Match));
+    }
+
+    #[test]
+    fn test_from_probabilities() {
+        let tree = HuffmanTree::from_probabilities(vec![(b'a', 0.5), (b'b', 0.25), (b'c', 0.25), (b'd', 0.0)]).unwrap();
+
+        let mut code_lengths = tree.get_code_lengths();
+        code_lengths.sort();
+        assert_eq!(code_lengths, vec![(b'a', 1), (b'b', 2), (b'c', 2)]);
+        assert_eq!(tree.root_node.value.frequency(), 1 << 40);
+
+        // Unnormalized, and tiny probabilities still get a code
+        let tree = HuffmanTree::from_probabilities(vec![(1u16, 3.0), (2, 1e-30)]).unwrap();
+        assert_eq!(tree.get_code_lengths().len(), 2);
+
+        assert!(HuffmanTree::<u8>::from_probabilities(vec![(b'a', -0.5), (b'b', 1.0)]).is_none());
+        assert!(HuffmanTree::<u8>::from_probabilities(vec![(b'a', f64::NAN)]).is_none());
+        assert!(HuffmanTree::<u8>::from_probabilities(vec![(b'a', 0.0)]).is_none());
+    }
+
+    #[test]
+    fn test_code_for() {
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[..4].copy_from_slice(&[1, 1, 1, 2]);
+
+        let tree = HuffmanTree::new(&freq_table).unwrap();
+
+        assert_eq!(tree.code_for(&1), Some(Code::new(0b000, 3)));
+        assert_eq!(tree.code_for(&2), Some(Code::new(0b01, 2)));
+        assert_eq!(tree.code_for(&3), Some(Code::new(0b1, 1)));
+        assert_eq!(tree.code_for(&4), None);
+
+        for leaf in tree.leaves() {
+            assert_eq!(tree.code_for(&leaf.symbol), leaf.code);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_encode_decode() {
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[..4].copy_from_slice(&[1, 1, 1, 2]);
+
+        let tree = HuffmanTree::new(&freq_table).unwrap();
+        let code_book: CodeBook = tree.leaves().map(|leaf| (leaf.symbol, leaf.code.unwrap())).collect();
+
+        // The codes of the tree are not canonical
+        assert!(CanonicalTree::from_code_book(&code_book).is_err());
+
+        let symbols = [3, 0, 1, 2, 3, 3];
+        let mut encoded = Vec::new();
+        tree.encode(&symbols, &mut encoded).unwrap();
+
+        // 1 001 000 01 1 1
+        assert_eq!(encoded, vec![0b10010000, 0b11100000]);
+        assert_eq!(tree.decode(Cursor::new(&encoded), 6).unwrap(), symbols.to_vec());
+
+        let err = tree.decode(Cursor::new(&encoded[..1]), 6).unwrap_err();
+        assert_eq!(err.downcast_ref::<DecodeError>().unwrap().kind, DecodeErrorKind::UnexpectedEnd);
+        assert!(tree.encode(&[9], &mut Vec::new()).is_err());
+
+        // A lone leaf
+        let tree = HuffmanTree::from_weights(vec![(b'x', 5)]).unwrap();
+        assert_eq!(tree.decode(Cursor::new(vec![0b00100000]), 2).unwrap(), b"xx".to_vec());
+        assert!(tree.decode(Cursor::new(vec![0b00100000]), 3).is_err());
+    }
+
+    #[test]
+    fn test_shape() {
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[..4].copy_from_slice(&[1, 1, 1, 2]);
+
+        let tree = HuffmanTree::new(&freq_table).unwrap();
+        assert_eq!(tree.max_depth(), 3);
+        assert_eq!(tree.symbol_count(), 4);
+        assert_eq!(tree.weight(), 5);
+
+        let tree = HuffmanTree::from_weights(vec![(b'x', 5)]).unwrap();
+        assert_eq!(tree.max_depth(), 1);
+        assert_eq!(tree.symbol_count(), 1);
+        assert_eq!(tree.weight(), 5);
+    }
+}
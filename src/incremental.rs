@@ -0,0 +1,223 @@
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Frequencies that can be changed one symbol at a time, with code lengths that are only
+/// recomputed when they are next needed.
+///
+/// The symbols are kept sorted by frequency as they change. A small change moves a symbol only a
+/// few places, so refreshing the code lengths costs a single linear pass instead of a sort and a
+/// tree build, which suits encoders that periodically refresh their model.
+#[derive(Debug, Clone)]
+pub struct CodeLengthModel<S = u8> {
+    frequencies: FrequencyTable<S>,
+    /// Symbols with a nonzero frequency in increasing order of `(frequency, symbol)`
+    order: Vec<S>,
+    /// Index of each symbol in `order`, or `None` if its frequency is 0
+    positions: Vec<Option<usize>>,
+    /// Code length of each symbol, or `None` if they must be recomputed
+    lengths: Option<Vec<u8>>,
+}
+
+impl<S: Symbol> CodeLengthModel<S> {
+    /// Create a model where every symbol has a frequency of 0.
+    pub fn new() -> CodeLengthModel<S> {
+        CodeLengthModel {
+            frequencies: FrequencyTable::new(),
+            order: Vec::new(),
+            positions: vec![None; S::ALPHABET_SIZE],
+            lengths: None,
+        }
+    }
+
+    /// Create a model starting from `frequencies`.
+    pub fn from_frequencies<F: Frequencies<Symbol = S> + ?Sized>(frequencies: &F) -> CodeLengthModel<S> {
+        let mut model = CodeLengthModel::new();
+
+        for (symbol, &frequency) in frequencies.frequencies().iter().enumerate() {
+            model.frequencies.set(S::from_index(symbol), frequency);
+        }
+
+        let mut order: Vec<(u64, S)> = model.frequencies.iter().map(|(symbol, frequency)| (frequency, symbol)).collect();
+        order.sort();
+        model.order = order.into_iter().map(|(_frequency, symbol)| symbol).collect();
+
+        for (position, &symbol) in model.order.iter().enumerate() {
+            model.positions[symbol.to_index()] = Some(position);
+        }
+
+        model
+    }
+
+    /// The frequency of `symbol`.
+    pub fn get(&self, symbol: S) -> u64 {
+        self.frequencies.get(symbol)
+    }
+
+    /// The current frequencies.
+    pub fn frequencies(&self) -> &FrequencyTable<S> {
+        &self.frequencies
+    }
+
+    /// Add `count` to the frequency of `symbol`.
+    pub fn add(&mut self, symbol: S, count: u64) {
+        let frequency = self.frequencies.get(symbol).saturating_add(count);
+        self.set(symbol, frequency);
+    }
+
+    /// Set the frequency of `symbol`, moving it to its new place in the sorted order.
+    pub fn set(&mut self, symbol: S, frequency: u64) {
+        if self.frequencies.get(symbol) == frequency {
+            return;
+        }
+
+        self.frequencies.set(symbol, frequency);
+        self.lengths = None;
+
+        let mut position = match (self.positions[symbol.to_index()], frequency) {
+            (None, _) => {
+                self.order.push(symbol);
+                self.order.len() - 1
+            },
+            (Some(position), 0) => {
+                self.order.remove(position);
+                self.positions[symbol.to_index()] = None;
+
+                for (position, &symbol) in self.order.iter().enumerate().skip(position) {
+                    self.positions[symbol.to_index()] = Some(position);
+                }
+
+                return;
+            },
+            (Some(position), _) => position,
+        };
+
+        while position > 0 && self.key(position - 1) > self.key(position) {
+            self.swap(position - 1, position);
+            position -= 1;
+        }
+        while position + 1 < self.order.len() && self.key(position + 1) < self.key(position) {
+            self.swap(position, position + 1);
+            position += 1;
+        }
+
+        self.positions[symbol.to_index()] = Some(position);
+    }
+
+    /// The optimal code length of each symbol with a nonzero frequency, in increasing order of
+    /// symbol, recomputing them if any frequency changed.
+    ///
+    /// Lengths are not limited, so they can be longer than `MAX_CODE_LENGTH` for very skewed
+    /// frequencies.
+    pub fn code_lengths(&mut self) -> CodeLengths<S> {
+        let lengths = self.lengths();
+
+        lengths.iter().enumerate()
+            .filter(|&(_symbol, &length)| length > 0)
+            .map(|(symbol, &length)| (S::from_index(symbol), length))
+            .collect()
+    }
+
+    /// Build a `CanonicalTree` for the current frequencies, limiting codes to `MAX_CODE_LENGTH`
+    /// bits if needed.
+    pub fn tree(&mut self) -> Result<CanonicalTree<S>, Box<dyn Error>> {
+        if self.order.is_empty() {
+            return Err(From::from("Every frequency is 0"));
+        }
+
+        let code_lengths = self.code_lengths();
+
+        if code_lengths.iter().any(|&(_symbol, length)| length > MAX_CODE_LENGTH) {
+            return Ok(CanonicalTree::from_frequencies_limited(&self.frequencies, MAX_CODE_LENGTH)?);
+        }
+
+        Ok(CanonicalTree::new(code_lengths)?)
+    }
+
+    fn lengths(&mut self) -> &[u8] {
+        if self.lengths.is_none() {
+            let mut lengths = vec![0; S::ALPHABET_SIZE];
+
+            match self.order.len() {
+                0 => {},
+                1 => lengths[self.order[0].to_index()] = 1,
+                _ => {
+                    let weights: Vec<u64> = self.order.iter().map(|&symbol| self.frequencies.get(symbol)).collect();
+
+                    for (&symbol, length) in self.order.iter().zip(sorted_code_lengths(&weights)) {
+                        lengths[symbol.to_index()] = length;
+                    }
+                },
+            }
+
+            self.lengths = Some(lengths);
+        }
+
+        self.lengths.as_ref().expect("Code lengths were just computed")
+    }
+
+    fn key(&self, position: usize) -> (u64, S) {
+        let symbol = self.order[position];
+        (self.frequencies.get(symbol), symbol)
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.order.swap(a, b);
+        self.positions[self.order[a].to_index()] = Some(a);
+        self.positions[self.order[b].to_index()] = Some(b);
+    }
+}
+
+impl<S: Symbol> Default for CodeLengthModel<S> {
+    fn default() -> CodeLengthModel<S> {
+        CodeLengthModel::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_updates_match_rebuild() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"a small sample string");
+
+        let mut model = CodeLengthModel::from_frequencies(&frequencies);
+
+        let mut updates: Vec<(u8, u64)> = vec![(b'z', 7), (b'a', 1), (b' ', 0), (b'q', 1), (b's', 40), (b'z', 0)];
+        updates.extend((0..20).map(|i| (b'a' + i, i as u64 * 3)));
+
+        for (symbol, frequency) in updates {
+            model.set(symbol, frequency);
+            frequencies.set(symbol, frequency);
+
+            let rebuilt = optimal_code_lengths(&frequencies).map(|mut lengths| {
+                lengths.sort();
+                lengths
+            });
+
+            match rebuilt {
+                Ok(rebuilt) => assert_eq!(model.code_lengths(), rebuilt),
+                Err(_) => assert!(model.code_lengths().is_empty()),
+            }
+        }
+
+        model.add(b'b', 5);
+        assert_eq!(model.get(b'b'), 8);
+        assert!(model.tree().is_ok());
+    }
+
+    #[test]
+    fn test_empty_and_single() {
+        let mut model = CodeLengthModel::<u8>::new();
+        assert!(model.tree().is_err());
+
+        model.add(b'x', 3);
+        assert_eq!(model.code_lengths(), vec![(b'x', 1)]);
+
+        model.set(b'x', 0);
+        assert!(model.code_lengths().is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! Conversion between `CanonicalTree` and the huffman tables of JPEG DHT segments (ITU T.81).
+
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Longest code allowed in a JPEG huffman table.
+pub const JPEG_MAX_CODE_LENGTH: u8 = 16;
+
+/// A huffman table as stored in a JPEG DHT segment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhtTable {
+    /// Number of codes of each length, where `bits[0]` is the number of codes of 1 bit.
+    pub bits: [u8; JPEG_MAX_CODE_LENGTH as usize],
+    /// Symbols in the order their codes are assigned.
+    pub huffval: Vec<u8>,
+}
+
+impl DhtTable {
+    /// Parse the BITS and HUFFVAL arrays of a table, which follow the table class and
+    /// destination byte in a DHT segment.
+    ///
+    /// Returns the table and the number of bytes it used.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(DhtTable, usize), Box<dyn Error>> {
+        let size = JPEG_MAX_CODE_LENGTH as usize;
+
+        if bytes.len() < size {
+            return Err(From::from("DHT table is missing BITS"));
+        }
+
+        let mut bits = [0; JPEG_MAX_CODE_LENGTH as usize];
+        bits.copy_from_slice(&bytes[..size]);
+
+        let count: usize = bits.iter().map(|&count| count as usize).sum();
+
+        if bytes.len() < size + count {
+            return Err(From::from("DHT table is missing HUFFVAL"));
+        }
+
+        let table = DhtTable {bits, huffval: bytes[size..size + count].to_vec()};
+        table.validate()?;
+
+        Ok((table, size + count))
+    }
+
+    /// The BITS array followed by the HUFFVAL array.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.bits.to_vec();
+        bytes.extend_from_slice(&self.huffval);
+
+        bytes
+    }
+
+    /// Check that the table describes a valid code.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let count: usize = self.bits.iter().map(|&count| count as usize).sum();
+
+        if count != self.huffval.len() {
+            return Err(From::from(format!("BITS has {} codes but HUFFVAL has {} values",
+                count, self.huffval.len())));
+        }
+
+        let mut seen = [false; NUM_BYTES];
+        for &symbol in self.huffval.iter() {
+            if seen[symbol as usize] {
+                return Err(From::from(format!("Symbol {} is in HUFFVAL more than once", symbol)));
+            }
+            seen[symbol as usize] = true;
+        }
+
+        // Number of codes still available at the current length
+        let mut available: u32 = 1;
+
+        for &count in self.bits.iter() {
+            available *= 2;
+
+            if count as u32 > available {
+                return Err(From::from("BITS has more codes than fit in their lengths"));
+            }
+
+            available -= count as u32;
+        }
+
+        Ok(())
+    }
+
+    /// The codes assigned to the symbols, as in annex C of T.81.
+    ///
+    /// Codes of the same length are assigned in the order of HUFFVAL, which need not be
+    /// increasing, so the codes are not always those of a `CanonicalTree`.
+    pub fn code_book(&self) -> Result<CodeBook, Box<dyn Error>> {
+        self.validate()?;
+
+        let mut code_book = CodeBook::new();
+        let mut symbols = self.huffval.iter();
+        let mut code: u64 = 0;
+
+        for (length, &count) in (1..=JPEG_MAX_CODE_LENGTH).zip(self.bits.iter()) {
+            for symbol in symbols.by_ref().take(count as usize) {
+                code_book.insert(*symbol, Code::new(code, length));
+                code += 1;
+            }
+
+            code <<= 1;
+        }
+
+        Ok(code_book)
+    }
+}
+
+impl CanonicalTree {
+    /// Build the tree from a JPEG huffman table.
+    ///
+    /// Fails if HUFFVAL is not in increasing order within each length, since the codes would
+    /// not be canonical. Use `DhtTable::code_book` to read such tables.
+    pub fn from_dht(table: &DhtTable) -> Result<CanonicalTree, Box<dyn Error>> {
+        Ok(CanonicalTree::from_code_book(&table.code_book()?)?)
+    }
+
+    /// The tree as a JPEG huffman table.
+    ///
+    /// Fails if a code is longer than `JPEG_MAX_CODE_LENGTH` bits.
+    pub fn to_dht(&self) -> Result<DhtTable, Box<dyn Error>> {
+        if let Some(length) = self.max_code_length().filter(|&length| length > JPEG_MAX_CODE_LENGTH) {
+            return Err(From::from(format!("Code length {} is longer than {} bits",
+                length, JPEG_MAX_CODE_LENGTH)));
+        }
+
+        let mut bits = [0; JPEG_MAX_CODE_LENGTH as usize];
+        for (_symbol, code) in self.code_book().iter() {
+            bits[code.length() as usize - 1] += 1;
+        }
+
+        Ok(DhtTable {bits, huffval: self.sorted_symbols()})
+    }
+
+    /// Build a tree for a JPEG huffman table from the frequency of each symbol.
+    ///
+    /// No code is longer than `JPEG_MAX_CODE_LENGTH` bits, and no code is all 1 bits since JPEG
+    /// reserves those. The code lengths may be slightly worse than optimal to reserve it.
+    pub fn from_frequencies_jpeg<F: Frequencies<Symbol = u8> + ?Sized>(frequencies: &F) -> Result<CanonicalTree, Box<dyn Error>> {
+        let mut code_lengths = limited_code_lengths(frequencies, JPEG_MAX_CODE_LENGTH)?;
+
+        let max_length = code_lengths.iter().map(|&(_symbol, length)| length).max().unwrap_or(0);
+        let kraft_sum: u64 = code_lengths.iter()
+            .map(|&(_symbol, length)| 1 << (JPEG_MAX_CODE_LENGTH - length))
+            .sum();
+
+        // A complete code gives the all 1 bits code to the last symbol
+        if kraft_sum == 1 << JPEG_MAX_CODE_LENGTH {
+            if max_length == JPEG_MAX_CODE_LENGTH {
+                code_lengths = limited_code_lengths(frequencies, JPEG_MAX_CODE_LENGTH - 1)?;
+            }
+
+            // Lengthen the code of the last symbol, so it is followed by a 0 bit
+            if let Some(last) = code_lengths.iter_mut().max_by_key(|&&mut (symbol, length)| (length, symbol)) {
+                last.1 += 1;
+            }
+        }
+
+        Ok(CanonicalTree::new(code_lengths)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Table K.3, luminance DC coefficients, from annex K of T.81
+    const LUMINANCE_DC: [u8; 28] = [
+        0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11,
+    ];
+
+    #[test]
+    fn test_luminance_dc() {
+        let (table, size) = DhtTable::from_bytes(&LUMINANCE_DC).unwrap();
+        assert_eq!(size, LUMINANCE_DC.len());
+        assert_eq!(table.to_bytes(), LUMINANCE_DC.to_vec());
+
+        let tree = CanonicalTree::from_dht(&table).unwrap();
+        assert_eq!(tree.code_book().get(0), Some(Code::new(0b00, 2)));
+        assert_eq!(tree.code_book().get(5), Some(Code::new(0b110, 3)));
+        assert_eq!(tree.code_book().get(6), Some(Code::new(0b1110, 4)));
+        assert_eq!(tree.code_book().get(11), Some(Code::new(0b111111110, 9)));
+
+        assert_eq!(tree.to_dht().unwrap(), table);
+    }
+
+    #[test]
+    fn test_unsorted_huffval() {
+        let mut bits = [0; JPEG_MAX_CODE_LENGTH as usize];
+        bits[1] = 3;
+        let table = DhtTable {bits, huffval: vec![2, 0, 1]};
+
+        let code_book = table.code_book().unwrap();
+        assert_eq!(code_book.get(2), Some(Code::new(0b00, 2)));
+        assert_eq!(code_book.get(1), Some(Code::new(0b10, 2)));
+
+        assert!(CanonicalTree::from_dht(&table).is_err());
+    }
+
+    #[test]
+    fn test_invalid_tables() {
+        assert!(DhtTable::from_bytes(&LUMINANCE_DC[..20]).is_err());
+
+        let mut bits = [0; JPEG_MAX_CODE_LENGTH as usize];
+        bits[0] = 3;
+        assert!(DhtTable {bits, huffval: vec![0, 1, 2]}.validate().is_err());
+
+        bits[0] = 2;
+        assert!(DhtTable {bits, huffval: vec![0, 0]}.validate().is_err());
+        assert!(DhtTable {bits, huffval: vec![0]}.validate().is_err());
+    }
+
+    #[test]
+    fn test_from_frequencies_jpeg() {
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[0] = 2;
+        freq_table[1] = 1;
+        freq_table[2] = 1;
+
+        let tree = CanonicalTree::from_frequencies_jpeg(&freq_table).unwrap();
+        assert_eq!(tree.code_lengths()[..3], [1, 2, 3]);
+
+        // Every symbol needs 8 bits, and one more to avoid the all 1 bits code
+        let tree = CanonicalTree::from_frequencies_jpeg(&[1; NUM_BYTES]).unwrap();
+        let last = tree.code_book().get(255).unwrap();
+        assert_eq!(last, Code::new(0b111111110, 9));
+        assert!(tree.to_dht().is_ok());
+    }
+}
@@ -0,0 +1,366 @@
+//! Huffman codes whose output digits have a radix other than 2.
+//!
+//! Each code is a string of digits between 0 and `radix - 1`. With a radix of 4 or 16, for example,
+//! every digit fills 2 or 4 bits, so codes line up with a nibble oriented channel.
+
+use core::cmp::Reverse;
+#[cfg(feature = "std")]
+use std::collections::BinaryHeap;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BinaryHeap;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+use core::result::Result;
+use core::error::Error;
+
+use super::*;
+
+/// Largest radix supported, so that every digit fits in a byte.
+pub const MAX_RADIX: u32 = 256;
+
+/// A code of `digits` digits, packed into an integer with the first digit most significant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KaryCode {
+    value: u64,
+    digits: u8,
+}
+
+impl KaryCode {
+    /// The code as an integer in the code's radix.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    /// The number of digits in the code.
+    pub fn digits(&self) -> u8 {
+        self.digits
+    }
+
+    /// Each digit of the code, first digit first, for the `radix` of the tree it came from. A
+    /// radix outside 2 to `MAX_RADIX` is clamped into that range.
+    pub fn to_digits(&self, radix: u32) -> Vec<u8> {
+        let radix = radix.clamp(2, MAX_RADIX);
+        let mut digits = vec![0; self.digits as usize];
+        let mut value = self.value;
+
+        for digit in digits.iter_mut().rev() {
+            *digit = (value % radix as u64) as u8;
+            value /= radix as u64;
+        }
+
+        digits
+    }
+}
+
+/// Canonical huffman codes over digits of any radix from 2 to `MAX_RADIX`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KaryTree<S = u8> {
+    radix: u32,
+    codes: Vec<Option<KaryCode>>,
+    /// Symbols in increasing order of `(digits, symbol)`, which is the order of their codes
+    sorted_symbols: Vec<S>,
+    /// For each number of digits, the first code with that many digits and the number of codes
+    first_codes: Vec<(u64, usize)>,
+}
+
+impl<S: Symbol> KaryTree<S> {
+    /// Build the codes from `(symbol, digits)` pairs, where `digits` is the length of the code of
+    /// the symbol.
+    ///
+    /// Codes of the same length are assigned consecutively in increasing order of symbol. Fails
+    /// if the lengths are over-subscribed for the radix, or a code does not fit in 64 bits.
+    pub fn new(code_lengths: Vec<(S, u8)>, radix: u32) -> Result<KaryTree<S>, Box<dyn Error>> {
+        if !(2..=MAX_RADIX).contains(&radix) {
+            return Err(From::from(format!("Radix {} is not between 2 and {}", radix, MAX_RADIX)));
+        }
+
+        let mut sorted: Vec<(u8, S)> = code_lengths.into_iter()
+            .filter(|&(_symbol, length)| length > 0)
+            .map(|(symbol, length)| (length, symbol))
+            .collect();
+        sorted.sort();
+
+        let mut codes = vec![None; S::ALPHABET_SIZE];
+        let mut first_codes = vec![(0, 0); sorted.last().map_or(0, |&(length, _symbol)| length as usize) + 1];
+        let mut code: u128 = 0;
+        let mut previous_length = sorted.first().map_or(0, |&(length, _symbol)| length);
+
+        for (i, &(length, symbol)) in sorted.iter().enumerate() {
+            if i > 0 {
+                code += 1;
+            }
+
+            for _ in previous_length..length {
+                code *= radix as u128;
+            }
+            previous_length = length;
+
+            let limit = (radix as u128).checked_pow(length as u32).filter(|&limit| limit <= 1 << 64)
+                .ok_or(format!("Code of {} digits does not fit in 64 bits", length))?;
+
+            if code >= limit {
+                return Err(From::from("Code lengths are over-subscribed"));
+            }
+
+            if codes[symbol.to_index()].is_some() {
+                return Err(From::from(format!("Symbol {} has more than one code length", symbol)));
+            }
+
+            codes[symbol.to_index()] = Some(KaryCode {value: code as u64, digits: length});
+
+            let first = &mut first_codes[length as usize];
+            if first.1 == 0 {
+                first.0 = code as u64;
+            }
+            first.1 += 1;
+        }
+
+        Ok(KaryTree {
+            radix,
+            codes,
+            sorted_symbols: sorted.into_iter().map(|(_length, symbol)| symbol).collect(),
+            first_codes,
+        })
+    }
+
+    /// Build optimal codes from the frequency of each symbol.
+    pub fn from_frequencies<F: Frequencies<Symbol = S> + ?Sized>(frequencies: &F, radix: u32) -> Result<KaryTree<S>, Box<dyn Error>> {
+        KaryTree::new(kary_code_lengths(frequencies, radix)?, radix)
+    }
+
+    /// The radix of the digits.
+    pub fn radix(&self) -> u32 {
+        self.radix
+    }
+
+    /// The code of `symbol`.
+    pub fn code(&self, symbol: S) -> Option<KaryCode> {
+        self.codes[symbol.to_index()]
+    }
+
+    /// The digits of the codes for every symbol in `symbols`, one digit per byte.
+    pub fn encode_digits(&self, symbols: &[S]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut digits = Vec::new();
+
+        for &symbol in symbols {
+            let code = self.code(symbol).ok_or(format!("Symbol {} not found in code book", symbol))?;
+            digits.extend(code.to_digits(self.radix));
+        }
+
+        Ok(digits)
+    }
+
+    /// Decode exactly `count` symbols from digits given one per byte.
+    pub fn decode_digits(&self, digits: &[u8], count: u64) -> Result<Vec<S>, Box<dyn Error>> {
+        let mut digits = digits.iter().map(|&digit| Ok(Some(digit as u32)));
+
+        self.decode_impl(&mut || digits.next().unwrap_or(Ok(None)), count)
+    }
+
+    /// Write the digits of the codes for every symbol in `symbols` as `log2(radix)` bits each,
+    /// padding the last byte with zeros.
+    ///
+    /// Fails unless the radix is a power of two.
+    #[cfg(feature = "std")]
+    pub fn encode<W: Write>(&self, symbols: &[S], write: &mut W) -> Result<(), Box<dyn Error>> {
+        let bits = self.digit_bits()?;
+        let mut bit_writer = BitWriter::new(write);
+
+        for digit in self.encode_digits(symbols)? {
+            bit_writer.write_bits_u64(digit as u64, bits)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decode exactly `count` symbols written by `encode`.
+    #[cfg(feature = "std")]
+    pub fn decode<R: Read>(&self, read: R, count: u64) -> Result<Vec<S>, Box<dyn Error>> {
+        let bits = self.digit_bits()?;
+        let mut bit_reader = BitReader::new(read);
+
+        self.decode_impl(&mut || {
+            if bit_reader.fill(bits)? < bits {
+                return Ok(None);
+            }
+
+            let digit = (bit_reader.peek() >> (64 - bits as u32)) as u32;
+            bit_reader.consume(bits);
+
+            Ok(Some(digit))
+        }, count)
+    }
+
+    /// Number of bits of each digit, if the radix is a power of two.
+    #[cfg(feature = "std")]
+    fn digit_bits(&self) -> Result<u8, Box<dyn Error>> {
+        if !self.radix.is_power_of_two() {
+            return Err(From::from(format!("Radix {} is not a power of two", self.radix)));
+        }
+
+        Ok(self.radix.trailing_zeros() as u8)
+    }
+
+    /// Decode `count` symbols from the digits returned by `next_digit`.
+    fn decode_impl<F>(&self, next_digit: &mut F, count: u64) -> Result<Vec<S>, Box<dyn Error>>
+        where F: FnMut() -> Result<Option<u32>, Box<dyn Error>>
+    {
+        let mut symbols = Vec::new();
+
+        while (symbols.len() as u64) < count {
+            let mut code: u64 = 0;
+            let mut offset = 0;
+            let mut symbol = None;
+
+            for &(first, codes) in self.first_codes.iter().skip(1) {
+                let digit = next_digit()?.ok_or("Data ended in the middle of a code")?;
+
+                if digit >= self.radix {
+                    return Err(From::from(format!("Digit {} is too large for radix {}", digit, self.radix)));
+                }
+
+                code = code * self.radix as u64 + digit as u64;
+
+                if code >= first && code - first < codes as u64 {
+                    symbol = Some(self.sorted_symbols[offset + (code - first) as usize]);
+                    break;
+                }
+
+                offset += codes;
+            }
+
+            symbols.push(symbol.ok_or("Invalid code")?);
+        }
+
+        Ok(symbols)
+    }
+}
+
+/// Compute optimal code lengths in digits of `radix` for `frequencies`.
+///
+/// Dummy symbols of frequency 0 are added so that every internal node of the tree has exactly
+/// `radix` children, which keeps the code optimal. A lone symbol gets a code of one digit.
+pub fn kary_code_lengths<F: Frequencies + ?Sized>(frequencies: &F, radix: u32) -> Result<CodeLengths<F::Symbol>, Box<dyn Error>> {
+    if !(2..=MAX_RADIX).contains(&radix) {
+        return Err(From::from(format!("Radix {} is not between 2 and {}", radix, MAX_RADIX)));
+    }
+
+    let symbols: Vec<(usize, u64)> = frequencies.frequencies().iter().enumerate()
+        .filter(|&(_symbol, &frequency)| frequency != 0)
+        .map(|(symbol, &frequency)| (symbol, frequency))
+        .collect();
+
+    let n = symbols.len();
+
+    if n == 0 {
+        return Err(From::from("No symbols to build codes for"));
+    }
+
+    if n == 1 {
+        return Ok(vec![(F::Symbol::from_index(symbols[0].0), 1)]);
+    }
+
+    let radix = radix as usize;
+
+    // Pad with dummies until n - 1 is a multiple of radix - 1
+    let dummies = (radix - 1 - (n - 1) % (radix - 1)) % (radix - 1);
+
+    // Nodes are the leaves, then the dummies, then the internal nodes as they are made
+    let mut parents: Vec<usize> = vec![0; n + dummies];
+    let mut queue: BinaryHeap<Reverse<(u128, usize)>> = symbols.iter()
+        .map(|&(_symbol, frequency)| frequency as u128)
+        .chain((0..dummies).map(|_| 0))
+        .enumerate()
+        .map(|(node, weight)| Reverse((weight, node)))
+        .collect();
+
+    while queue.len() > 1 {
+        let node = parents.len();
+        parents.push(node);
+
+        let mut weight = 0;
+        for _ in 0..radix {
+            let Reverse((child_weight, child)) = queue.pop().expect("Dummies fill every internal node");
+            weight += child_weight;
+            parents[child] = node;
+        }
+
+        queue.push(Reverse((weight, node)));
+    }
+
+    // Parents come after their children, so depths can be found from the root down
+    let mut depths = vec![0usize; parents.len()];
+    for node in (0..parents.len() - 1).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+
+    Ok(symbols.iter().zip(depths)
+        .map(|(&(symbol, _frequency), depth)| (F::Symbol::from_index(symbol), depth.min(u8::MAX as usize) as u8))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use std::io::Cursor;
+
+    #[test]
+    fn test_kary_code_lengths() {
+        // With radix 3, 4 symbols need a dummy so the root has 3 children
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[..4].copy_from_slice(&[5, 4, 2, 1]);
+
+        let mut code_lengths = kary_code_lengths(&freq_table, 3).unwrap();
+        code_lengths.sort();
+        assert_eq!(code_lengths, vec![(0, 1), (1, 1), (2, 2), (3, 2)]);
+
+        // Radix 2 matches binary huffman codes
+        let binary = kary_code_lengths(&freq_table, 2).unwrap();
+        let cost = |lengths: &[(u8, u8)]| lengths.iter()
+            .map(|&(symbol, length)| freq_table[symbol as usize] * length as u64)
+            .sum::<u64>();
+        assert_eq!(cost(&binary), cost(&optimal_code_lengths(&freq_table).unwrap()));
+
+        assert!(kary_code_lengths(&freq_table, 1).is_err());
+        assert!(kary_code_lengths(&freq_table, 257).is_err());
+    }
+
+    #[test]
+    fn test_kary_tree() {
+        let tree = KaryTree::<u8>::new(vec![(b'a', 1), (b'b', 1), (b'c', 2), (b'd', 2)], 3).unwrap();
+        assert_eq!(tree.code(b'a').unwrap().to_digits(3), vec![0]);
+        assert_eq!(tree.code(b'b').unwrap().to_digits(3), vec![1]);
+        assert_eq!(tree.code(b'c').unwrap().to_digits(3), vec![2, 0]);
+        assert_eq!(tree.code(b'd').unwrap().to_digits(3), vec![2, 1]);
+
+        let digits = tree.encode_digits(b"dcab").unwrap();
+        assert_eq!(digits, vec![2, 1, 2, 0, 0, 1]);
+        assert_eq!(tree.decode_digits(&digits, 4).unwrap(), b"dcab".to_vec());
+        assert!(tree.decode_digits(&[2, 2], 1).is_err());
+        assert!(tree.decode_digits(&[2], 1).is_err());
+        assert!(tree.decode_digits(&[3], 1).is_err());
+
+        // Radix 3 digits cannot be packed into bits
+        #[cfg(feature = "std")]
+        assert!(tree.encode(b"a", &mut Vec::new()).is_err());
+
+        // Over-subscribed
+        assert!(KaryTree::<u8>::new(vec![(0, 1), (1, 1), (2, 1), (3, 1)], 3).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_nibbles() {
+        let text = b"a small sample string, in nibbles";
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(text);
+
+        let tree = KaryTree::from_frequencies(&frequencies, 16).unwrap();
+        assert!(tree.code(b' ').unwrap().digits() == 1);
+
+        let mut encoded = Vec::new();
+        tree.encode(text, &mut encoded).unwrap();
+        assert_eq!(tree.decode(Cursor::new(&encoded), text.len() as u64).unwrap(), text.to_vec());
+    }
+}
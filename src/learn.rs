@@ -0,0 +1,347 @@
+//! One pass coding with canonical codes that are learned from the data as it is coded.
+//!
+//! Coding starts from a small table with codes only for `ESCAPE_SYMBOL`, `REBUILD_SYMBOL` and
+//! `END_SYMBOL`, so every byte is at first escaped and written as 8 raw bits. The encoder counts
+//! the bytes it codes, and every so often writes `REBUILD_SYMBOL`, after which both sides rebuild
+//! the table from the counts so far. No table is ever stored, and the ratio soon approaches that of
+//! a static table built in a separate pass. `END_SYMBOL` marks the end of the data.
+//!
+//! Short messages end before much can be learned, so both sides can instead start from the counts
+//! of a preset dictionary, a sample of similar data that they share ahead of time.
+
+use std::io;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// The symbol that tells the decoder to rebuild its table from the bytes decoded so far.
+pub const REBUILD_SYMBOL: u16 = ESCAPE_SYMBOL + 1;
+
+/// The symbol that marks the end of the data.
+pub const END_SYMBOL: u16 = ESCAPE_SYMBOL + 2;
+
+/// Default number of bytes a `LearningEncoder` codes between rebuilding its table.
+pub const DEFAULT_REBUILD_INTERVAL: u64 = 4096;
+
+/// Table and counts kept in step by the encoder and decoder.
+#[derive(Debug, Clone)]
+struct LearnedTable {
+    counts: FrequencyTable<u8>,
+    tree: CanonicalTree<u16>,
+}
+
+impl LearnedTable {
+    fn new() -> LearnedTable {
+        let counts = FrequencyTable::new();
+        let tree = LearnedTable::build(&counts);
+
+        LearnedTable {counts, tree}
+    }
+
+    /// Add `frequencies` to the counts and rebuild the table from them.
+    fn preset(&mut self, frequencies: &FrequencyTable) {
+        self.counts.merge(frequencies);
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        self.tree = LearnedTable::build(&self.counts);
+    }
+
+    /// Build the table from the counts. The escape symbol gets the number of distinct bytes seen,
+    /// so escapes grow rarer as bytes are learned.
+    fn build(counts: &FrequencyTable<u8>) -> CanonicalTree<u16> {
+        let mut frequencies = FrequencyTable::new();
+
+        for (byte, count) in counts.iter() {
+            frequencies.set(byte as u16, count);
+        }
+        frequencies.set(ESCAPE_SYMBOL, (counts.symbols() as u64).max(1));
+        frequencies.set(REBUILD_SYMBOL, 1);
+        frequencies.set(END_SYMBOL, 1);
+
+        CanonicalTree::from_frequencies(&frequencies).expect("Control symbols have a frequency")
+    }
+
+    fn write_symbol<W: Write>(&self, symbol: u16, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        bit_writer.write_code(self.tree.code_book().get(symbol).expect("Byte has a code"))
+    }
+}
+
+/// Encodes bytes written to it, learning its table as it goes.
+///
+/// Call `finish` after the last byte to mark the end of the data. The final partial byte is
+/// padded with zeros when the encoder is dropped.
+pub struct LearningEncoder<W: Write> {
+    bit_writer: BitWriter<W>,
+    table: LearnedTable,
+    rebuild_interval: u64,
+    /// Bytes coded since the table was last rebuilt
+    since_rebuild: u64,
+}
+
+impl<W: Write> LearningEncoder<W> {
+    pub fn new(write: W) -> LearningEncoder<W> {
+        LearningEncoder {
+            bit_writer: BitWriter::new(write),
+            table: LearnedTable::new(),
+            rebuild_interval: DEFAULT_REBUILD_INTERVAL,
+            since_rebuild: 0,
+        }
+    }
+
+    /// Rebuild the table after every `bytes` bytes, or never if `bytes` is 0. Defaults to
+    /// `DEFAULT_REBUILD_INTERVAL`.
+    ///
+    /// Frequent rebuilds learn faster but take more time. The decoder needs no matching setting.
+    pub fn rebuild_interval(mut self, bytes: u64) -> LearningEncoder<W> {
+        self.rebuild_interval = bytes;
+        self
+    }
+
+    /// Start from a table built from `frequencies`, which the decoder must also be given.
+    ///
+    /// Must be called before any bytes are written.
+    pub fn preset(mut self, frequencies: &FrequencyTable) -> LearningEncoder<W> {
+        self.table.preset(frequencies);
+        self
+    }
+
+    /// Start from a table built from the bytes of `dictionary`, which the decoder must also be
+    /// given. Must be called before any bytes are written.
+    pub fn dictionary(self, dictionary: &[u8]) -> LearningEncoder<W> {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(dictionary);
+
+        self.preset(&frequencies)
+    }
+
+    /// Rebuild the table now, rather than waiting for the interval to pass.
+    pub fn rebuild(&mut self) -> io::Result<()> {
+        self.table.write_symbol(REBUILD_SYMBOL, &mut self.bit_writer)?;
+        self.table.rebuild();
+        self.since_rebuild = 0;
+
+        Ok(())
+    }
+
+    /// Mark the end of the data and write the final partial byte.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.table.write_symbol(END_SYMBOL, &mut self.bit_writer)
+    }
+}
+
+impl<W: Write> Write for LearningEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            match self.table.tree.code_book().get(byte as u16) {
+                Some(code) => self.bit_writer.write_code(code)?,
+                None => {
+                    self.table.write_symbol(ESCAPE_SYMBOL, &mut self.bit_writer)?;
+                    self.bit_writer.write_bits_u64(byte as u64, 8)?;
+                },
+            }
+
+            self.table.counts.add_symbol(byte);
+            self.since_rebuild += 1;
+
+            if self.since_rebuild == self.rebuild_interval {
+                self.rebuild()?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes data written by a `LearningEncoder`, reading as far as the end of the data.
+///
+/// Errors are returned as `io::Error`s wrapping a `DecodeError` where there is one.
+pub struct LearningDecoder<R> {
+    bit_reader: BitReader<R>,
+    table: LearnedTable,
+    bytes_decoded: u64,
+    finished: bool,
+    /// Error to return from the next `read`, after the bytes decoded before it
+    error: Option<io::Error>,
+}
+
+impl<R: Read> LearningDecoder<R> {
+    pub fn new(read: R) -> LearningDecoder<R> {
+        LearningDecoder {
+            bit_reader: BitReader::new(read),
+            table: LearnedTable::new(),
+            bytes_decoded: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Start from a table built from `frequencies`, as given to the encoder.
+    ///
+    /// Must be called before any bytes are read.
+    pub fn preset(mut self, frequencies: &FrequencyTable) -> LearningDecoder<R> {
+        self.table.preset(frequencies);
+        self
+    }
+
+    /// Start from a table built from the bytes of `dictionary`, as given to the encoder. Must be
+    /// called before any bytes are read.
+    pub fn dictionary(self, dictionary: &[u8]) -> LearningDecoder<R> {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(dictionary);
+
+        self.preset(&frequencies)
+    }
+
+    /// Decode the next byte, rebuilding the table as signaled. Returns `None` at the end of the
+    /// data.
+    fn decode_byte(&mut self) -> Result<Option<u8>, Box<dyn Error>> {
+        loop {
+            let decode_table = self.table.tree.decode_table();
+
+            let byte = match decode_table.decode_checked(&mut self.bit_reader, self.bytes_decoded)? {
+                Some(REBUILD_SYMBOL) => {
+                    self.table.rebuild();
+                    continue;
+                },
+                Some(END_SYMBOL) => return Ok(None),
+                Some(ESCAPE_SYMBOL) => {
+                    if self.bit_reader.fill(8)? < 8 {
+                        return Err(decode_table.unexpected_end(&mut self.bit_reader, self.bytes_decoded).into());
+                    }
+
+                    let byte = (self.bit_reader.peek() >> 56) as u8;
+                    self.bit_reader.consume(8);
+
+                    byte
+                },
+                Some(symbol) => symbol as u8,
+                None => return Err(decode_table.unexpected_end(&mut self.bit_reader, self.bytes_decoded).into()),
+            };
+
+            self.table.counts.add_symbol(byte);
+            self.bytes_decoded += 1;
+
+            return Ok(Some(byte));
+        }
+    }
+}
+
+impl<R: Read> Read for LearningDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let mut read = 0;
+
+        while read < buf.len() && !self.finished {
+            match self.decode_byte() {
+                Ok(Some(byte)) => {
+                    buf[read] = byte;
+                    read += 1;
+                },
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    let err = into_io_error(err);
+
+                    if read == 0 {
+                        return Err(err);
+                    }
+
+                    self.error = Some(err);
+                    break;
+                },
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(data: &[u8], rebuild_interval: u64) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = LearningEncoder::new(&mut encoded).rebuild_interval(rebuild_interval);
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let mut decoded = Vec::new();
+        LearningDecoder::new(Cursor::new(&encoded)).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"", 16);
+        round_trip(b"abracadabra", 4);
+
+        let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor \
+            incididunt ut labore et dolore magna aliqua.".repeat(50);
+
+        // Without rebuilds every byte is escaped
+        let never = round_trip(text.as_bytes(), 0);
+        assert!(never.len() > text.len());
+
+        let learned = round_trip(text.as_bytes(), 256);
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(text.as_bytes());
+        let (_header_bytes, payload_bits) = estimate_compressed_size(&frequencies);
+        assert!((learned.len() as u64) < payload_bits / 8 * 11 / 10);
+
+        let every_byte: Vec<u8> = (0..8).flat_map(|_| 0..=255).collect();
+        round_trip(&every_byte, 100);
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let dictionary = "{\"user\":\"alice\",\"action\":\"login\",\"ok\":true}\n".repeat(20);
+        let message = b"{\"user\":\"bob\",\"action\":\"logout\",\"ok\":false}";
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = LearningEncoder::new(&mut encoded).dictionary(dictionary.as_bytes());
+            encoder.write_all(message).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let mut decoded = Vec::new();
+        LearningDecoder::new(Cursor::new(&encoded)).dictionary(dictionary.as_bytes())
+            .read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, &message[..]);
+
+        // Without the dictionary most bytes would be escaped
+        assert!(encoded.len() < message.len() * 3 / 4);
+        assert!(round_trip(message, DEFAULT_REBUILD_INTERVAL).len() > message.len());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = LearningEncoder::new(&mut encoded).rebuild_interval(2);
+            encoder.write_all(b"abcabcabc").unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let mut decoded = Vec::new();
+        let err = LearningDecoder::new(Cursor::new(&encoded[..encoded.len() - 1])).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<DecodeError>().unwrap().kind, DecodeErrorKind::UnexpectedEnd);
+        assert!(b"abcabcabc".starts_with(&decoded));
+    }
+}
@@ -1,15 +1,252 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
 extern crate byteorder;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
 
 mod bitstream;
-use bitstream::*;
+pub use bitstream::*;
+
+#[cfg(feature = "std")]
+mod pack;
+#[cfg(feature = "std")]
+use pack::*;
+
+#[cfg(feature = "std")]
+mod checksum;
+#[cfg(feature = "std")]
+use checksum::*;
+
+mod symbol;
+pub use symbol::*;
 
 mod huffman;
 pub use huffman::*;
 
+mod limit;
+pub use limit::*;
+
+mod error;
+pub use error::*;
+
+mod frequency;
+pub use frequency::*;
+
+mod code_book;
+pub use code_book::*;
+
+mod table;
+pub use table::*;
+
 mod canonical;
 pub use canonical::*;
 
+#[cfg(feature = "std")]
+mod incremental;
+#[cfg(feature = "std")]
+pub use incremental::*;
+
+mod kary;
+pub use kary::*;
+
+#[cfg(feature = "std")]
+mod adaptive;
+#[cfg(feature = "std")]
+pub use adaptive::*;
+
+#[cfg(feature = "std")]
+mod shape;
+
+mod validate;
+pub use validate::*;
+
+#[cfg(feature = "std")]
+mod deflate;
+#[cfg(feature = "std")]
+pub use deflate::*;
+
+#[cfg(feature = "std")]
+mod jpeg;
+#[cfg(feature = "std")]
+pub use jpeg::*;
+
+#[cfg(feature = "std")]
+mod prefix;
+#[cfg(feature = "std")]
+pub use prefix::*;
+
+#[cfg(feature = "std")]
+mod escape;
+#[cfg(feature = "std")]
+pub use escape::*;
+
+#[cfg(feature = "std")]
+mod remap;
+#[cfg(feature = "std")]
+pub use remap::*;
+
+#[cfg(feature = "std")]
+mod learn;
+#[cfg(feature = "std")]
+pub use learn::*;
+
+#[cfg(feature = "std")]
+mod window;
+#[cfg(feature = "std")]
+pub use window::*;
+
+#[cfg(feature = "std")]
+mod single_pass;
+#[cfg(feature = "std")]
+pub use single_pass::*;
+
+#[cfg(feature = "std")]
+mod model;
+#[cfg(feature = "std")]
+pub use model::*;
+
+#[cfg(feature = "std")]
+mod arithmetic;
+#[cfg(feature = "std")]
+pub use arithmetic::*;
+
+#[cfg(feature = "std")]
+mod range;
+#[cfg(feature = "std")]
+pub use range::*;
+
+#[cfg(feature = "std")]
+mod tans;
+#[cfg(feature = "std")]
+pub use tans::*;
+
+#[cfg(feature = "std")]
+mod coder;
+#[cfg(feature = "std")]
+pub use coder::*;
+
+#[cfg(feature = "std")]
+mod context;
+#[cfg(feature = "std")]
+pub use context::*;
+
+#[cfg(feature = "std")]
+mod rle;
+#[cfg(feature = "std")]
+pub use rle::*;
+
+#[cfg(feature = "std")]
+mod mtf;
+#[cfg(feature = "std")]
+pub use mtf::*;
+
+#[cfg(feature = "std")]
+mod bwt;
+#[cfg(feature = "std")]
+pub use bwt::*;
+
+#[cfg(feature = "std")]
+mod delta;
+#[cfg(feature = "std")]
+pub use delta::*;
+
+#[cfg(feature = "std")]
+mod lz77;
+#[cfg(feature = "std")]
+pub use lz77::*;
+
+#[cfg(feature = "std")]
+mod stage;
+#[cfg(feature = "std")]
+pub use stage::*;
+
+#[cfg(feature = "std")]
+mod train;
+#[cfg(feature = "std")]
+pub use train::*;
+
+#[cfg(feature = "std")]
+mod dictionary;
+#[cfg(feature = "std")]
+pub use dictionary::*;
+
+#[cfg(feature = "builtin-codebooks")]
+mod builtin;
+#[cfg(feature = "builtin-codebooks")]
+pub use builtin::*;
+
+#[cfg(feature = "std")]
+mod source;
+#[cfg(feature = "std")]
+pub use source::*;
+
+#[cfg(feature = "std")]
+mod tree_cache;
+#[cfg(feature = "std")]
+pub use tree_cache::*;
+
+#[cfg(feature = "std")]
+mod decode_limits;
+#[cfg(feature = "std")]
+pub use decode_limits::*;
+
+#[cfg(feature = "std")]
+mod recovery;
+#[cfg(feature = "std")]
+pub use recovery::*;
+
+#[cfg(feature = "std")]
 mod encode;
+#[cfg(feature = "std")]
 pub use encode::*;
 
+#[cfg(feature = "std")]
+mod pipeline;
+#[cfg(feature = "std")]
+pub use pipeline::*;
+
+#[cfg(feature = "std")]
+mod preset;
+#[cfg(feature = "std")]
+pub use preset::*;
+
+#[cfg(feature = "std")]
+mod block;
+#[cfg(feature = "std")]
+pub use block::*;
+
+#[cfg(feature = "std")]
+mod streaming;
+#[cfg(feature = "std")]
+pub use streaming::*;
+
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "mmap")]
+pub use mmap::*;
+
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+pub use stats::*;
+
+#[cfg(feature = "std")]
+mod report;
+#[cfg(feature = "std")]
+pub use report::*;
+
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "serde")]
+pub use serialize::*;
+
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, string::{String, ToString}, vec::Vec};
+
 const NUM_BYTES: usize = 256;
\ No newline at end of file
@@ -0,0 +1,297 @@
+use core::result::Result;
+use core::error::Error;
+
+use super::*;
+
+/// Symbols paired with the length of their code.
+pub type CodeLengths<S> = Vec<(S, u8)>;
+
+/// Compute optimal code lengths for `frequencies` without building a tree.
+///
+/// Uses the in-place algorithm of Moffat and Katajainen on the sorted frequencies, which needs no
+/// allocation beyond a single array. Symbols with a frequency of 0 get no code, and a lone symbol
+/// gets a 1 bit code. Lengths longer than 255 bits are reported as 255.
+pub fn optimal_code_lengths<F: Frequencies + ?Sized>(frequencies: &F) -> Result<CodeLengths<F::Symbol>, Box<dyn Error>> {
+    let mut symbols: Vec<(u64, usize)> = frequencies.frequencies().iter().enumerate()
+        .filter(|&(_symbol, &frequency)| frequency != 0)
+        .map(|(symbol, &frequency)| (frequency, symbol))
+        .collect();
+    symbols.sort();
+
+    let n = symbols.len();
+
+    if n == 0 {
+        return Err(From::from("No symbols to build codes for"));
+    }
+
+    if n == 1 {
+        return Ok(vec![(F::Symbol::from_index(symbols[0].1), 1)]);
+    }
+
+    let weights: Vec<u64> = symbols.iter().map(|&(frequency, _symbol)| frequency).collect();
+
+    Ok(symbols.iter().zip(sorted_code_lengths(&weights))
+        .map(|(&(_frequency, symbol), length)| (F::Symbol::from_index(symbol), length))
+        .collect())
+}
+
+/// Optimal code lengths for at least 2 nonzero weights in increasing order, using the in-place
+/// algorithm of Moffat and Katajainen. Lengths longer than 255 bits are reported as 255.
+pub(crate) fn sorted_code_lengths(weights: &[u64]) -> Vec<u8> {
+    let n = weights.len();
+    debug_assert!(n >= 2);
+
+    // The array holds weights, then parent indices, then depths, then code lengths
+    let mut a: Vec<u128> = weights.iter().map(|&weight| weight as u128).collect();
+
+    // Build the internal nodes in a[..n - 1], where each node takes the weight of its children.
+    // `leaf` is the next leaf, and `node` the next internal node, to be merged.
+    let mut leaf = 0;
+    let mut node = 0;
+
+    for next in 0..n - 1 {
+        for child in 0..2 {
+            let weight = if leaf >= n || (node < next && a[node] < a[leaf]) {
+                // The merged node's slot now holds the index of its parent
+                let weight = a[node];
+                a[node] = next as u128;
+                node += 1;
+                weight
+            } else {
+                leaf += 1;
+                a[leaf - 1]
+            };
+
+            a[next] = if child == 0 { weight } else { a[next] + weight };
+        }
+    }
+
+    // Turn parent indices into depths, from the root down
+    a[n - 2] = 0;
+    for next in (0..n - 2).rev() {
+        a[next] = a[a[next] as usize] + 1;
+    }
+
+    // Turn the depths of internal nodes into the depths of leaves, from the top of the tree down.
+    // The deepest leaves are those of the least frequent symbols at the start of the array.
+    let mut available: usize = 1;
+    let mut depth: u128 = 0;
+    let mut node = n as isize - 2;
+    let mut next = n as isize - 1;
+
+    while available > 0 {
+        let mut used = 0;
+
+        while node >= 0 && a[node as usize] == depth {
+            used += 1;
+            node -= 1;
+        }
+
+        while available > used {
+            a[next as usize] = depth;
+            next -= 1;
+            available -= 1;
+        }
+
+        available = 2 * used;
+        depth += 1;
+    }
+
+    a.into_iter().map(|length| length.min(u8::MAX as u128) as u8).collect()
+}
+
+/// Compute optimal code lengths for `frequencies` with no code longer than `max_length` bits.
+///
+/// Uses the package-merge algorithm. Symbols with a frequency of 0 get no code, and a lone symbol
+/// gets a 1 bit code. Fails if the symbols cannot fit in codes of `max_length` bits.
+pub fn limited_code_lengths<F: Frequencies + ?Sized>(frequencies: &F, max_length: u8) -> Result<CodeLengths<F::Symbol>, Box<dyn Error>> {
+    let mut symbols: Vec<(u64, usize)> = frequencies.frequencies().iter().enumerate()
+        .filter(|&(_symbol, &frequency)| frequency != 0)
+        .map(|(symbol, &frequency)| (frequency, symbol))
+        .collect();
+    symbols.sort();
+
+    let n = symbols.len();
+
+    if n == 0 {
+        return Err(From::from("No symbols to build codes for"));
+    }
+
+    if n == 1 {
+        return Ok(vec![(F::Symbol::from_index(symbols[0].1), 1)]);
+    }
+
+    if max_length == 0 || (max_length < 64 && n as u64 > 1 << max_length) {
+        return Err(From::from(format!("Cannot fit {} symbols in codes of at most {} bits", n, max_length)));
+    }
+
+    let leaves: Vec<u128> = symbols.iter().map(|&(frequency, _symbol)| frequency as u128).collect();
+
+    // Weights of the current list, and for each list whether each of its items is a leaf
+    let mut list = leaves.clone();
+    let mut is_leaf_lists = vec![vec![true; n]];
+
+    for _ in 1..max_length {
+        // Package adjacent pairs of the previous list
+        let packages: Vec<u128> = list.chunks_exact(2)
+            .map(|pair| pair[0] + pair[1])
+            .collect();
+
+        // Merge the packages with the leaves, preferring leaves on ties
+        let mut merged = Vec::with_capacity(leaves.len() + packages.len());
+        let mut is_leaf = Vec::with_capacity(leaves.len() + packages.len());
+        let mut leaves_iter = leaves.iter().peekable();
+        let mut packages_iter = packages.iter().peekable();
+
+        loop {
+            let take_leaf = match (leaves_iter.peek(), packages_iter.peek()) {
+                (Some(leaf), Some(package)) => leaf <= package,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+
+            if take_leaf {
+                merged.push(*leaves_iter.next().expect("A leaf is left"));
+            } else {
+                merged.push(*packages_iter.next().expect("A package is left"));
+            }
+
+            is_leaf.push(take_leaf);
+        }
+
+        // Only the first 2n - 2 items can ever be selected
+        merged.truncate(2 * n - 2);
+        is_leaf.truncate(2 * n - 2);
+
+        list = merged;
+        is_leaf_lists.push(is_leaf);
+    }
+
+    // Select the first 2n - 2 items of the last list, and the items of the previous lists that
+    // make up the selected packages. The leaves of a list come in increasing order of frequency,
+    // so the selected leaves are always those of the least frequent symbols, and the code length
+    // of a symbol is the number of lists where its leaf is selected.
+    let mut lengths = vec![0u8; n];
+    let mut selected = 2 * n - 2;
+
+    for is_leaf in is_leaf_lists.iter().rev() {
+        let selected_leaves = is_leaf[..selected].iter().filter(|&&leaf| leaf).count();
+
+        for length in lengths[..selected_leaves].iter_mut() {
+            *length += 1;
+        }
+
+        selected = 2 * (selected - selected_leaves);
+    }
+
+    Ok(symbols.iter().zip(lengths)
+        .map(|(&(_frequency, symbol), length)| (F::Symbol::from_index(symbol), length))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fibonacci_frequencies(symbols: usize) -> [u64; NUM_BYTES] {
+        let mut freq_table = [0; NUM_BYTES];
+        let (mut a, mut b) = (1, 1);
+
+        for frequency in freq_table.iter_mut().take(symbols) {
+            *frequency = a;
+
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+
+        freq_table
+    }
+
+    #[cfg(feature = "std")]
+    fn cost(freq_table: &[u64; NUM_BYTES], code_lengths: &[(u8, u8)]) -> u64 {
+        code_lengths.iter()
+            .map(|&(symbol, length)| freq_table[symbol as usize] * length as u64)
+            .sum()
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_matches_huffman_when_unconstrained() {
+        let freq_table = fibonacci_frequencies(20);
+
+        let limited = limited_code_lengths(&freq_table, 32).unwrap();
+        let unlimited = HuffmanTree::new(&freq_table).unwrap().get_code_lengths();
+
+        assert_eq!(cost(&freq_table, &limited), cost(&freq_table, &unlimited));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_optimal_matches_huffman() {
+        for symbols in [2, 3, 20, 70] {
+            let freq_table = fibonacci_frequencies(symbols);
+
+            let optimal = optimal_code_lengths(&freq_table).unwrap();
+            let huffman = HuffmanTree::new(&freq_table).unwrap().get_code_lengths();
+
+            assert_eq!(optimal.len(), symbols);
+            assert_eq!(cost(&freq_table, &optimal), cost(&freq_table, &huffman));
+        }
+
+        let mut freq_table = [0; NUM_BYTES];
+        for (symbol, frequency) in freq_table.iter_mut().enumerate() {
+            *frequency = (symbol as u64 * 7919) % 1000;
+        }
+
+        let optimal = optimal_code_lengths(&freq_table).unwrap();
+        let huffman = HuffmanTree::new(&freq_table).unwrap().get_code_lengths();
+        assert_eq!(cost(&freq_table, &optimal), cost(&freq_table, &huffman));
+
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[9] = 4;
+        assert_eq!(optimal_code_lengths(&freq_table).unwrap(), vec![(9, 1)]);
+        assert!(optimal_code_lengths(&[0; NUM_BYTES]).is_err());
+    }
+
+    #[test]
+    fn test_limits_length() {
+        let freq_table = fibonacci_frequencies(20);
+
+        let code_lengths = limited_code_lengths(&freq_table, 8).unwrap();
+
+        assert_eq!(code_lengths.len(), 20);
+        assert_eq!(code_lengths.iter().map(|&(_symbol, length)| length).max(), Some(8));
+
+        // The code is still complete
+        let kraft_sum: u64 = code_lengths.iter()
+            .map(|&(_symbol, length)| 1 << (8 - length))
+            .sum();
+        assert_eq!(kraft_sum, 1 << 8);
+
+        assert!(CanonicalTree::new(code_lengths).is_ok());
+    }
+
+    #[test]
+    fn test_too_many_symbols() {
+        let freq_table = [1; NUM_BYTES];
+
+        assert!(limited_code_lengths(&freq_table, 7).is_err());
+        assert_eq!(limited_code_lengths(&freq_table, 8).unwrap().len(), NUM_BYTES);
+    }
+
+    #[test]
+    fn test_wide_alphabet() {
+        let mut freq_table = FrequencyTable::<u16>::new();
+        for symbol in 0..=u16::MAX {
+            freq_table.add_symbol(symbol);
+        }
+
+        assert!(limited_code_lengths(&freq_table, 15).is_err());
+
+        let code_lengths = limited_code_lengths(&freq_table, 16).unwrap();
+        assert_eq!(code_lengths.len(), 1 << 16);
+        assert!(code_lengths.iter().all(|&(_symbol, length)| length == 16));
+    }
+}
@@ -0,0 +1,393 @@
+//! LZ77 match finding with the literals and matches Huffman coded, much like DEFLATE.
+//!
+//! Huffman codes of single bytes cannot take advantage of repeated strings. The data is instead
+//! split into literal bytes and matches, each a length and a distance back to an earlier copy of
+//! the same bytes, found with hash chains over a window of `LZ77_WINDOW_SIZE` bytes. Literals and
+//! lengths share one alphabet and distances have their own, with the same symbols and extra bits
+//! as DEFLATE (RFC 1951), and each alphabet gets its own table built for the whole data.
+//!
+//! The tables are written first, each as the number of code lengths followed by the lengths as
+//! written by `CanonicalTree::deflate_lengths`, 4 bits each. The number is 9 bits for the
+//! literal/length table and 5 bits for the distance table, which is left out when it is 0. The
+//! codes of the literals and matches follow. There is no end of block symbol, as the `Encoder`
+//! stores the size.
+
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Farthest back a match can refer.
+pub const LZ77_WINDOW_SIZE: usize = 32 * 1024;
+
+/// Shortest match worth coding.
+pub const LZ77_MIN_MATCH: usize = 3;
+
+/// Longest match that can be coded.
+pub const LZ77_MAX_MATCH: usize = 258;
+
+/// Most earlier positions tried for each match.
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: u32 = 15;
+
+/// No position in the hash chains.
+const NONE: usize = usize::MAX;
+
+/// First literal/length symbol for a length.
+const FIRST_LENGTH_SYMBOL: u16 = 257;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// A literal byte, or a copy of earlier bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LzToken {
+    Literal(u8),
+    /// Copy `length` bytes starting `distance` bytes back, which may overlap the copy itself.
+    Match {
+        length: u16,
+        distance: u16,
+    },
+}
+
+/// Split `bytes` into literals and the longest matches found.
+pub fn lz77_tokens(bytes: &[u8]) -> Vec<LzToken> {
+    let mut tokens = Vec::new();
+    let mut chains = HashChains::new(bytes);
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let (length, distance) = chains.longest_match(position);
+
+        if length >= LZ77_MIN_MATCH {
+            tokens.push(LzToken::Match { length: length as u16, distance: distance as u16 });
+
+            for inserted in position..position + length {
+                chains.insert(inserted);
+            }
+
+            position += length;
+        } else {
+            tokens.push(LzToken::Literal(bytes[position]));
+            chains.insert(position);
+            position += 1;
+        }
+    }
+
+    tokens
+}
+
+/// For each hash of `LZ77_MIN_MATCH` bytes, the positions in the window that start with them,
+/// newest first.
+struct HashChains<'a> {
+    bytes: &'a [u8],
+    /// Newest position for each hash
+    head: Vec<usize>,
+    /// Next older position with the same hash, indexed by position within the window
+    previous: Vec<usize>,
+}
+
+impl<'a> HashChains<'a> {
+    fn new(bytes: &'a [u8]) -> HashChains<'a> {
+        HashChains {
+            bytes,
+            head: vec![NONE; 1 << HASH_BITS],
+            previous: vec![NONE; LZ77_WINDOW_SIZE],
+        }
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + LZ77_MIN_MATCH <= self.bytes.len() {
+            let hash = hash(&self.bytes[position..]);
+            self.previous[position % LZ77_WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+
+    /// The longest match for `position` among the earlier positions with the same hash, as a
+    /// length and a distance.
+    fn longest_match(&self, position: usize) -> (usize, usize) {
+        let bytes = self.bytes;
+        let max_length = (bytes.len() - position).min(LZ77_MAX_MATCH);
+        let mut best = (0, 0);
+
+        if max_length < LZ77_MIN_MATCH {
+            return best;
+        }
+
+        let mut candidate = self.head[hash(&bytes[position..])];
+        let mut chain = 0;
+
+        // Positions further back than the window may have had their slot reused
+        while candidate != NONE && position - candidate <= LZ77_WINDOW_SIZE && chain < MAX_CHAIN {
+            let length = bytes[candidate..].iter().zip(bytes[position..position + max_length].iter())
+                .take_while(|&(a, b)| a == b)
+                .count();
+
+            if length > best.0 {
+                best = (length, position - candidate);
+
+                if length == max_length {
+                    break;
+                }
+            }
+
+            candidate = self.previous[candidate % LZ77_WINDOW_SIZE];
+            chain += 1;
+        }
+
+        best
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let key = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (key.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// The symbol of a length or distance among `bases`, along with its extra bits and their value.
+fn base_symbol(value: u16, bases: &[u16], extra_bits: &[u8]) -> (usize, u8, u16) {
+    let symbol = bases.iter().rposition(|&base| base <= value).expect("Value is at least the first base");
+    (symbol, extra_bits[symbol], value - bases[symbol])
+}
+
+/// Encode `bytes` as literals and matches, writing the tables first.
+pub fn lz77_encode<W: Write>(bytes: &[u8], write: &mut W) -> Result<(), Box<dyn Error>> {
+    let tokens = lz77_tokens(bytes);
+
+    let mut literal_frequencies: FrequencyTable<u16> = FrequencyTable::new();
+    let mut distance_frequencies: FrequencyTable<u16> = FrequencyTable::new();
+
+    for &token in tokens.iter() {
+        match token {
+            LzToken::Literal(byte) => literal_frequencies.add_symbol(byte as u16),
+            LzToken::Match { length, distance } => {
+                let (symbol, _bits, _extra) = base_symbol(length, &LENGTH_BASES, &LENGTH_EXTRA_BITS);
+                literal_frequencies.add_symbol(FIRST_LENGTH_SYMBOL + symbol as u16);
+
+                let (symbol, _bits, _extra) = base_symbol(distance, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS);
+                distance_frequencies.add_symbol(symbol as u16);
+            },
+        }
+    }
+
+    let mut bit_writer = BitWriter::new(write);
+
+    if literal_frequencies.is_empty() {
+        return Ok(());
+    }
+
+    let literal_tree = CanonicalTree::from_frequencies_deflate(&literal_frequencies)?;
+    write_lengths(&literal_tree.deflate_lengths(), 9, &mut bit_writer)?;
+
+    let distance_tree = if distance_frequencies.is_empty() {
+        write_lengths(&[], 5, &mut bit_writer)?;
+        None
+    } else {
+        let tree = CanonicalTree::from_frequencies_deflate(&distance_frequencies)?;
+        write_lengths(&tree.deflate_lengths(), 5, &mut bit_writer)?;
+        Some(tree)
+    };
+
+    let literal_codes = literal_tree.code_book();
+
+    for &token in tokens.iter() {
+        match token {
+            LzToken::Literal(byte) => bit_writer.write_code(literal_codes.get(byte as u16).expect("Literals have codes"))?,
+            LzToken::Match { length, distance } => {
+                let (symbol, bits, extra) = base_symbol(length, &LENGTH_BASES, &LENGTH_EXTRA_BITS);
+                bit_writer.write_code(literal_codes.get(FIRST_LENGTH_SYMBOL + symbol as u16).expect("Lengths have codes"))?;
+                bit_writer.write_bits_u64(extra as u64, bits)?;
+
+                let distance_codes = distance_tree.as_ref().expect("Matches have a distance table").code_book();
+                let (symbol, bits, extra) = base_symbol(distance, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS);
+                bit_writer.write_code(distance_codes.get(symbol as u16).expect("Distances have codes"))?;
+                bit_writer.write_bits_u64(extra as u64, bits)?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn write_lengths<W: Write>(lengths: &[u8], count_bits: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+    bit_writer.write_bits_u64(lengths.len() as u64, count_bits)?;
+
+    for &length in lengths {
+        bit_writer.write_bits_u64(length as u64, 4)?;
+    }
+
+    Ok(())
+}
+
+/// Decode `bytes` bytes written by `lz77_encode`, writing them to `write`.
+///
+/// Everything decoded before an error is written. If `strict`, fails if anything other than zero
+/// padding follows the coded bytes.
+pub fn lz77_decode<R: Read, W: Write>(read: &mut R, write: &mut W, bytes: u64, strict: bool) -> Result<(), Box<dyn Error>> {
+    lz77_decode_trailing(read, write, bytes, Trailing::new(strict, false))?;
+
+    Ok(())
+}
+
+/// Like `lz77_decode`, dealing with what follows the coded bytes as `trailing` says and returning
+/// the number of bytes skipped.
+pub(crate) fn lz77_decode_trailing<R: Read, W: Write>(read: &mut R, write: &mut W, bytes: u64, trailing: Trailing) -> Result<u64, Box<dyn Error>> {
+    let mut bit_reader = BitReader::new(read);
+    let mut decoded = Vec::new();
+
+    let result = decode_tokens(&mut bit_reader, &mut decoded, bytes);
+    write.write_all(&decoded)?;
+    result?;
+
+    Ok(finish_trailing(&mut bit_reader, trailing)?)
+}
+
+fn decode_tokens<R: Read>(bit_reader: &mut BitReader<R>, decoded: &mut Vec<u8>, bytes: u64) -> Result<(), Box<dyn Error>> {
+    if bytes == 0 {
+        return Ok(());
+    }
+
+    let literal_tree: CanonicalTree<u16> = CanonicalTree::from_deflate_lengths(&read_lengths(bit_reader, 9)?)?;
+    let distance_lengths = read_lengths(bit_reader, 5)?;
+    let distance_tree: Option<CanonicalTree<u16>> = if distance_lengths.is_empty() {
+        None
+    } else {
+        Some(CanonicalTree::from_deflate_lengths(&distance_lengths)?)
+    };
+
+    let literal_table = literal_tree.decode_table();
+
+    while (decoded.len() as u64) < bytes {
+        let symbols_decoded = decoded.len() as u64;
+
+        let symbol = match literal_table.decode_checked(bit_reader, symbols_decoded)? {
+            Some(symbol) => symbol,
+            None => return Err(literal_table.unexpected_end(bit_reader, symbols_decoded).into()),
+        };
+
+        if symbol < 256 {
+            decoded.push(symbol as u8);
+            continue;
+        }
+
+        let length_symbol = (symbol - FIRST_LENGTH_SYMBOL) as usize;
+        if length_symbol >= LENGTH_BASES.len() {
+            return Err(From::from(format!("Invalid length symbol {}", symbol)));
+        }
+        let length = LENGTH_BASES[length_symbol] as usize + read_bits(bit_reader, LENGTH_EXTRA_BITS[length_symbol], symbols_decoded)? as usize;
+
+        let distance_table = distance_tree.as_ref().ok_or("Match without a distance table")?.decode_table();
+        let distance_symbol = match distance_table.decode_checked(bit_reader, symbols_decoded)? {
+            Some(symbol) => symbol as usize,
+            None => return Err(distance_table.unexpected_end(bit_reader, symbols_decoded).into()),
+        };
+        if distance_symbol >= DISTANCE_BASES.len() {
+            return Err(From::from(format!("Invalid distance symbol {}", distance_symbol)));
+        }
+        let distance = DISTANCE_BASES[distance_symbol] as usize + read_bits(bit_reader, DISTANCE_EXTRA_BITS[distance_symbol], symbols_decoded)? as usize;
+
+        if distance > decoded.len() {
+            return Err(From::from(format!("Match refers {} bytes back after {} bytes", distance, decoded.len())));
+        }
+
+        if (decoded.len() + length) as u64 > bytes {
+            return Err(From::from("Match runs past the end of the data"));
+        }
+
+        // The copy may overlap the bytes it is copying
+        let start = decoded.len() - distance;
+        for i in 0..length {
+            let byte = decoded[start + i];
+            decoded.push(byte);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_lengths<R: Read>(bit_reader: &mut BitReader<R>, count_bits: u8) -> Result<Vec<u8>, Box<dyn Error>> {
+    let count = read_bits(bit_reader, count_bits, 0)?;
+    (0..count).map(|_| Ok(read_bits(bit_reader, 4, 0)? as u8)).collect()
+}
+
+/// Read `length` bits, failing with a `DecodeError` if the data ends first.
+fn read_bits<R: Read>(bit_reader: &mut BitReader<R>, length: u8, symbols_decoded: u64) -> Result<u64, Box<dyn Error>> {
+    if length == 0 {
+        return Ok(0);
+    }
+
+    if bit_reader.fill(length)? < length {
+        return Err(Box::new(DecodeError {
+            kind: DecodeErrorKind::UnexpectedEnd,
+            bit_offset: bit_reader.position(),
+            symbols_decoded,
+            partial_code: Code::new(0, 0),
+        }));
+    }
+
+    let bits = bit_reader.peek() >> (64 - length);
+    bit_reader.consume(length);
+
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        lz77_encode(data, &mut encoded).unwrap();
+
+        let mut decoded = Vec::new();
+        lz77_decode(&mut &encoded[..], &mut decoded, data.len() as u64, true).unwrap();
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abcabcabcabcabcabcabc");
+        round_trip(&[0; 100000]);
+        round_trip(&(0..=255).cycle().take(100000).collect::<Vec<u8>>());
+
+        assert_eq!(lz77_tokens(b"abcabcabcx"), [
+            LzToken::Literal(b'a'),
+            LzToken::Literal(b'b'),
+            LzToken::Literal(b'c'),
+            LzToken::Match { length: 6, distance: 3 },
+            LzToken::Literal(b'x'),
+        ]);
+
+        // Repeated strings cost far less than their bytes would
+        let text = b"the quick brown fox jumps over the lazy dog. ".repeat(500);
+        let encoded = round_trip(&text);
+        assert!(encoded.len() < text.len() / 50);
+
+        let mut decoded = Vec::new();
+        let err = lz77_decode(&mut &encoded[..encoded.len() / 2], &mut decoded, text.len() as u64, false).unwrap_err();
+        assert_eq!(err.downcast_ref::<DecodeError>().unwrap().kind, DecodeErrorKind::UnexpectedEnd);
+        assert!(text.starts_with(&decoded));
+    }
+}
@@ -0,0 +1,82 @@
+//! Memory mapped input files, with the `mmap` feature.
+//!
+//! A mapped file is read as a single slice, so `encode_file` and `decode_file` count frequencies
+//! and code the data straight from the page cache, and `BlockEncoder::encode_slice` splits it
+//! into blocks without copying them.
+
+use std::io;
+use std::fs::File;
+use std::path::Path;
+use std::ops::Deref;
+
+use memmap2::Mmap;
+
+/// A file mapped into memory for reading, which dereferences to its bytes.
+///
+/// The file must not be changed while it is mapped, which would change the bytes under the
+/// coder, or end the program if the file shrinks.
+pub struct MappedFile {
+    /// Empty files cannot be mapped, so they have no map
+    map: Option<Mmap>,
+}
+
+impl MappedFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedFile> {
+        let file = File::open(path)?;
+
+        if file.metadata()?.len() == 0 {
+            return Ok(MappedFile { map: None });
+        }
+
+        // Safe as long as the file is not changed while mapped, as documented above
+        let map = unsafe { Mmap::map(&file)? };
+
+        Ok(MappedFile { map: Some(map) })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.map {
+            Some(ref map) => map,
+            None => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::*;
+    use std::fs;
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("huffman-mmap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (name, data) in [("empty", Vec::new()), ("text", b"the quick brown fox jumps over the lazy dog".repeat(100))] {
+            let original = dir.join(name);
+            let encoded = dir.join(format!("{}.huff", name));
+            let decoded = dir.join(format!("{}.out", name));
+            fs::write(&original, &data).unwrap();
+
+            assert_eq!(&MappedFile::open(&original).unwrap()[..], &data[..]);
+
+            encode_file(&original, &encoded).unwrap();
+            decode_file(&encoded, &decoded).unwrap();
+            assert_eq!(fs::read(&decoded).unwrap(), data);
+
+            let mut blocks = Vec::new();
+            BlockEncoder::new(Pipeline::default()).block_size(1000).unwrap()
+                .encode_slice(&MappedFile::open(&original).unwrap(), &mut blocks).unwrap();
+            let mut decoded = Vec::new();
+            BlockDecoder::new().decode(&blocks[..], &mut decoded).unwrap();
+            assert_eq!(decoded, data);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! Byte frequencies scaled to a fixed total, the model shared by the arithmetic coding backends.
+
+use std::io;
+use std::mem;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use super::*;
+
+/// Number of bits in `MODEL_TOTAL`.
+pub const MODEL_TOTAL_BITS: u8 = 15;
+
+/// Total of the frequencies of every `FrequencyModel`.
+pub const MODEL_TOTAL: u32 = 1 << MODEL_TOTAL_BITS;
+
+/// Number of bytes written by `FrequencyModel::write_header`.
+pub const MODEL_HEADER_SIZE: u64 = 2 * NUM_BYTES as u64;
+
+/// Frequency of each byte, scaled so they total `MODEL_TOTAL`.
+///
+/// Every byte that occurs keeps a frequency of at least 1, so it can still be coded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrequencyModel {
+    frequencies: Vec<u32>,
+    /// Total frequency of the bytes before each byte, with `MODEL_TOTAL` at the end
+    cumulative: Vec<u32>,
+    /// Byte for each value below `MODEL_TOTAL`
+    symbols: Vec<u8>,
+}
+
+impl FrequencyModel {
+    /// Scale `frequencies` to total `MODEL_TOTAL`. Fails if every frequency is 0.
+    pub fn from_frequencies<F: Frequencies<Symbol = u8> + ?Sized>(frequencies: &F) -> Result<FrequencyModel, Box<dyn Error>> {
+        let frequencies = frequencies.frequencies();
+        let total = frequencies.iter().fold(0, |total: u64, &frequency| total.saturating_add(frequency));
+
+        if total == 0 {
+            return Err(From::from("Every frequency is 0"));
+        }
+
+        let mut scaled: Vec<u32> = frequencies.iter()
+            .map(|&frequency| match frequency {
+                0 => 0,
+                _ => ((frequency as u128 * MODEL_TOTAL as u128 / total as u128) as u32).max(1),
+            })
+            .collect();
+
+        // Rounding leaves the total a little off, so adjust the most frequent bytes, which
+        // changes their share the least
+        let mut order: Vec<usize> = (0..NUM_BYTES).filter(|&byte| scaled[byte] > 0).collect();
+        order.sort_by_key(|&byte| std::cmp::Reverse(scaled[byte]));
+
+        let mut scaled_total: u32 = scaled.iter().sum();
+
+        while scaled_total != MODEL_TOTAL {
+            for &byte in order.iter() {
+                if scaled_total < MODEL_TOTAL {
+                    scaled[byte] += 1;
+                    scaled_total += 1;
+                } else if scaled_total > MODEL_TOTAL && scaled[byte] > 1 {
+                    scaled[byte] -= 1;
+                    scaled_total -= 1;
+                }
+
+                if scaled_total == MODEL_TOTAL {
+                    break;
+                }
+            }
+        }
+
+        FrequencyModel::new(&scaled)
+    }
+
+    /// Use `frequencies`, one for each byte, which must total `MODEL_TOTAL`.
+    pub fn new(frequencies: &[u32]) -> Result<FrequencyModel, Box<dyn Error>> {
+        if frequencies.len() != NUM_BYTES {
+            return Err(From::from(format!("Expected {} frequencies but got {}", NUM_BYTES, frequencies.len())));
+        }
+
+        let total: u64 = frequencies.iter().map(|&frequency| frequency as u64).sum();
+
+        if total != MODEL_TOTAL as u64 {
+            return Err(From::from(format!("Frequencies total {} instead of {}", total, MODEL_TOTAL)));
+        }
+
+        let mut cumulative = Vec::with_capacity(NUM_BYTES + 1);
+        let mut symbols = Vec::with_capacity(MODEL_TOTAL as usize);
+        let mut sum = 0;
+
+        for (byte, &frequency) in frequencies.iter().enumerate() {
+            cumulative.push(sum);
+            symbols.resize(symbols.len() + frequency as usize, byte as u8);
+            sum += frequency;
+        }
+        cumulative.push(sum);
+
+        Ok(FrequencyModel {
+            frequencies: frequencies.to_vec(),
+            cumulative,
+            symbols,
+        })
+    }
+
+    /// The scaled frequency of `byte`.
+    #[inline]
+    pub fn frequency(&self, byte: u8) -> u32 {
+        self.frequencies[byte as usize]
+    }
+
+    /// Total scaled frequency of the bytes below `byte`.
+    #[inline]
+    pub fn cumulative(&self, byte: u8) -> u32 {
+        self.cumulative[byte as usize]
+    }
+
+    /// The byte whose range of cumulative frequencies holds `value`, which must be below
+    /// `MODEL_TOTAL`.
+    #[inline]
+    pub fn symbol_at(&self, value: u32) -> u8 {
+        self.symbols[value as usize]
+    }
+
+    /// The scaled frequency of every byte.
+    pub fn frequencies(&self) -> &[u32] {
+        &self.frequencies
+    }
+
+    /// Bytes of memory held by the frequencies and the tables for looking bytes up.
+    pub fn memory(&self) -> usize {
+        mem::size_of_val(&self.frequencies[..]) + mem::size_of_val(&self.cumulative[..]) + mem::size_of_val(&self.symbols[..])
+    }
+
+    /// Write each scaled frequency as a little endian `u16`.
+    pub fn write_header<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        for &frequency in self.frequencies.iter() {
+            write.write_u16::<LittleEndian>(frequency as u16)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a model written by `write_header`.
+    pub fn read_header<R: Read>(read: &mut R) -> Result<FrequencyModel, Box<dyn Error>> {
+        let mut frequencies = vec![0; NUM_BYTES];
+
+        for frequency in frequencies.iter_mut() {
+            *frequency = read.read_u16::<LittleEndian>()? as u32;
+        }
+
+        FrequencyModel::new(&frequencies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_scaling() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"aaaabbc");
+        frequencies.set(b'z', 1_000_000);
+
+        let model = FrequencyModel::from_frequencies(&frequencies).unwrap();
+        assert_eq!(model.frequencies().iter().sum::<u32>(), MODEL_TOTAL);
+        assert_eq!(model.frequency(b'c'), 1);
+        assert_eq!(model.frequency(b'y'), 0);
+        assert_eq!(model.cumulative(b'b'), model.frequency(b'a'));
+        assert_eq!(model.symbol_at(model.cumulative(b'z')), b'z');
+        assert_eq!(model.symbol_at(MODEL_TOTAL - 1), b'z');
+
+        let mut header = Vec::new();
+        model.write_header(&mut header).unwrap();
+        assert_eq!(header.len() as u64, MODEL_HEADER_SIZE);
+        assert_eq!(FrequencyModel::read_header(&mut Cursor::new(&header)).unwrap(), model);
+    }
+
+    #[test]
+    fn test_lone_byte() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"zzz");
+
+        let model = FrequencyModel::from_frequencies(&frequencies).unwrap();
+        assert_eq!(model.frequency(b'z'), MODEL_TOTAL);
+
+        let mut header = Vec::new();
+        model.write_header(&mut header).unwrap();
+        assert_eq!(FrequencyModel::read_header(&mut Cursor::new(&header)).unwrap(), model);
+
+        assert!(FrequencyModel::read_header(&mut Cursor::new(vec![0; 512])).is_err());
+        assert!(FrequencyModel::from_frequencies(&FrequencyTable::new()).is_err());
+        assert!(FrequencyModel::new(&[1; NUM_BYTES]).is_err());
+    }
+}
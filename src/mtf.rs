@@ -0,0 +1,49 @@
+//! Move-to-front transform of bytes.
+//!
+//! Each byte is replaced by its position in a list of all bytes, and then moved to the front of
+//! the list. Bytes that recur close together become small numbers, mostly zeros, which Huffman
+//! codes well and which `Stage::Rle` shortens further. The size of the data is unchanged.
+
+/// Replace each byte with its position in the list of recently used bytes.
+pub fn mtf_encode(bytes: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=u8::MAX).collect();
+
+    bytes.iter().map(|&byte| {
+        let position = list.iter().position(|&listed| listed == byte).expect("Every byte is in the list");
+        move_to_front(&mut list, position);
+        position as u8
+    }).collect()
+}
+
+/// Undo `mtf_encode`.
+pub fn mtf_decode(positions: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=u8::MAX).collect();
+
+    positions.iter().map(|&position| {
+        let byte = list[position as usize];
+        move_to_front(&mut list, position as usize);
+        byte
+    }).collect()
+}
+
+fn move_to_front(list: &mut [u8], position: usize) {
+    list[..=position].rotate_right(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(mtf_encode(b""), b"");
+        assert_eq!(mtf_encode(b"aaab"), [b'a', 0, 0, b'b']);
+        assert_eq!(mtf_encode(b"abab"), [b'a', b'b', 1, 1]);
+
+        let text = b"the quick brown fox jumps over the lazy dog".repeat(10);
+        assert_eq!(mtf_decode(&mtf_encode(&text)), text);
+
+        let all: Vec<u8> = (0..=255).rev().collect();
+        assert_eq!(mtf_decode(&mtf_encode(&all)), all);
+    }
+}
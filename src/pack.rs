@@ -0,0 +1,174 @@
+//! Writing codes packed into 64-bit words.
+//!
+//! `BitWriter` places a code a byte at a time, which costs a loop with branches for every code.
+//! `WordWriter` instead shifts codes into a 64-bit accumulator and moves every whole byte it holds
+//! out at once. When no code is longer than `PACKED_MAX_CODE_LENGTH` bits, the codes of
+//! `PACKED_SYMBOLS` bytes always fit in the accumulator together, so they are added without
+//! checking for room in between. The output is exactly that of a `BitWriter`.
+
+use std::io;
+use std::io::Write;
+use std::result::Result;
+
+use super::*;
+
+/// Number of codes added to the accumulator between moving out its whole bytes.
+pub(crate) const PACKED_SYMBOLS: usize = 4;
+
+/// Longest code for which `PACKED_SYMBOLS` codes fit in the accumulator after the up to 7 bits
+/// left over from the last whole byte.
+const PACKED_MAX_CODE_LENGTH: u8 = 14;
+
+/// Longest code added to the accumulator in one piece, after the up to 7 bits left over.
+const MAX_PUSH_LENGTH: u8 = 57;
+
+/// Number of bytes collected before they are written out.
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Writes codes to a `Write`, most significant bit first, a word at a time.
+///
+/// Call `finish` after the last code to pad the final byte with zeros and write it out.
+pub(crate) struct WordWriter<W: Write> {
+    write: W,
+    /// Bits not yet moved to `buf`, left aligned
+    accumulator: u64,
+    bits: u32,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> WordWriter<W> {
+    pub fn new(write: W) -> WordWriter<W> {
+        WordWriter {
+            write,
+            accumulator: 0,
+            bits: 0,
+            buf: Vec::with_capacity(WRITE_BUFFER_SIZE + 8),
+        }
+    }
+
+    /// Write the code of every byte of `bytes` from `codes`, failing if a byte has no code.
+    pub fn write_bytes(&mut self, codes: &CodeTable, bytes: &[u8]) -> Result<(), HuffmanError> {
+        let code_of = |byte: u8, offset: usize| codes[byte as usize]
+            .ok_or(HuffmanError::SymbolNotInCodebook { symbol: byte as usize, offset: offset as u64 });
+        let max_length = codes.iter().flatten().map(|code| code.length()).max().unwrap_or(0);
+
+        let mut rest = bytes;
+
+        if max_length <= PACKED_MAX_CODE_LENGTH {
+            let mut chunks = bytes.chunks_exact(PACKED_SYMBOLS);
+
+            for (index, chunk) in (&mut chunks).enumerate() {
+                for (i, &byte) in chunk.iter().enumerate() {
+                    self.push(code_of(byte, index * PACKED_SYMBOLS + i)?);
+                }
+
+                self.move_bytes();
+            }
+
+            rest = chunks.remainder();
+        }
+
+        let start = bytes.len() - rest.len();
+
+        for (i, &byte) in rest.iter().enumerate() {
+            self.write_code(code_of(byte, start + i)?);
+        }
+
+        self.write_buffer(WRITE_BUFFER_SIZE)?;
+
+        Ok(())
+    }
+
+    /// Write a single code of any length.
+    pub fn write_code(&mut self, code: Code) {
+        let length = code.length();
+
+        if length > MAX_PUSH_LENGTH {
+            self.write_code(Code::new(code.bits() >> 32, length - 32));
+            self.write_code(Code::new(code.bits() & u32::MAX as u64, 32));
+            return;
+        }
+
+        if self.bits + length as u32 > 64 {
+            self.move_bytes();
+        }
+
+        self.push(code);
+    }
+
+    /// Pad the final byte with zeros and write everything out.
+    pub fn finish(mut self) -> io::Result<()> {
+        let bytes = self.bits.div_ceil(8) as usize;
+        self.buf.extend_from_slice(&self.accumulator.to_be_bytes()[..bytes]);
+        self.bits = 0;
+
+        self.write_buffer(0)
+    }
+
+    /// Add `code` after the bits in the accumulator, which must have room for it.
+    #[inline]
+    fn push(&mut self, code: Code) {
+        let length = code.length() as u32;
+
+        if length > 0 {
+            self.accumulator |= code.bits() << (64 - self.bits - length);
+            self.bits += length;
+        }
+    }
+
+    /// Move the whole bytes in the accumulator to the buffer.
+    #[inline]
+    fn move_bytes(&mut self) {
+        let bytes = self.bits / 8;
+
+        self.buf.extend_from_slice(&self.accumulator.to_be_bytes()[..bytes as usize]);
+        self.accumulator = self.accumulator.checked_shl(bytes * 8).unwrap_or(0);
+        self.bits -= bytes * 8;
+    }
+
+    /// Write out the buffer if it holds at least `at_least` bytes.
+    fn write_buffer(&mut self, at_least: usize) -> io::Result<()> {
+        if self.buf.len() >= at_least {
+            self.write.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_bit_writer() {
+        let short = CanonicalTree::from_slice(b"abracadabra").unwrap();
+
+        // Codes far past the packed limit, and past what fits in the accumulator at once
+        let long: CanonicalTree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 60), (b'd', 64), (b'e', 64)]).unwrap();
+
+        let data = b"abracadabra, abracadabra, a dead cab".iter().cloned()
+            .filter(|byte| b"abcde".contains(byte))
+            .collect::<Vec<u8>>();
+
+        for tree in [short, long] {
+            for length in 0..data.len() {
+                let bytes: Vec<u8> = data[..length].iter().cloned().filter(|&byte| tree.code_book().get(byte).is_some()).collect();
+
+                let mut expected = Vec::new();
+                tree.encode_symbols(&bytes, &mut expected).unwrap();
+
+                let mut packed = Vec::new();
+                let mut word_writer = WordWriter::new(&mut packed);
+                word_writer.write_bytes(tree.code_book().as_table(), &bytes).unwrap();
+                word_writer.finish().unwrap();
+
+                assert_eq!(packed, expected);
+            }
+        }
+
+        let tree = CanonicalTree::from_slice(b"abc").unwrap();
+        assert!(WordWriter::new(Vec::new()).write_bytes(tree.code_book().as_table(), b"abcd").is_err());
+    }
+}
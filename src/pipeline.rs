@@ -0,0 +1,166 @@
+//! Chains of stages and a backend, recorded in full so the data decodes without being told how
+//! it was encoded.
+//!
+//! The `Encoder` records its stages, `Backend` and `HeaderFormat`, so the output of a `Pipeline`
+//! is just that of its `Encoder`, and any `Decoder` reads it.
+
+use std::io::{Read, Write};
+use std::result::Result;
+
+use super::*;
+
+/// Stages applied in order, and the backend that codes the result.
+///
+/// Start from a `Preset` or build one with `PipelineBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    backend: Backend,
+    header_format: HeaderFormat,
+    max_code_length: Option<u8>,
+}
+
+/// Builds a `Pipeline`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use huffman_rust::{Backend, Pipeline, PipelineBuilder, Stage};
+///
+/// let pipeline = PipelineBuilder::new()
+///     .stage(Stage::Bwt { block_size: 100_000 })
+///     .stage(Stage::Mtf)
+///     .stage(Stage::Rle)
+///     .backend(Backend::Huffman)
+///     .build();
+///
+/// let text = "a small sample string, a small sample string";
+/// let mut encoded = Vec::new();
+/// pipeline.encode(Cursor::new(text), &mut encoded).unwrap();
+///
+/// let mut decoded = Vec::new();
+/// Pipeline::decoder(Cursor::new(&encoded)).unwrap().decode(&mut decoded).unwrap();
+/// assert_eq!(decoded, text.as_bytes());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PipelineBuilder {
+    pipeline: Pipeline,
+}
+
+impl PipelineBuilder {
+    /// Start with no stages and the default backend and header format.
+    pub fn new() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Apply `stage` after any stages added before.
+    pub fn stage(mut self, stage: Stage) -> PipelineBuilder {
+        self.pipeline.stages.push(stage);
+        self
+    }
+
+    /// Code the result of the stages with `backend`. Defaults to `Backend::Huffman`.
+    pub fn backend(mut self, backend: Backend) -> PipelineBuilder {
+        self.pipeline.backend = backend;
+        self
+    }
+
+    /// Store the tree in `header_format`. Defaults to `HeaderFormat::CodeLengths`.
+    pub fn header_format(mut self, header_format: HeaderFormat) -> PipelineBuilder {
+        self.pipeline.header_format = header_format;
+        self
+    }
+
+    /// Limit the Huffman codes to `max_length` bits, as with `Encoder::max_code_length`.
+    pub fn max_code_length(mut self, max_length: u8) -> PipelineBuilder {
+        self.pipeline.max_code_length = Some(max_length);
+        self
+    }
+
+    pub fn build(self) -> Pipeline {
+        self.pipeline
+    }
+}
+
+impl Pipeline {
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub fn header_format(&self) -> HeaderFormat {
+        self.header_format
+    }
+
+    pub fn max_code_length(&self) -> Option<u8> {
+        self.max_code_length
+    }
+
+    /// The `Encoder` for `read` with the stages, backend and header format of the pipeline.
+    pub fn encoder<R: EncoderSource>(&self, read: R) -> Result<Encoder<R>, HuffmanError> {
+        let mut encoder = Encoder::new(read)?
+            .backend(self.backend)
+            .header_format(self.header_format);
+
+        if let Some(max_length) = self.max_code_length {
+            encoder = encoder.max_code_length(max_length)?;
+        }
+
+        Ok(self.stages.iter().fold(encoder, |encoder, &stage| encoder.stage(stage)))
+    }
+
+    /// Write `read` encoded with the pipeline.
+    pub fn encode<R: EncoderSource, W: Write>(&self, read: R, write: W) -> Result<(), HuffmanError> {
+        self.encoder(read)?.encode(write)
+    }
+
+    /// The `Decoder` for data written by `encode`, which reads the stages, backend and header
+    /// format from the data.
+    pub fn decoder<R: Read>(read: R) -> Result<Decoder<R>, HuffmanError> {
+        Ok(Decoder::new(read))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "the quick brown fox jumps over the lazy dog, and the dog sleeps".repeat(50);
+
+        let pipelines = [
+            PipelineBuilder::new().build(),
+            PipelineBuilder::new().header_format(HeaderFormat::TreeShape).stage(Stage::Rle).build(),
+            PipelineBuilder::new().stage(Stage::Delta { stride: 2 }).backend(Backend::Adaptive(AdaptiveAlgorithm::Vitter)).build(),
+            PipelineBuilder::new().stage(Stage::Bwt { block_size: 1000 }).stage(Stage::Mtf).backend(Backend::Tans).build(),
+            PipelineBuilder::new().backend(Backend::Lz77).build(),
+        ];
+
+        for pipeline in pipelines.iter() {
+            let mut encoded = Vec::new();
+            pipeline.encode(Cursor::new(&text), &mut encoded).unwrap();
+
+            let mut decoded = Vec::new();
+            Pipeline::decoder(Cursor::new(&encoded)).unwrap().strict(true).decode(&mut decoded).unwrap();
+            assert_eq!(decoded, text.as_bytes());
+        }
+
+        // The backend and header format follow the size
+        let mut encoded = Vec::new();
+        Pipeline::default().encode(Cursor::new(&text), &mut encoded).unwrap();
+
+        for (offset, id) in [(8, 8), (9, 9)] {
+            let mut corrupt = encoded.clone();
+            corrupt[offset] = id;
+
+            let result = Pipeline::decoder(Cursor::new(&corrupt)).unwrap().decode(&mut Vec::new());
+            assert!(matches!(result, Err(HuffmanError::CorruptHeader(_))));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Coding with arbitrary prefix free codes, such as those produced by other huffman
+//! implementations.
+
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// A prefix free code that need not be canonical.
+///
+/// Unlike `CanonicalTree`, the codes are used exactly as given, so streams written by other
+/// huffman implementations can be decoded as long as their code table is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixCode<S = u8> {
+    code_book: CodeBook<S>,
+    table: DecodeTable<S>,
+}
+
+impl<S: Symbol> PrefixCode<S> {
+    /// Build the code from `(symbol, bits, length)` triples, where the code of each symbol is the
+    /// low `length` bits of `bits`, first bit most significant.
+    ///
+    /// Fails if a symbol is given more than once or the codes are not prefix free.
+    pub fn new<I: IntoIterator<Item = (S, u64, u8)>>(codes: I) -> Result<PrefixCode<S>, Box<dyn Error>> {
+        let mut code_book = CodeBook::new();
+
+        for (symbol, bits, length) in codes {
+            if code_book.insert(symbol, Code::new(bits, length)).is_some() {
+                return Err(From::from(format!("Symbol {} has more than one code", symbol)));
+            }
+        }
+
+        PrefixCode::from_code_book(code_book)
+    }
+
+    /// Build the code from a code book, failing if the codes are not prefix free.
+    pub fn from_code_book(code_book: CodeBook<S>) -> Result<PrefixCode<S>, Box<dyn Error>> {
+        code_book.validate()?;
+
+        if code_book.is_empty() {
+            return Err(From::from("No symbols have a code"));
+        }
+
+        let table = DecodeTable::new(&code_book);
+
+        Ok(PrefixCode {
+            code_book,
+            table,
+        })
+    }
+
+    /// Write the code for a single symbol.
+    pub fn encode_symbol<W: Write>(&self, symbol: S, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        let code = self.code_book.get(symbol)
+            .ok_or(format!("Symbol {} not found in code book", symbol))?;
+
+        bit_writer.write_code(code)?;
+
+        Ok(())
+    }
+
+    /// Write the codes for every symbol in `symbols`, padding the last byte with zeros.
+    pub fn encode_symbols<W: Write>(&self, symbols: &[S], write: &mut W) -> Result<(), Box<dyn Error>> {
+        let mut bit_writer = BitWriter::new(write);
+
+        for &symbol in symbols {
+            self.encode_symbol(symbol, &mut bit_writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a single symbol.
+    ///
+    /// Returns `None` if the `BitReader` ends before a complete code.
+    pub fn decode_symbol<R: Read>(&self, bit_reader: &mut BitReader<R>) -> Result<Option<S>, Box<dyn Error>> {
+        Ok(self.table.decode_checked(bit_reader, 0)?)
+    }
+
+    /// Decode exactly `count` symbols, failing with a `DecodeError` if the data ends early.
+    pub fn decode_symbols<R: Read>(&self, read: R, count: u64) -> Result<Vec<S>, Box<dyn Error>> {
+        let mut bit_reader = BitReader::new(read);
+        let mut symbols = Vec::new();
+
+        while (symbols.len() as u64) < count {
+            match self.table.decode_checked(&mut bit_reader, symbols.len() as u64)? {
+                Some(symbol) => symbols.push(symbol),
+                None => return Err(self.table.unexpected_end(&mut bit_reader, symbols.len() as u64).into()),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// The code of each symbol.
+    pub fn code_book(&self) -> &CodeBook<S> {
+        &self.code_book
+    }
+
+    /// The table used to decode symbols.
+    pub fn decode_table(&self) -> &DecodeTable<S> {
+        &self.table
+    }
+}
+
+impl<S: Symbol> From<CanonicalTree<S>> for PrefixCode<S> {
+    fn from(tree: CanonicalTree<S>) -> PrefixCode<S> {
+        PrefixCode {
+            code_book: tree.code_book().clone(),
+            table: tree.decode_table().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_non_canonical_codes() {
+        // Not canonical, since the longer codes come first
+        let code = PrefixCode::new(vec![
+            (b'a', 0b11, 2),
+            (b'b', 0b0, 1),
+            (b'c', 0b10, 2),
+        ]).unwrap();
+        assert!(CanonicalTree::from_code_book(code.code_book()).is_err());
+
+        let mut encoded = Vec::new();
+        code.encode_symbols(b"abcab", &mut encoded).unwrap();
+        // 11 0 10 11 0
+        assert_eq!(encoded, vec![0b11010110]);
+
+        assert_eq!(code.decode_symbols(Cursor::new(&encoded), 5).unwrap(), b"abcab".to_vec());
+
+        // 11 0 0 0 0 0 0 holds only 7 symbols
+        let err = code.decode_symbols(Cursor::new(vec![0b11000000]), 8).unwrap_err();
+        assert!(matches!(HuffmanError::from(err), HuffmanError::Decode(DecodeError { kind: DecodeErrorKind::UnexpectedEnd, .. })));
+    }
+
+    #[test]
+    fn test_invalid_codes() {
+        // 1 is a prefix of 10
+        assert!(PrefixCode::new(vec![(b'a', 0b1, 1), (b'b', 0b10, 2)]).is_err());
+        assert!(PrefixCode::new(vec![(b'a', 0b0, 1), (b'a', 0b1, 1)]).is_err());
+        assert!(PrefixCode::new(vec![(b'a', 0b100, 2)]).is_err());
+        assert!(PrefixCode::<u8>::new(vec![]).is_err());
+
+        // An incomplete code is allowed, but the missing codes are invalid
+        let code = PrefixCode::new(vec![(b'a', 0b0, 1), (b'b', 0b10, 2)]).unwrap();
+        let err = code.decode_symbols(Cursor::new(vec![0b01100000]), 3).unwrap_err();
+        assert!(matches!(HuffmanError::from(err), HuffmanError::Decode(DecodeError { kind: DecodeErrorKind::InvalidCode, .. })));
+    }
+}
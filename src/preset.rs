@@ -0,0 +1,76 @@
+//! Ready made pipelines for common needs, so good results do not take knowing every option.
+
+use super::*;
+
+/// Longest code used by `Preset::Fast`, short enough for quick table lookups.
+pub const FAST_MAX_CODE_LENGTH: u8 = 11;
+
+/// A choice of stages, backend and code length limit, trading speed for size.
+///
+/// On text, `Fast` codes each byte with a Huffman code, `Default` also replaces repeated strings
+/// with matches for about three quarters of the size, and `Best` sorts the data with the
+/// Burrows-Wheeler transform and codes it with a table for each previous byte, for about three
+/// fifths of the size of `Fast` at several times the cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preset {
+    /// Huffman codes limited to `FAST_MAX_CODE_LENGTH` bits, stored as a tree shape.
+    Fast,
+    /// `Backend::Lz77`.
+    #[default]
+    Default,
+    /// `Stage::Bwt` in blocks of `DEFAULT_BWT_BLOCK_SIZE` bytes, then `Stage::Mtf` and
+    /// `Stage::Rle`, coded with `Backend::Context`. Holds several times the block size in memory.
+    Best,
+}
+
+impl Preset {
+    /// A builder set up for the preset, to change further.
+    pub fn builder(&self) -> PipelineBuilder {
+        match *self {
+            Preset::Fast => PipelineBuilder::new()
+                .header_format(HeaderFormat::TreeShape)
+                .max_code_length(FAST_MAX_CODE_LENGTH),
+            Preset::Default => PipelineBuilder::new()
+                .backend(Backend::Lz77),
+            Preset::Best => PipelineBuilder::new()
+                .stage(Stage::Bwt { block_size: DEFAULT_BWT_BLOCK_SIZE })
+                .stage(Stage::Mtf)
+                .stage(Stage::Rle)
+                .backend(Backend::Context),
+        }
+    }
+
+    pub fn pipeline(&self) -> Pipeline {
+        self.builder().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_presets() {
+        let text = "the quick brown fox jumps over the lazy dog, and the dog sleeps. ".repeat(100);
+
+        let mut sizes = Vec::new();
+        for preset in [Preset::Fast, Preset::Default, Preset::Best] {
+            let mut encoded = Vec::new();
+            preset.pipeline().encode(Cursor::new(&text), &mut encoded).unwrap();
+
+            let mut decoded = Vec::new();
+            Pipeline::decoder(Cursor::new(&encoded)).unwrap().strict(true).decode(&mut decoded).unwrap();
+            assert_eq!(decoded, text.as_bytes());
+
+            sizes.push(encoded.len());
+        }
+
+        assert!(sizes[0] < text.len() * 3 / 4);
+        assert!(sizes[1] < sizes[0] / 4);
+        assert!(sizes[2] < sizes[0] / 4);
+
+        // Too short a limit for the bytes in the data
+        assert!(Preset::Fast.builder().max_code_length(3).build().encode(Cursor::new(&text), &mut Vec::new()).is_err());
+    }
+}
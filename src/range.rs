@@ -0,0 +1,299 @@
+//! Range coding of bytes with a static `FrequencyModel`.
+//!
+//! A range coder is an arithmetic coder that settles a whole byte at a time rather than a bit, so
+//! it renormalizes far less often. It keeps a 32 bit range, which is shifted out a byte at a time
+//! once it falls below 2^24, and propagates carries into the bytes already settled.
+
+use std::io;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Smallest range before a byte is shifted out.
+const RANGE_BOTTOM: u32 = 1 << 24;
+
+/// State for writing bytes as range codes.
+///
+/// Call `finish` after the last byte to write out the rest of the range.
+#[derive(Debug, Clone)]
+pub struct RangeEncoder {
+    /// Low end of the range, with a carry in bit 32
+    low: u64,
+    range: u32,
+    /// Settled byte that a carry may still change
+    cache: u8,
+    /// Number of settled bytes not yet written, being `cache` and a run of 0xff bytes
+    cache_size: u64,
+}
+
+impl RangeEncoder {
+    pub fn new() -> RangeEncoder {
+        RangeEncoder {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+        }
+    }
+
+    /// Write `byte`, which must have a nonzero frequency in `model`.
+    pub fn encode_byte<W: Write>(&mut self, model: &FrequencyModel, byte: u8, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        let frequency = model.frequency(byte);
+
+        if frequency == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Byte {} has no frequency in the model", byte)));
+        }
+
+        let step = self.range >> MODEL_TOTAL_BITS;
+        self.low += step as u64 * model.cumulative(byte) as u64;
+        self.range = step * frequency;
+
+        while self.range < RANGE_BOTTOM {
+            self.range <<= 8;
+            self.shift_low(bit_writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write out the rest of the range. The output is exactly as long as the decoder reads, and
+    /// the encoder is then ready for new data.
+    pub fn finish<W: Write>(&mut self, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        for _ in 0..5 {
+            self.shift_low(bit_writer)?;
+        }
+
+        *self = RangeEncoder::new();
+
+        Ok(())
+    }
+
+    fn shift_low<W: Write>(&mut self, bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        if self.low < 0xff00_0000 || self.low > u32::MAX as u64 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+
+            while self.cache_size > 0 {
+                bit_writer.write_bits_u64(byte.wrapping_add(carry) as u64, 8)?;
+                byte = 0xff;
+                self.cache_size -= 1;
+            }
+
+            self.cache = (self.low >> 24) as u8;
+        }
+
+        self.cache_size += 1;
+        self.low = (self.low & 0x00ff_ffff) << 8;
+
+        Ok(())
+    }
+}
+
+impl Default for RangeEncoder {
+    fn default() -> RangeEncoder {
+        RangeEncoder::new()
+    }
+}
+
+/// State for reading bytes written by a `RangeEncoder`.
+#[derive(Debug, Clone)]
+pub struct RangeDecoder {
+    range: u32,
+    code: u32,
+    /// Whether the first bytes have been read into `code`
+    started: bool,
+}
+
+impl RangeDecoder {
+    pub fn new() -> RangeDecoder {
+        RangeDecoder {
+            range: u32::MAX,
+            code: 0,
+            started: false,
+        }
+    }
+
+    /// Read the next byte with the same `model` used to write it.
+    ///
+    /// Fails with a `DecodeError` if the data ends early or cannot have come from `model`.
+    /// `bytes_decoded` is only used to describe errors.
+    pub fn decode_byte<R: Read>(&mut self, model: &FrequencyModel, bit_reader: &mut BitReader<R>, bytes_decoded: u64) -> Result<u8, Box<dyn Error>> {
+        if !self.started {
+            // The encoder always starts with a zero byte
+            for _ in 0..5 {
+                self.code = (self.code << 8) | next_byte(bit_reader, bytes_decoded)? as u32;
+            }
+
+            self.started = true;
+        }
+
+        let step = self.range >> MODEL_TOTAL_BITS;
+        let value = self.code / step;
+
+        if value >= MODEL_TOTAL {
+            return Err(range_error(DecodeErrorKind::InvalidCode, bit_reader.position(), bytes_decoded));
+        }
+
+        let byte = model.symbol_at(value);
+        self.code -= step * model.cumulative(byte);
+        self.range = step * model.frequency(byte);
+
+        while self.range < RANGE_BOTTOM {
+            self.range <<= 8;
+            self.code = (self.code << 8) | next_byte(bit_reader, bytes_decoded)? as u32;
+        }
+
+        Ok(byte)
+    }
+}
+
+impl Default for RangeDecoder {
+    fn default() -> RangeDecoder {
+        RangeDecoder::new()
+    }
+}
+
+/// A `FrequencyModel` with the state to range code with it, for use as an `EntropyCoder`.
+#[derive(Debug, Clone)]
+pub struct RangeCoder {
+    model: FrequencyModel,
+    encoder: RangeEncoder,
+    decoder: RangeDecoder,
+}
+
+impl RangeCoder {
+    pub fn new(model: FrequencyModel) -> RangeCoder {
+        RangeCoder {
+            model,
+            encoder: RangeEncoder::new(),
+            decoder: RangeDecoder::new(),
+        }
+    }
+
+    pub fn model(&self) -> &FrequencyModel {
+        &self.model
+    }
+}
+
+impl EntropyCoder for RangeCoder {
+    fn table_memory(&self) -> usize {
+        self.model.memory()
+    }
+
+    fn from_frequencies(frequencies: &FrequencyTable) -> Result<RangeCoder, Box<dyn Error>> {
+        Ok(RangeCoder::new(FrequencyModel::from_frequencies(frequencies)?))
+    }
+
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>> {
+        Ok(self.model.write_header(write)?)
+    }
+
+    fn read_header<R: Read>(read: &mut R) -> Result<RangeCoder, Box<dyn Error>> {
+        Ok(RangeCoder::new(FrequencyModel::read_header(read)?))
+    }
+
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(self.encoder.encode_byte(&self.model, byte, bit_writer)?)
+    }
+
+    fn finish<W: Write>(&mut self, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        Ok(self.encoder.finish(bit_writer)?)
+    }
+
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, _symbols_left: u64) -> Result<u8, Box<dyn Error>> {
+        self.decoder.decode_byte(&self.model, bit_reader, symbols_decoded)
+    }
+}
+
+fn next_byte<R: Read>(bit_reader: &mut BitReader<R>, bytes_decoded: u64) -> Result<u8, Box<dyn Error>> {
+    if bit_reader.fill(8)? < 8 {
+        return Err(range_error(DecodeErrorKind::UnexpectedEnd, bit_reader.position(), bytes_decoded));
+    }
+
+    let byte = (bit_reader.peek() >> 56) as u8;
+    bit_reader.consume(8);
+
+    Ok(byte)
+}
+
+fn range_error(kind: DecodeErrorKind, bit_offset: u64, bytes_decoded: u64) -> Box<dyn Error> {
+    Box::new(DecodeError {
+        kind,
+        bit_offset,
+        symbols_decoded: bytes_decoded,
+        partial_code: Code::new(0, 0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(data);
+        let model = FrequencyModel::from_frequencies(&frequencies).unwrap();
+
+        let mut encoded = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(&mut encoded);
+            let mut encoder = RangeEncoder::new();
+            for &byte in data {
+                encoder.encode_byte(&model, byte, &mut bit_writer).unwrap();
+            }
+            encoder.finish(&mut bit_writer).unwrap();
+        }
+
+        let mut bit_reader = BitReader::new(&encoded[..]);
+        let mut decoder = RangeDecoder::new();
+        let decoded: Vec<u8> = (0..data.len() as u64).map(|i| decoder.decode_byte(&model, &mut bit_reader, i).unwrap()).collect();
+        assert_eq!(decoded, data);
+        assert_eq!(bit_reader.position(), encoded.len() as u64 * 8);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"a");
+        round_trip(b"abracadabra");
+        round_trip(&(0..=255).collect::<Vec<u8>>());
+
+        // Runs of 0xff bytes settled before a carry
+        let mut carries: Vec<u8> = (0..5000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        carries.extend(vec![0xff; 3000]);
+        round_trip(&carries);
+
+        let mut skewed = vec![b'a'; 10000];
+        for i in (0..10000).step_by(100) {
+            skewed[i] = b'b';
+        }
+        let encoded = round_trip(&skewed);
+        assert!(encoded.len() < 10000 / 8 / 5);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(data);
+        let model = FrequencyModel::from_frequencies(&frequencies).unwrap();
+
+        let mut encoded = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(&mut encoded);
+            let mut encoder = RangeEncoder::new();
+            for &byte in data.iter() {
+                encoder.encode_byte(&model, byte, &mut bit_writer).unwrap();
+            }
+            encoder.finish(&mut bit_writer).unwrap();
+        }
+
+        let mut bit_reader = BitReader::new(&encoded[..encoded.len() - 1]);
+        let mut decoder = RangeDecoder::new();
+        let err = (0..data.len() as u64).map(|i| decoder.decode_byte(&model, &mut bit_reader, i)).find_map(Result::err).unwrap();
+        assert_eq!(err.downcast_ref::<DecodeError>().unwrap().kind, DecodeErrorKind::UnexpectedEnd);
+    }
+}
@@ -0,0 +1,138 @@
+//! Decoding as much as possible of data that is damaged.
+//!
+//! The decoders write everything decoded before an error, but the error alone does not say how
+//! much that was. `Decoder::decode_lossy` and `BlockDecoder::decode_lossy` never fail: they
+//! return a `Recovery` counting what was written, which is everything decoded successfully, along
+//! with the error that stopped decoding. A `HuffmanError::Decode` error gives the bit offset of
+//! the code that could not be decoded.
+
+use std::io;
+use std::io::Write;
+
+use super::*;
+
+/// What a lossy decode wrote, and why it stopped if it did not decode everything.
+#[derive(Debug)]
+pub struct Recovery {
+    /// Number of bytes written, all of which were decoded successfully.
+    pub bytes_decoded: u64,
+    /// Number of whole blocks written by a `BlockDecoder`. Always 0 for a `Decoder`.
+    pub blocks_decoded: u64,
+    /// The error that stopped decoding, or `None` if everything was decoded.
+    pub error: Option<HuffmanError>,
+}
+
+impl Recovery {
+    /// Whether everything was decoded.
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Passes writes on, counting the bytes written.
+pub(crate) struct CountingWrite<W> {
+    write: W,
+    bytes_written: u64,
+}
+
+impl<W: Write> CountingWrite<W> {
+    pub(crate) fn new(write: W) -> CountingWrite<W> {
+        CountingWrite {
+            write,
+            bytes_written: 0,
+        }
+    }
+
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+}
+
+impl<W: Write> Write for CountingWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.write.write(buf)?;
+        self.bytes_written += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use byteorder::{ReadBytesExt, LittleEndian};
+
+    #[test]
+    fn test_decode_lossy() {
+        let text = "the quick brown fox jumps over the lazy dog".repeat(100);
+        let mut encoded = Vec::new();
+        Encoder::from_slice(text.as_bytes()).unwrap().encode(&mut encoded).unwrap();
+
+        let mut decoded = Vec::new();
+        let recovery = Decoder::new(Cursor::new(&encoded)).decode_lossy(&mut decoded);
+        assert!(recovery.is_complete());
+        assert_eq!(decoded, text.as_bytes());
+
+        // Cut short, everything before the end is kept
+        let mut decoded = Vec::new();
+        let recovery = Decoder::new(Cursor::new(&encoded[..encoded.len() / 2])).decode_lossy(&mut decoded);
+        assert!(matches!(recovery.error, Some(HuffmanError::Decode(DecodeError { kind: DecodeErrorKind::UnexpectedEnd, .. }))));
+        assert_eq!(recovery.bytes_decoded, decoded.len() as u64);
+        assert!(decoded.len() > text.len() / 4);
+        assert!(text.as_bytes().starts_with(&decoded));
+    }
+
+    #[test]
+    fn test_block_decode_lossy() {
+        let text = "the quick brown fox jumps over the lazy dog".repeat(100);
+        let mut encoded = Vec::new();
+        BlockEncoder::new(Pipeline::default()).block_size(1000).unwrap().checksum(true).encode(text.as_bytes(), &mut encoded).unwrap();
+
+        // Break the checksum of the second block
+        let mut read = Cursor::new(&encoded);
+        let first_length = read.read_u32::<LittleEndian>().unwrap() & !CHECKSUM_FLAG;
+        read.set_position(read.position() + first_length as u64 + 4);
+        let second_length = read.read_u32::<LittleEndian>().unwrap() & !CHECKSUM_FLAG;
+        let second_checksum = (read.position() + second_length as u64) as usize;
+        encoded[second_checksum] ^= 1;
+
+        let mut decoded = Vec::new();
+        let recovery = BlockDecoder::new().threads(2).decode_lossy(&encoded[..], &mut decoded);
+        assert!(matches!(recovery.error, Some(HuffmanError::ChecksumMismatch)));
+        assert_eq!(recovery.blocks_decoded, 1);
+        assert_eq!(recovery.bytes_decoded, 1000);
+        assert_eq!(decoded, &text.as_bytes()[..1000]);
+
+        // Cut the coded bytes of the second block short, leaving the stream whole
+        let mut encoded = Vec::new();
+        BlockEncoder::new(Pipeline::default()).block_size(1000).unwrap().encode(text.as_bytes(), &mut encoded).unwrap();
+
+        let mut read = Cursor::new(&encoded);
+        let first_length = read.read_u32::<LittleEndian>().unwrap() as usize;
+        let second_start = 4 + first_length;
+        read.set_position(second_start as u64);
+        let second_length = read.read_u32::<LittleEndian>().unwrap() as usize;
+
+        let mut truncated = encoded[..second_start].to_vec();
+        truncated.extend_from_slice(&(second_length as u32 / 2).to_le_bytes());
+        truncated.extend_from_slice(&encoded[second_start + 4..second_start + 4 + second_length / 2]);
+        truncated.extend_from_slice(&encoded[second_start + 4 + second_length..]);
+
+        let mut decoded = Vec::new();
+        let recovery = BlockDecoder::new().threads(2).decode_lossy(&truncated[..], &mut decoded);
+        assert!(matches!(recovery.error, Some(HuffmanError::Decode(_))));
+        assert_eq!(recovery.blocks_decoded, 1);
+        assert_eq!(recovery.bytes_decoded, 1000);
+        assert_eq!(decoded, &text.as_bytes()[..1000]);
+
+        // Decoding normally writes what the failing block decoded
+        let mut decoded = Vec::new();
+        assert!(BlockDecoder::new().decode(&truncated[..], &mut decoded).is_err());
+        assert!(decoded.len() > 1000);
+    }
+}
@@ -0,0 +1,171 @@
+//! Reversible remapping of bytes to their frequency rank.
+//!
+//! After remapping, the most frequent byte is always 0, the next most frequent 1, and so on. Data
+//! with similar frequencies over different bytes then looks alike, so a single code table built
+//! for ranks, such as a static `CanonicalTree`, suits all of it.
+
+use std::result::Result;
+use std::error::Error;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::*;
+
+/// Map from each byte to its rank, and back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RankMap {
+    ranks: [u8; NUM_BYTES],
+    bytes: [u8; NUM_BYTES],
+    /// Number of bytes ranked explicitly. The rest follow in increasing order of byte.
+    ranked: usize,
+}
+
+impl RankMap {
+    /// The map where every byte is its own rank.
+    pub fn identity() -> RankMap {
+        RankMap::from_order(&[]).expect("Empty order is valid")
+    }
+
+    /// Rank the bytes in decreasing order of frequency, breaking ties by the smaller byte.
+    ///
+    /// Bytes with a frequency of 0 take the remaining ranks in increasing order of byte, so they
+    /// are not stored by `to_bytes`.
+    pub fn from_frequencies<F: Frequencies<Symbol = u8> + ?Sized>(frequencies: &F) -> RankMap {
+        let mut order: Vec<(u8, u64)> = frequencies.frequencies().iter().enumerate()
+            .filter(|&(_byte, &frequency)| frequency > 0)
+            .map(|(byte, &frequency)| (byte as u8, frequency))
+            .collect();
+        order.sort_by_key(|&(byte, frequency)| (std::cmp::Reverse(frequency), byte));
+
+        let order: Vec<u8> = order.into_iter().map(|(byte, _frequency)| byte).collect();
+
+        RankMap::from_order(&order).expect("Every byte is ranked at most once")
+    }
+
+    /// Give rank 0 to `order[0]`, rank 1 to `order[1]`, and so on. Bytes missing from `order` take
+    /// the remaining ranks in increasing order of byte.
+    pub fn from_order(order: &[u8]) -> Result<RankMap, Box<dyn Error>> {
+        let mut seen = [false; NUM_BYTES];
+        let mut bytes = [0; NUM_BYTES];
+
+        for (rank, &byte) in order.iter().enumerate() {
+            if seen[byte as usize] {
+                return Err(From::from(format!("Byte {} is ranked more than once", byte)));
+            }
+
+            seen[byte as usize] = true;
+            bytes[rank] = byte;
+        }
+
+        let rest = (0..NUM_BYTES).filter(|&byte| !seen[byte]).map(|byte| byte as u8);
+        for (slot, byte) in bytes[order.len()..].iter_mut().zip(rest) {
+            *slot = byte;
+        }
+
+        let mut ranks = [0; NUM_BYTES];
+        for (rank, &byte) in bytes.iter().enumerate() {
+            ranks[byte as usize] = rank as u8;
+        }
+
+        Ok(RankMap {ranks, bytes, ranked: order.len()})
+    }
+
+    /// The rank of `byte`.
+    #[inline]
+    pub fn rank(&self, byte: u8) -> u8 {
+        self.ranks[byte as usize]
+    }
+
+    /// The byte with rank `rank`.
+    #[inline]
+    pub fn byte(&self, rank: u8) -> u8 {
+        self.bytes[rank as usize]
+    }
+
+    /// Replace each byte of `data` by its rank.
+    pub fn apply(&self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            *byte = self.rank(*byte);
+        }
+    }
+
+    /// Replace each rank in `data` by its byte, undoing `apply`.
+    pub fn invert(&self, data: &mut [u8]) {
+        for rank in data.iter_mut() {
+            *rank = self.byte(*rank);
+        }
+    }
+
+    /// The number of explicitly ranked bytes as a little endian `u16`, followed by those bytes in
+    /// order of rank.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; 2];
+        LittleEndian::write_u16(&mut bytes, self.ranked as u16);
+        bytes.extend_from_slice(&self.bytes[..self.ranked]);
+
+        bytes
+    }
+
+    /// Read a map written by `to_bytes`.
+    ///
+    /// Returns the map and the number of bytes it used.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(RankMap, usize), Box<dyn Error>> {
+        if bytes.len() < 2 {
+            return Err(From::from("Rank map is missing its length"));
+        }
+
+        let ranked = LittleEndian::read_u16(bytes) as usize;
+
+        if ranked > NUM_BYTES {
+            return Err(From::from(format!("Rank map of {} bytes is larger than {} bytes", ranked, NUM_BYTES)));
+        }
+
+        if bytes.len() < 2 + ranked {
+            return Err(From::from("Rank map is missing bytes"));
+        }
+
+        Ok((RankMap::from_order(&bytes[2..2 + ranked])?, 2 + ranked))
+    }
+}
+
+impl Default for RankMap {
+    fn default() -> RankMap {
+        RankMap::identity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_map() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"zzzyyx");
+
+        let map = RankMap::from_frequencies(&frequencies);
+        assert_eq!(map.rank(b'z'), 0);
+        assert_eq!(map.rank(b'y'), 1);
+        assert_eq!(map.rank(b'x'), 2);
+        assert_eq!(map.rank(0), 3);
+        assert_eq!(map.byte(3), 0);
+
+        let mut data = b"xyz\x00\xff".to_vec();
+        map.apply(&mut data);
+        assert_eq!(data, vec![2, 1, 0, 3, 255]);
+        map.invert(&mut data);
+        assert_eq!(data, b"xyz\x00\xff".to_vec());
+
+        let bytes = map.to_bytes();
+        assert_eq!(bytes, vec![3, 0, b'z', b'y', b'x']);
+        assert_eq!(RankMap::from_bytes(&bytes).unwrap(), (map, 5));
+    }
+
+    #[test]
+    fn test_invalid_maps() {
+        assert!(RankMap::from_order(&[1, 2, 1]).is_err());
+        assert!(RankMap::from_bytes(&[3, 0, 1, 2]).is_err());
+        assert!(RankMap::from_bytes(&[1, 1]).is_err());
+        assert_eq!(RankMap::from_bytes(&[0, 0]).unwrap().0, RankMap::identity());
+    }
+}
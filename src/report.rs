@@ -0,0 +1,89 @@
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+use super::*;
+
+/// The code of every symbol of a `CanonicalTree`, for comparing code tables.
+///
+/// With the `serde` feature the report can be serialized, for example to JSON.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CodeReport {
+    /// One entry for each symbol with a code, in increasing order of symbol.
+    pub entries: Vec<CodeReportEntry>,
+}
+
+/// The code of a single symbol in a `CodeReport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CodeReportEntry {
+    pub symbol: u8,
+    /// The symbol as an ASCII character, if it is printable.
+    pub character: Option<char>,
+    /// How often the symbol occurs, if the frequencies were given.
+    pub frequency: Option<u64>,
+    pub length: u8,
+    /// The code written as a string of `0` and `1`.
+    pub bits: String,
+}
+
+impl CodeReport {
+    /// Report the codes of `tree`.
+    pub fn new(tree: &CanonicalTree) -> CodeReport {
+        CodeReport::build(tree, None)
+    }
+
+    /// Report the codes of `tree` along with the frequency of each symbol.
+    pub fn with_frequencies<F: Frequencies<Symbol = u8> + ?Sized>(tree: &CanonicalTree, frequencies: &F) -> CodeReport {
+        CodeReport::build(tree, Some(frequencies.frequencies()))
+    }
+
+    fn build(tree: &CanonicalTree, frequencies: Option<&[u64]>) -> CodeReport {
+        let entries = tree.code_book().iter()
+            .map(|(symbol, code)| CodeReportEntry {
+                symbol,
+                character: Some(symbol as char).filter(|c| c.is_ascii_graphic() || *c == ' '),
+                frequency: frequencies.map(|frequencies| frequencies.get(symbol as usize).cloned().unwrap_or(0)),
+                length: code.length(),
+                bits: code.to_string(),
+            })
+            .collect();
+
+        CodeReport {entries}
+    }
+}
+
+impl CanonicalTree {
+    /// Report the code of every symbol.
+    pub fn report(&self) -> CodeReport {
+        CodeReport::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'\n', 2), (b' ', 2)]).unwrap();
+
+        let report = tree.report();
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(report.entries[0], CodeReportEntry {
+            symbol: b'\n',
+            character: None,
+            frequency: None,
+            length: 2,
+            bits: "10".to_string(),
+        });
+        assert_eq!(report.entries[1].character, Some(' '));
+        assert_eq!(report.entries[2].bits, "0");
+
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"aaa \n");
+
+        let report = CodeReport::with_frequencies(&tree, &frequencies);
+        assert_eq!(report.entries[2].frequency, Some(3));
+    }
+}
@@ -0,0 +1,96 @@
+//! Run-length encoding of bytes, ahead of coding data with long runs.
+//!
+//! Huffman codes spend at least a bit on every byte, so a run of a thousand zeros costs at least
+//! a thousand bits. As in bzip2, four equal bytes are instead followed by a count of how many
+//! more times the byte repeats, up to 255. Data without runs grows only by the count after each
+//! run of four.
+
+use std::iter;
+use std::result::Result;
+use std::error::Error;
+
+/// Number of equal bytes after which a count follows.
+pub const RLE_MIN_RUN: usize = 4;
+
+/// Longest run written as `RLE_MIN_RUN` bytes and a count.
+const RLE_MAX_RUN: usize = RLE_MIN_RUN + u8::MAX as usize;
+
+/// Replace the runs of `bytes` with counts.
+pub fn rle_encode(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len());
+    let mut start = 0;
+
+    while start < bytes.len() {
+        let byte = bytes[start];
+        let run = bytes[start..].iter().take(RLE_MAX_RUN).take_while(|&&next| next == byte).count();
+
+        if run >= RLE_MIN_RUN {
+            encoded.extend(iter::repeat_n(byte, RLE_MIN_RUN));
+            encoded.push((run - RLE_MIN_RUN) as u8);
+        } else {
+            encoded.extend(iter::repeat_n(byte, run));
+        }
+
+        start += run;
+    }
+
+    encoded
+}
+
+/// Expand the runs of bytes written by `rle_encode`.
+///
+/// Fails if the data ends right after a run, where a count should be.
+pub fn rle_decode(bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut previous = None;
+    let mut run = 0;
+    let mut bytes = bytes.iter();
+
+    while let Some(&byte) = bytes.next() {
+        if previous == Some(byte) {
+            run += 1;
+        } else {
+            previous = Some(byte);
+            run = 1;
+        }
+
+        decoded.push(byte);
+
+        if run == RLE_MIN_RUN {
+            let count = *bytes.next().ok_or("Run-length data ends before the count of a run")?;
+            decoded.extend(iter::repeat_n(byte, count as usize));
+
+            // The count ends the run, even if the next byte is the same
+            previous = None;
+        }
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let cases: Vec<Vec<u8>> = vec![
+            vec![],
+            b"abc".to_vec(),
+            b"aaab".to_vec(),
+            b"aaaa".to_vec(),
+            vec![0; RLE_MAX_RUN],
+            vec![0; RLE_MAX_RUN + 1],
+            vec![7; 10000],
+            b"aaaabbbbbcccccccccdaaaa".to_vec(),
+        ];
+
+        for bytes in cases {
+            assert_eq!(rle_decode(&rle_encode(&bytes)).unwrap(), bytes);
+        }
+
+        assert_eq!(rle_encode(b"aaaaaab"), b"aaaa\x02b");
+        assert_eq!(rle_encode(&[0; 10000]).len(), 10000 / RLE_MAX_RUN * 5 + 5);
+        assert!(rle_decode(b"xaaaa").is_err());
+    }
+}
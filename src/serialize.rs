@@ -0,0 +1,175 @@
+//! `serde` support, enabled with the `serde` feature.
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as DeError;
+use alloc::collections::BTreeMap;
+
+use super::*;
+
+/// A `CanonicalTree` is serialized as its `(symbol, code length)` pairs, in increasing order of
+/// symbol. The codes are rebuilt and validated when deserializing.
+impl<T: Symbol + Serialize> Serialize for CanonicalTree<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let code_lengths: Vec<(T, u8)> = self.code_book().iter()
+            .map(|(symbol, code)| (symbol, code.length()))
+            .collect();
+
+        code_lengths.serialize(serializer)
+    }
+}
+
+impl<'de, T: Symbol + Deserialize<'de>> Deserialize<'de> for CanonicalTree<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<CanonicalTree<T>, D::Error> {
+        let code_lengths: Vec<(T, u8)> = Vec::deserialize(deserializer)?;
+
+        CanonicalTree::new(code_lengths).map_err(D::Error::custom)
+    }
+}
+
+/// A `CodeBook` is serialized as a map from symbol to code.
+impl<T: Symbol + Serialize> Serialize for CodeBook<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de, T: Symbol + Deserialize<'de>> Deserialize<'de> for CodeBook<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<CodeBook<T>, D::Error> {
+        let map: BTreeMap<T, Code> = BTreeMap::deserialize(deserializer)?;
+
+        Ok(map.into_iter().collect())
+    }
+}
+
+/// A `FrequencyTable` is serialized as the frequency of each symbol of the alphabet.
+impl<T: Symbol> Serialize for FrequencyTable<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de, T: Symbol> Deserialize<'de> for FrequencyTable<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FrequencyTable<T>, D::Error> {
+        let frequencies: Vec<u64> = Vec::deserialize(deserializer)?;
+
+        if frequencies.len() != T::ALPHABET_SIZE {
+            return Err(D::Error::invalid_length(frequencies.len(), &"a frequency for every symbol"));
+        }
+
+        let mut table = FrequencyTable::new();
+        for (symbol, frequency) in frequencies.into_iter().enumerate() {
+            table.set(T::from_index(symbol), frequency);
+        }
+
+        Ok(table)
+    }
+}
+
+/// Serialize a `[u64; 256]` frequency table, for use with `#[serde(with = "...")]`.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Model {
+///     #[serde(with = "huffman::freq_table")]
+///     frequencies: [u64; 256],
+/// }
+/// ```
+pub mod freq_table {
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::Error as DeError;
+    use alloc::vec::Vec;
+
+    use super::super::NUM_BYTES;
+
+    pub fn serialize<S: Serializer>(freq_table: &[u64; NUM_BYTES], serializer: S) -> Result<S::Ok, S::Error> {
+        freq_table[..].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u64; NUM_BYTES], D::Error> {
+        let frequencies: Vec<u64> = Vec::deserialize(deserializer)?;
+
+        if frequencies.len() != NUM_BYTES {
+            return Err(D::Error::invalid_length(frequencies.len(), &"256 frequencies"));
+        }
+
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table.copy_from_slice(&frequencies);
+
+        Ok(freq_table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+
+    fn sample_tree(data: &[u8]) -> CanonicalTree {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(data);
+
+        CanonicalTree::from_frequencies(&frequencies).unwrap()
+    }
+
+    #[test]
+    fn test_tree_round_trip() {
+        let tree = sample_tree(b"a small sample string");
+
+        let json = serde_json::to_string(&tree).unwrap();
+        let deserialized: CanonicalTree = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(deserialized, tree);
+
+        // Over-subscribed lengths are rejected
+        assert!(serde_json::from_str::<CanonicalTree>("[[0,1],[1,1],[2,1]]").is_err());
+    }
+
+    #[test]
+    fn test_code_book_round_trip() {
+        let tree = sample_tree(b"a small sample string");
+        let code_book = tree.code_book();
+
+        let json = serde_json::to_string(&code_book).unwrap();
+        let deserialized: CodeBook = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(&deserialized, code_book);
+    }
+
+    #[test]
+    fn test_freq_table_round_trip() {
+        #[derive(Serialize, Deserialize)]
+        struct Model {
+            #[serde(with = "freq_table")]
+            frequencies: [u64; NUM_BYTES],
+        }
+
+        let mut frequencies = [0; NUM_BYTES];
+        frequencies[b'a' as usize] = 3;
+
+        let json = serde_json::to_string(&Model { frequencies }).unwrap();
+        let deserialized: Model = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(deserialized.frequencies[..], frequencies[..]);
+
+        let table = FrequencyTable::from(frequencies);
+        let json = serde_json::to_string(&table).unwrap();
+
+        assert_eq!(serde_json::from_str::<FrequencyTable>(&json).unwrap(), table);
+        assert!(serde_json::from_str::<FrequencyTable>("[1,2,3]").is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_report_json() {
+        let tree = sample_tree(b"aab");
+
+        let json = serde_json::to_string(&tree.report()).unwrap();
+
+        assert_eq!(json, concat!(
+            r#"{"entries":["#,
+            r#"{"symbol":97,"character":"a","frequency":null,"length":1,"bits":"0"},"#,
+            r#"{"symbol":98,"character":"b","frequency":null,"length":1,"bits":"1"}"#,
+            r#"]}"#));
+    }
+}
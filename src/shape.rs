@@ -0,0 +1,176 @@
+//! Storing a `CanonicalTree` as the shape of its tree.
+//!
+//! The tree is written in pre-order, with a 0 bit for each internal node and a 1 bit followed by
+//! the 8 bits of the symbol for each leaf, left child before right child. The bits are padded with
+//! zeros to a whole byte. For a small alphabet this is far smaller than a code length for every
+//! byte.
+
+use std::io::{Read, Write};
+use std::result::Result;
+
+use super::*;
+
+impl CanonicalTree {
+    /// Write the shape of the tree.
+    ///
+    /// Fails unless the codes are complete, meaning every internal node has two children, or
+    /// there is a single code. Trees from `from_frequencies` always qualify.
+    pub fn write_shape<W: Write>(&self, write: &mut W) -> Result<(), HuffmanError> {
+        self.write_shape_bits(&mut BitWriter::new(write))
+    }
+
+    /// Write the shape of the tree without padding, so more bits can follow it.
+    pub(crate) fn write_shape_bits<W: Write>(&self, bit_writer: &mut BitWriter<W>) -> Result<(), HuffmanError> {
+        let mut codes: Vec<(u8, Code)> = self.code_book().iter().collect();
+        codes.sort_by_key(|&(_symbol, code)| code.left_aligned());
+
+        // A single code is written as a tree that is just a leaf
+        if codes.len() == 1 {
+            bit_writer.write_bit(true)?;
+            bit_writer.write_bits_u64(codes[0].0 as u64, 8)?;
+
+            return Ok(());
+        }
+
+        let kraft_sum: u128 = codes.iter()
+            .map(|&(_symbol, code)| 1 << (MAX_CODE_LENGTH - code.length()))
+            .sum();
+
+        if kraft_sum != 1 << MAX_CODE_LENGTH {
+            return Err(HuffmanError::InvalidInput("Only complete codes can be written as a tree shape".to_string()));
+        }
+
+        let mut previous: Option<Code> = None;
+
+        for &(symbol, code) in codes.iter() {
+            // The internal nodes between the last shared ancestor and the leaf
+            let internal_nodes = match previous {
+                None => code.length(),
+                Some(previous) => code.length() - common_prefix(previous, code) - 1,
+            };
+
+            for _ in 0..internal_nodes {
+                bit_writer.write_bit(false)?;
+            }
+
+            bit_writer.write_bit(true)?;
+            bit_writer.write_bits_u64(symbol as u64, 8)?;
+
+            previous = Some(code);
+        }
+
+        Ok(())
+    }
+
+    /// Number of bytes written by `write_shape`.
+    pub fn shape_size(&self) -> u64 {
+        self.shape_bits().div_ceil(8)
+    }
+
+    /// Number of bits written by `write_shape_bits`.
+    pub(crate) fn shape_bits(&self) -> u64 {
+        let leaves = self.code_book().len() as u64;
+        let internal_nodes = leaves.saturating_sub(1);
+
+        internal_nodes + 9 * leaves
+    }
+
+    /// Read a tree written by `write_shape`, reading no further than its last byte.
+    pub fn read_shape<R: Read>(read: &mut R) -> Result<CanonicalTree, HuffmanError> {
+        CanonicalTree::read_shape_bits(&mut BitReader::new(read))
+    }
+
+    /// Read a tree written by `write_shape_bits`, leaving the bits after it in `bit_reader`.
+    pub(crate) fn read_shape_bits<R: Read>(bit_reader: &mut BitReader<R>) -> Result<CanonicalTree, HuffmanError> {
+        let mut code_lengths = Vec::new();
+
+        // Depths of the nodes still to be read, with the next node last
+        let mut pending: Vec<u8> = vec![0];
+
+        while let Some(depth) = pending.pop() {
+            let is_leaf = bit_reader.read_bit()?.ok_or_else(|| HuffmanError::CorruptHeader("Tree shape ended early".to_string()))?;
+
+            if !is_leaf {
+                if depth >= MAX_CODE_LENGTH {
+                    return Err(HuffmanError::CorruptHeader(format!("Tree shape is deeper than {} bits", MAX_CODE_LENGTH)));
+                }
+
+                pending.push(depth + 1);
+                pending.push(depth + 1);
+                continue;
+            }
+
+            if code_lengths.len() == NUM_BYTES {
+                return Err(HuffmanError::CorruptHeader(format!("Tree shape has more than {} leaves", NUM_BYTES)));
+            }
+
+            if bit_reader.fill(8)? < 8 {
+                return Err(HuffmanError::CorruptHeader("Tree shape ended early".to_string()));
+            }
+
+            let symbol = (bit_reader.peek() >> 56) as u8;
+            bit_reader.consume(8);
+
+            code_lengths.push((symbol, depth.max(1)));
+        }
+
+        CanonicalTree::new(code_lengths)
+    }
+}
+
+/// Number of leading bits two codes have in common.
+fn common_prefix(a: Code, b: Code) -> u8 {
+    let shared = (a.left_aligned() ^ b.left_aligned()).leading_zeros() as u8;
+
+    shared.min(a.length()).min(b.length())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_shape() {
+        // Codes 0, 10, 11
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 2)]).unwrap();
+
+        let mut shape = Vec::new();
+        tree.write_shape(&mut shape).unwrap();
+
+        // 0 1 01100001 0 1 01100010 1 01100011
+        assert_eq!(shape, vec![0b01011000, 0b01010110, 0b00101011, 0b00011000]);
+        assert_eq!(tree.shape_size(), shape.len() as u64);
+
+        let mut read = Cursor::new(&shape);
+        assert_eq!(CanonicalTree::read_shape(&mut read).unwrap(), tree);
+        assert_eq!(read.position(), shape.len() as u64);
+    }
+
+    #[test]
+    fn test_shape_round_trip() {
+        let text = b"a somewhat longer sample string, with punctuation!";
+        let tree = CanonicalTree::from_slice(text).unwrap();
+
+        let mut shape = Vec::new();
+        tree.write_shape(&mut shape).unwrap();
+        assert!(shape.len() < NUM_BYTES);
+        assert_eq!(tree.shape_size(), shape.len() as u64);
+        assert_eq!(CanonicalTree::read_shape(&mut Cursor::new(&shape)).unwrap(), tree);
+
+        // A single symbol
+        let tree = CanonicalTree::from_slice(b"zzz").unwrap();
+        let mut shape = Vec::new();
+        tree.write_shape(&mut shape).unwrap();
+        assert_eq!(shape.len(), 2);
+        assert_eq!(CanonicalTree::read_shape(&mut Cursor::new(&shape)).unwrap(), tree);
+
+        // Incomplete codes
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2)]).unwrap();
+        assert!(matches!(tree.write_shape(&mut Vec::new()), Err(HuffmanError::InvalidInput(_))));
+
+        // Truncated and endless shapes
+        assert!(matches!(CanonicalTree::read_shape(&mut Cursor::new(vec![0b01011000])), Err(HuffmanError::CorruptHeader(_))));
+        assert!(matches!(CanonicalTree::read_shape(&mut Cursor::new(vec![0; 16])), Err(HuffmanError::CorruptHeader(_))));
+    }
+}
@@ -0,0 +1,292 @@
+//! One pass coding with a table estimated from the start of the stream.
+//!
+//! When the input can be read only once and cannot be held in memory, the encoder buffers a
+//! bounded sample from the start of the stream and builds its table from that. The table is
+//! stored once, then the sample and the rest of the stream are coded with it. This costs a
+//! little against a table counted from all of the data, but codes as fast as a static table,
+//! unlike an adaptive one.
+//!
+//! The sample is counted exactly, since for a byte alphabet the counts take no more memory than
+//! a sketch would. Bytes missing from the sample are written after `ESCAPE_SYMBOL`, whose
+//! frequency is the number of bytes seen only once, a Good-Turing estimate of how often unseen
+//! bytes occur. `END_SYMBOL` marks the end of the stream.
+//!
+//! The stream starts with the length of the table as a little endian `u16`, followed by the table
+//! written by `CanonicalTree::to_bytes` and then the codes.
+
+use std::io;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Default number of bytes the table is estimated from.
+pub const DEFAULT_SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Encodes bytes written to it with a table estimated from the first of them.
+///
+/// Call `finish` after the last byte to mark the end of the stream.
+pub struct SinglePassEncoder<W: Write> {
+    bit_writer: BitWriter<W>,
+    sample_bytes: usize,
+    /// Bytes buffered until the table is built
+    sample: Vec<u8>,
+    tree: Option<CanonicalTree<u16>>,
+}
+
+impl<W: Write> SinglePassEncoder<W> {
+    pub fn new(write: W) -> SinglePassEncoder<W> {
+        SinglePassEncoder {
+            bit_writer: BitWriter::new(write),
+            sample_bytes: DEFAULT_SAMPLE_BYTES,
+            sample: Vec::new(),
+            tree: None,
+        }
+    }
+
+    /// Estimate the table from the first `bytes` bytes, which are buffered until it is built.
+    /// Defaults to `DEFAULT_SAMPLE_BYTES`.
+    ///
+    /// Must be called before any bytes are written.
+    pub fn sample_bytes(mut self, bytes: usize) -> SinglePassEncoder<W> {
+        self.sample_bytes = bytes;
+        self
+    }
+
+    /// The table, once it has been estimated.
+    pub fn tree(&self) -> Option<&CanonicalTree<u16>> {
+        self.tree.as_ref()
+    }
+
+    /// Code any buffered bytes and mark the end of the stream.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.build_tree()?;
+        self.write_symbol(END_SYMBOL)
+    }
+
+    /// Estimate the table from the sample, store it, and code the sample with it.
+    fn build_tree(&mut self) -> io::Result<()> {
+        if self.tree.is_some() {
+            return Ok(());
+        }
+
+        let mut counts = FrequencyTable::new();
+        counts.count(&self.sample);
+
+        let mut frequencies = FrequencyTable::new();
+        for (byte, count) in counts.iter() {
+            frequencies.set(byte as u16, count);
+        }
+
+        let seen_once = counts.iter().filter(|&(_byte, count)| count == 1).count() as u64;
+        frequencies.set(ESCAPE_SYMBOL, seen_once.max(1));
+        frequencies.set(END_SYMBOL, 1);
+
+        let tree = CanonicalTree::from_frequencies(&frequencies).map_err(into_io_error)?;
+        let table = tree.to_bytes();
+
+        self.bit_writer.write_bits_u64(table.len() as u64 & 0xff, 8)?;
+        self.bit_writer.write_bits_u64(table.len() as u64 >> 8, 8)?;
+        for &byte in table.iter() {
+            self.bit_writer.write_bits_u64(byte as u64, 8)?;
+        }
+
+        self.tree = Some(tree);
+
+        let sample = std::mem::take(&mut self.sample);
+        for &byte in sample.iter() {
+            self.write_byte(byte)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        let code = self.tree.as_ref().expect("Table is built").code_book().get(byte as u16);
+
+        match code {
+            Some(code) => self.bit_writer.write_code(code),
+            None => {
+                self.write_symbol(ESCAPE_SYMBOL)?;
+                self.bit_writer.write_bits_u64(byte as u64, 8)
+            },
+        }
+    }
+
+    fn write_symbol(&mut self, symbol: u16) -> io::Result<()> {
+        let code = self.tree.as_ref().expect("Table is built").code_book().get(symbol);
+        self.bit_writer.write_code(code.expect("Control symbols have a code"))
+    }
+}
+
+impl<W: Write> Write for SinglePassEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if self.tree.is_some() {
+                self.write_byte(byte)?;
+            } else {
+                self.sample.push(byte);
+
+                if self.sample.len() >= self.sample_bytes {
+                    self.build_tree()?;
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes data written by a `SinglePassEncoder`, reading as far as the end of the stream.
+///
+/// Errors are returned as `io::Error`s wrapping a `DecodeError` where there is one.
+pub struct SinglePassDecoder<R> {
+    bit_reader: BitReader<R>,
+    tree: Option<CanonicalTree<u16>>,
+    bytes_decoded: u64,
+    finished: bool,
+    /// Error to return from the next `read`, after the bytes decoded before it
+    error: Option<io::Error>,
+}
+
+impl<R: Read> SinglePassDecoder<R> {
+    pub fn new(read: R) -> SinglePassDecoder<R> {
+        SinglePassDecoder {
+            bit_reader: BitReader::new(read),
+            tree: None,
+            bytes_decoded: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Decode the next byte, reading the table first. Returns `None` at the end of the stream.
+    fn decode_byte(&mut self) -> Result<Option<u8>, Box<dyn Error>> {
+        if self.tree.is_none() {
+            let length = self.read_byte()? as usize | (self.read_byte()? as usize) << 8;
+
+            let mut table = Vec::with_capacity(length);
+            for _ in 0..length {
+                table.push(self.read_byte()?);
+            }
+
+            self.tree = Some(CanonicalTree::from_bytes(&table)?);
+        }
+
+        let decode_table = self.tree.as_ref().expect("Table was just read").decode_table();
+
+        let byte = match decode_table.decode_checked(&mut self.bit_reader, self.bytes_decoded)? {
+            Some(END_SYMBOL) => return Ok(None),
+            Some(ESCAPE_SYMBOL) => self.read_byte()?,
+            Some(symbol) => symbol as u8,
+            None => return Err(decode_table.unexpected_end(&mut self.bit_reader, self.bytes_decoded).into()),
+        };
+
+        self.bytes_decoded += 1;
+
+        Ok(Some(byte))
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Box<dyn Error>> {
+        if self.bit_reader.fill(8)? < 8 {
+            return Err(Box::new(DecodeError {
+                kind: DecodeErrorKind::UnexpectedEnd,
+                bit_offset: self.bit_reader.position(),
+                symbols_decoded: self.bytes_decoded,
+                partial_code: Code::new(0, 0),
+            }));
+        }
+
+        let byte = (self.bit_reader.peek() >> 56) as u8;
+        self.bit_reader.consume(8);
+
+        Ok(byte)
+    }
+}
+
+impl<R: Read> Read for SinglePassDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let mut read = 0;
+
+        while read < buf.len() && !self.finished {
+            match self.decode_byte() {
+                Ok(Some(byte)) => {
+                    buf[read] = byte;
+                    read += 1;
+                },
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    let err = into_io_error(err);
+
+                    if read == 0 {
+                        return Err(err);
+                    }
+
+                    self.error = Some(err);
+                    break;
+                },
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(data: &[u8], sample_bytes: usize) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = SinglePassEncoder::new(&mut encoded).sample_bytes(sample_bytes);
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let mut decoded = Vec::new();
+        SinglePassDecoder::new(Cursor::new(&encoded)).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"", 100);
+        round_trip(b"abracadabra", 4);
+        round_trip(b"abracadabra", 0);
+
+        // Bytes after the sample that it never saw are escaped
+        let mut text = "the quick brown fox jumps over the lazy dog. ".repeat(200).into_bytes();
+        text.extend(0..=255);
+        let estimated = round_trip(&text, 1000);
+
+        // Close to a table counted from all of the data
+        let tree = CanonicalTree::from_slice(&text).unwrap();
+        let mut counted = Vec::new();
+        tree.encode(&mut Cursor::new(&text), &mut counted).unwrap();
+        assert!(estimated.len() < (counted.len() + NUM_BYTES) * 11 / 10);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = b"the quick brown fox jumps over the lazy dog".repeat(10);
+        let encoded = round_trip(&data, 50);
+
+        let mut decoded = Vec::new();
+        let err = SinglePassDecoder::new(Cursor::new(&encoded[..encoded.len() - 10])).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<DecodeError>().unwrap().kind, DecodeErrorKind::UnexpectedEnd);
+        assert!(data.starts_with(&decoded));
+    }
+}
@@ -0,0 +1,107 @@
+//! Where the `Encoder` reads its data from.
+//!
+//! The `Encoder` reads the data once to count it and again to code it, so it needs a source it
+//! can go back to the start of. Any `Read + Seek` will do. A `SliceSource` over data already in
+//! memory does without `Seek`, and lets the `Encoder` count and code the slice directly instead
+//! of copying it through `Read` in small chunks. A `Cursor` is still read through `Read` and
+//! `Seek`, so use `Encoder::from_cursor`, or `SliceSource::from`, to code its data as a slice.
+
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// Data the `Encoder` can read more than once.
+pub trait EncoderSource: Read {
+    /// Go back to the start of the data.
+    fn rewind(&mut self) -> io::Result<()>;
+
+    /// All of the data from the start, if it is already in memory. `None` by default.
+    fn as_slice(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl<R: Read + Seek> EncoderSource for R {
+    fn rewind(&mut self) -> io::Result<()> {
+        self.seek(SeekFrom::Start(0)).map(|_position| ())
+    }
+}
+
+/// A slice of data in memory, read by the `Encoder` without copying.
+#[derive(Debug, Clone, Copy)]
+pub struct SliceSource<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SliceSource<'a> {
+    pub fn new(data: &'a [u8]) -> SliceSource<'a> {
+        SliceSource {
+            data,
+            position: 0,
+        }
+    }
+}
+
+impl<'a, T: AsRef<[u8]>> From<&'a Cursor<T>> for SliceSource<'a> {
+    /// All of the data of `cursor`, from the start.
+    fn from(cursor: &'a Cursor<T>) -> SliceSource<'a> {
+        SliceSource::new(cursor.get_ref().as_ref())
+    }
+}
+
+impl<'a> Read for SliceSource<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = (&self.data[self.position..]).read(buf)?;
+        self.position += bytes_read;
+
+        Ok(bytes_read)
+    }
+}
+
+impl<'a> EncoderSource for SliceSource<'a> {
+    fn rewind(&mut self) -> io::Result<()> {
+        self.position = 0;
+        Ok(())
+    }
+
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_slice_source() {
+        let text = "the quick brown fox jumps over the lazy dog, and the dog sleeps. ".repeat(100);
+
+        let backends = [Backend::Huffman, Backend::Arithmetic, Backend::Tans, Backend::Adaptive(AdaptiveAlgorithm::Vitter), Backend::Context, Backend::Lz77];
+
+        for data in [&b""[..], b"a", text.as_bytes()] {
+            for &backend in backends.iter() {
+                // The same output as reading the data through a `Cursor`
+                let mut read = Vec::new();
+                Encoder::new(Cursor::new(data)).unwrap().backend(backend).encode(&mut read).unwrap();
+
+                let mut sliced = Vec::new();
+                Encoder::from_slice(data).unwrap().backend(backend).encode(&mut sliced).unwrap();
+                assert_eq!(sliced, read);
+
+                let cursor = Cursor::new(data.to_vec());
+                let mut from_cursor = Vec::new();
+                Encoder::from_cursor(&cursor).unwrap().backend(backend).encode(&mut from_cursor).unwrap();
+                assert_eq!(from_cursor, read);
+
+                let mut staged = Vec::new();
+                Encoder::from_slice(data).unwrap().backend(backend).stage(Stage::Mtf).encode(&mut staged).unwrap();
+
+                let mut decoded = Vec::new();
+                Decoder::new(Cursor::new(&staged)).backend(backend).strict(true).decode(&mut decoded).unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Transforms applied to the data before it is coded, and undone after it is decoded.
+//!
+//! The `Encoder` records the number of stages in the top byte of the size it writes first, which
+//! older data always leaves 0, and the size is then that of the transformed data. A descriptor
+//! for each stage follows the size, in the order the stages were applied, and the `Decoder` undoes
+//! them in reverse without being told what they were.
+
+use std::io;
+use std::io::{Read, Write};
+use std::result::Result;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use super::*;
+
+/// Position of the number of stages in the size written by the `Encoder`.
+pub(crate) const STAGE_COUNT_SHIFT: u32 = 56;
+
+/// A transform that makes data easier to code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Run-length encoding with `rle_encode`, for data with long runs of a byte.
+    Rle,
+    /// The move-to-front transform with `mtf_encode`, for data where bytes recur close together.
+    /// Best followed by `Rle`.
+    Mtf,
+    /// The Burrows-Wheeler transform with `bwt_encode`, in blocks of `block_size` bytes, which
+    /// must not be 0. Followed by `Mtf` and `Rle`, this compresses text far better than Huffman
+    /// codes alone.
+    Bwt {
+        block_size: u32,
+    },
+    /// Delta encoding with `delta_encode`, differencing each byte from the one `stride` bytes
+    /// before it, which must not be 0. Use a stride of 1 for bytes and of the sample size for
+    /// wider samples.
+    Delta {
+        stride: u32,
+    },
+}
+
+impl Stage {
+    /// Transform `bytes`, failing if the parameters of the stage are out of range.
+    pub fn forward(&self, bytes: &[u8]) -> Result<Vec<u8>, HuffmanError> {
+        match self.check()? {
+            Stage::Rle => Ok(rle_encode(bytes)),
+            Stage::Mtf => Ok(mtf_encode(bytes)),
+            Stage::Bwt { block_size } => Ok(bwt_encode(bytes, block_size)),
+            Stage::Delta { stride } => Ok(delta_encode(bytes, stride)),
+        }
+    }
+
+    /// Undo `forward`, failing if `bytes` cannot have come from it.
+    pub fn inverse(&self, bytes: &[u8]) -> Result<Vec<u8>, HuffmanError> {
+        match self.check()? {
+            Stage::Rle => Ok(rle_decode(bytes)?),
+            Stage::Mtf => Ok(mtf_decode(bytes)),
+            Stage::Bwt { block_size } => Ok(bwt_decode(bytes, block_size)?),
+            Stage::Delta { stride } => Ok(delta_decode(bytes, stride)),
+        }
+    }
+
+    /// Write the descriptor of the stage.
+    pub(crate) fn write_descriptor<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        match *self {
+            Stage::Rle => write.write_u8(0),
+            Stage::Mtf => write.write_u8(1),
+            Stage::Bwt { block_size } => {
+                write.write_u8(2)?;
+                write.write_u32::<LittleEndian>(block_size)
+            },
+            Stage::Delta { stride } => {
+                write.write_u8(3)?;
+                write.write_u32::<LittleEndian>(stride)
+            },
+        }
+    }
+
+    /// Read a descriptor written by `write_descriptor`.
+    pub(crate) fn read_descriptor<R: Read>(read: &mut R) -> Result<Stage, HuffmanError> {
+        let stage = match read.read_u8()? {
+            0 => Stage::Rle,
+            1 => Stage::Mtf,
+            2 => Stage::Bwt { block_size: read.read_u32::<LittleEndian>()? },
+            3 => Stage::Delta { stride: read.read_u32::<LittleEndian>()? },
+            id => return Err(HuffmanError::CorruptHeader(format!("Unknown stage {}", id))),
+        };
+
+        // Parameters out of range in a descriptor mean the header is corrupt
+        stage.check().map_err(|err| match err {
+            HuffmanError::InvalidInput(message) => HuffmanError::CorruptHeader(message),
+            err => err,
+        })
+    }
+
+    /// Fail if the parameters of the stage are out of range.
+    pub(crate) fn check(self) -> Result<Stage, HuffmanError> {
+        match self {
+            Stage::Bwt { block_size: 0 } => Err(HuffmanError::InvalidInput("Invalid block size 0".to_string())),
+            Stage::Delta { stride: 0 } => Err(HuffmanError::InvalidInput("Invalid stride 0".to_string())),
+            stage => Ok(stage),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_encoder_stages() {
+        // A sparse bitmap of mostly zero bytes
+        let mut bitmap = vec![0u8; 50000];
+        for i in (0..bitmap.len()).step_by(997) {
+            bitmap[i] = 0x80;
+        }
+
+        let mut plain = Vec::new();
+        Encoder::new(Cursor::new(&bitmap)).unwrap().encode(&mut plain).unwrap();
+
+        let mut staged = Vec::new();
+        Encoder::new(Cursor::new(&bitmap)).unwrap().stage(Stage::Rle).encode(&mut staged).unwrap();
+        assert!(staged.len() < plain.len() / 4);
+
+        // The decoder finds the stages in the header
+        let mut decoded = Vec::new();
+        Decoder::new(Cursor::new(&staged)).strict(true).decode(&mut decoded).unwrap();
+        assert_eq!(decoded, bitmap);
+
+        // Stages are undone in reverse
+        let mut staged_twice = Vec::new();
+        Encoder::new(Cursor::new(&bitmap)).unwrap().stage(Stage::Mtf).stage(Stage::Rle).encode(&mut staged_twice).unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(Cursor::new(&staged_twice)).strict(true).decode(&mut decoded).unwrap();
+        assert_eq!(decoded, bitmap);
+
+        let mut empty = Vec::new();
+        Encoder::new(Cursor::new(Vec::new())).unwrap().stage(Stage::Rle).encode(&mut empty).unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(Cursor::new(&empty)).strict(true).decode(&mut decoded).unwrap();
+        assert!(decoded.is_empty());
+
+        assert!(Encoder::new(Cursor::new(&bitmap)).unwrap().stage(Stage::Delta { stride: 0 }).encode(&mut Vec::new()).is_err());
+        assert!(matches!(Stage::Bwt { block_size: 0 }.forward(&bitmap), Err(HuffmanError::InvalidInput(_))));
+        assert!(matches!(Stage::Delta { stride: 0 }.forward(&bitmap), Err(HuffmanError::InvalidInput(_))));
+        assert!(matches!(Stage::Bwt { block_size: 0 }.inverse(&bitmap), Err(HuffmanError::InvalidInput(_))));
+
+        // An unknown stage is rejected
+        staged[8] = 0xff;
+        assert!(matches!(Decoder::new(Cursor::new(&staged)).decode(&mut Vec::new()), Err(HuffmanError::CorruptHeader(_))));
+
+        // As is a stage with parameters out of range
+        let mut corrupt = Vec::new();
+        Encoder::new(Cursor::new(&bitmap)).unwrap().stage(Stage::Delta { stride: 1 }).encode(&mut corrupt).unwrap();
+        corrupt[9..13].copy_from_slice(&[0; 4]);
+        assert!(matches!(Decoder::new(Cursor::new(&corrupt)).decode(&mut Vec::new()), Err(HuffmanError::CorruptHeader(_))));
+    }
+}
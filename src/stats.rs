@@ -0,0 +1,268 @@
+use super::*;
+
+/// Statistics about the codes of a `CanonicalTree`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    /// Number of codes of each length, indexed by length.
+    pub length_counts: Vec<u32>,
+    /// Number of symbols with a code.
+    pub symbols: usize,
+    /// Length of the shortest code, 0 if there are no codes.
+    pub min_length: u8,
+    /// Length of the longest code, 0 if there are no codes.
+    pub max_length: u8,
+    /// Mean code length over all symbols with a code, ignoring how often they occur.
+    pub average_length: f64,
+    /// Bytes of header written by the `Encoder` in front of the encoded data.
+    pub header_bytes: u64,
+    /// Bytes needed to store the tree with `CanonicalTree::to_bytes`.
+    pub serialized_bytes: u64,
+}
+
+impl TreeStats {
+    pub fn new(tree: &CanonicalTree) -> TreeStats {
+        let lengths: Vec<u8> = tree.code_lengths().iter()
+            .cloned()
+            .filter(|&length| length > 0)
+            .collect();
+
+        let max_length = tree.max_code_length().unwrap_or(0);
+        let min_length = tree.min_code_length().unwrap_or(0);
+
+        let mut length_counts = vec![0; max_length as usize + 1];
+        for &length in lengths.iter() {
+            length_counts[length as usize] += 1;
+        }
+
+        let average_length = if lengths.is_empty() {
+            0.0
+        } else {
+            lengths.iter().map(|&length| length as f64).sum::<f64>() / lengths.len() as f64
+        };
+
+        TreeStats {
+            length_counts,
+            symbols: lengths.len(),
+            min_length,
+            max_length,
+            average_length,
+            header_bytes: header_size(tree),
+            serialized_bytes: 3 + 2 * lengths.len() as u64,
+        }
+    }
+}
+
+/// Number of bits of encoded output produced by each byte, returned by
+/// `CanonicalTree::encode_with_usage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitUsage {
+    bits: Vec<u64>,
+}
+
+impl BitUsage {
+    /// The bits `tree` produces for data with these byte frequencies.
+    ///
+    /// Bytes without a code in `tree` count as 0 bits.
+    pub fn new<F: Frequencies<Symbol = u8> + ?Sized>(tree: &CanonicalTree, frequencies: &F) -> BitUsage {
+        let bits = frequencies.frequencies().iter()
+            .zip(tree.code_lengths().iter())
+            .map(|(&frequency, &length)| frequency * length as u64)
+            .collect();
+
+        BitUsage {bits}
+    }
+
+    /// Bits produced by `byte`.
+    pub fn get(&self, byte: u8) -> u64 {
+        self.bits[byte as usize]
+    }
+
+    /// Bits produced by all bytes, before padding to a whole byte.
+    pub fn total(&self) -> u64 {
+        self.bits.iter().sum()
+    }
+
+    /// The fraction of all bits produced by `byte`, or 0 if nothing was encoded.
+    pub fn share(&self, byte: u8) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => self.get(byte) as f64 / total as f64,
+        }
+    }
+
+    /// The bytes that produced any bits with their bit counts, from most bits to fewest.
+    pub fn largest(&self) -> Vec<(u8, u64)> {
+        let mut usage: Vec<(u8, u64)> = self.bits.iter().enumerate()
+            .filter(|&(_byte, &bits)| bits > 0)
+            .map(|(byte, &bits)| (byte as u8, bits))
+            .collect();
+        usage.sort_by_key(|&(byte, bits)| (std::cmp::Reverse(bits), byte));
+
+        usage
+    }
+}
+
+/// Shannon entropy of the frequencies in bits per symbol, the lower bound for any code.
+///
+/// Returns 0 if all frequencies are 0.
+pub fn entropy<F: Frequencies + ?Sized>(frequencies: &F) -> f64 {
+    let freq_table = frequencies.frequencies();
+    let total = freq_table.iter().fold(0, |total: u64, &frequency| total.saturating_add(frequency));
+
+    if total == 0 {
+        return 0.0;
+    }
+
+    freq_table.iter()
+        .filter(|&&frequency| frequency > 0)
+        .map(|&frequency| {
+            let probability = frequency as f64 / total as f64;
+            -probability * probability.log2()
+        })
+        .sum()
+}
+
+/// Average number of bits `tree` spends per symbol when encoding data with these frequencies.
+///
+/// Returns `None` if all frequencies are 0 or a symbol that occurs has no code in `tree`.
+pub fn expected_bits_per_symbol<F: Frequencies + ?Sized>(tree: &CanonicalTree<F::Symbol>, frequencies: &F) -> Option<f64> {
+    let freq_table = frequencies.frequencies();
+    let total = freq_table.iter().fold(0, |total: u64, &frequency| total.saturating_add(frequency));
+
+    if total == 0 {
+        return None;
+    }
+
+    let mut bits: f64 = 0.0;
+
+    for (symbol, &frequency) in freq_table.iter().enumerate() {
+        if frequency == 0 {
+            continue;
+        }
+
+        let code = tree.code_book().get(F::Symbol::from_index(symbol))?;
+        bits += frequency as f64 * code.length() as f64;
+    }
+
+    Some(bits / total as f64)
+}
+
+/// Ratio of the entropy to the expected bits per symbol of `tree`, at most 1.
+///
+/// A value of 1 means the codes reach the Shannon bound for these frequencies. Returns `None`
+/// under the same conditions as `expected_bits_per_symbol`.
+pub fn efficiency<F: Frequencies + ?Sized>(tree: &CanonicalTree<F::Symbol>, frequencies: &F) -> Option<f64> {
+    expected_bits_per_symbol(tree, frequencies)
+        .map(|bits| entropy(frequencies) / bits)
+}
+
+/// Size of the output of the `Encoder` for data with these byte frequencies, without encoding
+/// anything.
+///
+/// Returns the bytes of header in front of the encoded data and the bits of encoded data, before
+/// padding to a whole byte.
+pub fn estimate_compressed_size<F: Frequencies<Symbol = u8> + ?Sized>(frequencies: &F) -> (u64, u64) {
+    let tree = match CanonicalTree::from_frequencies(frequencies) {
+        Ok(tree) => tree,
+        // Empty data is written as just its size
+        Err(_) => return (8, 0),
+    };
+
+    let payload_bits = frequencies.frequencies().iter()
+        .zip(tree.code_lengths().iter())
+        .map(|(&frequency, &length)| frequency * length as u64)
+        .sum();
+
+    (header_size(&tree), payload_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_stats() {
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 3), (b'd', 3)]).unwrap();
+
+        let stats = tree.stats();
+
+        assert_eq!(stats.length_counts, vec![0, 1, 1, 2]);
+        assert_eq!(stats.symbols, 4);
+        assert_eq!(stats.min_length, 1);
+        assert_eq!(stats.max_length, 3);
+        assert_eq!(stats.average_length, 2.25);
+        assert_eq!(stats.header_bytes, 8 + 2 + 256);
+        assert_eq!(stats.serialized_bytes, tree.to_bytes().len() as u64);
+    }
+
+    #[test]
+    fn test_entropy() {
+        let mut freq_table = [0; NUM_BYTES];
+        assert_eq!(entropy(&freq_table), 0.0);
+
+        freq_table[b'a' as usize] = 10;
+        assert_eq!(entropy(&freq_table), 0.0);
+
+        freq_table[b'b' as usize] = 10;
+        freq_table[b'c' as usize] = 10;
+        freq_table[b'd' as usize] = 10;
+        assert_eq!(entropy(&freq_table), 2.0);
+    }
+
+    #[test]
+    fn test_efficiency() {
+        let mut freq_table = [0; NUM_BYTES];
+        freq_table[b'a' as usize] = 2;
+        freq_table[b'b' as usize] = 1;
+        freq_table[b'c' as usize] = 1;
+
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 2)]).unwrap();
+        assert_eq!(expected_bits_per_symbol(&tree, &freq_table), Some(1.5));
+        assert_eq!(efficiency(&tree, &freq_table), Some(1.0));
+
+        let tree = CanonicalTree::new(vec![(b'a', 2), (b'b', 2), (b'c', 2)]).unwrap();
+        assert_eq!(expected_bits_per_symbol(&tree, &freq_table), Some(2.0));
+        assert_eq!(efficiency(&tree, &freq_table), Some(0.75));
+
+        // Symbol without a code
+        freq_table[b'd' as usize] = 1;
+        assert_eq!(expected_bits_per_symbol(&tree, &freq_table), None);
+        assert_eq!(efficiency(&tree, &freq_table), None);
+    }
+
+    #[test]
+    fn test_estimate_compressed_size() {
+        let text = b"a small sample string";
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(text);
+
+        let (header_bytes, payload_bits) = estimate_compressed_size(&frequencies);
+
+        let mut encoder = Encoder::new(std::io::Cursor::new(&text[..])).unwrap();
+        let mut encoded = Vec::new();
+        encoder.encode(&mut encoded).unwrap();
+
+        assert_eq!(header_bytes, 8 + 2 + 256);
+        assert_eq!(header_bytes + payload_bits.div_ceil(8), encoded.len() as u64);
+
+        assert_eq!(estimate_compressed_size(&[0; NUM_BYTES]), (8, 0));
+    }
+
+    #[test]
+    fn test_bit_usage() {
+        let text = b"aaaabbc";
+        let tree = CanonicalTree::new(vec![(b'a', 1), (b'b', 2), (b'c', 2)]).unwrap();
+
+        let mut encoded = Vec::new();
+        let usage = tree.encode_with_usage(&mut &text[..], &mut encoded).unwrap();
+
+        assert_eq!(usage.get(b'a'), 4);
+        assert_eq!(usage.get(b'b'), 4);
+        assert_eq!(usage.get(b'c'), 2);
+        assert_eq!(usage.get(b'z'), 0);
+        assert_eq!(usage.total(), 10);
+        assert_eq!(usage.share(b'c'), 0.2);
+        assert_eq!(usage.largest(), vec![(b'a', 4), (b'b', 4), (b'c', 2)]);
+        assert_eq!(encoded.len(), 2);
+    }
+}
@@ -0,0 +1,252 @@
+//! Encoding and decoding within a fixed amount of memory, however long the stream.
+//!
+//! A `StreamingEncoder` writes the same stream of blocks as a `BlockEncoder`, one block at a
+//! time on the calling thread, with Huffman codes no longer than `STREAMING_MAX_CODE_LENGTH`
+//! bits. That bounds the size of each encoded block, so both the block being read and its encoded
+//! bytes fit in buffers allocated once up front. Together with the tables and I/O buffers, which
+//! take at most `STREAMING_OVERHEAD` bytes, the encoder and the decoder each use at most
+//! `3 * block_size + STREAMING_OVERHEAD` bytes, where the block size is picked to keep that
+//! within the memory limit.
+//!
+//! The `StreamingDecoder` refuses any block that could not have been written within its limit,
+//! before reading it into memory: blocks whose encoded length is too large, that hold more than a
+//! block of data, or that use stages or a backend other than `Backend::Huffman`. It checks the
+//! checksums written by `BlockEncoder::checksum` as the blocks are decoded.
+
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::result::Result;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use super::*;
+
+/// Default memory limit, which gives blocks of 1 MiB.
+pub const DEFAULT_STREAMING_MEMORY: usize = 4 * 1024 * 1024;
+
+/// Memory allowed for the tables and I/O buffers on top of the block buffers.
+pub const STREAMING_OVERHEAD: usize = 1024 * 1024;
+
+/// Longest code written by a `StreamingEncoder`, which keeps an encoded block under twice the
+/// size of the block.
+pub const STREAMING_MAX_CODE_LENGTH: u8 = 15;
+
+/// Encodes a stream of blocks within a fixed memory limit.
+#[derive(Debug, Clone)]
+pub struct StreamingEncoder {
+    block_size: usize,
+}
+
+impl StreamingEncoder {
+    /// Encode within `DEFAULT_STREAMING_MEMORY` bytes.
+    pub fn new() -> StreamingEncoder {
+        StreamingEncoder {
+            block_size: streaming_block_size(DEFAULT_STREAMING_MEMORY).expect("Default limit is valid"),
+        }
+    }
+
+    /// Use at most `bytes` bytes of memory, however long the stream.
+    ///
+    /// Fails if `bytes` leaves no room for a block after `STREAMING_OVERHEAD`.
+    pub fn memory_limit(mut self, bytes: usize) -> Result<StreamingEncoder, HuffmanError> {
+        self.block_size = streaming_block_size(bytes)?;
+        Ok(self)
+    }
+
+    /// Number of bytes in each block.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Encode all of `read` to `write`.
+    pub fn encode<R: Read, W: Write>(&self, mut read: R, mut write: W) -> Result<(), HuffmanError> {
+        let pipeline = PipelineBuilder::new().max_code_length(STREAMING_MAX_CODE_LENGTH).build();
+
+        let mut block = vec![0; self.block_size];
+        let mut encoded = Vec::with_capacity(max_encoded_size(self.block_size));
+
+        loop {
+            let length = read_block_bytes(&mut read, &mut block)?;
+
+            if length == 0 {
+                break;
+            }
+
+            encoded.clear();
+            pipeline.encode(SliceSource::new(&block[..length]), &mut encoded)?;
+
+            write.write_u32::<LittleEndian>(encoded.len() as u32)?;
+            write.write_all(&encoded)?;
+
+            if length < self.block_size {
+                break;
+            }
+        }
+
+        Ok(write.write_u32::<LittleEndian>(0)?)
+    }
+}
+
+impl Default for StreamingEncoder {
+    fn default() -> StreamingEncoder {
+        StreamingEncoder::new()
+    }
+}
+
+/// Decodes a stream of blocks within a fixed memory limit.
+///
+/// Streams from a `StreamingEncoder` with the same limit or a lower one always decode. Streams
+/// from a `BlockEncoder` decode if their blocks are no larger and use only `Backend::Huffman`.
+#[derive(Debug, Clone)]
+pub struct StreamingDecoder {
+    block_size: usize,
+}
+
+impl StreamingDecoder {
+    /// Decode within `DEFAULT_STREAMING_MEMORY` bytes.
+    pub fn new() -> StreamingDecoder {
+        StreamingDecoder {
+            block_size: streaming_block_size(DEFAULT_STREAMING_MEMORY).expect("Default limit is valid"),
+        }
+    }
+
+    /// Use at most `bytes` bytes of memory, refusing blocks that need more.
+    ///
+    /// Fails if `bytes` leaves no room for a block after `STREAMING_OVERHEAD`.
+    pub fn memory_limit(mut self, bytes: usize) -> Result<StreamingDecoder, HuffmanError> {
+        self.block_size = streaming_block_size(bytes)?;
+        Ok(self)
+    }
+
+    /// Decode all of the blocks in `read` to `write`, stopping after the end marker.
+    ///
+    /// Each block must decode exactly, with nothing but padding after its coded bytes, and match
+    /// its checksum if it has one. On an error, everything decoded before it is written, which
+    /// includes a block that does not match its checksum.
+    pub fn decode<R: Read, W: Write>(&self, mut read: R, mut write: W) -> Result<(), HuffmanError> {
+        let max_encoded = max_encoded_size(self.block_size);
+        let mut block = Vec::with_capacity(max_encoded);
+
+        loop {
+            let length = read.read_u32::<LittleEndian>()?;
+
+            if length == 0 {
+                return Ok(());
+            }
+
+            let has_checksum = length & CHECKSUM_FLAG != 0;
+            let length = (length & !CHECKSUM_FLAG) as usize;
+
+            if length > max_encoded {
+                return Err(HuffmanError::InvalidInput(format!("Encoded block of {} bytes is larger than the limit of {} bytes", length, max_encoded)));
+            }
+
+            block.resize(length, 0);
+            read.read_exact(&mut block)?;
+
+            let checksum = if has_checksum { Some(read.read_u32::<LittleEndian>()?) } else { None };
+
+            let mut checksum_write = ChecksumWriter::new(&mut write);
+
+            // Only Huffman coded blocks can be decoded within a memory limit
+            Pipeline::decoder(Cursor::new(&block[..]))?
+                .backend(Backend::Huffman)
+                .strict(true)
+                .max_size(self.block_size as u64)
+                .decode(&mut checksum_write)?;
+
+            if checksum.is_some_and(|checksum| checksum != checksum_write.checksum()) {
+                return Err(HuffmanError::ChecksumMismatch);
+            }
+        }
+    }
+}
+
+impl Default for StreamingDecoder {
+    fn default() -> StreamingDecoder {
+        StreamingDecoder::new()
+    }
+}
+
+/// Size of the blocks that fit in `memory_limit` bytes.
+fn streaming_block_size(memory_limit: usize) -> Result<usize, HuffmanError> {
+    let block_size = (memory_limit.saturating_sub(STREAMING_OVERHEAD) / 3).min(MAX_BLOCK_SIZE);
+
+    if block_size == 0 {
+        return Err(HuffmanError::InvalidInput(format!("Memory limit of {} bytes leaves no room for a block", memory_limit)));
+    }
+
+    Ok(block_size)
+}
+
+/// Largest encoded block for a block of `block_size` bytes: the size, the backend and header
+/// format, the code lengths, and the codes.
+fn max_encoded_size(block_size: usize) -> usize {
+    2 + 8 + NUM_BYTES + (block_size * STREAMING_MAX_CODE_LENGTH as usize).div_ceil(8)
+}
+
+/// Read until `buf` is full or the data ends, returning the number of bytes read.
+fn read_block_bytes<R: Read>(read: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match read_some(read, &mut buf[filled..])? {
+            0 => break,
+            bytes_read => filled += bytes_read,
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "the quick brown fox jumps over the lazy dog, and the dog sleeps. ".repeat(200);
+        let limit = STREAMING_OVERHEAD + 3 * 1000;
+
+        let encoder = StreamingEncoder::new().memory_limit(limit).unwrap();
+        assert_eq!(encoder.block_size(), 1000);
+
+        let decoder = StreamingDecoder::new().memory_limit(limit).unwrap();
+
+        for data in [&b""[..], b"a", &text.as_bytes()[..1000], text.as_bytes()] {
+            let mut encoded = Vec::new();
+            encoder.encode(data, &mut encoded).unwrap();
+
+            let mut decoded = Vec::new();
+            decoder.decode(&encoded[..], &mut decoded).unwrap();
+            assert_eq!(decoded, data);
+
+            // The same stream as a `BlockEncoder` writes
+            decoded.clear();
+            BlockDecoder::new().decode(&encoded[..], &mut decoded).unwrap();
+            assert_eq!(decoded, data);
+        }
+
+        assert!(StreamingEncoder::new().memory_limit(STREAMING_OVERHEAD + 2).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let text = "the quick brown fox jumps over the lazy dog".repeat(100);
+        let decoder = StreamingDecoder::new().memory_limit(STREAMING_OVERHEAD + 3 * 1000).unwrap();
+
+        let decode = |pipeline: Pipeline, block_size| {
+            let mut encoded = Vec::new();
+            BlockEncoder::new(pipeline).block_size(block_size).unwrap().encode(text.as_bytes(), &mut encoded).unwrap();
+            decoder.decode(&encoded[..], &mut Vec::new())
+        };
+
+        assert!(decode(Pipeline::default(), 1000).is_ok());
+        // Blocks holding more than the limit allows
+        assert!(decode(Pipeline::default(), 1001).is_err());
+        assert!(decode(Pipeline::default(), text.len()).is_err());
+        // Blocks that need more memory to decode
+        assert!(decode(PipelineBuilder::new().stage(Stage::Rle).build(), 1000).is_err());
+        assert!(decode(PipelineBuilder::new().backend(Backend::Lz77).build(), 1000).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use core::fmt::{Debug, Display};
+use core::hash::Hash;
+
+/// A symbol of an alphabet that can be huffman coded.
+///
+/// Implemented for `u8`, with an alphabet of 256 symbols, and `u16`, with an alphabet of 65,536
+/// symbols.
+pub trait Symbol: Copy + Ord + Hash + Debug + Display + Send + Sync + 'static {
+    /// Number of symbols in the alphabet.
+    const ALPHABET_SIZE: usize;
+
+    /// Number of bytes used to store a symbol.
+    const BYTES: usize;
+
+    /// Index of the symbol in the alphabet.
+    fn to_index(self) -> usize;
+
+    /// The symbol at `index` in the alphabet. `index` must be less than `ALPHABET_SIZE`.
+    fn from_index(index: usize) -> Self;
+}
+
+impl Symbol for u8 {
+    const ALPHABET_SIZE: usize = 1 << 8;
+    const BYTES: usize = 1;
+
+    #[inline]
+    fn to_index(self) -> usize {
+        self as usize
+    }
+
+    #[inline]
+    fn from_index(index: usize) -> u8 {
+        debug_assert!(index < Self::ALPHABET_SIZE);
+        index as u8
+    }
+}
+
+impl Symbol for u16 {
+    const ALPHABET_SIZE: usize = 1 << 16;
+    const BYTES: usize = 2;
+
+    #[inline]
+    fn to_index(self) -> usize {
+        self as usize
+    }
+
+    #[inline]
+    fn from_index(index: usize) -> u16 {
+        debug_assert!(index < Self::ALPHABET_SIZE);
+        index as u16
+    }
+}
@@ -0,0 +1,390 @@
+#[cfg(feature = "std")]
+use std::io;
+use core::mem;
+use core::convert::Infallible;
+#[cfg(feature = "std")]
+use std::io::Read;
+use core::error::Error;
+
+use super::*;
+
+/// Maximum code length decoded with a single table.
+const MAX_TABLE_BITS: u8 = 12;
+
+/// Number of bits indexing the primary table when some codes are longer than `MAX_TABLE_BITS`.
+const PRIMARY_BITS: u8 = 10;
+
+/// Maximum number of bits indexing a secondary table.
+const MAX_SECONDARY_BITS: u8 = 8;
+
+/// Entry of a `DecodeTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableEntry<S = u8> {
+    /// No code starts with these bits.
+    Invalid,
+    /// The bits start with the rest of the code for `symbol`, which is `length` more bits long.
+    Symbol { symbol: S, length: u8 },
+    /// The bits are the start of longer codes, continued in the table at `offset` which is
+    /// indexed by the next `bits` bits.
+    Link { offset: u32, bits: u8 },
+}
+
+/// Table used to decode a symbol by indexing with the next bits of the stream.
+///
+/// Short codes are decoded with a single lookup in the primary table. When codes are too long to
+/// fit a single table of reasonable size, the primary table links to smaller secondary tables
+/// that are indexed by the bits following the primary bits.
+///
+/// All tables are stored one after the other in `entries`. The primary table starts at offset 0
+/// and is indexed by the next `bits` bits of the stream, most significant bit first. A `Link`
+/// entry gives the offset of a secondary table and the number of bits indexing it, which are
+/// the bits following the ones already used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeTable<S = u8> {
+    bits: u8,
+    entries: Vec<TableEntry<S>>,
+}
+
+impl<S: Symbol> DecodeTable<S> {
+    /// Build the table for prefix free codes.
+    pub fn new(code_book: &CodeBook<S>) -> DecodeTable<S> {
+        let codes: Vec<(S, Code)> = code_book.iter().collect();
+
+        let max_length = codes.iter()
+            .map(|&(_symbol, code)| code.length())
+            .max()
+            .unwrap_or(1);
+
+        let bits = if max_length <= MAX_TABLE_BITS {
+            max_length
+        } else {
+            PRIMARY_BITS
+        };
+
+        let mut entries = Vec::new();
+        build_table(&mut entries, &codes, bits);
+
+        DecodeTable {
+            bits,
+            entries,
+        }
+    }
+
+    /// Number of bits indexing the primary table.
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    /// The entries of the primary table followed by those of the secondary tables.
+    pub fn entries(&self) -> &[TableEntry<S>] {
+        &self.entries
+    }
+
+    /// Bytes of memory held by the entries.
+    pub fn memory(&self) -> usize {
+        mem::size_of_val(&self.entries[..])
+    }
+
+    /// Decode the next symbol.
+    #[cfg(feature = "std")]
+    pub fn decode_next<R: Read>(&self, bit_reader: &mut BitReader<R>) -> io::Result<Decoded<S>> {
+        self.decode_bits(bit_reader)
+    }
+
+    /// Decode the next symbol of a slice.
+    pub fn decode_slice_next(&self, bit_reader: &mut SliceBitReader) -> Decoded<S> {
+        match self.decode_bits(bit_reader) {
+            Ok(decoded) => decoded,
+            Err(never) => match never {},
+        }
+    }
+
+    fn decode_bits<B: TableBits>(&self, bit_reader: &mut B) -> Result<Decoded<S>, B::Error> {
+        let mut offset: usize = 0;
+        let mut bits = self.bits;
+
+        // Bits consumed by the primary and secondary tables
+        let mut prefix = Code::new(0, 0);
+
+        loop {
+            let available = bit_reader.fill(bits)?;
+            let index = (bit_reader.peek() >> (64 - bits as u32)) as usize;
+
+            match self.entries[offset + index] {
+                TableEntry::Symbol { symbol, length } if length <= available => {
+                    bit_reader.consume(length);
+                    return Ok(Decoded::Symbol(symbol));
+                },
+                TableEntry::Invalid if available >= bits => {
+                    return Ok(Decoded::Invalid { partial: append(prefix, index as u64, bits) });
+                },
+                TableEntry::Link { offset: next_offset, bits: next_bits } if available >= bits => {
+                    bit_reader.consume(bits);
+                    prefix = append(prefix, index as u64, bits);
+                    offset = next_offset as usize;
+                    bits = next_bits;
+                },
+                _ => {
+                    // The read ended in the middle of a code
+                    let partial = append(prefix, index as u64 >> (bits - available), available);
+                    return Ok(Decoded::End { partial });
+                },
+            }
+        }
+    }
+
+    /// Decode the next symbol, failing with a `DecodeError` on an invalid code.
+    ///
+    /// Returns `None` if the stream ends before a complete code.
+    pub(crate) fn decode_checked<B: TableBits>(&self, bit_reader: &mut B, symbols_decoded: u64) -> Result<Option<S>, HuffmanError> {
+        let bit_offset = bit_reader.position();
+
+        match self.decode_bits(bit_reader).map_err(Into::into)? {
+            Decoded::Symbol(symbol) => Ok(Some(symbol)),
+            Decoded::End { .. } => Ok(None),
+            Decoded::Invalid { partial } => Err(HuffmanError::Decode(DecodeError {
+                kind: DecodeErrorKind::InvalidCode,
+                bit_offset,
+                symbols_decoded,
+                partial_code: partial,
+            })),
+        }
+    }
+
+    /// The `DecodeError` for data that ended in the middle of a code.
+    pub(crate) fn unexpected_end<B: TableBits>(&self, bit_reader: &mut B, symbols_decoded: u64) -> HuffmanError {
+        // Find the bits of the incomplete code
+        let bit_offset = bit_reader.position();
+        let partial_code = match self.decode_bits(bit_reader) {
+            Ok(Decoded::End { partial }) => partial,
+            Ok(_) => Code::new(0, 0),
+            Err(err) => return err.into(),
+        };
+
+        HuffmanError::Decode(DecodeError {
+            kind: DecodeErrorKind::UnexpectedEnd,
+            bit_offset,
+            symbols_decoded,
+            partial_code,
+        })
+    }
+}
+
+/// Bits decoded with a `DecodeTable`, most significant bit first.
+pub(crate) trait TableBits {
+    type Error: Error + Into<HuffmanError> + 'static;
+
+    /// Make at least `bits` bits available to `peek`, or as many as are left, returning the
+    /// number available.
+    fn fill(&mut self, bits: u8) -> Result<u8, Self::Error>;
+
+    /// The available bits, left aligned. Bits past those available are zero.
+    fn peek(&self) -> u64;
+
+    fn consume(&mut self, bits: u8);
+
+    fn position(&self) -> u64;
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> TableBits for BitReader<R> {
+    type Error = io::Error;
+
+    fn fill(&mut self, bits: u8) -> io::Result<u8> {
+        BitReader::fill(self, bits)
+    }
+
+    fn peek(&self) -> u64 {
+        BitReader::peek(self)
+    }
+
+    fn consume(&mut self, bits: u8) {
+        BitReader::consume(self, bits)
+    }
+
+    fn position(&self) -> u64 {
+        BitReader::position(self)
+    }
+}
+
+impl<'a> TableBits for SliceBitReader<'a> {
+    type Error = Infallible;
+
+    fn fill(&mut self, _bits: u8) -> Result<u8, Infallible> {
+        Ok(self.bits_left().min(64) as u8)
+    }
+
+    fn peek(&self) -> u64 {
+        SliceBitReader::peek(self)
+    }
+
+    fn consume(&mut self, bits: u8) {
+        SliceBitReader::consume(self, bits)
+    }
+
+    fn position(&self) -> u64 {
+        SliceBitReader::position(self)
+    }
+}
+
+/// Result of decoding a single symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoded<S = u8> {
+    /// A complete code was read for the symbol.
+    Symbol(S),
+    /// The read ended before a complete code. `partial` holds the bits of the incomplete code.
+    End { partial: Code },
+    /// No code starts with the bits in `partial`.
+    Invalid { partial: Code },
+}
+
+/// Append the low `length` bits of `bits` to `code`.
+fn append(code: Code, bits: u64, length: u8) -> Code {
+    if length == 0 {
+        return code;
+    }
+
+    Code::new((code.bits() << length) | bits, code.length() + length)
+}
+
+/// Append a table indexed by `bits` bits to `entries`, followed by any secondary tables it needs.
+///
+/// Returns the offset of the table.
+fn build_table<S: Symbol>(entries: &mut Vec<TableEntry<S>>, codes: &[(S, Code)], bits: u8) -> usize {
+    let offset = entries.len();
+    entries.resize(offset + (1 << bits), TableEntry::Invalid);
+
+    // Codes longer than the table grouped by their first `bits` bits
+    let mut long_codes: Vec<(usize, Vec<(S, Code)>)> = Vec::new();
+
+    for &(symbol, code) in codes.iter() {
+        if code.length() <= bits {
+            // Every index starting with the code maps to the symbol
+            let shift = bits - code.length();
+            let start = offset + (code.bits() << shift) as usize;
+            let entry = TableEntry::Symbol { symbol, length: code.length() };
+
+            for slot in entries[start..start + (1 << shift)].iter_mut() {
+                *slot = entry;
+            }
+
+            continue;
+        }
+
+        let rest = code.length() - bits;
+        let prefix = (code.bits() >> rest) as usize;
+        let suffix = Code::new(code.bits() & (u64::MAX >> (64 - rest as u32)), rest);
+
+        match long_codes.iter_mut().find(|&&mut (other, _)| other == prefix) {
+            Some(&mut (_, ref mut group)) => group.push((symbol, suffix)),
+            None => long_codes.push((prefix, vec![(symbol, suffix)])),
+        }
+    }
+
+    for (prefix, group) in long_codes {
+        let max_length = group.iter()
+            .map(|&(_symbol, code)| code.length())
+            .max()
+            .unwrap_or(1);
+
+        let next_bits = max_length.min(MAX_SECONDARY_BITS);
+        let next_offset = build_table(entries, &group, next_bits);
+
+        entries[offset + prefix] = TableEntry::Link { offset: next_offset as u32, bits: next_bits };
+    }
+
+    offset
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_decode_table() {
+        // 00 -> a, 01 -> b, 100 -> c, 101 -> d, 110 -> e, 111 -> f
+        let mut code_book = CodeBook::new();
+        code_book.insert(b'a', Code::new(0b00, 2));
+        code_book.insert(b'b', Code::new(0b01, 2));
+        code_book.insert(b'c', Code::new(0b100, 3));
+        code_book.insert(b'd', Code::new(0b101, 3));
+        code_book.insert(b'e', Code::new(0b110, 3));
+        code_book.insert(b'f', Code::new(0b111, 3));
+
+        let table = DecodeTable::new(&code_book);
+
+        assert_eq!(table.bits(), 3);
+        assert_eq!(table.entries().len(), 8);
+        assert_eq!(table.entries()[0b001], TableEntry::Symbol { symbol: b'a', length: 2 });
+        assert_eq!(table.entries()[0b110], TableEntry::Symbol { symbol: b'e', length: 3 });
+
+        // 01 111 00 101 0000000
+        let mut bit_reader = BitReader::new(Cursor::new(vec![0b01111001, 0b01000000]));
+
+        assert_eq!(decode_n(&table, &mut bit_reader, 4), b"bfad");
+    }
+
+    #[test]
+    fn test_secondary_tables() {
+        // Codes 0, 10, 110, ..., 1{19}0, 1{20}
+        let mut code_book = CodeBook::new();
+
+        for length in 1..21 {
+            code_book.insert(length, Code::new((1 << length) - 2, length));
+        }
+        code_book.insert(0, Code::new((1 << 20) - 1, 20));
+
+        let table = DecodeTable::new(&code_book);
+
+        assert_eq!(table.bits, PRIMARY_BITS);
+        assert!(table.entries.len() < 1 << 12);
+
+        // 1{20} 1{14}0 0 10 0000
+        let mut bit_reader = BitReader::new(Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0xc8]));
+
+        assert_eq!(decode_n(&table, &mut bit_reader, 4), &[0, 15, 1, 2]);
+    }
+
+    #[test]
+    fn test_partial_codes() {
+        let mut code_book = CodeBook::new();
+
+        for length in 1..21 {
+            code_book.insert(length, Code::new((1 << length) - 2, length));
+        }
+
+        let table = DecodeTable::new(&code_book);
+
+        // 1{20} is not a code
+        let mut bit_reader = BitReader::new(Cursor::new(vec![0xff, 0xff, 0xff]));
+        assert_eq!(table.decode_next(&mut bit_reader).unwrap(),
+            Decoded::Invalid { partial: Code::new((1 << 20) - 1, 20) });
+
+        // 1{9} and 1{14} are the start of codes
+        let mut bit_reader = BitReader::new(Cursor::new(vec![0xff, 0xff]));
+        bit_reader.fill(16).unwrap();
+        bit_reader.consume(7);
+        assert_eq!(table.decode_next(&mut bit_reader).unwrap(),
+            Decoded::End { partial: Code::new((1 << 9) - 1, 9) });
+
+        let mut bit_reader = BitReader::new(Cursor::new(vec![0xff, 0xff, 0xff]));
+        bit_reader.fill(24).unwrap();
+        bit_reader.consume(10);
+        assert_eq!(table.decode_next(&mut bit_reader).unwrap(),
+            Decoded::End { partial: Code::new((1 << 14) - 1, 14) });
+    }
+
+    fn decode_n<R: Read>(table: &DecodeTable, bit_reader: &mut BitReader<R>, n: usize) -> Vec<u8> {
+        let mut decoded = Vec::new();
+
+        while decoded.len() < n {
+            match table.decode_next(bit_reader).unwrap() {
+                Decoded::Symbol(symbol) => decoded.push(symbol),
+                _ => break,
+            }
+        }
+
+        decoded
+    }
+}
@@ -0,0 +1,322 @@
+//! Table based asymmetric numeral systems (tANS) coding of bytes with a `FrequencyModel`.
+//!
+//! The coder keeps a state of `MODEL_TOTAL_BITS` bits. Each byte moves the state by a table
+//! lookup and a few raw bits, so it codes as fast as a Huffman table while spending fractions of
+//! a bit like an arithmetic coder. The state is a stack, so bytes are encoded last to first and
+//! the bits of a block are written in reverse. The data is split into blocks of
+//! `TANS_BLOCK_SIZE` bytes, each of which starts from the state left by the previous one.
+
+use std::io;
+use std::mem;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Most bytes coded together, bounding the memory used by the encoder.
+pub const TANS_BLOCK_SIZE: usize = 1 << 16;
+
+/// Number of states, which is the size of the tables.
+const TABLE_SIZE: u32 = MODEL_TOTAL;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DecodeEntry {
+    symbol: u8,
+    bits: u8,
+    /// Next state before adding the bits that are read
+    base: u16,
+}
+
+/// Tables for coding bytes with tANS, built from a `FrequencyModel`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TansTable {
+    decode: Vec<DecodeEntry>,
+    /// For each byte, the states it moves to, indexed from `cumulative`
+    encode: Vec<u16>,
+    frequencies: Vec<u32>,
+    cumulative: Vec<u32>,
+}
+
+impl TansTable {
+    /// Spread the bytes over the states in proportion to their frequency in `model`.
+    pub fn new(model: &FrequencyModel) -> TansTable {
+        let mask = TABLE_SIZE - 1;
+        let step = (TABLE_SIZE >> 1) + (TABLE_SIZE >> 3) + 3;
+
+        // The step is odd, so it visits every state once
+        let mut symbols = vec![0u8; TABLE_SIZE as usize];
+        let mut position = 0;
+
+        for byte in 0..NUM_BYTES {
+            for _ in 0..model.frequency(byte as u8) {
+                symbols[position as usize] = byte as u8;
+                position = (position + step) & mask;
+            }
+        }
+
+        let frequencies = model.frequencies().to_vec();
+        let cumulative: Vec<u32> = (0..NUM_BYTES).map(|byte| model.cumulative(byte as u8)).collect();
+
+        let mut next = frequencies.clone();
+        let mut decode = Vec::with_capacity(TABLE_SIZE as usize);
+        let mut encode = vec![0u16; TABLE_SIZE as usize];
+
+        for (state, &symbol) in symbols.iter().enumerate() {
+            let byte = symbol as usize;
+            let x = next[byte];
+            next[byte] += 1;
+
+            let bits = MODEL_TOTAL_BITS - floor_log2(x);
+            decode.push(DecodeEntry {
+                symbol,
+                bits,
+                base: ((x << bits) - TABLE_SIZE) as u16,
+            });
+
+            encode[(cumulative[byte] + x - frequencies[byte]) as usize] = state as u16;
+        }
+
+        TansTable {decode, encode, frequencies, cumulative}
+    }
+
+    /// Bytes of memory held by the tables.
+    pub fn memory(&self) -> usize {
+        mem::size_of_val(&self.decode[..]) + mem::size_of_val(&self.encode[..])
+            + mem::size_of_val(&self.frequencies[..]) + mem::size_of_val(&self.cumulative[..])
+    }
+
+    /// Encode `bytes` as a single block, each of which must have a nonzero frequency.
+    pub fn encode_block<W: Write>(&self, bytes: &[u8], bit_writer: &mut BitWriter<W>) -> io::Result<()> {
+        // Bits are written in the reverse of the order they are produced
+        let mut chunks: Vec<(u16, u8)> = Vec::with_capacity(bytes.len());
+        let mut state = TABLE_SIZE;
+
+        for &byte in bytes.iter().rev() {
+            let frequency = self.frequencies[byte as usize];
+
+            if frequency == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Byte {} has no frequency in the model", byte)));
+            }
+
+            let max_bits = MODEL_TOTAL_BITS - floor_log2(frequency);
+            let bits = if state >= frequency << max_bits { max_bits } else { max_bits - 1 };
+
+            chunks.push(((state & ((1 << bits) - 1)) as u16, bits));
+
+            let x = state >> bits;
+            state = TABLE_SIZE + self.encode[(self.cumulative[byte as usize] + x - frequency) as usize] as u32;
+        }
+
+        bit_writer.write_bits_u64((state - TABLE_SIZE) as u64, MODEL_TOTAL_BITS)?;
+
+        for &(bits, length) in chunks.iter().rev() {
+            bit_writer.write_bits_u64(bits as u64, length)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decode a block of `count` bytes written by `encode_block`, appending them to `bytes`.
+    ///
+    /// Fails with a `DecodeError` if the data ends early, or does not end the block in the state
+    /// the encoder started from. `bytes_decoded` is only used to describe errors.
+    pub fn decode_block<R: Read>(&self, bit_reader: &mut BitReader<R>, count: usize, bytes: &mut Vec<u8>, bytes_decoded: u64) -> Result<(), Box<dyn Error>> {
+        let block_offset = bit_reader.position();
+        let mut state = read_bits(bit_reader, MODEL_TOTAL_BITS)
+            .ok_or_else(|| tans_error(DecodeErrorKind::UnexpectedEnd, block_offset, bytes_decoded))?;
+
+        for decoded in 0..count as u64 {
+            let entry = self.decode[state as usize];
+            bytes.push(entry.symbol);
+
+            let bits = read_bits(bit_reader, entry.bits)
+                .ok_or_else(|| tans_error(DecodeErrorKind::UnexpectedEnd, bit_reader.position(), bytes_decoded + decoded))?;
+            state = entry.base as u32 + bits;
+        }
+
+        if state != 0 {
+            return Err(tans_error(DecodeErrorKind::InvalidCode, block_offset, bytes_decoded));
+        }
+
+        Ok(())
+    }
+}
+
+/// A `TansTable` with the blocks being coded, for use as an `EntropyCoder`.
+///
+/// Symbols are coded in blocks of `TANS_BLOCK_SIZE` bytes, so `finish` must be called after
+/// the last one to write out the final block.
+#[derive(Debug, Clone)]
+pub struct TansCoder {
+    model: FrequencyModel,
+    table: TansTable,
+    /// Bytes waiting to be encoded, or decoded bytes not yet returned
+    block: Vec<u8>,
+    /// Position of the next decoded byte in `block`
+    position: usize,
+}
+
+impl TansCoder {
+    pub fn new(model: FrequencyModel) -> TansCoder {
+        TansCoder {
+            table: TansTable::new(&model),
+            model,
+            block: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn model(&self) -> &FrequencyModel {
+        &self.model
+    }
+}
+
+impl EntropyCoder for TansCoder {
+    fn table_memory(&self) -> usize {
+        self.model.memory() + self.table.memory()
+    }
+
+    fn from_frequencies(frequencies: &FrequencyTable) -> Result<TansCoder, Box<dyn Error>> {
+        Ok(TansCoder::new(FrequencyModel::from_frequencies(frequencies)?))
+    }
+
+    fn write_header<W: Write>(&self, write: &mut W) -> Result<(), Box<dyn Error>> {
+        Ok(self.model.write_header(write)?)
+    }
+
+    fn read_header<R: Read>(read: &mut R) -> Result<TansCoder, Box<dyn Error>> {
+        Ok(TansCoder::new(FrequencyModel::read_header(read)?))
+    }
+
+    fn encode_symbol<W: Write>(&mut self, byte: u8, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        if self.model.frequency(byte) == 0 {
+            return Err(From::from(format!("Byte {} has no frequency in the model", byte)));
+        }
+
+        self.block.push(byte);
+
+        if self.block.len() == TANS_BLOCK_SIZE {
+            self.finish(bit_writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish<W: Write>(&mut self, bit_writer: &mut BitWriter<W>) -> Result<(), Box<dyn Error>> {
+        if !self.block.is_empty() {
+            self.table.encode_block(&self.block, bit_writer)?;
+            self.block.clear();
+        }
+
+        Ok(())
+    }
+
+    fn decode_symbol<R: Read>(&mut self, bit_reader: &mut BitReader<R>, symbols_decoded: u64, symbols_left: u64) -> Result<u8, Box<dyn Error>> {
+        if self.position == self.block.len() {
+            self.block.clear();
+            self.position = 0;
+
+            let count = symbols_left.min(TANS_BLOCK_SIZE as u64) as usize;
+            self.table.decode_block(bit_reader, count, &mut self.block, symbols_decoded)?;
+        }
+
+        let byte = self.block[self.position];
+        self.position += 1;
+
+        Ok(byte)
+    }
+}
+
+fn floor_log2(value: u32) -> u8 {
+    31 - value.leading_zeros() as u8
+}
+
+/// Read `length` bits, or `None` if the data ends first.
+fn read_bits<R: Read>(bit_reader: &mut BitReader<R>, length: u8) -> Option<u32> {
+    if bit_reader.fill(length).ok()? < length {
+        return None;
+    }
+
+    let bits = bit_reader.peek().checked_shr(64 - length as u32).unwrap_or(0) as u32;
+    bit_reader.consume(length);
+
+    Some(bits)
+}
+
+fn tans_error(kind: DecodeErrorKind, bit_offset: u64, bytes_decoded: u64) -> Box<dyn Error> {
+    Box::new(DecodeError {
+        kind,
+        bit_offset,
+        symbols_decoded: bytes_decoded,
+        partial_code: Code::new(0, 0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(data);
+        let table = TansTable::new(&FrequencyModel::from_frequencies(&frequencies).unwrap());
+
+        let mut encoded = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(&mut encoded);
+            for block in data.chunks(1000) {
+                table.encode_block(block, &mut bit_writer).unwrap();
+            }
+        }
+
+        let mut bit_reader = BitReader::new(&encoded[..]);
+        let mut decoded = Vec::new();
+        for block in data.chunks(1000) {
+            table.decode_block(&mut bit_reader, block.len(), &mut decoded, 0).unwrap();
+        }
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"a");
+        round_trip(b"zzzzzzzz");
+        round_trip(b"abracadabra");
+        round_trip(&(0..=255).cycle().take(5000).collect::<Vec<u8>>());
+
+        let mut skewed = vec![b'a'; 10000];
+        for i in (0..10000).step_by(100) {
+            skewed[i] = b'b';
+        }
+        let encoded = round_trip(&skewed);
+        assert!(encoded.len() < 10000 / 8 / 5);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"the quick brown fox jumps over the lazy dog");
+        let table = TansTable::new(&FrequencyModel::from_frequencies(&frequencies).unwrap());
+
+        let mut encoded = Vec::new();
+        {
+            let mut bit_writer = BitWriter::new(&mut encoded);
+            assert!(table.encode_block(b"xyZ", &mut bit_writer).is_err());
+            table.encode_block(b"lazy dog", &mut bit_writer).unwrap();
+        }
+
+        let decode = |encoded: &[u8], count| {
+            let mut decoded = Vec::new();
+            table.decode_block(&mut BitReader::new(encoded), count, &mut decoded, 0)
+                .map_err(|err| err.downcast_ref::<DecodeError>().unwrap().kind)
+        };
+
+        assert!(decode(&encoded, 8).is_ok());
+        assert_eq!(decode(&encoded[..2], 8), Err(DecodeErrorKind::UnexpectedEnd));
+        // Stopping early leaves the wrong final state
+        assert_eq!(decode(&encoded, 7), Err(DecodeErrorKind::InvalidCode));
+    }
+}
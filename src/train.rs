@@ -0,0 +1,70 @@
+//! Training a codebook on many samples, for records too small to carry their own table.
+//!
+//! The frequencies of every sample are added together and a single tree is built from the
+//! totals. The tree can then be shared by the encoder and decoder of each record, so a record
+//! costs only its codes.
+
+use std::io::Read;
+use std::result::Result;
+use std::error::Error;
+
+use super::*;
+
+/// Longest code in a trained codebook, which bounds the cost of bytes never seen in training.
+pub const MAX_TRAINED_CODE_LENGTH: u8 = 24;
+
+/// Count the bytes of every sample and build a codebook from the totals.
+///
+/// Bytes that never occur in the samples still get a code, so any record can be encoded with
+/// the codebook. Fails if reading a sample fails.
+pub fn train<I>(samples: I) -> Result<CanonicalTree, Box<dyn Error>>
+    where I: IntoIterator,
+          I::Item: Read
+{
+    let mut frequencies = FrequencyTable::new();
+
+    for sample in samples {
+        let (_bytes_read, sample_frequencies) = read_frequencies(sample)?;
+        frequencies.merge(&sample_frequencies);
+    }
+
+    for byte in 0..NUM_BYTES {
+        if frequencies.get(byte as u8) == 0 {
+            frequencies.set(byte as u8, 1);
+        }
+    }
+
+    Ok(CanonicalTree::from_frequencies_limited(&frequencies, MAX_TRAINED_CODE_LENGTH)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_train() {
+        let records: Vec<String> = (0..200)
+            .map(|i| format!("{{\"id\":{},\"user\":\"user{}\",\"ok\":true}}", i, i % 17))
+            .collect();
+
+        let tree = train(records.iter().map(|record| record.as_bytes())).unwrap();
+        assert_eq!(tree.code_book().len(), NUM_BYTES);
+        assert!(tree.max_code_length().unwrap() <= MAX_TRAINED_CODE_LENGTH);
+
+        // Common bytes get short codes, and each record codes to well under its size
+        assert!(tree.code_length_of(b'"') < tree.code_length_of(b'Z'));
+
+        let record = records[42].as_bytes();
+        let mut encoded = Vec::new();
+        tree.encode(&mut Cursor::new(record), &mut encoded).unwrap();
+        assert!(encoded.len() < record.len() * 3 / 4);
+
+        let mut decoded = Vec::new();
+        tree.decode_exact(&mut Cursor::new(&encoded), &mut decoded, record.len() as u64).unwrap();
+        assert_eq!(decoded, record);
+
+        // Bytes outside the samples can still be coded
+        tree.encode(&mut Cursor::new(&[0xfe, 0x00]), &mut Vec::new()).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Sharing the trees read from headers between decoders.
+//!
+//! Many small messages coded with a handful of tables each carry the same code lengths. A
+//! `Decoder` given a `TreeCache` looks the code lengths up instead of building the tree and its
+//! decode table again, and shares the tree it finds. The cache holds at most a fixed number of
+//! trees, dropping the oldest when it is full, so headers from untrusted data cannot make it grow
+//! without bound.
+
+use std::collections::{HashMap, VecDeque};
+use std::result::Result;
+use std::sync::{Arc, Mutex};
+
+use super::*;
+
+/// Default number of trees held by a `TreeCache`.
+pub const DEFAULT_TREE_CACHE_CAPACITY: usize = 64;
+
+/// Trees built from code lengths, shared between decoders and threads.
+#[derive(Debug)]
+pub struct TreeCache {
+    capacity: usize,
+    trees: Mutex<CachedTrees>,
+}
+
+#[derive(Debug, Default)]
+struct CachedTrees {
+    trees: HashMap<[u8; NUM_BYTES], Arc<CanonicalTree>>,
+    /// Code lengths of the trees, oldest first
+    order: VecDeque<[u8; NUM_BYTES]>,
+}
+
+impl TreeCache {
+    /// A cache holding up to `DEFAULT_TREE_CACHE_CAPACITY` trees.
+    pub fn new() -> TreeCache {
+        TreeCache::with_capacity(DEFAULT_TREE_CACHE_CAPACITY)
+    }
+
+    /// A cache holding up to `trees` trees, and at least one.
+    pub fn with_capacity(trees: usize) -> TreeCache {
+        TreeCache {
+            capacity: trees.max(1),
+            trees: Mutex::new(CachedTrees::default()),
+        }
+    }
+
+    /// Number of trees in the cache.
+    pub fn len(&self) -> usize {
+        self.lock().trees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The tree for `code_lengths`, built and added to the cache if it is not there already.
+    ///
+    /// Fails if the code lengths are not valid, in which case nothing is added.
+    pub fn get_or_build(&self, code_lengths: &[u8; NUM_BYTES]) -> Result<Arc<CanonicalTree>, HuffmanError> {
+        if let Some(tree) = self.lock().trees.get(code_lengths) {
+            return Ok(Arc::clone(tree));
+        }
+
+        // Build outside the lock, so other threads are not held up
+        let tree = Arc::new(CanonicalTree::from_code_lengths(code_lengths)?);
+
+        let mut cached = self.lock();
+
+        if !cached.trees.contains_key(code_lengths) {
+            if cached.order.len() == self.capacity {
+                let oldest = cached.order.pop_front().expect("Cache is full");
+                cached.trees.remove(&oldest);
+            }
+
+            cached.trees.insert(*code_lengths, Arc::clone(&tree));
+            cached.order.push_back(*code_lengths);
+        }
+
+        Ok(tree)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CachedTrees> {
+        // The trees are only ever added whole, so they are fine to use after a panic
+        self.trees.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Default for TreeCache {
+    fn default() -> TreeCache {
+        TreeCache::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_tree_cache() {
+        let cache = Arc::new(TreeCache::with_capacity(2));
+
+        let texts = ["abracadabra", "the quick brown fox", "abracadabra", "hello world"];
+        let messages: Vec<Vec<u8>> = texts.iter()
+            .map(|message| {
+                let mut encoded = Vec::new();
+                Encoder::from_slice(message.as_bytes()).unwrap().encode(&mut encoded).unwrap();
+                encoded
+            })
+            .collect();
+
+        let mut trees = Vec::new();
+        for (encoded, message) in messages.iter().zip(texts) {
+            let mut decoded = Vec::new();
+            Decoder::new(Cursor::new(encoded)).tree_cache(Arc::clone(&cache)).strict(true).decode(&mut decoded).unwrap();
+            assert_eq!(decoded, message.as_bytes());
+
+            trees.push(cache.get_or_build(&encoded[10..10 + NUM_BYTES].try_into().unwrap()).unwrap());
+        }
+
+        // The same header shares the tree, and the oldest is dropped when full
+        assert!(Arc::ptr_eq(&trees[0], &trees[2]));
+        assert!(!Arc::ptr_eq(&trees[0], &trees[1]));
+        assert_eq!(cache.len(), 2);
+
+        assert!(cache.get_or_build(&[1; NUM_BYTES]).is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
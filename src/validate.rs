@@ -0,0 +1,200 @@
+//! Checks that codes are optimal, for testing custom constructions and validating code lengths
+//! that come from elsewhere.
+
+use core::result::Result;
+use core::error::Error;
+
+use super::*;
+
+/// Check that `code_lengths` are optimal for `frequencies`.
+///
+/// The lengths must give every symbol with a nonzero frequency a code, satisfy the Kraft
+/// inequality so a prefix code exists, and give the same total number of bits as a Huffman code.
+/// Symbols with a frequency of 0 may also have a code. The error describes the first check that
+/// fails.
+pub fn check_optimal<F: Frequencies + ?Sized>(frequencies: &F, code_lengths: &[(F::Symbol, u8)]) -> Result<(), Box<dyn Error>> {
+    let frequencies = frequencies.frequencies();
+    let mut lengths = vec![0; frequencies.len()];
+
+    for &(symbol, length) in code_lengths {
+        if length == 0 {
+            return Err(From::from(format!("Symbol {} has a code length of 0", symbol)));
+        }
+
+        if lengths[symbol.to_index()] != 0 {
+            return Err(From::from(format!("Symbol {} has more than one code length", symbol)));
+        }
+
+        lengths[symbol.to_index()] = length;
+    }
+
+    if let Some(symbol) = (0..frequencies.len()).find(|&symbol| frequencies[symbol] > 0 && lengths[symbol] == 0) {
+        return Err(From::from(format!("Symbol {} has a frequency but no code", F::Symbol::from_index(symbol))));
+    }
+
+    let mut counts = vec![0u64; 256];
+    for &(_symbol, length) in code_lengths {
+        counts[length as usize] += 1;
+    }
+
+    if !satisfies_kraft(&counts) {
+        return Err(From::from("Code lengths do not satisfy the Kraft inequality"));
+    }
+
+    let cost: u128 = frequencies.iter().zip(lengths.iter())
+        .map(|(&frequency, &length)| frequency as u128 * length as u128)
+        .sum();
+
+    let optimal = match optimal_cost(frequencies) {
+        Some(optimal) => optimal,
+        None => return Ok(()),
+    };
+
+    if cost != optimal {
+        return Err(From::from(format!("Codes take {} bits where {} bits are optimal", cost, optimal)));
+    }
+
+    Ok(())
+}
+
+/// Whether codes with `counts[l]` codes of length `l` fit in a prefix code.
+fn satisfies_kraft(counts: &[u64]) -> bool {
+    // Codes still free at the current length
+    let mut available: u64 = 1;
+
+    for &count in counts.iter().skip(1) {
+        available = available.saturating_mul(2);
+
+        if count > available {
+            return false;
+        }
+
+        available -= count;
+    }
+
+    true
+}
+
+/// Total bits of a Huffman code for `frequencies`, or `None` if no frequency is nonzero.
+fn optimal_cost(frequencies: &[u64]) -> Option<u128> {
+    let mut weights: Vec<u64> = frequencies.iter().cloned().filter(|&frequency| frequency > 0).collect();
+    weights.sort();
+
+    match weights.len() {
+        0 => None,
+        1 => Some(weights[0] as u128),
+        _ => Some(weights.iter().zip(sorted_code_lengths(&weights))
+            .map(|(&weight, length)| weight as u128 * length as u128)
+            .sum()),
+    }
+}
+
+impl<S> HuffmanTree<S> {
+    /// Check that the tree has the sibling property, which holds exactly when it is a Huffman tree
+    /// for the frequencies of its leaves.
+    ///
+    /// Every node but the root must have a sibling, every internal node must have the total
+    /// frequency of its children, and the nodes must be listed in order of decreasing frequency
+    /// with each node next to its sibling. The error describes the first check that fails.
+    pub fn check_sibling_property(&self) -> Result<(), Box<dyn Error>> {
+        // Frequencies of each pair of siblings, larger first
+        let mut siblings: Vec<(u64, u64)> = Vec::new();
+        let mut stack = vec![self.root_node.as_ref()];
+
+        while let Some(node) = stack.pop() {
+            if node.is_leaf() {
+                continue;
+            }
+
+            let (left, right) = match (node.left.as_ref(), node.right.as_ref()) {
+                (Some(left), Some(right)) => (left.as_ref(), right.as_ref()),
+                _ => return Err(From::from("Internal node has a single child")),
+            };
+
+            let (left_frequency, right_frequency) = (left.value.frequency(), right.value.frequency());
+
+            if left_frequency.checked_add(right_frequency) != Some(node.value.frequency()) {
+                return Err(From::from(format!("Internal node has frequency {} but its children total {} and {}",
+                    node.value.frequency(), left_frequency, right_frequency)));
+            }
+
+            siblings.push((left_frequency.max(right_frequency), left_frequency.min(right_frequency)));
+            stack.push(left);
+            stack.push(right);
+        }
+
+        // If any order of the pairs works, so does decreasing order of their larger frequency
+        siblings.sort_by(|a, b| b.cmp(a));
+
+        for pair in siblings.windows(2) {
+            if pair[0].1 < pair[1].0 {
+                return Err(From::from(format!("Siblings of frequency {} and {} cannot be ordered after siblings of frequency {} and {}",
+                    pair[1].0, pair[1].1, pair[0].0, pair[0].1)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_optimal() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"aaaabbc");
+
+        assert!(check_optimal(&frequencies, &[(b'a', 1), (b'b', 2), (b'c', 2)]).is_ok());
+        assert!(check_optimal(&frequencies, &[(b'a', 1), (b'b', 2), (b'c', 2), (b'd', 3)]).is_err());
+        // Making room for a symbol that never occurs costs bits
+        assert!(check_optimal(&frequencies, &[(b'a', 1), (b'b', 2), (b'c', 3), (b'd', 3)]).is_err());
+
+        // Longer than needed
+        assert!(check_optimal(&frequencies, &[(b'a', 2), (b'b', 2), (b'c', 2)]).is_err());
+        // Missing a symbol
+        assert!(check_optimal(&frequencies, &[(b'a', 1), (b'b', 1)]).is_err());
+        // Not a prefix code
+        assert!(check_optimal(&frequencies, &[(b'a', 1), (b'b', 1), (b'c', 1)]).is_err());
+
+        let text = b"a somewhat longer sample string, with punctuation!";
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(text);
+        let tree = CanonicalTree::from_frequencies(&frequencies).unwrap();
+        assert!(check_optimal(&frequencies, &tree.code_book().iter()
+            .map(|(symbol, code)| (symbol, code.length())).collect::<Vec<_>>()).is_ok());
+    }
+
+    #[test]
+    fn test_check_sibling_property() {
+        let mut frequencies = FrequencyTable::new();
+        frequencies.count(b"a somewhat longer sample string, with punctuation!");
+
+        let tree = HuffmanTree::new(&frequencies).unwrap();
+        assert!(tree.check_sibling_property().is_ok());
+
+        let pair = |a: (u8, u64), b: (u8, u64)| {
+            let mut node = Node::new(HuffmanType::internal(a.1 + b.1));
+            node.set_left(Box::new(Node::new(HuffmanType::new(a.0, a.1))));
+            node.set_right(Box::new(Node::new(HuffmanType::new(b.0, b.1))));
+            Box::new(node)
+        };
+
+        let mut rare = Node::new(HuffmanType::internal(7));
+        rare.set_left(pair((b'a', 1), (b'b', 1)));
+        rare.set_right(Box::new(Node::new(HuffmanType::new(b'c', 5))));
+        let mut root_node = Node::new(HuffmanType::internal(12));
+        root_node.set_left(Box::new(rare));
+        root_node.set_right(Box::new(Node::new(HuffmanType::new(b'd', 5))));
+        let tree = HuffmanTree { root_node: Box::new(root_node) };
+        assert!(tree.check_sibling_property().is_ok());
+
+        // Balanced, so siblings of frequency 2 and 10 must come before siblings of 5 and 5
+        let mut root_node = Node::new(HuffmanType::internal(12));
+        root_node.set_left(pair((b'a', 1), (b'b', 1)));
+        root_node.set_right(pair((b'c', 5), (b'd', 5)));
+        let tree = HuffmanTree { root_node: Box::new(root_node) };
+        assert!(tree.check_sibling_property().is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Streaming coding with a table retrained every block from a sliding window of recent data.
+//!
+//! A single table suits data whose statistics hold for the whole stream. Long streams of mixed
+//! content do better with a table that follows the data, so the encoder buffers a block at a
+//! time and builds a table from the most recent bytes, including the block. The new table is
+//! stored in front of the block only when it saves more than it costs, so a steady stream pays
+//! for few tables. The decoder just reads the tables, and keeps no window of its own.
+//!
+//! The stream starts with the block size as 32 bits. Each block starts with a 1 bit, or a 0 bit
+//! marks the end of the stream. Then comes a 1 bit followed by a table written as a tree shape,
+//! or a 0 bit to keep the last table. Then a 1 bit for a full block, or a 0 bit followed by the
+//! length of a final short block as 32 bits. The codes of the block follow.
+
+use std::io;
+use std::io::{Read, Write};
+use std::result::Result;
+use std::error::Error;
+use std::collections::VecDeque;
+
+use super::*;
+
+/// Default number of bytes coded with each table.
+pub const DEFAULT_WINDOW_BLOCK_SIZE: usize = 16 * 1024;
+
+/// Default number of recent bytes each table is built from.
+pub const DEFAULT_WINDOW_SIZE: usize = 64 * 1024;
+
+/// Encodes bytes written to it with a table retrained from a sliding window.
+///
+/// Call `finish` after the last byte to code the final block and mark the end of the stream.
+pub struct WindowEncoder<W: Write> {
+    bit_writer: BitWriter<W>,
+    block_size: usize,
+    window_size: usize,
+    /// Bytes waiting to be coded
+    block: Vec<u8>,
+    /// The most recent bytes, oldest first, along with their counts
+    window: VecDeque<u8>,
+    counts: FrequencyTable,
+    table: Option<CanonicalTree>,
+    started: bool,
+}
+
+impl<W: Write> WindowEncoder<W> {
+    pub fn new(write: W) -> WindowEncoder<W> {
+        WindowEncoder {
+            bit_writer: BitWriter::new(write),
+            block_size: DEFAULT_WINDOW_BLOCK_SIZE,
+            window_size: DEFAULT_WINDOW_SIZE,
+            block: Vec::new(),
+            window: VecDeque::new(),
+            counts: FrequencyTable::new(),
+            table: None,
+            started: false,
+        }
+    }
+
+    /// Code `bytes` bytes with each table. Defaults to `DEFAULT_WINDOW_BLOCK_SIZE`.
+    ///
+    /// Must be called before any bytes are written. Fails if `bytes` is 0 or does not fit in
+    /// 32 bits.
+    pub fn block_size(mut self, bytes: usize) -> Result<WindowEncoder<W>, Box<dyn Error>> {
+        if bytes == 0 || bytes as u64 > u32::MAX as u64 {
+            return Err(From::from(format!("Invalid block size {}", bytes)));
+        }
+
+        self.block_size = bytes;
+        Ok(self)
+    }
+
+    /// Build each table from the last `bytes` bytes. Defaults to `DEFAULT_WINDOW_SIZE`.
+    ///
+    /// The window always holds at least the block being coded.
+    pub fn window_size(mut self, bytes: usize) -> WindowEncoder<W> {
+        self.window_size = bytes;
+        self
+    }
+
+    /// Code the final block and mark the end of the stream.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.bit_writer.write_bit(false)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.bit_writer.write_bits_u64(self.block_size as u64, 32)?;
+            self.started = true;
+        }
+
+        if self.block.is_empty() {
+            return Ok(());
+        }
+
+        self.slide_window();
+
+        let mut block_counts = FrequencyTable::new();
+        block_counts.count(&self.block);
+
+        let retrained = CanonicalTree::from_frequencies(&self.counts).map_err(into_io_error)?;
+
+        // Keep the last table unless the new one saves more than it costs to store
+        let keep = match self.table {
+            Some(ref table) => match block_cost(table, &block_counts) {
+                Some(cost) => cost <= block_cost(&retrained, &block_counts).expect("Window covers the block") + retrained.shape_bits(),
+                None => false,
+            },
+            None => false,
+        };
+
+        self.bit_writer.write_bit(true)?;
+        self.bit_writer.write_bit(!keep)?;
+
+        if !keep {
+            retrained.write_shape_bits(&mut self.bit_writer).map_err(into_io_error)?;
+            self.table = Some(retrained);
+        }
+
+        if self.block.len() == self.block_size {
+            self.bit_writer.write_bit(true)?;
+        } else {
+            self.bit_writer.write_bit(false)?;
+            self.bit_writer.write_bits_u64(self.block.len() as u64, 32)?;
+        }
+
+        let code_book = self.table.as_ref().expect("Table was just set").code_book();
+        for &byte in self.block.iter() {
+            self.bit_writer.write_code(code_book.get(byte).expect("Table covers the block"))?;
+        }
+
+        self.block.clear();
+
+        Ok(())
+    }
+
+    /// Add the block to the window, then drop the oldest bytes that no longer fit.
+    fn slide_window(&mut self) {
+        self.window.extend(self.block.iter().cloned());
+        self.counts.count(&self.block);
+
+        while self.window.len() > self.window_size.max(self.block.len()) {
+            let byte = self.window.pop_front().expect("Window is not empty");
+            self.counts.set(byte, self.counts.get(byte) - 1);
+        }
+    }
+}
+
+impl<W: Write> Write for WindowEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.block.push(byte);
+
+            if self.block.len() == self.block_size {
+                self.write_block()?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Number of bits needed to code `counts` with `table`, or `None` if it lacks a code for one of
+/// the bytes.
+fn block_cost(table: &CanonicalTree, counts: &FrequencyTable) -> Option<u64> {
+    counts.iter()
+        .map(|(byte, count)| table.code_length_of(byte).map(|length| length as u64 * count))
+        .sum()
+}
+
+/// Decodes data written by a `WindowEncoder`, reading as far as the end of the stream.
+///
+/// Errors are returned as `io::Error`s wrapping a `DecodeError` where there is one.
+pub struct WindowDecoder<R> {
+    bit_reader: BitReader<R>,
+    /// Block size, once read from the start of the stream
+    block_size: Option<u64>,
+    table: Option<CanonicalTree>,
+    /// Bytes left in the current block
+    remaining: u64,
+    bytes_decoded: u64,
+    finished: bool,
+    /// Error to return from the next `read`, after the bytes decoded before it
+    error: Option<io::Error>,
+}
+
+impl<R: Read> WindowDecoder<R> {
+    pub fn new(read: R) -> WindowDecoder<R> {
+        WindowDecoder {
+            bit_reader: BitReader::new(read),
+            block_size: None,
+            table: None,
+            remaining: 0,
+            bytes_decoded: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Decode the next byte, reading block headers as they come. Returns `None` at the end of
+    /// the stream.
+    fn decode_byte(&mut self) -> Result<Option<u8>, Box<dyn Error>> {
+        while self.remaining == 0 {
+            let block_size = match self.block_size {
+                Some(block_size) => block_size,
+                None => {
+                    let block_size = self.read_bits(32)?;
+                    self.block_size = Some(block_size);
+                    block_size
+                },
+            };
+
+            if !self.read_flag()? {
+                return Ok(None);
+            }
+
+            if self.read_flag()? {
+                self.table = Some(CanonicalTree::read_shape_bits(&mut self.bit_reader)?);
+            } else if self.table.is_none() {
+                return Err(From::from("Block reuses a table before any was stored"));
+            }
+
+            self.remaining = if self.read_flag()? { block_size } else { self.read_bits(32)? };
+        }
+
+        let decode_table = self.table.as_ref().expect("Table was read with the block").decode_table();
+
+        let byte = match decode_table.decode_checked(&mut self.bit_reader, self.bytes_decoded)? {
+            Some(byte) => byte,
+            None => return Err(decode_table.unexpected_end(&mut self.bit_reader, self.bytes_decoded).into()),
+        };
+
+        self.remaining -= 1;
+        self.bytes_decoded += 1;
+
+        Ok(Some(byte))
+    }
+
+    fn read_flag(&mut self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    fn read_bits(&mut self, length: u8) -> Result<u64, Box<dyn Error>> {
+        if self.bit_reader.fill(length)? < length {
+            return Err(Box::new(DecodeError {
+                kind: DecodeErrorKind::UnexpectedEnd,
+                bit_offset: self.bit_reader.position(),
+                symbols_decoded: self.bytes_decoded,
+                partial_code: Code::new(0, 0),
+            }));
+        }
+
+        let bits = self.bit_reader.peek() >> (64 - length);
+        self.bit_reader.consume(length);
+
+        Ok(bits)
+    }
+}
+
+impl<R: Read> Read for WindowDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let mut read = 0;
+
+        while read < buf.len() && !self.finished {
+            match self.decode_byte() {
+                Ok(Some(byte)) => {
+                    buf[read] = byte;
+                    read += 1;
+                },
+                Ok(None) => self.finished = true,
+                Err(err) => {
+                    let err = into_io_error(err);
+
+                    if read == 0 {
+                        return Err(err);
+                    }
+
+                    self.error = Some(err);
+                    break;
+                },
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(data: &[u8], block_size: usize, window_size: usize) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = WindowEncoder::new(&mut encoded).block_size(block_size).unwrap().window_size(window_size);
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let mut decoded = Vec::new();
+        WindowDecoder::new(Cursor::new(&encoded)).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        encoded
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(b"", 16, 64);
+        round_trip(b"abracadabra", 4, 0);
+        round_trip(b"abracadabra", 100, 100);
+
+        // Two halves with nothing in common are each coded with their own table
+        let mut mixed: Vec<u8> = b"0123456789".iter().cycle().take(20000).cloned().collect();
+        mixed.extend(b"abcdefghijklmnopqrstuvwxyz".iter().cycle().take(20000));
+
+        let windowed = round_trip(&mixed, 1024, 2048);
+        let tree = CanonicalTree::from_slice(&mixed).unwrap();
+        let mut whole = Vec::new();
+        tree.encode(&mut Cursor::new(&mixed), &mut whole).unwrap();
+        assert!(windowed.len() < whole.len() * 9 / 10);
+
+        // A steady stream keeps its first table
+        let steady = round_trip(&mixed[..20000], 1024, 2048);
+        assert!(steady.len() < 20000 * 4 / 8 + 100);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = b"the quick brown fox jumps over the lazy dog".repeat(10);
+        let encoded = round_trip(&data, 64, 128);
+
+        let mut decoded = Vec::new();
+        let err = WindowDecoder::new(Cursor::new(&encoded[..encoded.len() / 2])).read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.get_ref().unwrap().downcast_ref::<DecodeError>().unwrap().kind, DecodeErrorKind::UnexpectedEnd);
+        assert!(data.starts_with(&decoded));
+    }
+}